@@ -1,30 +1,423 @@
+use crate::{
+    concatenated_genome::{GenomeOffsets, GENOME_CHROM},
+    error::Error,
+    top_k::get_top_k_bin_map,
+    track_conversion::{
+        detect_track_format, track_from_path_autodetect, TrackFormat,
+    },
+    util::{
+        create_maybe_gzip, format_empty_output_warning,
+        get_aliased_excluded_interval_maps, get_chrom_interval_map,
+        merge_intervals, write_bed12_line, write_bed_line, write_bedgraph_line,
+        EndConvention, ReadOptions, TrackLine, TrackLineIter, ValueColumn,
+    },
+};
 use biofile::{
-    bed::{Bed, BedDataLine, BedDataLineIter, BedWriter, Chrom},
+    bed::{Bed, BedDataLine, Chrom, Strand},
     bedgraph::BedGraphDataLine,
 };
 use math::{
     interval::{traits::Interval, I64Interval},
     iter::{
-        AggregateOp, ConcatenatedIter, IntoBinnedIntervalIter, WeightedSum,
+        AggregateOp, CommonRefinementZip, ConcatenatedIter,
+        IntoBinnedIntervalIter, WeightedSum,
     },
     partition::integer_interval_map::IntegerIntervalMap,
     set::traits::{Finite, Intersect, Set},
-    traits::ToIterator,
 };
 use num::{Float, FromPrimitive};
 use std::{
-    collections::{HashMap, HashSet},
+    cell::OnceCell,
+    cmp::Ordering,
+    collections::{hash_map::Entry, BTreeMap, HashMap, HashSet},
     fmt::Debug,
+    io::Write,
     str::FromStr,
 };
+use tempfile::NamedTempFile;
 
+/// The values are aggregated as `D: Float`, so integer counts are only exact
+/// up to the largest integer below which all integers are representable in
+/// `D`, e.g., `2^53` for `f64`. Set `RefineryOptions::check_precision` to
+/// detect aggregated values that have reached that limit, including the
+/// values of the value columns and the bins summed under `BinAggregation::Sum`.
 pub struct BedRefinery<D> {
     chrom_to_interval_map: HashMap<Chrom, IntegerIntervalMap<D>>,
     stats: RefineryStats,
+    relog_output: bool,
+    bin_aggregation: BinAggregation,
+    output_order: OutputOrder,
+    merge_bedgraph_runs: bool,
+    normalize_scope: NormalizeScope,
+    end_convention: EndConvention,
+    running_max_window: Option<usize>,
+    interpolate_gaps: bool,
+    max_interpolate_gap: Option<usize>,
+    bed12: bool,
+    check_precision: bool,
+    debug: bool,
+
+    // the offsets of `RefineryOptions::concatenate_genome` when the output is
+    // split back into the per-chromosome coordinates
+    per_chrom_genome_offsets: Option<GenomeOffsets>,
+
+    // the number of distinct start coordinates under each chromosome
+    unique_position_counts: HashMap<Chrom, i64>,
+
+    // only recorded when `RefineryOptions::emit_support_count` is set
+    chrom_to_support_boundaries: Option<HashMap<Chrom, SupportBoundaries>>,
+
+    // one for each of the value columns passed to `new_multi`, and empty
+    // otherwise
+    column_to_chrom_interval_maps: Vec<HashMap<Chrom, IntegerIntervalMap<D>>>,
+
+    // the refined intervals of each chromosome in sorted order, built on the
+    // first call to `value_at` or `values_in` so that they can be looked up by
+    // binary search
+    chrom_to_sorted_intervals: OnceCell<HashMap<Chrom, Vec<(I64Interval, D)>>>,
+}
+
+/// The sorted start and inclusive end coordinates of the input intervals on a
+/// chromosome, from which the number of input intervals overlapping an output
+/// interval can be counted.
+#[derive(Default)]
+struct SupportBoundaries {
+    starts: Vec<i64>,
+    ends: Vec<i64>,
+}
+
+impl SupportBoundaries {
+    fn count_overlapping(&self, interval: &I64Interval) -> usize {
+        // Every input interval that ends before the start of `interval` also
+        // starts before its end, so the difference counts exactly the input
+        // intervals overlapping with `interval`.
+        let num_started =
+            self.starts.partition_point(|&s| s <= interval.get_end());
+        let num_ended =
+            self.ends.partition_point(|&e| e < interval.get_start());
+        num_started - num_ended
+    }
 }
 
 pub struct RefineryStats {
     pub num_duplicate_lines: Option<i64>,
+
+    /// The number of aggregated intervals whose values are too large in
+    /// magnitude for integer counts to be exact. Only computed when
+    /// `RefineryOptions::check_precision` is set.
+    pub num_imprecise_intervals: Option<i64>,
+
+    /// The number of lines on each chromosome that were skipped because the
+    /// chromosome is not in `RefineryOptions::filter_chroms`.
+    pub skipped_chrom_line_counts: BTreeMap<Chrom, usize>,
+
+    /// The number of lines read from the track.
+    pub num_lines: usize,
+
+    /// The number of lines dropped for being longer than
+    /// `RefineryOptions::max_len`.
+    pub num_long_lines: usize,
+
+    /// The number of lines dropped for overlapping with the excluded
+    /// intervals.
+    pub num_excluded_lines: usize,
+
+    /// The number of zero-length lines, i.e., with `end == start`, that were
+    /// skipped under `ZeroLengthHandling::Skip`.
+    pub num_zero_length_lines: usize,
+
+    /// The number of malformed lines skipped under
+    /// `ReadOptions::max_bad_lines`.
+    pub num_bad_lines: usize,
+
+    /// The number of lines whose value is `.` or empty, which were read as
+    /// zeros or skipped according to `ReadOptions::missing_score`.
+    pub num_missing_score_lines: usize,
+
+    /// The number of lines with an unexpected strand field, e.g., `plus`,
+    /// which were read as having an unknown strand. The strands are only read
+    /// under `RefineryOptions::unique` or `RefineryOptions::strand_extend_bp`.
+    pub num_invalid_strand_lines: usize,
+}
+
+/// The options for constructing a `BedRefinery`. See `BedRefinery::new` for
+/// the meaning of the first few fields.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RefineryOptions {
+    pub unique: bool,
+    pub max_len: Option<usize>,
+    pub binarize_score: bool,
+    pub filter_chroms: Option<HashSet<String>>,
+    pub exclude_track_filepath: Option<String>,
+    pub debug: bool,
+    pub read_options: ReadOptions,
+
+    /// If true, the output values will be transformed by `log2` when written.
+    /// This is meant to be used together with `read_options.input_log2` so
+    /// that the aggregation happens in the linear space while the output
+    /// stays in the log space.
+    pub relog_output: bool,
+
+    /// How the base pair values in each bin are aggregated when binning.
+    pub bin_aggregation: BinAggregation,
+
+    /// If true, the number of input intervals overlapping with each output
+    /// interval will be written into the name column of the output BED file.
+    pub emit_support_count: bool,
+
+    /// If true, will check whether any aggregated value has reached the
+    /// magnitude beyond which adding unit counts in `D` can lose precision.
+    pub check_precision: bool,
+
+    /// The order in which the refined intervals are written.
+    pub output_order: OutputOrder,
+
+    /// If true, consecutive bedGraph output lines with equal values and
+    /// contiguous coordinates will be merged into a single line. Has no
+    /// effect on BED output.
+    pub merge_bedgraph_runs: bool,
+
+    /// The scope over which the values are normalized when `normalize` is set
+    /// in `write_refined_bed`. Defaults to `Genome`.
+    pub normalize_scope: NormalizeScope,
+
+    /// An interval overlapping with the excluded intervals is dropped only if
+    /// the fraction of its length that is excluded exceeds this threshold.
+    /// Defaults to 0, which drops any interval with at least one excluded base
+    /// pair.
+    pub min_exclude_overlap_fraction: f64,
+
+    /// How the end coordinates are written in `write_refined_bed`.
+    pub end_convention: EndConvention,
+
+    /// The 1-based column holding the weight of each line, e.g., 7. If set,
+    /// the value of each base pair is the weighted mean of the values of the
+    /// lines covering it instead of their sum. A line without a weight has a
+    /// weight of 1.
+    pub weight_column: Option<usize>,
+
+    /// How the zero-length lines, i.e., with `end == start`, are handled.
+    /// Lines with `end < start` are always an error.
+    pub zero_length_handling: ZeroLengthHandling,
+
+    /// If set, the intervals of the lines passing the filters on each
+    /// chromosome are first merged with `merge_intervals` using this maximum
+    /// gap, and each merged interval is aggregated once with the score given
+    /// by `pre_merge_score` in place of the original lines. The gap cannot be
+    /// negative. Cannot be used together with `weight_column` or `new_multi`.
+    pub pre_merge_gap: Option<i64>,
+
+    /// How the scores of the lines merged under `pre_merge_gap` are combined.
+    pub pre_merge_score: PreMergeScore,
+
+    /// If set, the value of each output interval is replaced by the maximum
+    /// over the intervals within this many intervals on either side, e.g.,
+    /// bins when binning, giving the envelope of the signal. The window does
+    /// not extend across a gap between non-contiguous intervals or across
+    /// chromosomes.
+    pub running_max_window: Option<usize>,
+
+    /// If true, the bins without data between two covered bins on a
+    /// chromosome are filled by linearly interpolating between the values of
+    /// the covered bins at their midpoints, e.g., to draw a sparse bedGraph
+    /// as a continuous signal. Only applies when binning, after
+    /// `running_max_window`, and in the linear space before the `log2`
+    /// transformation of `relog_output`.
+    pub interpolate_gaps: bool,
+
+    /// The largest number of consecutive uncovered bins filled under
+    /// `interpolate_gaps`. Longer gaps are left empty. `None` fills every
+    /// gap.
+    pub max_interpolate_gap: Option<usize>,
+
+    /// How the lines that are duplicates under `unique` but carry different
+    /// scores are handled. `Max` and `Sum` cannot be used together with
+    /// `weight_column`, `pre_merge_gap` or `new_multi`.
+    pub unique_score_conflict: UniqueScoreConflict,
+
+    /// If set, each line is extended by this many base pairs on both sides
+    /// before being aggregated, e.g., to turn single-base cut sites into
+    /// windows. The start is clamped at 0, and the end is clamped at the
+    /// chromosome size if the chromosome is in `chrom_sizes`. Must be
+    /// non-negative. The lengths of the lines are checked before the
+    /// extension, so that a zero-length line is handled according to
+    /// `zero_length_handling` instead of being extended.
+    pub extend_bp: Option<i64>,
+
+    /// If set, each line is extended by this many base pairs in the 3'
+    /// direction, e.g., by the fragment length to model the fragment of a
+    /// read. The end is extended for the plus strand or a missing strand,
+    /// while the start is extended for the minus strand, with the same
+    /// clamping as `extend_bp`. Applied after `extend_bp`. Must be
+    /// non-negative.
+    pub strand_extend_bp: Option<i64>,
+
+    /// The sizes of the chromosomes under their canonical names, used to clamp
+    /// the intervals extended by `extend_bp` and `strand_extend_bp`.
+    pub chrom_sizes: Option<HashMap<Chrom, i64>>,
+
+    /// If set, the chromosomes are concatenated into a single chromosome
+    /// named `GENOME_CHROM` in the global coordinates of the offsets, so that
+    /// the bins continue across the chromosome boundaries. Every chromosome
+    /// read must be in the offsets and the intervals cannot extend beyond
+    /// the chromosome sizes. Cannot be used together with
+    /// `emit_support_count` or `new_multi`.
+    pub concatenate_genome: Option<GenomeOffsets>,
+
+    /// Whether the output of `concatenate_genome` is reported in the global
+    /// coordinates or split back into the per-chromosome coordinates.
+    pub genome_coordinates: GenomeCoordinates,
+
+    /// If true, the BED output is padded to the 12 columns of BED12 for tools
+    /// expecting them, with each interval as a single block as written by
+    /// `util::write_bed12_line`. Cannot be used with bedGraph output.
+    pub bed12: bool,
+}
+
+/// `Global` reports the output intervals of a concatenated genome on the
+/// single `GENOME_CHROM` chromosome in the global coordinates. `PerChrom`
+/// splits each output interval at the chromosome boundaries and reports the
+/// parts in the coordinates of their chromosomes, where a bin spanning a
+/// boundary gives each of its parts the value of the whole bin.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum GenomeCoordinates {
+    Global,
+    PerChrom,
+}
+
+impl Default for GenomeCoordinates {
+    fn default() -> Self {
+        GenomeCoordinates::Global
+    }
+}
+
+/// `Sum` gives a merged interval the sum of the scores of the lines merged
+/// into it, while `Max` gives it the largest of their scores.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PreMergeScore {
+    Sum,
+    Max,
+}
+
+impl Default for PreMergeScore {
+    fn default() -> Self {
+        PreMergeScore::Sum
+    }
+}
+
+impl PreMergeScore {
+    fn combine<D: Float>(self, a: D, b: D) -> D {
+        match self {
+            PreMergeScore::Sum => a + b,
+            PreMergeScore::Max => a.max(b),
+        }
+    }
+}
+
+/// `First` keeps the score of the first of the duplicate lines, `Error` fails
+/// on the first duplicate line with a different score, while `Max` and `Sum`
+/// give the line the largest and the sum of the scores of its duplicates,
+/// respectively. Duplicates with equal scores are always counted once.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum UniqueScoreConflict {
+    First,
+    Error,
+    Max,
+    Sum,
+}
+
+impl Default for UniqueScoreConflict {
+    fn default() -> Self {
+        UniqueScoreConflict::First
+    }
+}
+
+impl UniqueScoreConflict {
+    /// Returns `None` under `Error`.
+    fn combine<D: Float>(self, visited: D, duplicate: D) -> Option<D> {
+        match self {
+            UniqueScoreConflict::First => Some(visited),
+            UniqueScoreConflict::Error => None,
+            UniqueScoreConflict::Max => Some(visited.max(duplicate)),
+            UniqueScoreConflict::Sum => Some(visited + duplicate),
+        }
+    }
+}
+
+/// `Skip` skips the zero-length lines and counts them in
+/// `RefineryStats::num_zero_length_lines`, printing each of them if `debug` is
+/// set. `Error` fails on the first zero-length line.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ZeroLengthHandling {
+    Skip,
+    Error,
+}
+
+impl Default for ZeroLengthHandling {
+    fn default() -> Self {
+        ZeroLengthHandling::Skip
+    }
+}
+
+/// `Genome` divides all the values by a single normalization constant, i.e.,
+/// the total signal across all the chromosomes. `Chrom` divides the values on
+/// each chromosome by the total signal on that chromosome.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum NormalizeScope {
+    Genome,
+    Chrom,
+}
+
+impl Default for NormalizeScope {
+    fn default() -> Self {
+        NormalizeScope::Genome
+    }
+}
+
+/// `Position` writes the intervals sorted by chromosome and then by position.
+/// `Value` writes the intervals across all the chromosomes sorted by their
+/// output values in descending order, with ties broken by chromosome and then
+/// by start coordinate, keeping only the first `top_n` intervals if provided.
+/// The `top_n` intervals are selected by `get_top_k_bin_map`, so which of the
+/// intervals tied in value at the cutoff are kept is unspecified.
+///
+/// Note that output sorted by value is no longer sorted by position, and
+/// hence cannot be used as an input to `RefinedBedZipper`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum OutputOrder {
+    Position,
+    Value { top_n: Option<usize> },
+}
+
+impl Default for OutputOrder {
+    fn default() -> Self {
+        OutputOrder::Position
+    }
+}
+
+/// `Average` gives each bin the average of the values over all the base pairs
+/// in the bin, where uncovered base pairs count as zero. `Sum` gives each bin
+/// the total of the base pair values in the bin without dividing by the bin
+/// size, which is the proper aggregation for additive count data.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum BinAggregation {
+    Average,
+    Sum,
+}
+
+impl Default for BinAggregation {
+    fn default() -> Self {
+        BinAggregation::Average
+    }
+}
+
+impl BinAggregation {
+    fn to_aggregate_op(self) -> AggregateOp {
+        match self {
+            BinAggregation::Average => AggregateOp::Average,
+            BinAggregation::Sum => AggregateOp::Sum,
+        }
+    }
 }
 
 impl<D, E> BedRefinery<D>
@@ -41,34 +434,282 @@ where
         exclude_track_filepath: Option<String>,
         debug: bool,
     ) -> BedRefinery<D> {
-        let exclude = if let Some(path) = exclude_track_filepath {
-            // binarize_score is irrelevant for getting the intervals
-            Some(Bed::new(&path, false).get_chrom_to_intervals())
-        } else {
-            None
+        // reading from the score column with the default options can only fail
+        // on malformed coordinates
+        BedRefinery::with_options(track_filepath, RefineryOptions {
+            unique,
+            max_len,
+            binarize_score,
+            filter_chroms,
+            exclude_track_filepath,
+            debug,
+            ..RefineryOptions::default()
+        })
+        .unwrap()
+    }
+
+    /// Returns an error if a value cannot be read from the column selected by
+    /// `options.read_options.value_column`, or if a start or end coordinate is
+    /// not an integer on more than `options.read_options.max_bad_lines`
+    /// lines.
+    pub fn with_options(
+        track_filepath: &str,
+        options: RefineryOptions,
+    ) -> Result<BedRefinery<D>, Error> {
+        BedRefinery::read_track(track_filepath, options, &[])
+    }
+
+    /// Reads the values in each of the 1-based `value_columns`, e.g., one
+    /// signal column per condition, in a single pass over the track, in
+    /// addition to the value selected by `options.read_options.value_column`.
+    /// The values of each column go through the same filters, weighting and
+    /// `transform_value` as the usual value, and are written together by
+    /// `write_refined_multi`. A "." in a value column is read as zero.
+    pub fn new_multi(
+        track_filepath: &str,
+        value_columns: Vec<usize>,
+        options: RefineryOptions,
+    ) -> Result<BedRefinery<D>, Error> {
+        if value_columns.is_empty() {
+            return Err(Error::Validation(
+                "at least one value column is required".to_string(),
+            ));
+        }
+        BedRefinery::read_track(track_filepath, options, &value_columns)
+    }
+
+    fn read_track(
+        track_filepath: &str,
+        options: RefineryOptions,
+        value_columns: &[usize],
+    ) -> Result<BedRefinery<D>, Error> {
+        let RefineryOptions {
+            unique,
+            max_len,
+            binarize_score,
+            filter_chroms,
+            exclude_track_filepath,
+            debug,
+            read_options,
+            relog_output,
+            bin_aggregation,
+            emit_support_count,
+            check_precision,
+            output_order,
+            merge_bedgraph_runs,
+            normalize_scope,
+            min_exclude_overlap_fraction,
+            end_convention,
+            weight_column,
+            zero_length_handling,
+            pre_merge_gap,
+            pre_merge_score,
+            running_max_window,
+            interpolate_gaps,
+            max_interpolate_gap,
+            unique_score_conflict,
+            extend_bp,
+            strand_extend_bp,
+            chrom_sizes,
+            concatenate_genome,
+            genome_coordinates,
+            bed12,
+        } = options;
+        for &bp in extend_bp.iter().chain(strand_extend_bp.iter()) {
+            if bp < 0 {
+                return Err(Error::Validation(format!(
+                    "the number of base pairs to extend by cannot be \
+                    negative, received {}",
+                    bp
+                )));
+            }
+        }
+        if let Some(gap) = pre_merge_gap.filter(|&gap| gap < 0) {
+            return Err(Error::Validation(format!(
+                "the maximum gap to pre-merge the intervals within cannot be \
+                negative, received {}",
+                gap
+            )));
+        }
+        if pre_merge_gap.is_some()
+            && (weight_column.is_some() || !value_columns.is_empty())
+        {
+            return Err(Error::Validation(
+                "the intervals cannot be pre-merged together with a weight \
+                column or multiple value columns"
+                    .to_string(),
+            ));
+        }
+        if concatenate_genome.is_some()
+            && (emit_support_count || !value_columns.is_empty())
+        {
+            return Err(Error::Validation(
+                "the genome cannot be concatenated together with the support \
+                counts or multiple value columns"
+                    .to_string(),
+            ));
+        }
+        let aggregates_duplicate_scores = match unique_score_conflict {
+            UniqueScoreConflict::Max | UniqueScoreConflict::Sum => unique,
+            UniqueScoreConflict::First | UniqueScoreConflict::Error => false,
         };
+        if aggregates_duplicate_scores
+            && (weight_column.is_some()
+                || pre_merge_gap.is_some()
+                || !value_columns.is_empty())
+        {
+            return Err(Error::Validation(
+                "the scores of duplicate lines cannot be aggregated together \
+                with a weight column, pre-merging or multiple value columns"
+                    .to_string(),
+            ));
+        }
+
+        let exclude = get_aliased_excluded_interval_maps(
+            exclude_track_filepath.as_deref(),
+            &read_options.chrom_alias,
+        )?;
 
-        let mut visited = HashSet::new();
+        // the score of each visited line, aggregated over its duplicates under
+        // `UniqueScoreConflict::Max` and `UniqueScoreConflict::Sum`
+        let mut visited = HashMap::new();
         let mut num_pcr_duplicates = 0i64;
+        let mut skipped_chrom_line_counts = BTreeMap::<Chrom, usize>::new();
+        let mut num_lines = 0usize;
+        let mut num_long_lines = 0usize;
+        let mut num_excluded_lines = 0usize;
+        let mut num_zero_length_lines = 0usize;
+        let mut chrom_to_unique_starts = HashMap::<Chrom, HashSet<i64>>::new();
+        let mut chrom_to_support_boundaries =
+            HashMap::<Chrom, SupportBoundaries>::new();
 
         let mut chrom_to_interval_map =
             HashMap::<Chrom, IntegerIntervalMap<D>>::new();
 
-        let bed = Bed::new(track_filepath, binarize_score);
-        for BedDataLine {
-            chrom,
-            start,
-            end,
-            name: _,
-            score,
-            strand,
-        } in bed.to_iter(): BedDataLineIter<D>
-        {
+        if weight_column == Some(0) {
+            return Err(Error::Validation(
+                "the weight column must be 1-based".to_string(),
+            ));
+        }
+        let mut chrom_to_weight_map =
+            HashMap::<Chrom, IntegerIntervalMap<D>>::new();
+
+        // the strands are only needed for keying the duplicates and for
+        // extending the intervals
+        let reads_strands = unique || strand_extend_bp.is_some();
+        let mut num_invalid_strand_lines = 0usize;
+
+        if value_columns.contains(&0) {
+            return Err(Error::Validation(
+                "the value columns must be 1-based".to_string(),
+            ));
+        }
+        let mut column_to_chrom_interval_maps: Vec<
+            HashMap<Chrom, IntegerIntervalMap<D>>,
+        > = value_columns.iter().map(|_| HashMap::new()).collect();
+
+        // the lines to be merged under `pre_merge_gap`
+        let mut chrom_to_pre_merge_lines =
+            HashMap::<Chrom, Vec<(I64Interval, D)>>::new();
+
+        let mut track_lines = TrackLineIter::<D>::new(
+            track_filepath,
+            TrackFormat::Bed,
+            binarize_score,
+            &read_options,
+        )?;
+        for line in track_lines.by_ref() {
+            let line = line?;
+            num_lines += 1;
+            let weight = match weight_column {
+                Some(column) => {
+                    Some(get_line_weight(&line, column, track_filepath)?)
+                }
+                None => None,
+            };
+            let strand = if reads_strands {
+                line.strand().unwrap_or_else(|why| {
+                    if debug {
+                        eprintln!(
+                            "{} in line {} of {}, treated as an unknown strand",
+                            why, line.line_number, track_filepath
+                        );
+                    }
+                    num_invalid_strand_lines += 1;
+                    None
+                })
+            } else {
+                None
+            };
+            let column_values =
+                get_line_column_values(&line, value_columns, track_filepath)?;
+            let TrackLine {
+                chrom,
+                start,
+                end,
+                value,
+                line_number,
+                ..
+            } = line;
             if filter_chroms.is_some()
                 && !filter_chroms.as_ref().unwrap().contains(&chrom)
             {
+                *skipped_chrom_line_counts.entry(chrom).or_insert(0) += 1;
                 continue;
             }
+            if end < start {
+                return Err(Error::Parse(format!(
+                    "the end coordinate is less than the start coordinate in \
+                    line {} of {} (chrom, start, end): ({}, {}, {})",
+                    line_number, track_filepath, chrom, start, end
+                )));
+            }
+            if end == start {
+                match zero_length_handling {
+                    ZeroLengthHandling::Skip => {
+                        if debug {
+                            eprintln!(
+                                "skipping zero-length (chrom, start, end): \
+                                ({}, {}, {})",
+                                chrom, start, end
+                            )
+                        }
+                        num_zero_length_lines += 1;
+                        continue;
+                    }
+                    ZeroLengthHandling::Error => {
+                        return Err(Error::Parse(format!(
+                            "zero-length feature in line {} of {} (chrom, \
+                            start, end): ({}, {}, {})",
+                            line_number, track_filepath, chrom, start, end
+                        )));
+                    }
+                }
+            }
+
+            let (start, end) =
+                if extend_bp.is_some() || strand_extend_bp.is_some() {
+                    // the extensions before and after the interval
+                    let (before, after) = match strand_extend_bp {
+                        Some(bp) if strand == Some(Strand::Negative) => (bp, 0),
+                        Some(bp) => (0, bp),
+                        None => (0, 0),
+                    };
+                    let extend_bp = extend_bp.unwrap_or(0);
+                    let extended_end = end + extend_bp + after;
+                    (
+                        (start - extend_bp - before).max(0),
+                        match chrom_sizes
+                            .as_ref()
+                            .and_then(|sizes| sizes.get(&chrom))
+                        {
+                            Some(&size) => extended_end.min(size),
+                            None => extended_end,
+                        },
+                    )
+                } else {
+                    (start, end)
+                };
 
             let interval = I64Interval::new(start, end - 1);
 
@@ -86,6 +727,7 @@ where
                             max_len
                         )
                     }
+                    num_long_lines += 1;
                     continue;
                 }
             }
@@ -94,15 +736,27 @@ where
                 if let Some(excluded_intervals) =
                     chrom_to_excluded_intervals.get(&chrom)
                 {
-                    if interval
-                        .has_non_empty_intersection_with(excluded_intervals)
-                    {
+                    let excluded_fraction =
+                        interval.intersect(excluded_intervals).size() as f64
+                            / interval.size() as f64;
+                    if excluded_fraction > min_exclude_overlap_fraction {
+                        num_excluded_lines += 1;
                         continue;
                     }
                 }
             }
+            chrom_to_unique_starts
+                .entry(chrom.clone())
+                .or_insert_with(HashSet::new)
+                .insert(start);
+
+            let value = value.unwrap_or_else(D::zero);
+
             if unique {
-                if !visited.insert((chrom.clone(), start, end, strand)) {
+                if let Entry::Occupied(mut entry) =
+                    visited.entry((chrom.clone(), start, end, strand))
+                {
+                    let visited_value: &mut D = entry.get_mut();
                     // duplicate PCR reads
                     num_pcr_duplicates += 1;
                     if debug {
@@ -112,8 +766,31 @@ where
                             chrom, start, end, strand
                         )
                     }
+                    if value != *visited_value {
+                        let aggregated = unique_score_conflict
+                            .combine(*visited_value, value)
+                            .ok_or_else(|| {
+                                Error::Validation(format!(
+                                    "conflicting duplicate scores {} and {} \
+                                    at (chrom, start, end): ({}, {}, {})",
+                                    visited_value, value, chrom, start, end
+                                ))
+                            })?;
+                        if aggregated != *visited_value {
+                            // the first line has already been aggregated
+                            chrom_to_interval_map
+                                .entry(chrom)
+                                .or_insert_with(IntegerIntervalMap::new)
+                                .aggregate(
+                                    interval,
+                                    aggregated - *visited_value,
+                                );
+                            *visited_value = aggregated;
+                        }
+                    }
                     continue;
                 }
+                visited.insert((chrom.clone(), start, end, strand), value);
             }
             assert!(
                 end > 0,
@@ -124,13 +801,141 @@ where
                 end,
             );
 
-            let interval_map = chrom_to_interval_map
-                .entry(chrom)
-                .or_insert_with(IntegerIntervalMap::new);
+            if emit_support_count {
+                let boundaries = chrom_to_support_boundaries
+                    .entry(chrom.clone())
+                    .or_insert_with(SupportBoundaries::default);
+                boundaries.starts.push(start);
+                boundaries.ends.push(end - 1);
+            }
+
+            if pre_merge_gap.is_some() {
+                chrom_to_pre_merge_lines
+                    .entry(chrom)
+                    .or_insert_with(Vec::new)
+                    .push((interval, value));
+                continue;
+            }
+
+            for (chrom_to_column_map, column_value) in
+                column_to_chrom_interval_maps
+                    .iter_mut()
+                    .zip(column_values.into_iter())
+            {
+                let column_value = read_options.transform_value(column_value);
+                chrom_to_column_map
+                    .entry(chrom.clone())
+                    .or_insert_with(IntegerIntervalMap::new)
+                    .aggregate(
+                        interval,
+                        weight.map_or(column_value, |w| column_value * w),
+                    );
+            }
 
-            interval_map.aggregate(interval, score.unwrap_or(D::zero()));
+            if let Some(weight) = weight {
+                chrom_to_weight_map
+                    .entry(chrom.clone())
+                    .or_insert_with(IntegerIntervalMap::new)
+                    .aggregate(interval, weight);
+                chrom_to_interval_map
+                    .entry(chrom)
+                    .or_insert_with(IntegerIntervalMap::new)
+                    .aggregate(interval, value * weight);
+            } else {
+                chrom_to_interval_map
+                    .entry(chrom)
+                    .or_insert_with(IntegerIntervalMap::new)
+                    .aggregate(interval, value);
+            }
+        }
+        let num_bad_lines = track_lines.num_bad_lines();
+        let num_missing_score_lines = track_lines.num_missing_score_lines();
+        if let Some(max_gap) = pre_merge_gap {
+            let chrom_to_intervals: HashMap<Chrom, Vec<I64Interval>> =
+                chrom_to_pre_merge_lines
+                    .iter()
+                    .map(|(chrom, lines)| {
+                        (
+                            chrom.clone(),
+                            lines
+                                .iter()
+                                .map(|(interval, _)| *interval)
+                                .collect(),
+                        )
+                    })
+                    .collect();
+            for (chrom, merged) in merge_intervals(&chrom_to_intervals, max_gap)
+            {
+                let mut scores: Vec<Option<D>> = vec![None; merged.len()];
+                for &(interval, value) in
+                    chrom_to_pre_merge_lines[&chrom].iter()
+                {
+                    // the merged interval containing the line
+                    let i = merged.partition_point(|m| {
+                        m.get_end() < interval.get_start()
+                    });
+                    scores[i] = Some(match scores[i] {
+                        Some(score) => pre_merge_score.combine(score, value),
+                        None => value,
+                    });
+                }
+                let interval_map = chrom_to_interval_map
+                    .entry(chrom)
+                    .or_insert_with(IntegerIntervalMap::new);
+                for (interval, score) in merged.into_iter().zip(scores) {
+                    interval_map
+                        .aggregate(interval, score.unwrap_or_else(D::zero));
+                }
+            }
+        }
+        if weight_column.is_some() {
+            chrom_to_interval_map = get_weighted_means(
+                &chrom_to_interval_map,
+                &chrom_to_weight_map,
+            );
+            column_to_chrom_interval_maps = column_to_chrom_interval_maps
+                .iter()
+                .map(|chrom_to_column_map| {
+                    get_weighted_means(
+                        chrom_to_column_map,
+                        &chrom_to_weight_map,
+                    )
+                })
+                .collect();
         }
-        BedRefinery {
+        let num_imprecise_intervals = if check_precision {
+            Some(
+                std::iter::once(&chrom_to_interval_map)
+                    .chain(column_to_chrom_interval_maps.iter())
+                    .flat_map(|chrom_to_map| chrom_to_map.iter())
+                    .map(|(chrom, interval_map)| {
+                        count_imprecise_values(
+                            chrom,
+                            interval_map
+                                .iter()
+                                .map(|(&interval, &value)| (interval, value)),
+                            debug,
+                        )
+                    })
+                    .sum(),
+            )
+        } else {
+            None
+        };
+        if let Some(offsets) = concatenate_genome.as_ref() {
+            let mut genome_interval_map = IntegerIntervalMap::new();
+            for (chrom, interval_map) in chrom_to_interval_map.iter() {
+                for (&interval, &value) in interval_map.iter() {
+                    genome_interval_map
+                        .aggregate(offsets.to_global(chrom, interval)?, value);
+                }
+            }
+            chrom_to_interval_map = HashMap::new();
+            chrom_to_interval_map
+                .insert(GENOME_CHROM.to_string(), genome_interval_map);
+        }
+
+        Ok(BedRefinery {
             chrom_to_interval_map,
             stats: RefineryStats {
                 num_duplicate_lines: if unique {
@@ -138,18 +943,62 @@ where
                 } else {
                     None
                 },
+                num_imprecise_intervals,
+                skipped_chrom_line_counts,
+                num_lines,
+                num_long_lines,
+                num_excluded_lines,
+                num_zero_length_lines,
+                num_bad_lines,
+                num_missing_score_lines,
+                num_invalid_strand_lines,
             },
-        }
+            relog_output,
+            bin_aggregation,
+            output_order,
+            merge_bedgraph_runs,
+            normalize_scope,
+            end_convention,
+            running_max_window,
+            interpolate_gaps,
+            max_interpolate_gap,
+            bed12,
+            check_precision,
+            debug,
+            per_chrom_genome_offsets: match genome_coordinates {
+                GenomeCoordinates::Global => None,
+                GenomeCoordinates::PerChrom => concatenate_genome,
+            },
+            unique_position_counts: chrom_to_unique_starts
+                .into_iter()
+                .map(|(chrom, starts)| (chrom, starts.len() as i64))
+                .collect(),
+            chrom_to_support_boundaries: if emit_support_count {
+                for boundaries in chrom_to_support_boundaries.values_mut() {
+                    boundaries.starts.sort_unstable();
+                    boundaries.ends.sort_unstable();
+                }
+                Some(chrom_to_support_boundaries)
+            } else {
+                None
+            },
+            column_to_chrom_interval_maps,
+            chrom_to_sorted_intervals: OnceCell::new(),
+        })
     }
 
-    pub fn write_refined_bed(
+    /// Iterates over the final `(chrom, interval, value)` output tuples after
+    /// binning, normalization, scaling and the optional `log2` transformation,
+    /// in the order given by `RefineryOptions::output_order`. Intervals
+    /// without any base pairs are skipped. See `write_refined_bed` for the
+    /// meaning of the arguments.
+    pub fn refined_iter(
         &self,
-        out_path: &str,
         bin_size: i64,
         normalize: bool,
         scaling: Option<D>,
-        out_bedgraph: bool,
-    ) -> Result<(), biofile::error::Error> {
+    ) -> Result<Box<dyn Iterator<Item = (Chrom, I64Interval, D)> + '_>, Error>
+    {
         macro_rules! interval_map_to_iter {
             ($m:expr) => {
                 $m.iter().map(|(&interval, &val)| (interval, val))
@@ -159,19 +1008,33 @@ where
             ($m:expr) => {
                 $m.iter().into_binned_interval_iter(
                     bin_size,
-                    AggregateOp::Average,
+                    self.bin_aggregation.to_aggregate_op(),
                     Box::new(|item| (*item.0, *item.1)),
                 )
             };
         }
+        macro_rules! get_interval_value_iter {
+            ($m:expr) => {
+                if bin_size == 0 {
+                    Box::new(interval_map_to_iter!($m))
+                        as Box<dyn Iterator<Item = (I64Interval, D)>>
+                } else {
+                    Box::new(interval_map_to_binned_iter!($m))
+                        as Box<dyn Iterator<Item = (I64Interval, D)>>
+                }
+            };
+        }
 
         let sorted_chroms =
             crate::util::get_sorted_keys(&self.chrom_to_interval_map);
 
-        let normalization_constant = if normalize {
-            if bin_size == 0 {
+        // Summing the bins would double count the bin sizes in the weighted
+        // sum, so the normalization constant is computed from the unbinned
+        // intervals instead, which gives the same total signal.
+        let get_normalization_constant = |chroms: &[Chrom]| -> D {
+            if bin_size == 0 || self.bin_aggregation == BinAggregation::Sum {
                 ConcatenatedIter::from_iters(
-                    sorted_chroms
+                    chroms
                         .iter()
                         .map(|chrom| {
                             interval_map_to_iter!(
@@ -183,7 +1046,7 @@ where
                 .weighted_sum()
             } else {
                 ConcatenatedIter::from_iters(
-                    sorted_chroms
+                    chroms
                         .iter()
                         .map(|chrom| {
                             interval_map_to_binned_iter!(
@@ -194,77 +1057,354 @@ where
                 )
                 .weighted_sum()
             }
+        };
+        let zero_sum_error = |scope: &str| {
+            Error::Validation(format!(
+                "cannot normalize the values when they sum to zero over {}.",
+                scope
+            ))
+        };
+
+        let scaling = scaling.unwrap_or(D::one());
+        let chrom_to_scaling: HashMap<Chrom, D> = if !normalize {
+            sorted_chroms
+                .iter()
+                .map(|chrom| (chrom.clone(), scaling))
+                .collect()
         } else {
-            D::one()
+            match self.normalize_scope {
+                NormalizeScope::Genome => {
+                    let constant = get_normalization_constant(&sorted_chroms);
+                    if constant == D::zero() {
+                        return Err(zero_sum_error("all the chromosomes"));
+                    }
+                    sorted_chroms
+                        .iter()
+                        .map(|chrom| (chrom.clone(), scaling / constant))
+                        .collect()
+                }
+                NormalizeScope::Chrom => sorted_chroms
+                    .iter()
+                    .map(|chrom| {
+                        let constant = get_normalization_constant(
+                            std::slice::from_ref(chrom),
+                        );
+                        if constant == D::zero() {
+                            Err(zero_sum_error(chrom))
+                        } else {
+                            Ok((chrom.clone(), scaling / constant))
+                        }
+                    })
+                    .collect::<Result<_, _>>()?,
+            }
+        };
+        let relog_output = self.relog_output;
+        let relog = move |value: D| {
+            if relog_output {
+                value.log2()
+            } else {
+                value
+            }
         };
 
-        if normalization_constant == D::zero() {
-            return Err(biofile::error::Error::Generic(
-                "cannot normalize the values when they sum to zero.".into(),
+        if self.interpolate_gaps && bin_size == 0 {
+            return Err(Error::Validation(
+                "the gaps can only be interpolated between bins".into(),
             ));
         }
-        let scaling = scaling.unwrap_or(D::one()) / normalization_constant;
-
-        let mut writer = BedWriter::new(out_path)?;
-        for chrom in sorted_chroms {
-            let interval_map = &self.chrom_to_interval_map[&chrom];
 
-            macro_rules! get_interval_value_iter {
-                () => {
-                    if bin_size == 0 {
-                        Box::new(interval_map_to_iter!(interval_map))
-                            as Box<dyn Iterator<Item = (I64Interval, D)>>
-                    } else {
-                        Box::new(interval_map_to_binned_iter!(interval_map))
-                            as Box<dyn Iterator<Item = (I64Interval, D)>>
+        // The values are scaled before the running maximum, which does not
+        // commute with a negative scaling, whereas the log transform is
+        // increasing and is applied last.
+        let running_max_window = self.running_max_window;
+        let interpolate_gaps = self.interpolate_gaps;
+        let max_interpolate_gap = self.max_interpolate_gap;
+        let position_sorted_iter =
+            sorted_chroms.into_iter().flat_map(move |chrom| {
+                let scaling = chrom_to_scaling[&chrom];
+                let interval_value_iter = get_interval_value_iter!(
+                    &self.chrom_to_interval_map[&chrom]
+                )
+                .filter(|(interval, _)| !interval.is_empty())
+                .map(move |(interval, value)| (interval, value * scaling));
+                let interval_value_iter: Box<
+                    dyn Iterator<Item = (I64Interval, D)>,
+                > = match running_max_window {
+                    Some(window) => {
+                        let mut intervals: Vec<(I64Interval, D)> =
+                            interval_value_iter.collect();
+                        apply_running_max(&mut intervals, window);
+                        Box::new(intervals.into_iter())
                     }
+                    None => Box::new(interval_value_iter),
+                };
+                let interval_value_iter: Box<
+                    dyn Iterator<Item = (I64Interval, D)>,
+                > = if interpolate_gaps {
+                    let intervals: Vec<(I64Interval, D)> =
+                        interval_value_iter.collect();
+                    Box::new(
+                        get_interpolated_bins(
+                            &intervals,
+                            bin_size,
+                            max_interpolate_gap,
+                        )
+                        .into_iter(),
+                    )
+                } else {
+                    interval_value_iter
+                };
+                interval_value_iter.map(move |(interval, value)| {
+                    (chrom.clone(), interval, relog(value))
+                })
+            });
+        let position_sorted_iter: Box<
+            dyn Iterator<Item = (Chrom, I64Interval, D)> + '_,
+        > = match self.per_chrom_genome_offsets.as_ref() {
+            Some(offsets) => Box::new(position_sorted_iter.flat_map(
+                move |(_, interval, value)| {
+                    offsets
+                        .split(interval)
+                        .into_iter()
+                        .map(move |(chrom, interval)| (chrom, interval, value))
+                },
+            )),
+            None => Box::new(position_sorted_iter),
+        };
+
+        match self.output_order {
+            OutputOrder::Position => Ok(Box::new(position_sorted_iter)),
+            OutputOrder::Value {
+                top_n,
+            } => {
+                let mut lines: Vec<(Chrom, I64Interval, D)> = match top_n {
+                    Some(n) => get_top_n_lines(position_sorted_iter, n)?,
+                    None => position_sorted_iter.collect(),
                 };
+                // descending values with ties broken by the coordinates
+                lines.sort_by(|a, b| {
+                    b.2.partial_cmp(&a.2)
+                        .unwrap_or(Ordering::Equal)
+                        .then_with(|| a.0.cmp(&b.0))
+                        .then_with(|| a.1.get_start().cmp(&b.1.get_start()))
+                });
+                if let Some(n) = top_n {
+                    lines.truncate(n);
+                }
+                Ok(Box::new(lines.into_iter()))
             }
+        }
+    }
 
+    pub fn write_refined_bed(
+        &self,
+        out_path: &str,
+        bin_size: i64,
+        normalize: bool,
+        scaling: Option<D>,
+        out_bedgraph: bool,
+    ) -> Result<(), Error> {
+        if out_bedgraph && self.chrom_to_support_boundaries.is_some() {
+            return Err(Error::Validation(
+                "the support counts can only be written to the name column \
+                of a BED file"
+                    .into(),
+            ));
+        }
+        if out_bedgraph && self.bed12 {
+            return Err(Error::Validation(
+                "the BED12 columns cannot be written to a bedGraph file".into(),
+            ));
+        }
+        if let Some(warning) = self.empty_output_warning() {
+            eprintln!("{}", warning);
+        }
+        self.warn_imprecise_bins(bin_size);
+        let refined_iter = self.refined_iter(bin_size, normalize, scaling)?;
+
+        let mut writer = create_maybe_gzip(out_path, false)?;
+        // the last bedGraph line that can still be extended by merging
+        let mut pending_bedgraph_line: Option<BedGraphDataLine<D>> = None;
+        for (chrom, interval, value) in refined_iter {
             if out_bedgraph {
-                let mut bedgraph_line = BedGraphDataLine {
-                    chrom: chrom.to_string(),
-                    start: 0,
-                    end_exclusive: 0,
-                    value: D::zero(),
+                let line = BedGraphDataLine {
+                    chrom,
+                    start: interval.get_start(),
+                    end_exclusive: self
+                        .end_convention
+                        .end_coordinate(interval.get_end()),
+                    value,
                 };
-                get_interval_value_iter!().try_for_each(
-                    |(interval, value): (I64Interval, D)|
-                        -> Result<(), biofile::error::Error>{
-                        if !interval.is_empty() {
-                            bedgraph_line.start = interval.get_start();
-                            bedgraph_line.end_exclusive =
-                                interval.get_end() + 1i64;
-
-                            bedgraph_line.value = value * scaling;
-                            writer.write_bedgraph_line(&bedgraph_line)?;
-                        }
-                        Ok(())
-                    })?;
+                if !self.merge_bedgraph_runs {
+                    write_bedgraph_line(&mut writer, &line)?;
+                    continue;
+                }
+                if let Some(pending) = pending_bedgraph_line.as_mut() {
+                    // the pending line ends right before the current one
+                    if pending.chrom == line.chrom
+                        && pending.end_exclusive
+                            == self
+                                .end_convention
+                                .end_coordinate(line.start - 1)
+                        && pending.value == line.value
+                    {
+                        pending.end_exclusive = line.end_exclusive;
+                        continue;
+                    }
+                }
+                if let Some(finished) = pending_bedgraph_line.replace(line) {
+                    write_bedgraph_line(&mut writer, &finished)?;
+                }
             } else {
-                let mut bed_line = BedDataLine {
-                    chrom: chrom.to_string(),
-                    start: 0,
-                    end: 0,
-                    name: None,
-                    score: None::<D>,
+                let support_count = self
+                    .chrom_to_support_boundaries
+                    .as_ref()
+                    .and_then(|m| m.get(&chrom))
+                    .map(|b| b.count_overlapping(&interval).to_string());
+                let line = BedDataLine {
+                    chrom,
+                    start: interval.get_start(),
+                    end: self.end_convention.end_coordinate(interval.get_end()),
+                    name: support_count,
+                    score: Some(value),
                     strand: None,
                 };
-                get_interval_value_iter!().try_for_each(
-                    |(interval, value): (I64Interval, D)|
-                        -> Result<(), biofile::error::Error>{
-                        if !interval.is_empty() {
-                            bed_line.start = interval.get_start();
-                            // the end is exclusive in the BED format
-                            bed_line.end = interval.get_end() + 1i64;
-                            bed_line.score = Some(value * scaling);
-                            writer.write_bed_line(&bed_line)?;
-                        }
-                        Ok(())
-                    })?;
+                if self.bed12 {
+                    write_bed12_line(&mut writer, &line)?;
+                } else {
+                    write_bed_line(&mut writer, &line)?;
+                }
+            }
+        }
+        if let Some(line) = pending_bedgraph_line {
+            write_bedgraph_line(&mut writer, &line)?;
+        }
+        Ok(writer.finish()?)
+    }
+
+    /// Writes the values of all the columns passed to `new_multi` in a single
+    /// file, where each line consists of the start, the end and the
+    /// chromosome of a bin followed by the value of each column, in the
+    /// format of `TrackZipper::write_concatenated_tracks`. The bins are
+    /// aggregated according to `RefineryOptions::bin_aggregation`, a
+    /// `bin_size` of 0 writes the common refinement of the unbinned
+    /// intervals, and the end coordinates follow
+    /// `RefineryOptions::end_convention`. No normalization or scaling is
+    /// applied. The output is compressed with gzip if `out_path` ends in
+    /// `.gz`.
+    pub fn write_refined_multi(
+        &self,
+        out_path: &str,
+        bin_size: i64,
+    ) -> Result<(), Error> {
+        if self.column_to_chrom_interval_maps.is_empty() {
+            return Err(Error::Validation(
+                "the refinery must be constructed with new_multi to write \
+                multiple value columns"
+                    .into(),
+            ));
+        }
+        self.warn_imprecise_bins(bin_size);
+        let aggregate_op = self.bin_aggregation.to_aggregate_op();
+        macro_rules! get_interval_value_iter {
+            ($m:expr) => {
+                if bin_size == 0 {
+                    Box::new($m.iter().map(|(&interval, &val)| (interval, val)))
+                        as Box<dyn Iterator<Item = (I64Interval, D)>>
+                } else {
+                    Box::new($m.iter().into_binned_interval_iter(
+                        bin_size,
+                        aggregate_op,
+                        Box::new(|item| (*item.0, *item.1)),
+                    )) as Box<dyn Iterator<Item = (I64Interval, D)>>
+                }
+            };
+        }
+
+        let empty_interval_map = IntegerIntervalMap::new();
+        let union_zipped_chrom_interval_maps =
+            crate::util::get_union_zipped_chrom_interval_maps(
+                self.column_to_chrom_interval_maps.iter().collect(),
+                None,
+                &empty_interval_map,
+            );
+
+        let mut writer = create_maybe_gzip(out_path, false)?;
+        for chrom in
+            crate::util::get_sorted_keys(&union_zipped_chrom_interval_maps)
+        {
+            let interval_maps = &union_zipped_chrom_interval_maps[&chrom];
+            let zipped = interval_maps.iter().skip(1).fold(
+                get_interval_value_iter!(interval_maps[0])
+                    .into_common_refinement_zipped(),
+                |common_refinement, map| {
+                    common_refinement.common_refinement_flat_zip(
+                        get_interval_value_iter!(map),
+                    )
+                },
+            );
+            for (interval, values) in zipped {
+                if interval.is_empty() {
+                    continue;
+                }
+                write!(
+                    &mut writer,
+                    "{} {} {}",
+                    interval.get_start(),
+                    self.end_convention.end_coordinate(interval.get_end()),
+                    chrom,
+                )?;
+                for v in values.iter() {
+                    write!(&mut writer, " {}", v.unwrap_or_else(D::zero))?;
+                }
+                writeln!(&mut writer)?;
+            }
+        }
+        Ok(writer.finish()?)
+    }
+
+    /// Returns the number of bins of size `bin_size` whose values, including
+    /// those of the value columns, are large enough in magnitude to lose
+    /// precision as in `RefineryStats::num_imprecise_intervals`. Only the sums
+    /// of `BinAggregation::Sum` can exceed the values of the intervals, so
+    /// this is 0 for a `bin_size` of 0 or under `BinAggregation::Average`.
+    /// Returns `None` unless `RefineryOptions::check_precision` is set.
+    pub fn count_imprecise_bins(&self, bin_size: i64) -> Option<i64> {
+        if !self.check_precision {
+            return None;
+        }
+        if bin_size == 0 || self.bin_aggregation != BinAggregation::Sum {
+            return Some(0);
+        }
+        Some(
+            std::iter::once(&self.chrom_to_interval_map)
+                .chain(self.column_to_chrom_interval_maps.iter())
+                .flat_map(|chrom_to_map| chrom_to_map.iter())
+                .map(|(chrom, interval_map)| {
+                    count_imprecise_values(
+                        chrom,
+                        interval_map.iter().into_binned_interval_iter(
+                            bin_size,
+                            AggregateOp::Sum,
+                            Box::new(|item| (*item.0, *item.1)),
+                        ),
+                        self.debug,
+                    )
+                })
+                .sum(),
+        )
+    }
+
+    fn warn_imprecise_bins(&self, bin_size: i64) {
+        if let Some(num_imprecise_bins) = self.count_imprecise_bins(bin_size) {
+            if num_imprecise_bins > 0 {
+                eprintln!(
+                    "warning: {} summed bins of size {} have values large \
+                    enough to lose precision, rerun with --debug to list them",
+                    num_imprecise_bins, bin_size
+                );
             }
         }
-        Ok(())
     }
 
     pub fn get_chrom_to_interval_map(
@@ -273,14 +1413,515 @@ where
         &self.chrom_to_interval_map
     }
 
+    /// Returns the refined value at the 0-based `position` on `chrom`, or
+    /// `None` if no input interval covers the position.
+    pub fn value_at(&self, chrom: &str, position: i64) -> Option<D> {
+        self.get_sorted_intervals(chrom).and_then(|intervals| {
+            let i = intervals
+                .partition_point(|(interval, _)| interval.get_end() < position);
+            intervals
+                .get(i)
+                .filter(|(interval, _)| interval.get_start() <= position)
+                .map(|&(_, value)| value)
+        })
+    }
+
+    /// Returns the refined intervals on `chrom` overlapping with `interval`
+    /// together with their values, clipped to `interval`. The uncovered
+    /// positions within `interval` are not part of the returned intervals.
+    pub fn values_in(
+        &self,
+        chrom: &str,
+        interval: I64Interval,
+    ) -> Vec<(I64Interval, D)> {
+        match self.get_sorted_intervals(chrom) {
+            Some(intervals) => {
+                let first = intervals.partition_point(|(i, _)| {
+                    i.get_end() < interval.get_start()
+                });
+                intervals[first..]
+                    .iter()
+                    .take_while(|(i, _)| i.get_start() <= interval.get_end())
+                    .map(|&(i, value)| {
+                        (
+                            I64Interval::new(
+                                i.get_start().max(interval.get_start()),
+                                i.get_end().min(interval.get_end()),
+                            ),
+                            value,
+                        )
+                    })
+                    .collect()
+            }
+            None => Vec::new(),
+        }
+    }
+
+    fn get_sorted_intervals(
+        &self,
+        chrom: &str,
+    ) -> Option<&Vec<(I64Interval, D)>> {
+        self.chrom_to_sorted_intervals
+            .get_or_init(|| {
+                self.chrom_to_interval_map
+                    .iter()
+                    .map(|(chrom, interval_map)| {
+                        (
+                            chrom.clone(),
+                            interval_map
+                                .iter()
+                                .map(|(&interval, &value)| (interval, value))
+                                .collect(),
+                        )
+                    })
+                    .collect()
+            })
+            .get(chrom)
+    }
+
     pub fn stats(&self) -> &RefineryStats {
         &self.stats
     }
+
+    /// Returns a warning naming the filters that dropped the input lines if
+    /// no interval is left after reading the track, so that an empty output
+    /// is not mistaken for a crash. Returns `None` otherwise.
+    pub fn empty_output_warning(&self) -> Option<String> {
+        if !self.chrom_to_interval_map.is_empty() {
+            return None;
+        }
+        let stats = &self.stats;
+        let get_causes = |counts: Vec<(usize, &str)>| -> Vec<String> {
+            counts
+                .into_iter()
+                .filter(|(count, _)| *count > 0)
+                .map(|(count, cause)| format!("{} lines {}", count, cause))
+                .collect()
+        };
+        if stats.num_lines == 0 {
+            // the lines skipped while reading are not counted in `num_lines`
+            let causes = get_causes(vec![
+                (stats.num_bad_lines, "skipped for being malformed"),
+                (
+                    stats.num_missing_score_lines,
+                    "skipped for missing their values",
+                ),
+            ]);
+            return Some(format_empty_output_warning(&if causes.is_empty() {
+                "the input track has no data lines".to_string()
+            } else {
+                format!(
+                    "the input track has no valid lines: {}",
+                    causes.join(", ")
+                )
+            }));
+        }
+        // every line read is dropped by one of the following filters if no
+        // interval is left, as the PCR duplicates leave their first lines
+        let num_skipped_chrom_lines: usize =
+            stats.skipped_chrom_line_counts.values().sum();
+        let causes = get_causes(vec![
+            (
+                num_skipped_chrom_lines,
+                "on chromosomes not in the chromosome filter",
+            ),
+            (stats.num_long_lines, "longer than max_len"),
+            (
+                stats.num_excluded_lines,
+                "overlapping with the excluded intervals",
+            ),
+            (stats.num_zero_length_lines, "of zero length"),
+        ]);
+        Some(format_empty_output_warning(&format!(
+            "all the {} input lines were filtered out: {}",
+            stats.num_lines,
+            causes.join(", ")
+        )))
+    }
+
+    /// Returns the number of distinct start coordinates under each chromosome
+    /// among the lines that passed the filters. For sparse point data, this
+    /// is an indication of the library complexity.
+    pub fn unique_position_count(&self) -> &HashMap<Chrom, i64> {
+        &self.unique_position_counts
+    }
+
+    /// Returns the signal-weighted mean of the 0-based base pair positions on
+    /// each chromosome, i.e., the center of mass, together with the
+    /// signal-weighted standard deviation of the positions, where each base
+    /// pair is weighted by its refined value. Chromosomes with a zero total
+    /// signal have no center of mass and are left out. The values are meant
+    /// to be non-negative.
+    pub fn center_of_mass(&self) -> HashMap<Chrom, (f64, f64)> {
+        self.chrom_to_interval_map
+            .iter()
+            .filter_map(|(chrom, interval_map)| {
+                // (the number of base pairs, their mean position, value)
+                let intervals: Vec<(f64, f64, f64)> = interval_map
+                    .iter()
+                    .map(|(interval, value)| {
+                        (
+                            interval.size() as f64,
+                            (interval.get_start() + interval.get_end()) as f64
+                                / 2.,
+                            value.to_f64().unwrap(),
+                        )
+                    })
+                    .collect();
+                let total: f64 =
+                    intervals.iter().map(|(size, _, value)| size * value).sum();
+                if total == 0. {
+                    return None;
+                }
+                let mean = intervals
+                    .iter()
+                    .map(|(size, center, value)| size * center * value)
+                    .sum::<f64>()
+                    / total;
+                // the squared deviations of the n consecutive positions of an
+                // interval from the mean sum to n (center - mean)^2 plus
+                // n (n^2 - 1) / 12, which avoids summing the squared
+                // positions directly
+                let variance = intervals
+                    .iter()
+                    .map(|(size, center, value)| {
+                        value
+                            * size
+                            * ((center - mean).powi(2)
+                                + (size * size - 1.) / 12.)
+                    })
+                    .sum::<f64>()
+                    / total;
+                Some((chrom.clone(), (mean, variance.max(0.).sqrt())))
+            })
+            .collect()
+    }
+}
+
+/// Reads a track written by `BedRefinery::write_refined_bed` with the
+/// exclusive end convention, in either the BED or the bedGraph format, into
+/// the values of the base pairs on each chromosome.
+pub fn load_refined_bed(
+    path: &str,
+) -> Result<HashMap<Chrom, IntegerIntervalMap<f64>>, Error> {
+    get_chrom_interval_map(
+        &track_from_path_autodetect(path, false, TrackFormat::Bed)?,
+        None,
+    )
+}
+
+/// Refines the track written by `BedRefinery::write_refined_bed` at
+/// `refined_path` again with the same `bin_size` and returns an error naming
+/// the first interval whose value changes by more than `epsilon`, or which is
+/// present in only one of the two outputs. Refining a refined track should
+/// leave it unchanged, so an error points to a binning or coordinate bug.
+pub fn verify_idempotent(
+    refined_path: &str,
+    bin_size: i64,
+    epsilon: f64,
+) -> Result<(), Error> {
+    let format = detect_track_format(refined_path)?.unwrap_or(TrackFormat::Bed);
+    let refinery =
+        BedRefinery::<f64>::with_options(refined_path, RefineryOptions {
+            read_options: ReadOptions {
+                // the value of a bedGraph line is in the fourth column
+                value_column: match format {
+                    TrackFormat::Bed => ValueColumn::Score,
+                    TrackFormat::BedGraph => ValueColumn::Name,
+                },
+                ..ReadOptions::default()
+            },
+            ..RefineryOptions::default()
+        })?;
+    let rerefined_temp_path = NamedTempFile::new()
+        .map_err(|why| {
+            Error::io("failed to create a temporary file".to_string(), why)
+        })?
+        .into_temp_path();
+    let rerefined_path = rerefined_temp_path.to_str().ok_or_else(|| {
+        Error::Generic("invalid temporary file path".to_string())
+    })?;
+    refinery.write_refined_bed(
+        rerefined_path,
+        bin_size,
+        false,
+        None,
+        format == TrackFormat::BedGraph,
+    )?;
+
+    let original = load_refined_bed(refined_path)?;
+    let rerefined = load_refined_bed(rerefined_path)?;
+    let empty_interval_map = IntegerIntervalMap::new();
+    let mut chroms: Vec<&Chrom> = original
+        .keys()
+        .chain(rerefined.keys())
+        .collect::<HashSet<&Chrom>>()
+        .into_iter()
+        .collect();
+    chroms.sort();
+    for chrom in chroms {
+        let map_a = original.get(chrom).unwrap_or(&empty_interval_map);
+        let map_b = rerefined.get(chrom).unwrap_or(&empty_interval_map);
+        for (interval, values) in
+            map_a.iter().common_refinement_zip(map_b.iter())
+        {
+            let is_different = match (values[0], values[1]) {
+                (Some(a), Some(b)) => (a - b).abs() > epsilon,
+                (None, None) => false,
+                _ => true,
+            };
+            if is_different {
+                return Err(Error::Validation(format!(
+                    "refining {} again changes the values in {}:{}-{} from \
+                    {:?} to {:?}",
+                    refined_path,
+                    chrom,
+                    interval.get_start(),
+                    interval.get_end() + 1,
+                    values[0],
+                    values[1]
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Returns the `n` lines with the largest values on each chromosome, which
+/// include the `n` lines with the largest values overall, selected by
+/// `get_top_k_bin_map` with the intervals of the lines as the bins.
+fn get_top_n_lines<D, I>(
+    lines: I,
+    n: usize,
+) -> Result<Vec<(Chrom, I64Interval, D)>, Error>
+where
+    D: Float,
+    I: Iterator<Item = (Chrom, I64Interval, D)>,
+{
+    let mut chrom_to_interval_map =
+        HashMap::<Chrom, IntegerIntervalMap<f64>>::new();
+    for (chrom, interval, value) in lines {
+        chrom_to_interval_map
+            .entry(chrom)
+            .or_insert_with(IntegerIntervalMap::new)
+            .aggregate(interval, value.to_f64().expect("D converts to f64"));
+    }
+    let mut top_lines = Vec::new();
+    for (chrom, interval_map) in chrom_to_interval_map.iter() {
+        for (&interval, &value) in
+            get_top_k_bin_map(interval_map, n as i64, 0)?.iter()
+        {
+            top_lines.push((
+                chrom.clone(),
+                interval,
+                D::from(value).expect("f64 converts to D"),
+            ));
+        }
+    }
+    Ok(top_lines)
+}
+
+/// Reads the weight in the 1-based `column` of the `line` read from the file at
+/// `path`. A line without the column or with a "." or an empty field in the
+/// column has a weight of 1.
+fn get_line_weight<D, E>(
+    line: &TrackLine<D>,
+    column: usize,
+    path: &str,
+) -> Result<D, Error>
+where
+    D: Float + FromStr<Err = E>,
+    E: Debug,
+{
+    match line.field(column) {
+        None | Some("") | Some(".") => Ok(D::one()),
+        Some(token) => token.parse::<D>().map_err(|why| {
+            Error::Parse(format!(
+                "line {} of {}: failed to parse the weight {:?}: {:?}",
+                line.line_number, path, token, why
+            ))
+        }),
+    }
+}
+
+/// Replaces the value of each interval by the maximum over the intervals within
+/// `window` intervals on either side, where the window is cut short at any
+/// gap between non-contiguous intervals. The `intervals` must be sorted.
+fn apply_running_max<D: Float>(
+    intervals: &mut [(I64Interval, D)],
+    window: usize,
+) {
+    let values: Vec<D> = intervals.iter().map(|&(_, value)| value).collect();
+    // whether each interval is followed immediately by the next one
+    let is_contiguous_with_next: Vec<bool> = intervals
+        .windows(2)
+        .map(|pair| pair[0].0.get_end() + 1 == pair[1].0.get_start())
+        .collect();
+    let mut run_start = 0;
+    for (i, (_, value)) in intervals.iter_mut().enumerate() {
+        if i > 0 && !is_contiguous_with_next[i - 1] {
+            run_start = i;
+        }
+        let mut run_end = i;
+        while run_end < i + window
+            && run_end < is_contiguous_with_next.len()
+            && is_contiguous_with_next[run_end]
+        {
+            run_end += 1;
+        }
+        let lo = run_start.max(i.saturating_sub(window));
+        *value = values[lo..=run_end]
+            .iter()
+            .fold(values[i], |max, &v| max.max(v));
+    }
+}
+
+/// Returns the `bins` together with the bins filled in each gap of at most
+/// `max_gap` bins between two of the `bins`, where the value of a filled bin
+/// is linearly interpolated between the values of the two bins around the gap
+/// by the distance between the bin midpoints. The `bins` must be sorted and
+/// aligned at multiples of `bin_size`.
+fn get_interpolated_bins<D: Float + FromPrimitive>(
+    bins: &[(I64Interval, D)],
+    bin_size: i64,
+    max_gap: Option<usize>,
+) -> Vec<(I64Interval, D)> {
+    let mut interpolated = Vec::with_capacity(bins.len());
+    for (i, &(interval, value)) in bins.iter().enumerate() {
+        interpolated.push((interval, value));
+        let (next_interval, next_value) = match bins.get(i + 1) {
+            Some(&next) => next,
+            None => break,
+        };
+        // the number of bins from the current bin to the next one
+        let distance =
+            (next_interval.get_start() - interval.get_start()) / bin_size;
+        let gap = (distance - 1) as usize;
+        if gap == 0 || max_gap.map_or(false, |max_gap| gap > max_gap) {
+            continue;
+        }
+        let slope = (next_value - value) / D::from_i64(distance).unwrap();
+        for k in 1..distance {
+            let start = interval.get_start() + k * bin_size;
+            interpolated.push((
+                I64Interval::new(start, start + bin_size - 1),
+                value + slope * D::from_i64(k).unwrap(),
+            ));
+        }
+    }
+    interpolated
+}
+
+/// Reads the values in each of the 1-based `columns` of the `line` read from
+/// the file at `path`. A "." or an empty field in a column is read as zero.
+fn get_line_column_values<D, E>(
+    line: &TrackLine<D>,
+    columns: &[usize],
+    path: &str,
+) -> Result<Vec<D>, Error>
+where
+    D: Float + FromStr<Err = E>,
+    E: Debug,
+{
+    columns
+        .iter()
+        .map(|&column| match line.field(column) {
+            None => Err(Error::Parse(format!(
+                "line {} of {} does not have column {}",
+                line.line_number, path, column
+            ))),
+            Some("") | Some(".") => Ok(D::zero()),
+            Some(token) => token.parse::<D>().map_err(|why| {
+                Error::Parse(format!(
+                    "line {} of {}: failed to parse the value {:?}: {:?}",
+                    line.line_number, path, token, why
+                ))
+            }),
+        })
+        .collect()
+}
+
+/// Divides the weighted sum of the values by the total weight on each
+/// interval of the common refinement. Intervals with a total weight of zero
+/// are given a value of zero.
+fn get_weighted_means<D: Float>(
+    chrom_to_weighted_sum_map: &HashMap<Chrom, IntegerIntervalMap<D>>,
+    chrom_to_weight_map: &HashMap<Chrom, IntegerIntervalMap<D>>,
+) -> HashMap<Chrom, IntegerIntervalMap<D>> {
+    chrom_to_weighted_sum_map
+        .iter()
+        .map(|(chrom, weighted_sum_map)| {
+            let mut mean_map = IntegerIntervalMap::new();
+            for (interval, values) in weighted_sum_map
+                .iter()
+                .common_refinement_zip(chrom_to_weight_map[chrom].iter())
+            {
+                if let (Some(weighted_sum), Some(weight)) =
+                    (values[0], values[1])
+                {
+                    let mean = if weight == D::zero() {
+                        D::zero()
+                    } else {
+                        weighted_sum / weight
+                    };
+                    mean_map.aggregate(interval, mean);
+                }
+            }
+            (chrom.clone(), mean_map)
+        })
+        .collect()
+}
+
+/// Counts the values in `interval_values` on `chrom` whose magnitudes have
+/// reached `max_exact_integer`, printing each of them if `debug` is set.
+fn count_imprecise_values<D, I>(
+    chrom: &str,
+    interval_values: I,
+    debug: bool,
+) -> i64
+where
+    D: Float + std::fmt::Display,
+    I: Iterator<Item = (I64Interval, D)>,
+{
+    let limit = max_exact_integer::<D>();
+    let mut num_imprecise_values = 0i64;
+    for (interval, value) in interval_values {
+        if value.abs() >= limit {
+            num_imprecise_values += 1;
+            if debug {
+                eprintln!(
+                    "possible precision loss at (chrom, start, end): ({}, {}, \
+                    {}) with value {} >= {}",
+                    chrom,
+                    interval.get_start(),
+                    interval.get_end() + 1,
+                    value,
+                    limit
+                )
+            }
+        }
+    }
+    num_imprecise_values
+}
+
+/// Returns the smallest power of two `p` such that `p + 1` is not
+/// representable in `D`. All integers with magnitudes below `p` are exactly
+/// representable, e.g., `p` is `2^53` for `f64` and `2^24` for `f32`.
+fn max_exact_integer<D: Float>() -> D {
+    let two = D::one() + D::one();
+    let mut p = D::one();
+    while (p + D::one()) - p == D::one() {
+        p = p * two;
+    }
+    p
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{bed_refinery::BedRefinery, util::manifest_path_join};
+    use crate::{
+        bed_refinery::{max_exact_integer, BedRefinery},
+        util::manifest_path_join,
+    };
     use math::interval::I64Interval;
 
     #[test]
@@ -350,4 +1991,10 @@ mod tests {
             check_intervals!(chr1_interval_map, expected_chr1);
         }
     }
+
+    #[test]
+    fn test_max_exact_integer() {
+        assert_eq!(max_exact_integer::<f64>(), 2f64.powi(53));
+        assert_eq!(max_exact_integer::<f32>(), 2f32.powi(24));
+    }
 }
@@ -0,0 +1,123 @@
+use biostats::track_correlation::collect_correlation_matrix;
+use clap::{clap_app, Arg};
+use program_flow::{
+    argparse::{
+        extract_boolean_flag, extract_numeric_arg,
+        extract_optional_str_vec_arg, extract_str_arg,
+    },
+    debug_eprint_named_vars, eprint_named_vars, OrExit,
+};
+use std::{
+    fs::File,
+    io::{BufRead, BufReader},
+};
+
+fn main() {
+    let mut app = clap_app!(collect_correlations =>
+        (about: "Assembles the correlation files of separately computed pairs \
+        of tracks, e.g., on a cluster, into a symmetric correlation matrix")
+    );
+    app = app
+        .arg(
+            Arg::with_name("pairs")
+                .long("pairs")
+                .short("p")
+                .takes_value(true)
+                .required(true)
+                .long_help(
+                    "Path to a file in which each line consists of the names \
+                    of the two tracks and the path to their correlation file, \
+                    which is either the overall.corr.tsv written by \
+                    compute_track_correlation --out-dir or the stdout of \
+                    compute_track_correlation, e.g.,\n\
+                    a b /path/a_b/overall.corr.tsv\n\
+                    a c /path/a_c.txt",
+                ),
+        )
+        .arg(
+            Arg::with_name("bin_size")
+                .long("bin")
+                .takes_value(true)
+                .required(true)
+                .help("The bin size at which to assemble the matrix"),
+        )
+        .arg(
+            Arg::with_name("stdout_bin_sizes")
+                .long("stdout-bins")
+                .takes_value(true)
+                .multiple(true)
+                .long_help(
+                    "The bin sizes in the order passed to \
+                    compute_track_correlation for the correlation files in \
+                    the stdout format, which do not record the bin sizes. Not \
+                    required if each of these files has a single bin size.",
+                ),
+        )
+        .arg(
+            Arg::with_name("quiet")
+                .long("quiet")
+                .short("q")
+                .help("Suppresses the progress messages printed to stderr"),
+        );
+    let matches = app.get_matches();
+    let quiet = extract_boolean_flag(&matches, "quiet");
+    let pairs_path = extract_str_arg(&matches, "pairs");
+    let bin_size: i64 = extract_numeric_arg(&matches, "bin_size")
+        .unwrap_or_exit(Some("failed to parse --bin"));
+    let stdout_bin_sizes: Vec<i64> =
+        extract_optional_str_vec_arg(&matches, "stdout_bin_sizes")
+            .unwrap_or_else(Vec::new)
+            .into_iter()
+            .map(|s| {
+                s.parse::<i64>().unwrap_or_exit(Some(format_args!(
+                    "failed to parse {}",
+                    &s
+                )))
+            })
+            .collect();
+
+    if !quiet {
+        eprint_named_vars!(pairs_path, bin_size);
+        debug_eprint_named_vars!(stdout_bin_sizes);
+    }
+
+    let pairs: Vec<(String, String, String)> = BufReader::new(
+        File::open(&pairs_path).unwrap_or_exit(Some(format_args!(
+            "failed to open {}",
+            pairs_path
+        ))),
+    )
+    .lines()
+    .map(|line| {
+        line.unwrap_or_exit(Some(format_args!("failed to read {}", pairs_path)))
+    })
+    .filter(|line| !line.trim().is_empty())
+    .map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() != 3 {
+            eprintln!("expected three fields in the line: {}", line);
+            std::process::exit(1);
+        }
+        (
+            fields[0].to_string(),
+            fields[1].to_string(),
+            fields[2].to_string(),
+        )
+    })
+    .collect();
+
+    let correlation_matrix =
+        collect_correlation_matrix(&pairs, bin_size, &stdout_bin_sizes)
+            .unwrap_or_exit(Some("failed to collect the correlations"));
+
+    println!("\t{}", correlation_matrix.names.join("\t"));
+    for (name, row) in correlation_matrix
+        .names
+        .iter()
+        .zip(correlation_matrix.matrix.iter())
+    {
+        print!("{}", name);
+        row.iter().for_each(|c| print!("\t{:.5}", c));
+        println!();
+    }
+}
@@ -0,0 +1,102 @@
+use biofile::bed::Bed;
+use biostats::{
+    region_aggregation::{compute_chrom_frip, compute_frip},
+    track_conversion::{track_from_path_autodetect, TrackFormat},
+    util::get_sorted_keys,
+};
+use clap::{clap_app, Arg};
+use program_flow::{
+    argparse::{extract_boolean_flag, extract_str_arg},
+    eprint_named_vars, OrExit,
+};
+
+fn main() {
+    let mut app = clap_app!(compute_frip =>
+        (about: "Computes the fraction of reads in peaks (FRiP), i.e., the \
+        fraction of the total signal of a reads track that falls within the \
+        intervals of a peaks BED file.")
+    );
+    app = app
+        .arg(
+            Arg::with_name("reads_filepath")
+                .long("reads")
+                .short("r")
+                .takes_value(true)
+                .required(true)
+                .help("filepath to the reads track."),
+        )
+        .arg(
+            Arg::with_name("peaks_filepath")
+                .long("peaks")
+                .short("p")
+                .takes_value(true)
+                .required(true)
+                .help("filepath to the peaks in BED format."),
+        )
+        .arg(Arg::with_name("binarize_score").long("binarize").help(
+            "Each line in the reads track will contribute a unit score for \
+            the corresponding interval, i.e., each read is counted once per \
+            base pair",
+        ))
+        .arg(
+            Arg::with_name("reads_bedgraph")
+                .long("reads-bedgraph")
+                .help(
+                    "A flag to indicate that the reads track is in the \
+                    bedgraph format. Without it, the format is detected from \
+                    the first few lines of the track.",
+                ),
+        )
+        .arg(
+            Arg::with_name("by_chrom")
+                .long("by-chrom")
+                .help("Also prints the FRiP of each chromosome."),
+        )
+        .arg(
+            Arg::with_name("quiet")
+                .long("quiet")
+                .short("q")
+                .help("Suppresses the progress messages printed to stderr"),
+        );
+    let matches = app.get_matches();
+    let quiet = extract_boolean_flag(&matches, "quiet");
+    let reads_filepath = extract_str_arg(&matches, "reads_filepath");
+    let peaks_filepath = extract_str_arg(&matches, "peaks_filepath");
+    let binarize_score = extract_boolean_flag(&matches, "binarize_score");
+    let reads_bedgraph = extract_boolean_flag(&matches, "reads_bedgraph");
+    let by_chrom = extract_boolean_flag(&matches, "by_chrom");
+
+    if !quiet {
+        eprint_named_vars!(
+            reads_filepath,
+            peaks_filepath,
+            binarize_score,
+            reads_bedgraph,
+            by_chrom
+        );
+    }
+
+    let reads = if reads_bedgraph {
+        TrackFormat::BedGraph.to_track(&reads_filepath, binarize_score)
+    } else {
+        track_from_path_autodetect(
+            &reads_filepath,
+            binarize_score,
+            TrackFormat::Bed,
+        )
+        .unwrap_or_exit(None::<String>)
+    };
+    // the scores of the peaks are irrelevant for their intervals
+    let peaks = Bed::new(&peaks_filepath, false);
+
+    if by_chrom {
+        let chrom_frip = compute_chrom_frip(&reads, &peaks)
+            .unwrap_or_exit(Some("failed to compute the FRiP by chromosome"));
+        for chrom in get_sorted_keys(&chrom_frip) {
+            println!("{}\t{}", chrom, chrom_frip[&chrom]);
+        }
+    }
+    let frip = compute_frip(&reads, &peaks)
+        .unwrap_or_exit(Some("failed to compute the FRiP"));
+    println!("overall\t{}", frip);
+}
@@ -1,10 +1,17 @@
-use biofile::{bed::Bed, bedgraph::BedGraph, util::TrackVariant};
 use biostats::{
+    top_k::top_k_signal_fraction,
     top_k_overlap::{
         get_top_k_fraction_overlap_ratio,
         get_top_k_fraction_overlap_ratio_across_chroms,
+        overlap_enrichment_pvalue_with_options, OverlapWeight,
+        PermutationOptions, TopKOverlapOptions,
+    },
+    track_conversion::{track_from_path_autodetect, TrackFormat},
+    util::{
+        get_chrom_interval_map_with_options, get_chrom_intervals,
+        get_chrom_sizes, get_excluded_interval_maps, to_chrom_interval_map,
+        CoordinateSystem, ReadOptions,
     },
-    util::{get_chrom_interval_map, get_excluded_interval_maps},
 };
 use clap::{clap_app, Arg};
 use math::{
@@ -12,14 +19,16 @@ use math::{
 };
 use program_flow::{
     argparse::{
-        extract_boolean_flag, extract_numeric_arg, extract_optional_str_arg,
-        extract_optional_str_vec_arg, extract_str_arg,
+        extract_boolean_flag, extract_optional_numeric_arg,
+        extract_optional_str_arg, extract_optional_str_vec_arg,
+        extract_str_arg,
     },
     debug_eprint_named_vars, eprint_named_vars, OrExit,
 };
 
 const ZERO_BIN_SIZE_STR: &str = "0";
 const BINARIZE_SCORE: bool = false;
+const DEFAULT_CHECKPOINT_INTERVAL: usize = 100;
 
 fn main() {
     let mut app = clap_app!(compute_track_correlation =>
@@ -65,6 +74,31 @@ fn main() {
                     the overall correlations across chromosomes.",
                 ),
         )
+        .arg(
+            Arg::with_name("by_bp")
+                .long("by-bp")
+                .conflicts_with("signal_fraction")
+                .long_help(
+                    "Measures the overlap ratio as the fraction of the base \
+                    pairs in the top K bins of either track that are in the \
+                    top K bins of both tracks, instead of the fraction of the \
+                    bins. A bin size of 0 is then allowed and uses the \
+                    intervals of the tracks as the bins, which differ in \
+                    size. The permutation test still counts the bins.",
+                ),
+        )
+        .arg(
+            Arg::with_name("signal_fraction")
+                .long("signal-fraction")
+                .takes_value(true)
+                .long_help(
+                    "Instead of the overlap ratio, computes for each \
+                    chromosome the fraction of the total signal of each track \
+                    that is in its top K bins for this K, a measure of how \
+                    concentrated the signal is. --top-k is not required in \
+                    this mode.",
+                ),
+        )
         .arg(
             Arg::with_name("chroms")
                 .long("chroms")
@@ -99,12 +133,24 @@ fn main() {
                     file will be ignroed when computing correlations.",
                 ),
         )
+        .arg(
+            Arg::with_name("quiet")
+                .long("quiet")
+                .short("q")
+                .help("Suppresses the progress messages printed to stderr"),
+        )
+        .arg(Arg::with_name("one_based").long("one-based").help(
+            "The coordinates are 1-based with inclusive ends, e.g., as in \
+            GFF-derived BED files, instead of the 0-based half-open BED \
+            convention",
+        ))
         .arg(
             Arg::with_name("first_bedgraph")
                 .long("first-bedgraph")
                 .help(
                     "A flag to indicate that the first track is in the \
-                    bedgraph format",
+                    bedgraph format. Without it, the format is detected from \
+                    the first few lines of the track.",
                 ),
         )
         .arg(
@@ -112,7 +158,58 @@ fn main() {
                 .long("second-bedgraph")
                 .help(
                     "A flag to indicate that the second track is in the \
-                    bedgraph format",
+                    bedgraph format. Without it, the format is detected from \
+                    the first few lines of the track.",
+                ),
+        )
+        .arg(
+            Arg::with_name("permutations")
+                .long("permutations")
+                .takes_value(true)
+                .requires("chrom_sizes")
+                .long_help(
+                    "Shuffle the intervals of the first track this many times \
+                    to compute an empirical p-value for the overall overlap \
+                    ratio, i.e., the fraction of shuffles with an overlap \
+                    ratio at least as large as the observed one.",
+                ),
+        )
+        .arg(
+            Arg::with_name("chrom_sizes")
+                .long("chrom-sizes")
+                .short("g")
+                .takes_value(true)
+                .help(
+                    "Path to a file in which each line consists of the \
+                    chromosome name and the chromosome size. Required by \
+                    --permutations.",
+                ),
+        )
+        .arg(Arg::with_name("seed").long("seed").takes_value(true).help(
+            "Seed for the random number generator used by \
+                    --permutations, defaults to 0",
+        ))
+        .arg(
+            Arg::with_name("checkpoint")
+                .long("checkpoint")
+                .takes_value(true)
+                .requires("permutations")
+                .long_help(
+                    "Path prefix for the checkpoint files of --permutations. \
+                    The null distribution for each bin size is periodically \
+                    written to <checkpoint>.bin_<bin_size>, and an \
+                    interrupted run resumes from the existing checkpoints \
+                    when rerun with the same arguments.",
+                ),
+        )
+        .arg(
+            Arg::with_name("checkpoint_interval")
+                .long("checkpoint-interval")
+                .takes_value(true)
+                .requires("checkpoint")
+                .help(
+                    "The number of permutations between consecutive \
+                    checkpoints, defaults to 100",
                 ),
         );
     let matches = app.get_matches();
@@ -135,8 +232,12 @@ fn main() {
             .collect();
 
     let chroms = extract_optional_str_vec_arg(&matches, "chroms");
-    let top_k_fraction = extract_numeric_arg(&matches, "top_k_fraction")
-        .unwrap_or_exit(Some("failed to parse top-k"));
+    let top_k_fraction: Option<f64> =
+        extract_optional_numeric_arg(&matches, "top_k_fraction")
+            .unwrap_or_exit(Some("failed to parse top-k"));
+    let signal_fraction: Option<i64> =
+        extract_optional_numeric_arg(&matches, "signal_fraction")
+            .unwrap_or_exit(Some("failed to parse --signal-fraction"));
 
     let default_human_chroms =
         extract_boolean_flag(&matches, "default_human_chroms");
@@ -145,59 +246,157 @@ fn main() {
 
     let first_bedgraph = extract_boolean_flag(&matches, "first_bedgraph");
     let second_bedgraph = extract_boolean_flag(&matches, "second_bedgraph");
+    let one_based = extract_boolean_flag(&matches, "one_based");
+    let by_bp = extract_boolean_flag(&matches, "by_bp");
+    let quiet = extract_boolean_flag(&matches, "quiet");
+
+    let permutations: Option<usize> =
+        extract_optional_numeric_arg(&matches, "permutations")
+            .unwrap_or_exit(Some("failed to parse --permutations"));
+    let chrom_sizes_path = extract_optional_str_arg(&matches, "chrom_sizes");
+    let seed: u64 = extract_optional_numeric_arg(&matches, "seed")
+        .unwrap_or_exit(Some("failed to parse --seed"))
+        .unwrap_or(0);
+    let checkpoint = extract_optional_str_arg(&matches, "checkpoint");
+    let checkpoint_interval: usize =
+        extract_optional_numeric_arg(&matches, "checkpoint_interval")
+            .unwrap_or_exit(Some("failed to parse --checkpoint-interval"))
+            .unwrap_or(DEFAULT_CHECKPOINT_INTERVAL);
 
-    let first_track: TrackVariant = if first_bedgraph {
-        TrackVariant::BedGraph(BedGraph::new(
+    let first_track = if first_bedgraph {
+        TrackFormat::BedGraph.to_track(&first_track_filepath, BINARIZE_SCORE)
+    } else {
+        track_from_path_autodetect(
             &first_track_filepath,
             BINARIZE_SCORE,
-        ))
-    } else {
-        TrackVariant::Bed(Bed::new(&first_track_filepath, BINARIZE_SCORE))
+            TrackFormat::Bed,
+        )
+        .unwrap_or_exit(None::<String>)
     };
 
-    let second_track: TrackVariant = if second_bedgraph {
-        TrackVariant::BedGraph(BedGraph::new(
+    let second_track = if second_bedgraph {
+        TrackFormat::BedGraph.to_track(&second_track_filepath, BINARIZE_SCORE)
+    } else {
+        track_from_path_autodetect(
             &second_track_filepath,
             BINARIZE_SCORE,
-        ))
-    } else {
-        TrackVariant::Bed(Bed::new(&second_track_filepath, BINARIZE_SCORE))
+            TrackFormat::Bed,
+        )
+        .unwrap_or_exit(None::<String>)
     };
 
-    eprint_named_vars!(
-        first_track_filepath,
-        second_track_filepath,
-        default_human_chroms,
-        first_bedgraph,
-        second_bedgraph
+    if !quiet {
+        eprint_named_vars!(
+            first_track_filepath,
+            second_track_filepath,
+            default_human_chroms,
+            first_bedgraph,
+            second_bedgraph,
+            one_based,
+            by_bp
+        );
+    }
+    debug_eprint_named_vars!(
+        exclude,
+        bin_sizes,
+        chroms,
+        top_k_fraction,
+        signal_fraction,
+        permutations,
+        chrom_sizes_path,
+        seed,
+        checkpoint,
+        checkpoint_interval
     );
-    debug_eprint_named_vars!(exclude, bin_sizes, chroms, top_k_fraction);
 
     let exlcude = get_excluded_interval_maps(exclude);
 
-    let chrom_interval_map_1 =
-        get_chrom_interval_map(&first_track, exlcude.as_ref())
+    let read_options = ReadOptions {
+        coordinate_system: if one_based {
+            CoordinateSystem::Closed1
+        } else {
+            CoordinateSystem::HalfOpen0
+        },
+        ..ReadOptions::default()
+    };
+    // the lines of the first track are kept for shuffling in the permutation
+    // test
+    let chrom_intervals_1 =
+        get_chrom_intervals(&first_track, exlcude.as_ref(), &read_options)
             .unwrap_or_exit(None::<String>);
+    let chrom_interval_map_1 = to_chrom_interval_map(&chrom_intervals_1);
 
-    let chrom_interval_map_2 =
-        get_chrom_interval_map(&second_track, exlcude.as_ref())
-            .unwrap_or_exit(None::<String>);
+    let chrom_interval_map_2 = get_chrom_interval_map_with_options(
+        &second_track,
+        exlcude.as_ref(),
+        &read_options,
+    )
+    .unwrap_or_exit(None::<String>);
+
+    let chrom_sizes = chrom_sizes_path.map(|path| {
+        get_chrom_sizes(&path).unwrap_or_exit(Some(format_args!(
+            "failed to read the chromosome sizes from {}",
+            path
+        )))
+    });
 
+    // the intervals of the tracks can only be used as the bins when the
+    // overlap is measured in base pairs
     let invalid_bin_sizes: Vec<i64> = bin_sizes
         .iter()
-        .filter(|&&s| s <= 0i64)
+        .filter(|&&s| s < 0i64 || (s == 0 && !by_bp))
         .map(|&s| s)
         .collect();
 
     if !invalid_bin_sizes.is_empty() {
         eprintln!(
-            "bin sizes must be positive, but these are not: {:?}",
+            "bin sizes must be positive, or 0 with --by-bp, but these are \
+            not: {:?}",
             invalid_bin_sizes
         );
         std::process::exit(1);
     }
 
     let empty_interval_map = IntegerIntervalMap::new();
+    if let Some(k) = signal_fraction {
+        for b in bin_sizes {
+            println!(
+                "=> computing the signal fraction in the top {} bins with bin \
+                size {}",
+                k, b
+            );
+            chrom_interval_map_1
+                .union_zip(&chrom_interval_map_2)
+                .into_iter()
+                .for_each(|(chrom, map_list)| {
+                    let fractions: Vec<f64> = map_list
+                        .iter()
+                        .map(|map| {
+                            top_k_signal_fraction(
+                                map.unwrap_or(&empty_interval_map),
+                                k,
+                                b,
+                            )
+                            .unwrap_or_exit(None::<String>)
+                        })
+                        .collect();
+                    println!("{}, {}, {}", chrom, fractions[0], fractions[1]);
+                });
+        }
+        return;
+    }
+    let top_k_fraction = top_k_fraction.unwrap_or_else(|| {
+        eprintln!("--top-k is required unless --signal-fraction is set");
+        std::process::exit(1);
+    });
+    let overlap_options = TopKOverlapOptions {
+        weight: if by_bp {
+            OverlapWeight::BasePairs
+        } else {
+            OverlapWeight::Bins
+        },
+        verbose: !quiet,
+    };
     for b in bin_sizes {
         println!(
             "=> computing top {} overlap with bin size {}",
@@ -212,6 +411,7 @@ fn main() {
                     &map_list[1].unwrap_or_else(|| &empty_interval_map),
                     top_k_fraction,
                     b,
+                    &overlap_options,
                 )
                 .unwrap_or_exit(None::<String>);
 
@@ -223,6 +423,7 @@ fn main() {
             &chrom_interval_map_2,
             top_k_fraction,
             b,
+            &overlap_options,
         )
         .unwrap_or_exit(Some("failed to compute overall overlap ratio"));
 
@@ -231,5 +432,36 @@ fn main() {
             top_k_fraction, b
         );
         println!("top_k_overall, {}", ratio);
+
+        if let (Some(n_perm), Some(chrom_sizes)) =
+            (permutations, chrom_sizes.as_ref())
+        {
+            if !quiet {
+                eprintln!(
+                    "=> running {} permutations with bin size {}",
+                    n_perm, b
+                );
+            }
+            let enrichment = overlap_enrichment_pvalue_with_options(
+                &chrom_intervals_1,
+                &chrom_interval_map_2,
+                chrom_sizes,
+                top_k_fraction,
+                b,
+                &PermutationOptions {
+                    n_perm,
+                    seed,
+                    checkpoint_path: checkpoint
+                        .as_ref()
+                        .map(|prefix| format!("{}.bin_{}", prefix, b)),
+                    checkpoint_interval,
+                    exclude: exlcude.clone(),
+                },
+            )
+            .unwrap_or_exit(Some("failed to run the permutation test"));
+            println!("observed_ratio, {}", enrichment.observed_ratio);
+            println!("null_mean, {}", enrichment.null_mean);
+            println!("p_value, {}", enrichment.p_value);
+        }
     }
 }
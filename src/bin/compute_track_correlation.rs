@@ -1,7 +1,16 @@
-use biofile::{bed::Bed, bedgraph::BedGraph, util::TrackVariant};
 use biostats::{
-    track_correlation::{compute_track_correlations, ValueTransform},
-    util::get_default_human_chrom_inclusion_set,
+    track_conversion::{track_from_path_autodetect, TrackFormat},
+    track_correlation::{
+        compute_stranded_correlations, compute_track_correlations_with_stats,
+        compute_track_regressions, find_stable_bin_size,
+        write_correlations_to_dir, CorrelationOptions, CoveragePredicate,
+        DifferenceOrder, PValueMode, QuantileBinning, ValueTransform,
+        ZeroFillMode,
+    },
+    util::{
+        get_chrom_aliases, get_default_human_chrom_inclusion_set, ChromAlias,
+        CoordinateSystem, ReadOptions, UnknownStrand, ValueColumn,
+    },
 };
 use clap::{clap_app, Arg};
 use program_flow::{
@@ -15,6 +24,7 @@ use program_flow::{
 use std::{collections::HashSet, iter::FromIterator};
 
 const ZERO_BIN_SIZE_STR: &str = "0";
+const DEFAULT_STABILITY_EPSILON: f64 = 0.01;
 
 fn main() {
     let mut app = clap_app!(compute_track_correlation =>
@@ -48,10 +58,222 @@ fn main() {
                     value.",
                 ),
         )
+        .arg(
+            Arg::with_name("out_dir")
+                .long("out-dir")
+                .takes_value(true)
+                .help(
+                    "Writes the correlations of each chromosome to \
+                    chroms/<chrom>.corr.tsv and the overall correlations to \
+                    overall.corr.tsv under this directory instead of printing \
+                    them to stdout",
+                ),
+        )
+        .arg(
+            Arg::with_name("value_from_name")
+                .long("value-from-name")
+                .conflicts_with("binarize_score")
+                .help(
+                    "Reads the value of each BED line from the name column \
+                    instead of the score column",
+                ),
+        )
+        .arg(
+            Arg::with_name("ucsc_chrom_names")
+                .long("ucsc-chrom-names")
+                .help(
+                    "Converts Ensembl-style chromosome names into UCSC-style \
+                    names, e.g., 1 into chr1 and MT into chrM, so that tracks \
+                    using different naming conventions can be correlated",
+                ),
+        )
+        .arg(
+            Arg::with_name("chrom_alias")
+                .long("chrom-alias")
+                .takes_value(true)
+                .conflicts_with("ucsc_chrom_names")
+                .help(
+                    "Path to a file in which each line consists of a \
+                    chromosome name and the name it should be renamed to \
+                    before the tracks are compared",
+                ),
+        )
+        .arg(
+            Arg::with_name("report_skipped_chroms")
+                .long("report-skipped-chroms")
+                .help(
+                    "Prints to stderr the chromosomes that are skipped because \
+                    of --chroms or --default-human-chroms, together with the \
+                    number of lines on each of them",
+                ),
+        )
+        .arg(Arg::with_name("difference").long("difference").help(
+            "Correlates the differences between adjacent bins instead of \
+            the values in the bins, so as to compare where the tracks rise \
+            and fall rather than their levels",
+        ))
+        .arg(
+            Arg::with_name("within_covered_span")
+                .long("within-covered-span")
+                .help(
+                    "Counts a bin without data in a track as zero only \
+                    within the span covered by both tracks on the \
+                    chromosome, leaving out the flanking bins covered by only \
+                    one of them",
+                ),
+        )
+        .arg(
+            Arg::with_name("quantile_groups")
+                .long("quantile-groups")
+                .takes_value(true)
+                .long_help(
+                    "Sorts the bins by their values in the first track, \
+                    divides them into this many equal-count groups, e.g., 10 \
+                    for deciles, and correlates the group means of the two \
+                    tracks instead of the bins. This is a coarser but more \
+                    robust correlation.",
+                ),
+        )
+        .arg(
+            Arg::with_name("approx_quantiles")
+                .long("approx-quantiles")
+                .requires("quantile_groups")
+                .long_help(
+                    "Bounds the --quantile-groups by approximate quantiles \
+                    from a streaming sketch instead of sorting all the bins, \
+                    which keeps the memory bounded across the genome at the \
+                    cost of a rank error of up to 0.1% of the number of bins \
+                    at each group boundary",
+                ),
+        )
+        .arg(
+            Arg::with_name("min_bins")
+                .long("min-bins")
+                .takes_value(true)
+                .help(
+                    "Reports NaN for a chromosome and bin size with fewer \
+                    than this many bins instead of a spurious correlation",
+                ),
+        )
+        .arg(
+            Arg::with_name("coverage_rule")
+                .long("coverage-rule")
+                .takes_value(true)
+                .long_help(
+                    "Which bins are correlated based on which of the tracks \
+                    have data in them: 'all' for the bins covered by both \
+                    tracks, 'any' for the bins covered by either track, which \
+                    is the default, 'first' or 'second' for the bins covered \
+                    by the first or the second track, or a number n for the \
+                    bins covered by at least n of the tracks.",
+                ),
+        )
+        .arg(
+            Arg::with_name("max_bad_lines")
+                .long("max-bad-lines")
+                .takes_value(true)
+                .help(
+                    "Skips up to this many malformed lines in each track, \
+                    e.g., with a non-integer coordinate, instead of failing \
+                    on the first one, and reports how many were skipped",
+                ),
+        )
+        .arg(
+            Arg::with_name("min_coverage")
+                .long("min-coverage")
+                .takes_value(true)
+                .long_help(
+                    "Only correlates the bins where the binned values of both \
+                    tracks are at least this value, so that the noise in the \
+                    weakly covered bins is left out. Unlike \
+                    --within-covered-span, which depends on the presence of \
+                    data, this is a threshold on the values. A bin without \
+                    data in a track has a value of 0 in that track.",
+                ),
+        )
+        .arg(
+            Arg::with_name("quiet")
+                .long("quiet")
+                .short("q")
+                .help("Suppresses the progress messages printed to stderr"),
+        )
+        .arg(Arg::with_name("one_based").long("one-based").help(
+            "The coordinates are 1-based with inclusive ends, e.g., as in \
+            GFF-derived BED files, instead of the 0-based half-open BED \
+            convention",
+        ))
         .arg(Arg::with_name("binarize_score").long("binarize").help(
             "Each line in the original BED files will contribute a \
             unit score for the corresponding interval",
         ))
+        .arg(
+            Arg::with_name("count_reads")
+                .long("count-reads")
+                .conflicts_with_all(&["binarize_score", "value_from_name"])
+                .long_help(
+                    "Correlates the number of lines, e.g., reads or peaks, \
+                    overlapping each bin instead of the aggregated scores, \
+                    for tracks without meaningful scores. With a bin size of \
+                    0, correlates the depths of the base pairs.",
+                ),
+        )
+        .arg(Arg::with_name("p_values").long("p-values").help(
+            "Prints the two-sided p-value of the overall correlation for each \
+            bin size to stderr",
+        ))
+        .arg(
+            Arg::with_name("autocorr_correct")
+                .long("autocorr-correct")
+                .long_help(
+                    "Prints the p-values as --p-values does, except that the \
+                    number of bins is corrected for the lag-1 autocorrelation \
+                    between the adjacent bins, which otherwise overstates the \
+                    significance of the correlations",
+                ),
+        )
+        .arg(
+            Arg::with_name("regression")
+                .long("regression")
+                .conflicts_with_all(&[
+                    "out_dir",
+                    "quantile_groups",
+                    "p_values",
+                    "autocorr_correct",
+                ])
+                .help(
+                    "Prints the least-squares slope and intercept of the \
+                    second track against the first track together with each \
+                    correlation",
+                ),
+        )
+        .arg(
+            Arg::with_name("by_strand")
+                .long("by-strand")
+                .conflicts_with_all(&[
+                    "regression",
+                    "out_dir",
+                    "count_reads",
+                    "first_bedgraph",
+                    "second_bedgraph",
+                ])
+                .help(
+                    "Correlates the plus strand signals and the minus strand \
+                    signals of the BED tracks separately, using the strand \
+                    field of each line",
+                ),
+        )
+        .arg(
+            Arg::with_name("unknown_strand")
+                .long("unknown-strand")
+                .takes_value(true)
+                .possible_values(&["both", "neither"])
+                .requires("by_strand")
+                .long_help(
+                    "Under --by-strand, whether the lines without a strand \
+                    count on both strands or on neither strand. Defaults to \
+                    'neither'.",
+                ),
+        )
         .arg(
             Arg::with_name("chroms")
                 .long("chroms")
@@ -86,6 +308,27 @@ fn main() {
                     Threshold must be positive.",
                 ),
         )
+        .arg(
+            Arg::with_name("log_base")
+                .long("log-base")
+                .takes_value(true)
+                .conflicts_with("threshold")
+                .help(
+                    "Applies the signed log transform with this base, e.g., 2 \
+                    or 10, to the aggregate value x at each base pair, i.e., \
+                    x => sign(x) * log_base(|x| + pseudocount)",
+                ),
+        )
+        .arg(
+            Arg::with_name("log_pseudocount")
+                .long("log-pseudocount")
+                .takes_value(true)
+                .requires("log_base")
+                .help(
+                    "The pseudocount added inside the log transform, defaults \
+                    to 1",
+                ),
+        )
         .arg(
             Arg::with_name("exclude")
                 .long("exclude")
@@ -103,7 +346,8 @@ fn main() {
                 .long("first-bedgraph")
                 .help(
                     "A flag to indicate that the first track is in the \
-                    bedgraph format",
+                    bedgraph format. Without it, the format is detected from \
+                    the first few lines of the track.",
                 ),
         )
         .arg(
@@ -111,7 +355,28 @@ fn main() {
                 .long("second-bedgraph")
                 .help(
                     "A flag to indicate that the second track is in the \
-                    bedgraph format",
+                    bedgraph format. Without it, the format is detected from \
+                    the first few lines of the track.",
+                ),
+        )
+        .arg(
+            Arg::with_name("head")
+                .long("head")
+                .takes_value(true)
+                .help(
+                    "Only reads the first N lines of each track, e.g., for a \
+                    quick smoke test on a huge file.",
+                ),
+        )
+        .arg(
+            Arg::with_name("stability_epsilon")
+                .long("stability-epsilon")
+                .takes_value(true)
+                .help(
+                    "With multiple bin sizes, reports the smallest bin size \
+                    from which the overall correlation changes by less than \
+                    this amount between consecutive bin sizes. Defaults to \
+                    0.01.",
                 ),
         );
     let matches = app.get_matches();
@@ -136,11 +401,60 @@ fn main() {
     let chroms = extract_optional_str_vec_arg(&matches, "chroms");
 
     let binarize_score = extract_boolean_flag(&matches, "binarize_score");
+    let value_from_name = extract_boolean_flag(&matches, "value_from_name");
+    let count_reads = extract_boolean_flag(&matches, "count_reads");
+    let p_values = extract_boolean_flag(&matches, "p_values");
+    let regression = extract_boolean_flag(&matches, "regression");
+    let by_strand = extract_boolean_flag(&matches, "by_strand");
+    let unknown_strand =
+        match extract_optional_str_arg(&matches, "unknown_strand")
+            .as_ref()
+            .map(|s| s.as_str())
+        {
+            Some("both") => UnknownStrand::Both,
+            _ => UnknownStrand::Neither,
+        };
+    let autocorr_correct = extract_boolean_flag(&matches, "autocorr_correct");
+    let one_based = extract_boolean_flag(&matches, "one_based");
+    let quiet = extract_boolean_flag(&matches, "quiet");
+    let difference = extract_boolean_flag(&matches, "difference");
+    let within_covered_span =
+        extract_boolean_flag(&matches, "within_covered_span");
+    let quantile_groups: Option<usize> =
+        extract_optional_numeric_arg(&matches, "quantile_groups")
+            .unwrap_or_exit(Some("failed to parse --quantile-groups"));
+    let approx_quantiles = extract_boolean_flag(&matches, "approx_quantiles");
+    let min_bins: Option<usize> =
+        extract_optional_numeric_arg(&matches, "min_bins")
+            .unwrap_or_exit(Some("failed to parse --min-bins"));
+    let min_coverage: Option<f64> =
+        extract_optional_numeric_arg(&matches, "min_coverage")
+            .unwrap_or_exit(Some("failed to parse --min-coverage"));
+    let max_bad_lines: Option<usize> =
+        extract_optional_numeric_arg(&matches, "max_bad_lines")
+            .unwrap_or_exit(Some("failed to parse --max-bad-lines"));
+    let head: Option<usize> = extract_optional_numeric_arg(&matches, "head")
+        .unwrap_or_exit(Some("failed to parse --head"));
+    let stability_epsilon: f64 =
+        extract_optional_numeric_arg(&matches, "stability_epsilon")
+            .unwrap_or_exit(Some("failed to parse --stability-epsilon"))
+            .unwrap_or(DEFAULT_STABILITY_EPSILON);
+    let coverage_rule = extract_optional_str_arg(&matches, "coverage_rule");
+    let report_skipped_chroms =
+        extract_boolean_flag(&matches, "report_skipped_chroms");
+    let ucsc_chrom_names = extract_boolean_flag(&matches, "ucsc_chrom_names");
+    let chrom_alias_path = extract_optional_str_arg(&matches, "chrom_alias");
+    let out_dir = extract_optional_str_arg(&matches, "out_dir");
 
     let default_human_chroms =
         extract_boolean_flag(&matches, "default_human_chroms");
 
-    let log_transform = extract_boolean_flag(&matches, "log_transform");
+    let log_base: Option<f64> =
+        extract_optional_numeric_arg(&matches, "log_base")
+            .unwrap_or_exit(Some("failed to parse --log-base"));
+    let log_pseudocount: Option<f64> =
+        extract_optional_numeric_arg(&matches, "log_pseudocount")
+            .unwrap_or_exit(Some("failed to parse --log-pseudocount"));
     let threshold = extract_optional_numeric_arg(&matches, "threshold")
         .unwrap_or_exit(Some("failed to parse threshold"));
     let exclude = extract_optional_str_arg(&matches, "exclude");
@@ -148,34 +462,94 @@ fn main() {
     let first_bedgraph = extract_boolean_flag(&matches, "first_bedgraph");
     let second_bedgraph = extract_boolean_flag(&matches, "second_bedgraph");
 
-    let first_track: TrackVariant = if first_bedgraph {
-        TrackVariant::BedGraph(BedGraph::new(
-            &first_track_filepath,
-            binarize_score,
-        ))
+    let first_path = first_track_filepath.as_str();
+    let second_path = second_track_filepath.as_str();
+
+    let first_track = if first_bedgraph {
+        TrackFormat::BedGraph.to_track(first_path, binarize_score)
+    } else if value_from_name {
+        // the numeric name field would otherwise be detected as a bedGraph
+        TrackFormat::Bed.to_track(first_path, binarize_score)
     } else {
-        TrackVariant::Bed(Bed::new(&first_track_filepath, binarize_score))
+        track_from_path_autodetect(first_path, binarize_score, TrackFormat::Bed)
+            .unwrap_or_exit(None::<String>)
     };
 
-    let second_track: TrackVariant = if second_bedgraph {
-        TrackVariant::BedGraph(BedGraph::new(
-            &second_track_filepath,
-            binarize_score,
-        ))
+    let second_track = if second_bedgraph {
+        TrackFormat::BedGraph.to_track(second_path, binarize_score)
+    } else if value_from_name {
+        // the numeric name field would otherwise be detected as a bedGraph
+        TrackFormat::Bed.to_track(second_path, binarize_score)
     } else {
-        TrackVariant::Bed(Bed::new(&second_track_filepath, binarize_score))
+        track_from_path_autodetect(
+            second_path,
+            binarize_score,
+            TrackFormat::Bed,
+        )
+        .unwrap_or_exit(None::<String>)
     };
 
-    eprint_named_vars!(
-        first_track_filepath,
-        second_track_filepath,
-        binarize_score,
-        default_human_chroms,
-        log_transform,
-        first_bedgraph,
-        second_bedgraph
+    if !quiet {
+        eprint_named_vars!(
+            first_track_filepath,
+            second_track_filepath,
+            binarize_score,
+            value_from_name,
+            one_based,
+            ucsc_chrom_names,
+            difference,
+            within_covered_span,
+            default_human_chroms,
+            first_bedgraph,
+            second_bedgraph
+        );
+    }
+    debug_eprint_named_vars!(
+        threshold,
+        log_base,
+        log_pseudocount,
+        exclude,
+        bin_sizes,
+        chroms,
+        chrom_alias_path,
+        quantile_groups,
+        approx_quantiles,
+        min_bins,
+        min_coverage,
+        max_bad_lines,
+        head,
+        coverage_rule,
+        count_reads,
+        p_values,
+        autocorr_correct,
+        regression,
+        by_strand,
+        unknown_strand,
+        stability_epsilon,
+        out_dir
     );
-    debug_eprint_named_vars!(threshold, exclude, bin_sizes, chroms);
+
+    let coverage_predicate = match coverage_rule.as_ref().map(|s| s.as_str()) {
+        None | Some("any") => CoveragePredicate::Any,
+        Some("all") => CoveragePredicate::All,
+        Some("first") => CoveragePredicate::First,
+        Some("second") => CoveragePredicate::Second,
+        Some(rule) => {
+            CoveragePredicate::Count(rule.parse::<usize>().unwrap_or_exit(
+                Some(format_args!("invalid --coverage-rule {}", rule)),
+            ))
+        }
+    };
+
+    let chrom_alias = if let Some(path) = chrom_alias_path {
+        ChromAlias::Map(get_chrom_aliases(&path).unwrap_or_exit(Some(
+            format_args!("failed to read the chromosome aliases from {}", path),
+        )))
+    } else if ucsc_chrom_names {
+        ChromAlias::Ucsc
+    } else {
+        ChromAlias::Original
+    };
 
     let target_chroms = match chroms {
         Some(chroms) => {
@@ -199,22 +573,195 @@ fn main() {
 
     let transform_type = if let Some(t) = threshold {
         ValueTransform::Thresholding(t)
+    } else if let Some(base) = log_base {
+        ValueTransform::Log {
+            base,
+            pseudocount: log_pseudocount.unwrap_or(1.),
+        }
     } else {
         ValueTransform::Identity
     };
+    transform_type
+        .validate()
+        .unwrap_or_exit(Some("invalid --log-base"));
+
+    let options = CorrelationOptions {
+        target_chroms,
+        value_transform: transform_type,
+        exclude_track_filepath: exclude.clone(),
+        read_options: ReadOptions {
+            value_column: if value_from_name {
+                ValueColumn::Name
+            } else {
+                ValueColumn::Score
+            },
+            coordinate_system: if one_based {
+                CoordinateSystem::Closed1
+            } else {
+                CoordinateSystem::HalfOpen0
+            },
+            chrom_alias,
+            max_lines: head,
+            max_bad_lines,
+            ..ReadOptions::default()
+        },
+        verbose: !quiet,
+        difference_order: if difference {
+            DifferenceOrder::First
+        } else {
+            DifferenceOrder::None
+        },
+        report_skipped_chroms,
+        quantile_binning: match quantile_groups {
+            Some(10) => QuantileBinning::Deciles,
+            Some(n) => QuantileBinning::Custom(n),
+            None => QuantileBinning::None,
+        },
+        approx_quantiles,
+        zero_fill_mode: if within_covered_span {
+            ZeroFillMode::WithinCoveredSpan
+        } else {
+            ZeroFillMode::WholeChromosome
+        },
+        min_bins: min_bins.unwrap_or(0),
+        min_coverage,
+        coverage_predicate,
+        count_reads,
+        p_values: if autocorr_correct {
+            PValueMode::AutocorrCorrected
+        } else if p_values {
+            PValueMode::Naive
+        } else {
+            PValueMode::None
+        },
+        ..CorrelationOptions::default()
+    };
+
+    if regression {
+        let (chrom_regressions, overall_regressions) =
+            compute_track_regressions(
+                &first_track,
+                &second_track,
+                &bin_sizes,
+                &options,
+            )
+            .unwrap_or_exit(Some("failed to compute the regressions"));
+        for (chrom, regressions) in chrom_regressions.iter() {
+            print!("{}, ", chrom);
+            regressions.iter().for_each(|(r, slope, intercept)| {
+                print!("{:.5}, {:.5}, {:.5}, ", r, slope, intercept)
+            });
+            println!();
+        }
+        print!("overall, ");
+        overall_regressions
+            .iter()
+            .for_each(|(r, slope, intercept)| {
+                print!("{:.5}, {:.5}, {:.5}, ", r, slope, intercept)
+            });
+        println!();
+        return;
+    }
+
+    if by_strand {
+        let (plus_correlations, minus_correlations) =
+            compute_stranded_correlations(
+                first_path,
+                second_path,
+                binarize_score,
+                &bin_sizes,
+                &options,
+                unknown_strand,
+            )
+            .unwrap_or_exit(Some("failed to compute the strand correlations"));
+        for (strand, (chrom_correlations, overall_correlations)) in
+            vec![("+", plus_correlations), ("-", minus_correlations)]
+        {
+            for (chrom, correlation) in chrom_correlations.iter() {
+                print!("{}, {}, ", strand, chrom);
+                correlation.iter().for_each(|c| print!("{:.5}, ", c));
+                println!();
+            }
+            print!("{}, overall, ", strand);
+            overall_correlations
+                .iter()
+                .for_each(|c| print!("{:.5}, ", c));
+            println!();
+        }
+        return;
+    }
 
-    let (chrom_correlations, overall_correlations) =
-        compute_track_correlations(
+    let (chrom_correlations, overall_correlations, stats) =
+        compute_track_correlations_with_stats(
             &first_track,
             &second_track,
             &bin_sizes,
-            target_chroms,
-            transform_type,
-            None,
-            exclude.clone(),
+            &options,
         )
         .unwrap_or_exit(Some("failed to compute track correlations"));
 
+    for (bin_size, num_skipped) in
+        bin_sizes.iter().zip(stats.num_skipped_pairs.iter())
+    {
+        if *num_skipped > 0 {
+            eprintln!(
+                "warning: skipped {} bins with NaN or infinite values for bin \
+                size {}",
+                num_skipped, bin_size
+            );
+        }
+    }
+
+    if !quiet {
+        for ((bin_size, num_effective_bins), p_value) in bin_sizes
+            .iter()
+            .zip(stats.num_effective_bins.iter())
+            .zip(stats.p_values.iter())
+        {
+            eprintln!(
+                "=> bin size {}: p-value {:e} over {:.1} effective bins",
+                bin_size, p_value, num_effective_bins
+            );
+        }
+
+        if bin_sizes.len() > 1 {
+            let correlations_by_binsize: Vec<(i64, f64)> = bin_sizes
+                .iter()
+                .cloned()
+                .zip(overall_correlations.iter().cloned())
+                .collect();
+            match find_stable_bin_size(
+                &correlations_by_binsize,
+                stability_epsilon,
+            ) {
+                Some(bin_size) => eprintln!(
+                    "=> the overall correlation is stable within {} from bin \
+                    size {}",
+                    stability_epsilon, bin_size
+                ),
+                None => eprintln!(
+                    "=> the overall correlation does not stabilize within {} \
+                    over the bin sizes",
+                    stability_epsilon
+                ),
+            }
+        }
+    }
+
+    if let Some(out_dir) = out_dir {
+        write_correlations_to_dir(
+            &chrom_correlations,
+            &overall_correlations,
+            &bin_sizes,
+            &out_dir,
+        )
+        .unwrap_or_exit(Some(format_args!(
+            "failed to write the correlations to {}",
+            out_dir
+        )));
+        return;
+    }
+
     for (chrom, correlation) in chrom_correlations.iter() {
         print!("{}, ", chrom);
         correlation.iter().for_each(|c| print!("{:.5}, ", c));
@@ -0,0 +1,74 @@
+use biostats::track_conversion::{convert_track, TrackFormat};
+use clap::{clap_app, Arg};
+use program_flow::{
+    argparse::{extract_boolean_flag, extract_str_arg},
+    eprint_named_vars, OrExit,
+};
+
+fn main() {
+    let mut app = clap_app!(convert_track =>
+        (about: "Converts a track between the BED and the bedGraph formats \
+        line by line, without aggregating overlapping lines. The score column \
+        of BED maps to the value column of bedGraph and vice versa.")
+    );
+    app = app
+        .arg(
+            Arg::with_name("track_filepath")
+                .long("input")
+                .short("i")
+                .takes_value(true)
+                .required(true)
+                .help("filepath to the track to convert."),
+        )
+        .arg(
+            Arg::with_name("out_path")
+                .long("out-path")
+                .short("o")
+                .takes_value(true)
+                .required(true)
+                .help(
+                    "output file path, compressed with gzip if it ends in .gz",
+                ),
+        )
+        .arg(
+            Arg::with_name("from")
+                .long("from")
+                .takes_value(true)
+                .required(true)
+                .possible_values(&["bed", "bedgraph"])
+                .help(
+                    "The format of the input track. The output is in the \
+                    other format.",
+                ),
+        )
+        .arg(
+            Arg::with_name("quiet")
+                .long("quiet")
+                .short("q")
+                .help("Suppresses the progress messages printed to stderr"),
+        );
+    let matches = app.get_matches();
+    let quiet = extract_boolean_flag(&matches, "quiet");
+    let track_filepath = extract_str_arg(&matches, "track_filepath");
+    let out_path = extract_str_arg(&matches, "out_path");
+    let from = extract_str_arg(&matches, "from");
+
+    if !quiet {
+        eprint_named_vars!(track_filepath, out_path, from);
+    }
+
+    let from = match from.as_str() {
+        "bedgraph" => TrackFormat::BedGraph,
+        _ => TrackFormat::Bed,
+    };
+    let num_lines = convert_track(&track_filepath, &out_path, from)
+        .unwrap_or_exit(Some("failed to convert the track"));
+    if !quiet {
+        eprintln!(
+            "=> converted {} lines from {:?} to {:?}",
+            num_lines,
+            from,
+            from.other()
+        );
+    }
+}
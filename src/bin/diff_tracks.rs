@@ -0,0 +1,104 @@
+use biostats::track_diff::diff_tracks;
+use clap::{clap_app, Arg};
+use program_flow::{
+    argparse::{
+        extract_boolean_flag, extract_optional_numeric_arg, extract_str_arg,
+    },
+    eprint_named_vars, OrExit,
+};
+
+fn main() {
+    let mut app = clap_app!(diff_tracks =>
+        (about: "Compares two refined tracks bin by bin and reports the bins \
+        where the values differ by more than the tolerance or where only one \
+        of the tracks has data. Exits with a non-zero status if any bin \
+        differs.")
+    );
+    app = app
+        .arg(
+            Arg::with_name("first_track_filepath")
+                .takes_value(true)
+                .required(true)
+                .help(
+                    "filepath to the first track, whose format is detected \
+                    from its lines",
+                ),
+        )
+        .arg(
+            Arg::with_name("second_track_filepath")
+                .takes_value(true)
+                .required(true)
+                .help(
+                    "filepath to the second track, whose format is detected \
+                    from its lines",
+                ),
+        )
+        .arg(
+            Arg::with_name("bin_size")
+                .long("bin")
+                .takes_value(true)
+                .long_help(
+                    "Group the base pairs into consecutive bins of size \
+                    bin_size, aligned at index 0. A bin size of 0 means \
+                    not to bin, which is the default.",
+                ),
+        )
+        .arg(
+            Arg::with_name("tolerance")
+                .long("tolerance")
+                .short("t")
+                .takes_value(true)
+                .help(
+                    "Bins whose values differ by at most this amount are \
+                    considered equal, defaults to 0",
+                ),
+        )
+        .arg(
+            Arg::with_name("quiet")
+                .long("quiet")
+                .short("q")
+                .help("Suppresses the progress messages printed to stderr"),
+        );
+    let matches = app.get_matches();
+    let quiet = extract_boolean_flag(&matches, "quiet");
+    let first_track_filepath =
+        extract_str_arg(&matches, "first_track_filepath");
+    let second_track_filepath =
+        extract_str_arg(&matches, "second_track_filepath");
+    let bin_size: i64 = extract_optional_numeric_arg(&matches, "bin_size")
+        .unwrap_or_exit(Some("failed to parse --bin"))
+        .unwrap_or(0);
+    let tolerance: f64 = extract_optional_numeric_arg(&matches, "tolerance")
+        .unwrap_or_exit(Some("failed to parse --tolerance"))
+        .unwrap_or(0.);
+
+    if !quiet {
+        eprint_named_vars!(
+            first_track_filepath,
+            second_track_filepath,
+            bin_size,
+            tolerance
+        );
+    }
+
+    let diff = diff_tracks(
+        &first_track_filepath,
+        &second_track_filepath,
+        bin_size,
+        tolerance,
+    )
+    .unwrap_or_exit(Some("failed to diff the tracks"));
+
+    // a missing value counts as 0 when computing the difference
+    let max_difference = diff
+        .iter()
+        .map(|(.., a, b)| (a.unwrap_or(0.) - b.unwrap_or(0.)).abs())
+        .fold(0f64, f64::max);
+
+    println!("number of differing bins: {}", diff.len());
+    println!("max difference: {}", max_difference);
+
+    if !diff.is_empty() {
+        std::process::exit(1);
+    }
+}
@@ -1,5 +1,8 @@
 use biostats::{
-    track_histogram::generate_track_histograms,
+    track_histogram::{
+        generate_track_histograms_with_labels, get_bucket_enrichment,
+        read_expected_weights, HistogramBuckets, TrackHistograms,
+    },
     util::{extract_chrom_names, get_default_human_chrom_inclusion_set},
 };
 use clap::{clap_app, Arg};
@@ -80,8 +83,51 @@ fn main() {
                     will only compute histograms for the specified list of chromosome names. \
                     The leading and trailing whitespaces of each line will be ignored",
                 ),
+        )
+        .arg(
+            Arg::with_name("label_bins")
+                .long("label-bins")
+                .takes_value(true)
+                .help(
+                    "Also writes each bin to this BED file with the index of \
+                    its histogram bucket as the name and its average value as \
+                    the score. Bins outside [min, max] are labeled -1.",
+                ),
+        )
+        .arg(
+            Arg::with_name("head")
+                .long("head")
+                .takes_value(true)
+                .help(
+                    "Only reads the first N lines of the track, e.g., for a \
+                    quick smoke test on a huge file.",
+                ),
+        )
+        .arg(Arg::with_name("enrichment").long("enrichment").help(
+            "Also prints the log2 of the observed over the expected number \
+            of bins in each bucket, where the expectation is uniform over \
+            the buckets unless --expected is provided",
+        ))
+        .arg(
+            Arg::with_name("expected")
+                .long("expected")
+                .takes_value(true)
+                .long_help(
+                    "Path to a file with one line per bucket, in order, whose \
+                    last field is the expected count or weight of the bucket, \
+                    e.g., the bucket counts of a reference track. The \
+                    enrichment is computed against this distribution instead \
+                    of the uniform one. Implies --enrichment.",
+                ),
+        )
+        .arg(
+            Arg::with_name("quiet")
+                .long("quiet")
+                .short("q")
+                .help("Suppresses the progress messages printed to stderr"),
         );
     let matches = app.get_matches();
+    let quiet = extract_boolean_flag(&matches, "quiet");
     let track_filepath = extract_str_arg(&matches, "track_filepath");
     let bin_size: i64 = extract_numeric_arg(&matches, "bin_size")
         .unwrap_or_exit(Some(format_args!("failed to parse --bin")));
@@ -102,16 +148,40 @@ fn main() {
     let default_human_chrom =
         extract_boolean_flag(&matches, "default_human_chrom");
     let filter_chrom = extract_optional_str_arg(&matches, "filter_chrom");
+    let label_bins = extract_optional_str_arg(&matches, "label_bins");
+    let head: Option<usize> = extract_optional_numeric_arg(&matches, "head")
+        .unwrap_or_exit(Some("failed to parse --head"));
+    let expected = extract_optional_str_arg(&matches, "expected");
+    let enrichment =
+        extract_boolean_flag(&matches, "enrichment") || expected.is_some();
 
-    eprint_named_vars!(
-        track_filepath,
-        bin_size,
-        binarize_score,
+    if !quiet {
+        eprint_named_vars!(
+            track_filepath,
+            bin_size,
+            binarize_score,
+            min,
+            max,
+            default_human_chrom,
+            enrichment
+        );
+        debug_eprint_named_vars!(
+            num_histogram_buckets,
+            filter_chrom,
+            label_bins,
+            head,
+            expected
+        );
+    }
+    let buckets = HistogramBuckets {
+        num_buckets: num_histogram_buckets
+            .unwrap_or(DEFAULT_HISTOGRAM_NUM_BUCKETS),
         min,
         max,
-        default_human_chrom
-    );
-    debug_eprint_named_vars!(num_histogram_buckets, filter_chrom);
+    };
+    buckets
+        .validate()
+        .unwrap_or_exit(Some("invalid histogram buckets"));
 
     let filter_chroms = if default_human_chrom {
         Some(get_default_human_chrom_inclusion_set())
@@ -135,16 +205,33 @@ fn main() {
             None
         }
     };
-    let (overall_histogram, chrom_to_histogram) = generate_track_histograms(
+    let expected_weights = match &expected {
+        Some(path) => read_expected_weights(path, buckets.num_buckets)
+            .unwrap_or_exit(Some("failed to read the expected distribution")),
+        None => vec![1.; buckets.num_buckets],
+    };
+    let TrackHistograms {
+        overall: overall_histogram,
+        chrom_to_histogram,
+        bucket_counts,
+    } = generate_track_histograms_with_labels(
         &track_filepath,
-        num_histogram_buckets.unwrap_or(DEFAULT_HISTOGRAM_NUM_BUCKETS),
-        min,
-        max,
+        buckets,
         bin_size,
         binarize_score,
         filter_chroms,
+        head,
+        label_bins.as_deref(),
     )
     .unwrap_or_exit(Some("failed to generate the histogram"));
+    if let Some(label_path) = &label_bins {
+        if !quiet {
+            eprintln!(
+                "=> wrote the bucket labels to {}, with {} bins out of range",
+                label_path, bucket_counts[buckets.num_buckets]
+            );
+        }
+    }
     let mut keys: Vec<String> =
         chrom_to_histogram.keys().map(|s| s.to_string()).collect();
     keys.sort();
@@ -154,4 +241,22 @@ fn main() {
         println!("{}", chrom_to_histogram.get(chrom).unwrap());
     }
     println!("overall_histogram:\n{}", overall_histogram);
+
+    if enrichment {
+        let log2_enrichment =
+            get_bucket_enrichment(&bucket_counts, &expected_weights)
+                .unwrap_or_exit(Some("failed to compute the enrichment"));
+        let bucket_size = (max - min) / buckets.num_buckets as f64;
+        println!("bucket\tmin\tmax\tobserved\tlog2_enrichment");
+        for (i, e) in log2_enrichment.iter().enumerate() {
+            println!(
+                "{}\t{}\t{}\t{}\t{:.5}",
+                i,
+                min + bucket_size * i as f64,
+                min + bucket_size * (i + 1) as f64,
+                bucket_counts[i],
+                e
+            );
+        }
+    }
 }
@@ -1,6 +1,9 @@
 use biostats::{
-    linear_track_mixture::LinearTrackMixture,
-    util::{get_default_human_chrom_inclusion_set, get_weighted_track_paths},
+    linear_track_mixture::{AutoWeight, LinearTrackMixture},
+    util::{
+        get_default_human_chrom_inclusion_set, get_weighted_track_paths,
+        EndConvention, WriteOptions,
+    },
 };
 use clap::{clap_app, Arg};
 use program_flow::{
@@ -41,7 +44,9 @@ fn main() {
                 .short("o")
                 .takes_value(true)
                 .required(true)
-                .help("output file path."),
+                .help(
+                    "output file path, compressed with gzip if it ends in .gz.",
+                ),
         )
         .arg(
             Arg::with_name("bin_size")
@@ -54,6 +59,11 @@ fn main() {
                     base pair in the bin.",
                 ),
         )
+        .arg(Arg::with_name("inclusive_end").long("inclusive-end").help(
+            "Writes the last base pair of each interval as the end \
+            coordinate for tools expecting inclusive ends, instead of the \
+            exclusive end of the BED format",
+        ))
         .arg(Arg::with_name("binarize_score").long("binarize").help(
             "Each line in the original BED files will contribute a \
             unit score for the corresponding interval",
@@ -79,8 +89,48 @@ fn main() {
                     "Only process chromosomes \
                     chr1, chr2, ... chr22, chrX, chrY.",
                 ),
+        )
+        .arg(
+            Arg::with_name("auto_weight")
+                .long("auto-weight")
+                .takes_value(true)
+                .possible_values(&["inverse-variance"])
+                .long_help(
+                    "Ignores the weights in the weighted tracks file and \
+                    instead weights each track proportionally to the inverse \
+                    of the variance of its bin values across the genome, with \
+                    the weights normalized to sum to 1. The track paths are \
+                    still read from the weighted tracks file.",
+                ),
+        )
+        .arg(
+            Arg::with_name("argmax_out_path")
+                .long("argmax-out")
+                .takes_value(true)
+                .help(
+                    "Also writes a BED file in which the name of each bin is \
+                    the 0-based index of the track contributing the most to \
+                    the mixed value, and the score is its weighted value.",
+                ),
+        )
+        .arg(
+            Arg::with_name("with_contributions")
+                .long("with-contributions")
+                .help(
+                    "Writes the output as lines of the form \
+                    <chrom> <start> <end> <mixed> <contrib_0> ... <contrib_N> \
+                    where <contrib_i> is the weighted value of the i-th track \
+                    in the bin, instead of as a BED file.",
+                ),
+        )
+        .arg(
+            Arg::with_name("quiet")
+                .long("quiet")
+                .short("q")
+                .help("Suppresses the progress messages printed to stderr"),
         );
     let matches = app.get_matches();
+    let quiet = extract_boolean_flag(&matches, "quiet");
     let weighted_tracks_filepath =
         extract_str_arg(&matches, "weighted_tracks_filepath");
 
@@ -97,19 +147,33 @@ fn main() {
     }
 
     let binarize_score = extract_boolean_flag(&matches, "binarize_score");
+    let inclusive_end = extract_boolean_flag(&matches, "inclusive_end");
 
     let exclude = extract_optional_str_arg(&matches, "exclude");
     let default_human_chrom =
         extract_boolean_flag(&matches, "default_human_chrom");
+    let auto_weight = extract_optional_str_arg(&matches, "auto_weight");
+    let argmax_out_path = extract_optional_str_arg(&matches, "argmax_out_path");
+    let with_contributions =
+        extract_boolean_flag(&matches, "with_contributions");
 
-    eprint_named_vars!(
-        weighted_tracks_filepath,
-        out_path,
-        bin_size,
-        binarize_score,
-        default_human_chrom
-    );
-    debug_eprint_named_vars!(exclude);
+    if !quiet {
+        eprint_named_vars!(
+            weighted_tracks_filepath,
+            out_path,
+            bin_size,
+            binarize_score,
+            inclusive_end,
+            default_human_chrom,
+            with_contributions
+        );
+        debug_eprint_named_vars!(exclude, auto_weight, argmax_out_path);
+    }
+
+    let auto_weight = match auto_weight.as_ref().map(|s| s.as_str()) {
+        Some("inverse-variance") => AutoWeight::InverseVariance,
+        _ => AutoWeight::None,
+    };
 
     let target_chroms = if default_human_chrom {
         Some(get_default_human_chrom_inclusion_set())
@@ -127,10 +191,41 @@ fn main() {
         binarize_score,
         exclude,
         target_chroms,
+        auto_weight,
     )
     .unwrap_or_exit(Some("failed to linearly mix the tracks"));
+    if auto_weight != AutoWeight::None && !quiet {
+        eprintln!("=> track weights: {:?}", mixture.weights());
+    }
+
+    let write_options = WriteOptions {
+        end_convention: if inclusive_end {
+            EndConvention::Inclusive
+        } else {
+            EndConvention::Exclusive
+        },
+    };
+    if with_contributions {
+        mixture
+            .write_with_contributions(&out_path, &write_options)
+            .unwrap_or_exit(Some("failed to write to the output file"));
+    } else {
+        mixture
+            .write_to_bed_file_with_options(&out_path, &write_options)
+            .unwrap_or_exit(Some("failed to write to the output file"));
+    }
+    if let Some(path) = argmax_out_path {
+        mixture
+            .write_argmax_track(&path, &write_options)
+            .unwrap_or_exit(Some(format_args!(
+                "failed to write the argmax track to {}",
+                path
+            )));
+    }
 
-    mixture
-        .write_to_bed_file(&out_path)
-        .unwrap_or_exit(Some("failed to write to the output file"));
+    let summary = mixture.summary();
+    println!(
+        "total: {}\nmean: {}\nmax: {}\nnumber of bins: {}",
+        summary.total, summary.mean, summary.max, summary.num_bins
+    );
 }
@@ -0,0 +1,185 @@
+use biofile::{bed::BedWriter, bedgraph::BedGraphDataLine};
+use biostats::{
+    track_conversion::{track_from_path_autodetect, TrackFormat},
+    track_correlation::compute_windowed_local_correlation,
+};
+use clap::{clap_app, Arg};
+use program_flow::{
+    argparse::{extract_boolean_flag, extract_numeric_arg, extract_str_arg},
+    eprint_named_vars, OrExit,
+};
+
+fn main() {
+    let mut app = clap_app!(local_correlation =>
+        (about: "Slides a window along each chromosome and computes the \
+        Pearson correlation between the binned values of two tracks within \
+        each window. The output is a bedGraph where each line is a window \
+        together with its local correlation.")
+    );
+    app = app
+        .arg(
+            Arg::with_name("first_track_filepath")
+                .long("first")
+                .short("a")
+                .takes_value(true)
+                .required(true)
+                .help("filepath to the first track."),
+        )
+        .arg(
+            Arg::with_name("second_track_filepath")
+                .long("second")
+                .short("b")
+                .takes_value(true)
+                .required(true)
+                .help("filepath to the second track."),
+        )
+        .arg(
+            Arg::with_name("out_path")
+                .long("out-path")
+                .short("o")
+                .takes_value(true)
+                .required(true)
+                .help("output bedGraph file path."),
+        )
+        .arg(
+            Arg::with_name("bin_size")
+                .long("bin")
+                .takes_value(true)
+                .required(true)
+                .long_help(
+                    "Group the base pairs into consecutive bins of size \
+                    bin_size, aligned at index 0. The local correlation is \
+                    computed over the bins in each window.",
+                ),
+        )
+        .arg(
+            Arg::with_name("window")
+                .long("window")
+                .short("w")
+                .takes_value(true)
+                .required(true)
+                .help("window size in base pairs."),
+        )
+        .arg(
+            Arg::with_name("step")
+                .long("step")
+                .short("s")
+                .takes_value(true)
+                .required(true)
+                .help("the number of base pairs by which to slide the window."),
+        )
+        .arg(Arg::with_name("binarize_score").long("binarize").help(
+            "Each line in the original BED files will contribute a \
+            unit score for the corresponding interval",
+        ))
+        .arg(
+            Arg::with_name("first_bedgraph")
+                .long("first-bedgraph")
+                .help(
+                    "A flag to indicate that the first track is in the \
+                    bedgraph format. Without it, the format is detected from \
+                    the first few lines of the track.",
+                ),
+        )
+        .arg(
+            Arg::with_name("second_bedgraph")
+                .long("second-bedgraph")
+                .help(
+                    "A flag to indicate that the second track is in the \
+                    bedgraph format. Without it, the format is detected from \
+                    the first few lines of the track.",
+                ),
+        )
+        .arg(
+            Arg::with_name("quiet")
+                .long("quiet")
+                .short("q")
+                .help("Suppresses the progress messages printed to stderr"),
+        );
+    let matches = app.get_matches();
+    let quiet = extract_boolean_flag(&matches, "quiet");
+    let first_track_filepath =
+        extract_str_arg(&matches, "first_track_filepath");
+
+    let second_track_filepath =
+        extract_str_arg(&matches, "second_track_filepath");
+
+    let out_path = extract_str_arg(&matches, "out_path");
+
+    let bin_size: i64 = extract_numeric_arg(&matches, "bin_size")
+        .unwrap_or_exit(Some("failed to parse --bin"));
+    let window: i64 = extract_numeric_arg(&matches, "window")
+        .unwrap_or_exit(Some("failed to parse --window"));
+    let step: i64 = extract_numeric_arg(&matches, "step")
+        .unwrap_or_exit(Some("failed to parse --step"));
+
+    let binarize_score = extract_boolean_flag(&matches, "binarize_score");
+    let first_bedgraph = extract_boolean_flag(&matches, "first_bedgraph");
+    let second_bedgraph = extract_boolean_flag(&matches, "second_bedgraph");
+
+    if !quiet {
+        eprint_named_vars!(
+            first_track_filepath,
+            second_track_filepath,
+            out_path,
+            bin_size,
+            window,
+            step,
+            binarize_score,
+            first_bedgraph,
+            second_bedgraph
+        );
+    }
+
+    let first_track = if first_bedgraph {
+        TrackFormat::BedGraph.to_track(&first_track_filepath, binarize_score)
+    } else {
+        track_from_path_autodetect(
+            &first_track_filepath,
+            binarize_score,
+            TrackFormat::Bed,
+        )
+        .unwrap_or_exit(None::<String>)
+    };
+
+    let second_track = if second_bedgraph {
+        TrackFormat::BedGraph.to_track(&second_track_filepath, binarize_score)
+    } else {
+        track_from_path_autodetect(
+            &second_track_filepath,
+            binarize_score,
+            TrackFormat::Bed,
+        )
+        .unwrap_or_exit(None::<String>)
+    };
+
+    let local_correlations = compute_windowed_local_correlation(
+        &first_track,
+        &second_track,
+        window,
+        step,
+        bin_size,
+    )
+    .unwrap_or_exit(Some("failed to compute the local correlations"));
+
+    let mut writer = BedWriter::new(&out_path)
+        .unwrap_or_exit(Some(format_args!("failed to create {}", out_path)));
+
+    // windows with too few bins to compute a correlation are skipped
+    for (chrom, start, end_exclusive, value) in local_correlations
+        .into_iter()
+        .filter(|(.., value)| !value.is_nan())
+    {
+        writer
+            .write_bedgraph_line(&BedGraphDataLine {
+                chrom,
+                start,
+                end_exclusive,
+                value,
+            })
+            .unwrap_or_exit(Some(format_args!(
+                "failed to write to {}",
+                out_path
+            )));
+    }
+}
@@ -0,0 +1,118 @@
+use biostats::{
+    top_k_overlap::overlap_lengths,
+    track_conversion::{track_from_path_autodetect, TrackFormat},
+    util::{get_chrom_interval_map, get_sorted_keys},
+};
+use clap::{clap_app, Arg};
+use program_flow::{
+    argparse::{extract_boolean_flag, extract_str_arg},
+    eprint_named_vars, OrExit,
+};
+
+fn main() {
+    let mut app = clap_app!(overlap_report =>
+        (about: "Reports the number of base pairs covered by only the first \
+        track, only the second track, and both tracks on each chromosome, \
+        followed by the genome-wide totals.")
+    );
+    app = app
+        .arg(
+            Arg::with_name("first_track_filepath")
+                .long("first")
+                .short("a")
+                .takes_value(true)
+                .required(true)
+                .help("filepath to the first track."),
+        )
+        .arg(
+            Arg::with_name("second_track_filepath")
+                .long("second")
+                .short("b")
+                .takes_value(true)
+                .required(true)
+                .help("filepath to the second track."),
+        )
+        .arg(
+            Arg::with_name("first_bedgraph")
+                .long("first-bedgraph")
+                .help(
+                    "A flag to indicate that the first track is in the \
+                    bedgraph format. Without it, the format is detected from \
+                    the first few lines of the track.",
+                ),
+        )
+        .arg(
+            Arg::with_name("second_bedgraph")
+                .long("second-bedgraph")
+                .help(
+                    "A flag to indicate that the second track is in the \
+                    bedgraph format. Without it, the format is detected from \
+                    the first few lines of the track.",
+                ),
+        )
+        .arg(
+            Arg::with_name("quiet")
+                .long("quiet")
+                .short("q")
+                .help("Suppresses the progress messages printed to stderr"),
+        );
+    let matches = app.get_matches();
+    let quiet = extract_boolean_flag(&matches, "quiet");
+    let first_track_filepath =
+        extract_str_arg(&matches, "first_track_filepath");
+    let second_track_filepath =
+        extract_str_arg(&matches, "second_track_filepath");
+    let first_bedgraph = extract_boolean_flag(&matches, "first_bedgraph");
+    let second_bedgraph = extract_boolean_flag(&matches, "second_bedgraph");
+
+    if !quiet {
+        eprint_named_vars!(
+            first_track_filepath,
+            second_track_filepath,
+            first_bedgraph,
+            second_bedgraph
+        );
+    }
+
+    let first_track = if first_bedgraph {
+        TrackFormat::BedGraph.to_track(&first_track_filepath, false)
+    } else {
+        track_from_path_autodetect(
+            &first_track_filepath,
+            false,
+            TrackFormat::Bed,
+        )
+        .unwrap_or_exit(None::<String>)
+    };
+
+    let second_track = if second_bedgraph {
+        TrackFormat::BedGraph.to_track(&second_track_filepath, false)
+    } else {
+        track_from_path_autodetect(
+            &second_track_filepath,
+            false,
+            TrackFormat::Bed,
+        )
+        .unwrap_or_exit(None::<String>)
+    };
+
+    let map_a = get_chrom_interval_map(&first_track, None).unwrap_or_exit(
+        Some(format_args!("failed to read {}", first_track_filepath)),
+    );
+    let map_b = get_chrom_interval_map(&second_track, None).unwrap_or_exit(
+        Some(format_args!("failed to read {}", second_track_filepath)),
+    );
+
+    let chrom_to_lengths = overlap_lengths(&map_a, &map_b);
+
+    println!("chrom\ta_only\tb_only\tboth");
+    let mut total = (0, 0, 0);
+    for chrom in get_sorted_keys(&chrom_to_lengths) {
+        let (a_only, b_only, both) = chrom_to_lengths[&chrom];
+        println!("{}\t{}\t{}\t{}", chrom, a_only, b_only, both);
+        total.0 += a_only;
+        total.1 += b_only;
+        total.2 += both;
+    }
+    println!("total\t{}\t{}\t{}", total.0, total.1, total.2);
+}
@@ -0,0 +1,164 @@
+use biostats::{
+    top_k_overlap::compute_recovery_curve,
+    track_conversion::{track_from_path_autodetect, TrackFormat},
+    util::{get_chrom_interval_map, get_sorted_keys},
+};
+use clap::{clap_app, Arg};
+use program_flow::{
+    argparse::{
+        extract_boolean_flag, extract_numeric_arg,
+        extract_optional_numeric_arg, extract_str_arg,
+    },
+    eprint_named_vars, OrExit,
+};
+
+const DEFAULT_STEP: f64 = 0.01;
+
+fn main() {
+    let mut app = clap_app!(recovery_curve =>
+        (about: "For each chromosome and each fraction K of the bins, computes \
+        the fraction of the total signal of the second track lying in the top \
+        K bins of the first track. Each output line is of the form \
+        chrom fraction recovered_fraction")
+    );
+    app = app
+        .arg(
+            Arg::with_name("first_track_filepath")
+                .long("first")
+                .short("a")
+                .takes_value(true)
+                .required(true)
+                .help("filepath to the first track."),
+        )
+        .arg(
+            Arg::with_name("second_track_filepath")
+                .long("second")
+                .short("b")
+                .takes_value(true)
+                .required(true)
+                .help("filepath to the second track."),
+        )
+        .arg(
+            Arg::with_name("bin_size")
+                .long("bin")
+                .takes_value(true)
+                .required(true)
+                .long_help(
+                    "Group the base pairs into consecutive bins of size \
+                    bin_size, aligned at index 0.",
+                ),
+        )
+        .arg(Arg::with_name("step").long("step").takes_value(true).help(
+            "The fractions of bins are step, 2 * step, ..., up to 1, \
+            defaults to 0.01",
+        ))
+        .arg(
+            Arg::with_name("first_bedgraph")
+                .long("first-bedgraph")
+                .help(
+                    "A flag to indicate that the first track is in the \
+                    bedgraph format. Without it, the format is detected from \
+                    the first few lines of the track.",
+                ),
+        )
+        .arg(
+            Arg::with_name("second_bedgraph")
+                .long("second-bedgraph")
+                .help(
+                    "A flag to indicate that the second track is in the \
+                    bedgraph format. Without it, the format is detected from \
+                    the first few lines of the track.",
+                ),
+        )
+        .arg(
+            Arg::with_name("quiet")
+                .long("quiet")
+                .short("q")
+                .help("Suppresses the progress messages printed to stderr"),
+        );
+    let matches = app.get_matches();
+    let quiet = extract_boolean_flag(&matches, "quiet");
+    let first_track_filepath =
+        extract_str_arg(&matches, "first_track_filepath");
+    let second_track_filepath =
+        extract_str_arg(&matches, "second_track_filepath");
+    let bin_size: i64 = extract_numeric_arg(&matches, "bin_size")
+        .unwrap_or_exit(Some("failed to parse --bin"));
+    let step: f64 = extract_optional_numeric_arg(&matches, "step")
+        .unwrap_or_exit(Some("failed to parse --step"))
+        .unwrap_or(DEFAULT_STEP);
+    let first_bedgraph = extract_boolean_flag(&matches, "first_bedgraph");
+    let second_bedgraph = extract_boolean_flag(&matches, "second_bedgraph");
+
+    if !quiet {
+        eprint_named_vars!(
+            first_track_filepath,
+            second_track_filepath,
+            bin_size,
+            step,
+            first_bedgraph,
+            second_bedgraph
+        );
+    }
+
+    if bin_size <= 0 {
+        eprintln!("the bin size must be positive, got {}", bin_size);
+        std::process::exit(1);
+    }
+    if step <= 0. || step > 1. {
+        eprintln!("the step must be in (0, 1], got {}", step);
+        std::process::exit(1);
+    }
+
+    let first_track = if first_bedgraph {
+        TrackFormat::BedGraph.to_track(&first_track_filepath, false)
+    } else {
+        track_from_path_autodetect(
+            &first_track_filepath,
+            false,
+            TrackFormat::Bed,
+        )
+        .unwrap_or_exit(None::<String>)
+    };
+
+    let second_track = if second_bedgraph {
+        TrackFormat::BedGraph.to_track(&second_track_filepath, false)
+    } else {
+        track_from_path_autodetect(
+            &second_track_filepath,
+            false,
+            TrackFormat::Bed,
+        )
+        .unwrap_or_exit(None::<String>)
+    };
+
+    let map_a = get_chrom_interval_map(&first_track, None).unwrap_or_exit(
+        Some(format_args!("failed to read {}", first_track_filepath)),
+    );
+    let map_b = get_chrom_interval_map(&second_track, None).unwrap_or_exit(
+        Some(format_args!("failed to read {}", second_track_filepath)),
+    );
+
+    // computed from the step count to avoid accumulating rounding errors
+    let num_steps = (1. / step).round() as usize;
+    let fractions: Vec<f64> =
+        (1..=num_steps).map(|i| (i as f64 * step).min(1.)).collect();
+
+    // there is nothing to recover on the chromosomes missing from the second
+    // track
+    for chrom in get_sorted_keys(&map_b) {
+        let curve = match map_a.get(&chrom) {
+            Some(a) => {
+                compute_recovery_curve(a, &map_b[&chrom], bin_size, &fractions)
+                    .unwrap_or_exit(Some(format_args!(
+                        "failed to compute the recovery curve for {}",
+                        chrom
+                    )))
+            }
+            None => fractions.iter().map(|&f| (f, 0.)).collect(),
+        };
+        for (fraction, recovered_fraction) in curve {
+            println!("{}\t{}\t{}", chrom, fraction, recovered_fraction);
+        }
+    }
+}
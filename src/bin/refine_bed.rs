@@ -1,6 +1,16 @@
 use biostats::{
-    bed_refinery::BedRefinery,
-    util::{extract_chrom_names, get_default_human_chrom_inclusion_set},
+    bed_refinery::{
+        verify_idempotent, BedRefinery, BinAggregation, GenomeCoordinates,
+        NormalizeScope, OutputOrder, PreMergeScore, RefineryOptions,
+        UniqueScoreConflict, ZeroLengthHandling,
+    },
+    concatenated_genome::GenomeOffsets,
+    util::{
+        extract_chrom_names, get_chrom_aliases, get_chrom_size_list,
+        get_default_human_chrom_inclusion_set, get_sorted_keys, ChromAlias,
+        CoordinateSystem, EndConvention, MissingScore, ReadOptions,
+        ValueColumn,
+    },
 };
 use clap::{clap_app, Arg};
 use program_flow::{
@@ -11,6 +21,10 @@ use program_flow::{
     debug_eprint_named_vars, eprint_named_vars, OrExit,
 };
 
+/// The tolerance for the values when checking that refining the output again
+/// leaves it unchanged.
+const IDEMPOTENCY_EPSILON: f64 = 1e-6;
+
 fn main() {
     let mut app = clap_app!(common_refine_bed =>
         (about: "Takes the common refinement of the intervals under each \
@@ -27,7 +41,9 @@ fn main() {
         .arg(
             Arg::with_name("out_path")
                 .takes_value(true)
-                .help("output file path"),
+                .help(
+                    "output file path, compressed with gzip if it ends in .gz",
+                ),
         )
         .arg(
             Arg::with_name("bin_size")
@@ -40,10 +56,93 @@ fn main() {
                     not to bin.",
                 ),
         )
+        .arg(Arg::with_name("sort_by_value").long("sort-by-value").help(
+            "Writes the intervals sorted by value in descending \
+                    order instead of by position. Note that the output can \
+                    then no longer be used as an input to zip_refined_beds.",
+        ))
+        .arg(
+            Arg::with_name("top_n")
+                .long("top-n")
+                .takes_value(true)
+                .requires("sort_by_value")
+                .help(
+                    "Only writes the N intervals with the largest values. \
+                    Can only be used together with --sort-by-value.",
+                ),
+        )
+        .arg(
+            Arg::with_name("aggregate")
+                .long("aggregate")
+                .takes_value(true)
+                .possible_values(&["average", "sum"])
+                .long_help(
+                    "How the values of the base pairs in each bin are \
+                    aggregated when binning. 'average' (the default) divides \
+                    the total by the bin size, while 'sum' reports the total \
+                    of the base pair values in the bin, which is suitable for \
+                    additive count data.",
+                ),
+        )
+        .arg(
+            Arg::with_name("skip_zero_length")
+                .long("skip-zero-length")
+                .long_help(
+                    "Skips the zero-length lines where the end equals the \
+                    start, counting them in a warning. Without this flag, a \
+                    zero-length line is an error. Lines where the end is less \
+                    than the start are always an error.",
+                ),
+        )
+        .arg(Arg::with_name("one_based").long("one-based").help(
+            "The coordinates are 1-based with inclusive ends, e.g., as in \
+            GFF-derived BED files, instead of the 0-based half-open BED \
+            convention",
+        ))
+        .arg(
+            Arg::with_name("ucsc_chrom_names")
+                .long("ucsc-chrom-names")
+                .help(
+                    "Converts Ensembl-style chromosome names into UCSC-style \
+                    names, e.g., 1 into chr1 and MT into chrM",
+                ),
+        )
+        .arg(
+            Arg::with_name("chrom_alias")
+                .long("chrom-alias")
+                .takes_value(true)
+                .conflicts_with("ucsc_chrom_names")
+                .help(
+                    "Path to a file in which each line consists of a \
+                    chromosome name and the name it should be renamed to",
+                ),
+        )
+        .arg(
+            Arg::with_name("report_skipped_chroms")
+                .long("report-skipped-chroms")
+                .help(
+                    "Prints to stderr the chromosomes that are skipped because \
+                    of --filter-chrom or --default-human-chrom, together with \
+                    the number of lines on each of them",
+                ),
+        )
+        .arg(Arg::with_name("inclusive_end").long("inclusive-end").help(
+            "Writes the last base pair of each interval as the end \
+            coordinate for tools expecting inclusive ends, instead of the \
+            exclusive end of the BED format",
+        ))
         .arg(Arg::with_name("binarize_score").long("binarize").long_help(
             "Each line in the original BED files will contribute a \
             unit score for the corresponding interval",
         ))
+        .arg(Arg::with_name("complexity").long("complexity").help(
+            "Prints the number of distinct start positions under each \
+            chromosome and across all the chromosomes",
+        ))
+        .arg(Arg::with_name("center_of_mass").long("center-of-mass").help(
+            "Prints the signal-weighted mean position and its standard \
+            deviation under each chromosome with a non-zero total signal",
+        ))
         .arg(
             Arg::with_name("debug")
                 .long("debug")
@@ -71,6 +170,17 @@ fn main() {
                     file will be ignored.",
                 ),
         )
+        .arg(
+            Arg::with_name("exclude_min_overlap")
+                .long("exclude-min-overlap")
+                .takes_value(true)
+                .requires("exclude")
+                .help(
+                    "A line is only ignored if the fraction of its length \
+                    overlapping with the 'exclude' file exceeds this \
+                    threshold, e.g., 0.5. Defaults to 0, i.e., any overlap.",
+                ),
+        )
         .arg(
             Arg::with_name("filter_chrom")
                 .long("filter-chrom")
@@ -83,6 +193,156 @@ fn main() {
                     trailing whitespaces of each line will be ignored",
                 ),
         )
+        .arg(Arg::with_name("input_log2").long("input-log2").long_help(
+            "The scores in the BED file are log2 values. Each score x will \
+            be transformed into 2^x before the aggregation so that the \
+            values are aggregated in the linear space.",
+        ))
+        .arg(
+            Arg::with_name("value_from_name")
+                .long("value-from-name")
+                .conflicts_with("binarize_score")
+                .help(
+                    "Reads the value of each line from the name column \
+                    instead of the score column",
+                ),
+        )
+        .arg(
+            Arg::with_name("weight_column")
+                .long("weight-column")
+                .takes_value(true)
+                .long_help(
+                    "The 1-based column holding the weight of each line, \
+                    e.g., 7. Each base pair will be given the weighted mean \
+                    of the values of the lines covering it instead of their \
+                    sum. Lines without a weight have a weight of 1.",
+                ),
+        )
+        .arg(
+            Arg::with_name("pre_merge")
+                .long("pre-merge")
+                .takes_value(true)
+                .long_help(
+                    "Before the refinement, merges the intervals that overlap \
+                    or are at most this many base pairs apart into maximal \
+                    intervals, like bedtools merge -d. Two intervals exactly \
+                    this many base pairs apart are merged. Each merged \
+                    interval replaces the lines merged into it, with the \
+                    score given by --pre-merge-score.",
+                ),
+        )
+        .arg(
+            Arg::with_name("pre_merge_score")
+                .long("pre-merge-score")
+                .takes_value(true)
+                .requires("pre_merge")
+                .possible_values(&["sum", "max"])
+                .help(
+                    "How the scores of the merged lines are combined under \
+                    --pre-merge, defaults to sum",
+                ),
+        )
+        .arg(
+            Arg::with_name("running_max")
+                .long("running-max")
+                .takes_value(true)
+                .long_help(
+                    "After binning, replaces the value of each bin by the \
+                    maximum over the bins within this many bins on either \
+                    side, giving the envelope of the signal. The window is \
+                    reset at gaps between bins and at chromosome boundaries.",
+                ),
+        )
+        .arg(
+            Arg::with_name("interpolate_gaps")
+                .long("interpolate-gaps")
+                .requires_all(&["out_bedgraph", "bin_size"])
+                .long_help(
+                    "Fills the bins without data between two covered bins on \
+                    a chromosome by linearly interpolating between the values \
+                    of the covered bins at their midpoints, e.g., to draw a \
+                    sparse bedGraph as a continuous signal. Can only be used \
+                    together with --out-bedgraph and --bin.",
+                ),
+        )
+        .arg(
+            Arg::with_name("max_interpolate_gap")
+                .long("max-interpolate-gap")
+                .takes_value(true)
+                .requires("interpolate_gaps")
+                .help(
+                    "The largest number of consecutive uncovered bins filled \
+                    by --interpolate-gaps. Longer gaps are left empty.",
+                ),
+        )
+        .arg(
+            Arg::with_name("extend")
+                .long("extend")
+                .takes_value(true)
+                .long_help(
+                    "Extends each line by this many base pairs on both sides \
+                    before aggregating, e.g., to turn single-base cut sites \
+                    into windows. The start is clamped at 0, and the end is \
+                    clamped at the chromosome size if --chrom-sizes is \
+                    provided.",
+                ),
+        )
+        .arg(
+            Arg::with_name("shift_extend")
+                .long("shift-extend")
+                .takes_value(true)
+                .long_help(
+                    "Extends each line by this many base pairs in the 3' \
+                    direction, e.g., by the fragment length, before \
+                    aggregating. The end is extended for the plus strand or a \
+                    missing strand, while the start is extended for the minus \
+                    strand. Applied after --extend, with the same clamping.",
+                ),
+        )
+        .arg(
+            Arg::with_name("chrom_sizes")
+                .long("chrom-sizes")
+                .short("g")
+                .takes_value(true)
+                .help(
+                    "Path to a file in which each line consists of the \
+                    chromosome name and the chromosome size, used to clamp \
+                    the intervals extended by --extend or --shift-extend, \
+                    and to order the chromosomes for --concatenate-genome.",
+                ),
+        )
+        .arg(
+            Arg::with_name("concatenate_genome")
+                .long("concatenate-genome")
+                .requires("chrom_sizes")
+                .long_help(
+                    "Concatenates the chromosomes in the order of \
+                    --chrom-sizes into a single chromosome named 'genome', so \
+                    that the bins continue across the chromosome boundaries \
+                    in the global coordinates, as in tools operating on a \
+                    linearized genome.",
+                ),
+        )
+        .arg(
+            Arg::with_name("per_chrom_coordinates")
+                .long("per-chrom-coordinates")
+                .requires("concatenate_genome")
+                .help(
+                    "Splits the output of --concatenate-genome back into the \
+                    per-chromosome coordinates, where a bin spanning a \
+                    chromosome boundary is split into one line per \
+                    chromosome with the value of the whole bin",
+                ),
+        )
+        .arg(
+            Arg::with_name("relog")
+                .long("relog")
+                .requires("input_log2")
+                .help(
+                    "Transforms the output values back into the log2 space. \
+                    Can only be used together with --input-log2.",
+                ),
+        )
         .arg(
             Arg::with_name("max_len")
                 .long("max-len")
@@ -94,6 +354,27 @@ fn main() {
                     number of basepairs.",
                 ),
         )
+        .arg(
+            Arg::with_name("missing_score")
+                .long("missing-score")
+                .takes_value(true)
+                .possible_values(&["zero", "skip"])
+                .long_help(
+                    "How the lines whose value is '.' or empty are read: \
+                    'zero' (the default) reads the value as 0, while 'skip' \
+                    leaves the lines out.",
+                ),
+        )
+        .arg(
+            Arg::with_name("max_bad_lines")
+                .long("max-bad-lines")
+                .takes_value(true)
+                .help(
+                    "Skips up to this many malformed lines, e.g., with a \
+                    non-integer coordinate, instead of failing on the first \
+                    one, and reports how many were skipped",
+                ),
+        )
         .arg(
             Arg::with_name("normalize")
                 .short("n")
@@ -103,6 +384,19 @@ fn main() {
                     basepair by the sum of all values across the basepairs.",
                 ),
         )
+        .arg(
+            Arg::with_name("normalize_scope")
+                .long("normalize-scope")
+                .takes_value(true)
+                .possible_values(&["genome", "chrom"])
+                .requires("normalize")
+                .long_help(
+                    "The scope of the sum used by --normalize. 'genome' (the \
+                    default) divides all the values by the sum across all \
+                    the chromosomes, while 'chrom' divides the values on each \
+                    chromosome by the sum across that chromosome.",
+                ),
+        )
         .arg(
             Arg::with_name("scale")
                 .short("s")
@@ -122,55 +416,316 @@ fn main() {
                     "Multiple lines with the same \
                     (chromosome, start, end, strand) information will only be \
                     counted once. This option can be used only if \
-                    --binarize is set to true",
+                    --binarize is set to true, unless \
+                    --unique-score-conflict is set",
+                ),
+        )
+        .arg(
+            Arg::with_name("unique_score_conflict")
+                .long("unique-score-conflict")
+                .takes_value(true)
+                .requires("unique")
+                .possible_values(&["error", "first", "max", "sum"])
+                .long_help(
+                    "How the lines that are duplicates under --unique but \
+                    carry different scores are handled. error aborts with the \
+                    coordinates of the duplicates, first keeps the score of \
+                    the first line, while max and sum aggregate the scores of \
+                    the duplicates.",
                 ),
         )
         .arg(Arg::with_name("out_bedgraph").long("out-bedgraph").help(
             "Output will be in the Begraph format, i.e., each line \
                     will consist of 4 fields, \
                     (chromosome, start, end_exclusive, value)",
-        ));
+        ))
+        .arg(
+            Arg::with_name("check_precision")
+                .long("check-precision")
+                .help(
+                    "Warns if any aggregated value is so large that adding \
+                    unit counts to it can lose precision, e.g., beyond 2^53",
+                ),
+        )
+        .arg(
+            Arg::with_name("bed12")
+                .long("bed12")
+                .conflicts_with("out_bedgraph")
+                .help(
+                    "Pads the BED output to the 12 columns of BED12, with \
+                    each interval as a single block and the value in the \
+                    score column",
+                ),
+        )
+        .arg(Arg::with_name("no_merge").long("no-merge").help(
+            "By default, consecutive bedGraph output lines with equal \
+            values and contiguous coordinates are merged into a single \
+            line. This flag disables the merging.",
+        ))
+        .arg(
+            Arg::with_name("emit_support_count")
+                .long("emit-support-count")
+                .conflicts_with("out_bedgraph")
+                .help(
+                    "Writes the number of input intervals overlapping with \
+                    each output interval into the name column",
+                ),
+        )
+        .arg(
+            Arg::with_name("head")
+                .long("head")
+                .takes_value(true)
+                .help(
+                    "Only reads the first N lines of the track, e.g., for a \
+                    quick smoke test on a huge file.",
+                ),
+        )
+        .arg(
+            Arg::with_name("verify_idempotent")
+                .long("verify-idempotent")
+                .conflicts_with("inclusive_end")
+                .help(
+                    "After writing the output, refines it again with the same \
+                    bin size and exits with an error if the values change, \
+                    which would indicate a binning or coordinate bug",
+                ),
+        )
+        .arg(
+            Arg::with_name("quiet")
+                .long("quiet")
+                .short("q")
+                .help("Suppresses the progress messages printed to stderr"),
+        );
     let matches = app.get_matches();
+    let quiet = extract_boolean_flag(&matches, "quiet");
     let bin_size: i64 = extract_optional_numeric_arg(&matches, "bin_size")
         .unwrap_or_exit(Some(format_args!("failed to parse --bin")))
         .unwrap_or(0);
 
+    let bin_aggregation = match extract_optional_str_arg(&matches, "aggregate")
+        .as_ref()
+        .map(|s| s.as_str())
+    {
+        Some("sum") => BinAggregation::Sum,
+        _ => BinAggregation::Average,
+    };
     let binarize_score = extract_boolean_flag(&matches, "binarize_score");
+    let complexity = extract_boolean_flag(&matches, "complexity");
+    let center_of_mass = extract_boolean_flag(&matches, "center_of_mass");
     let debug = extract_boolean_flag(&matches, "debug");
     let default_human_chrom =
         extract_boolean_flag(&matches, "default_human_chrom");
 
     let exclude = extract_optional_str_arg(&matches, "exclude");
+    let exclude_min_overlap: f64 =
+        extract_optional_numeric_arg(&matches, "exclude_min_overlap")
+            .unwrap_or_exit(Some("failed to parse --exclude-min-overlap"))
+            .unwrap_or(0.);
     let filter_chrom = extract_optional_str_arg(&matches, "filter_chrom");
     let out_path = extract_str_arg(&matches, "out_path");
     let track_filepath = extract_str_arg(&matches, "track_filepath");
     let max_len: Option<usize> =
         extract_optional_numeric_arg(&matches, "max_len")
             .unwrap_or_exit(Some("failed to parse the --max-len argument"));
+    let max_bad_lines: Option<usize> =
+        extract_optional_numeric_arg(&matches, "max_bad_lines")
+            .unwrap_or_exit(Some("failed to parse --max-bad-lines"));
+    let head: Option<usize> = extract_optional_numeric_arg(&matches, "head")
+        .unwrap_or_exit(Some("failed to parse --head"));
+    let missing_score =
+        match extract_optional_str_arg(&matches, "missing_score")
+            .as_ref()
+            .map(|s| s.as_str())
+        {
+            Some("skip") => MissingScore::Skip,
+            _ => MissingScore::Zero,
+        };
+
+    let sort_by_value = extract_boolean_flag(&matches, "sort_by_value");
+    let top_n: Option<usize> = extract_optional_numeric_arg(&matches, "top_n")
+        .unwrap_or_exit(Some("failed to parse --top-n"));
 
     let normalize = extract_boolean_flag(&matches, "normalize");
+    let normalize_scope =
+        match extract_optional_str_arg(&matches, "normalize_scope")
+            .as_ref()
+            .map(|s| s.as_str())
+        {
+            Some("chrom") => NormalizeScope::Chrom,
+            _ => NormalizeScope::Genome,
+        };
     let scale: Option<f64> = extract_optional_numeric_arg(&matches, "scale")
         .unwrap_or_exit(None::<String>);
 
     let unique = extract_boolean_flag(&matches, "unique");
-    if unique && !binarize_score {
-        eprintln!("--unique can only be set when --binarize is set");
+    let unique_score_conflict =
+        extract_optional_str_arg(&matches, "unique_score_conflict");
+    if unique && !binarize_score && unique_score_conflict.is_none() {
+        eprintln!(
+            "--unique can only be set when --binarize or \
+            --unique-score-conflict is set"
+        );
         std::process::exit(1);
     }
     let out_bedgraph = extract_boolean_flag(&matches, "out_bedgraph");
+    let no_merge = extract_boolean_flag(&matches, "no_merge");
+    let bed12 = extract_boolean_flag(&matches, "bed12");
+    let emit_support_count =
+        extract_boolean_flag(&matches, "emit_support_count");
+    let check_precision = extract_boolean_flag(&matches, "check_precision");
+    let input_log2 = extract_boolean_flag(&matches, "input_log2");
+    let relog = extract_boolean_flag(&matches, "relog");
+    let value_from_name = extract_boolean_flag(&matches, "value_from_name");
+    let weight_column: Option<usize> =
+        extract_optional_numeric_arg(&matches, "weight_column")
+            .unwrap_or_exit(Some("failed to parse --weight-column"));
+    let pre_merge: Option<i64> =
+        extract_optional_numeric_arg(&matches, "pre_merge")
+            .unwrap_or_exit(Some("failed to parse --pre-merge"));
+    let pre_merge_score =
+        match extract_optional_str_arg(&matches, "pre_merge_score")
+            .as_ref()
+            .map(|s| s.as_str())
+        {
+            Some("max") => PreMergeScore::Max,
+            _ => PreMergeScore::Sum,
+        };
+    let interpolate_gaps = extract_boolean_flag(&matches, "interpolate_gaps");
+    let max_interpolate_gap: Option<usize> =
+        extract_optional_numeric_arg(&matches, "max_interpolate_gap")
+            .unwrap_or_exit(Some("failed to parse --max-interpolate-gap"));
+    let running_max: Option<usize> =
+        extract_optional_numeric_arg(&matches, "running_max")
+            .unwrap_or_exit(Some("failed to parse --running-max"));
+    let one_based = extract_boolean_flag(&matches, "one_based");
+    let ucsc_chrom_names = extract_boolean_flag(&matches, "ucsc_chrom_names");
+    let report_skipped_chroms =
+        extract_boolean_flag(&matches, "report_skipped_chroms");
+    let inclusive_end = extract_boolean_flag(&matches, "inclusive_end");
+    let verify_idempotent_output =
+        extract_boolean_flag(&matches, "verify_idempotent");
+    if verify_idempotent_output && out_path.ends_with(".gz") {
+        eprintln!("--verify-idempotent cannot read a gzipped output");
+        std::process::exit(1);
+    }
+    let zero_length_handling =
+        if extract_boolean_flag(&matches, "skip_zero_length") {
+            ZeroLengthHandling::Skip
+        } else {
+            ZeroLengthHandling::Error
+        };
+    let chrom_alias_path = extract_optional_str_arg(&matches, "chrom_alias");
+    let extend: Option<i64> = extract_optional_numeric_arg(&matches, "extend")
+        .unwrap_or_exit(Some("failed to parse --extend"));
+    let shift_extend: Option<i64> =
+        extract_optional_numeric_arg(&matches, "shift_extend")
+            .unwrap_or_exit(Some("failed to parse --shift-extend"));
+    let chrom_sizes_path = extract_optional_str_arg(&matches, "chrom_sizes");
+    let concatenate_genome =
+        extract_boolean_flag(&matches, "concatenate_genome");
+    let per_chrom_coordinates =
+        extract_boolean_flag(&matches, "per_chrom_coordinates");
+    if chrom_sizes_path.is_some()
+        && extend.is_none()
+        && shift_extend.is_none()
+        && !concatenate_genome
+    {
+        eprintln!(
+            "--chrom-sizes requires --extend, --shift-extend or \
+            --concatenate-genome"
+        );
+        std::process::exit(1);
+    }
 
-    eprint_named_vars!(
-        bin_size,
-        binarize_score,
-        debug,
-        default_human_chrom,
-        out_path,
-        track_filepath,
-        normalize,
-        unique,
-        out_bedgraph
-    );
-    debug_eprint_named_vars!(exclude, filter_chrom, max_len, scale);
+    if !quiet {
+        eprint_named_vars!(
+            bin_size,
+            binarize_score,
+            complexity,
+            center_of_mass,
+            debug,
+            default_human_chrom,
+            out_path,
+            track_filepath,
+            normalize,
+            unique,
+            out_bedgraph,
+            no_merge,
+            bed12,
+            emit_support_count,
+            check_precision,
+            input_log2,
+            relog,
+            value_from_name,
+            one_based,
+            ucsc_chrom_names,
+            inclusive_end,
+            sort_by_value,
+            concatenate_genome,
+            per_chrom_coordinates,
+            interpolate_gaps,
+            verify_idempotent_output
+        );
+        debug_eprint_named_vars!(
+            bin_aggregation,
+            normalize_scope,
+            exclude,
+            exclude_min_overlap,
+            filter_chrom,
+            max_len,
+            max_bad_lines,
+            head,
+            missing_score,
+            scale,
+            top_n,
+            chrom_alias_path,
+            weight_column,
+            zero_length_handling,
+            pre_merge,
+            pre_merge_score,
+            running_max,
+            max_interpolate_gap,
+            unique_score_conflict,
+            extend,
+            shift_extend,
+            chrom_sizes_path
+        );
+    }
+
+    let unique_score_conflict =
+        match unique_score_conflict.as_ref().map(|s| s.as_str()) {
+            Some("error") => UniqueScoreConflict::Error,
+            Some("max") => UniqueScoreConflict::Max,
+            Some("sum") => UniqueScoreConflict::Sum,
+            _ => UniqueScoreConflict::First,
+        };
+
+    let chrom_size_list = chrom_sizes_path.map(|path| {
+        get_chrom_size_list(&path).unwrap_or_exit(Some(format_args!(
+            "failed to read the chromosome sizes from {}",
+            path
+        )))
+    });
+    let genome_offsets = if concatenate_genome {
+        chrom_size_list.clone().map(|chrom_sizes| {
+            GenomeOffsets::new(chrom_sizes)
+                .unwrap_or_exit(Some("invalid chromosome sizes"))
+        })
+    } else {
+        None
+    };
+    let chrom_sizes =
+        chrom_size_list.map(|chrom_sizes| chrom_sizes.into_iter().collect());
+
+    let chrom_alias = if let Some(path) = chrom_alias_path {
+        ChromAlias::Map(get_chrom_aliases(&path).unwrap_or_exit(Some(
+            format_args!("failed to read the chromosome aliases from {}", path),
+        )))
+    } else if ucsc_chrom_names {
+        ChromAlias::Ucsc
+    } else {
+        ChromAlias::Original
+    };
 
     let filter_chroms = if default_human_chrom {
         Some(get_default_human_chrom_inclusion_set())
@@ -195,24 +750,162 @@ fn main() {
         }
     };
 
-    let refinery = BedRefinery::<f64>::new(
-        &track_filepath,
-        unique,
-        max_len,
-        binarize_score,
-        filter_chroms,
-        exclude,
-        debug,
-    );
+    let refinery =
+        BedRefinery::<f64>::with_options(&track_filepath, RefineryOptions {
+            unique,
+            max_len,
+            binarize_score,
+            filter_chroms,
+            exclude_track_filepath: exclude,
+            debug,
+            read_options: ReadOptions {
+                input_log2,
+                value_column: if value_from_name {
+                    ValueColumn::Name
+                } else {
+                    ValueColumn::Score
+                },
+                coordinate_system: if one_based {
+                    CoordinateSystem::Closed1
+                } else {
+                    CoordinateSystem::HalfOpen0
+                },
+                chrom_alias,
+                max_lines: head,
+                missing_score,
+                max_bad_lines,
+            },
+            relog_output: relog,
+            bin_aggregation,
+            emit_support_count,
+            check_precision,
+            output_order: if sort_by_value {
+                OutputOrder::Value {
+                    top_n,
+                }
+            } else {
+                OutputOrder::Position
+            },
+            merge_bedgraph_runs: !no_merge,
+            normalize_scope,
+            min_exclude_overlap_fraction: exclude_min_overlap,
+            end_convention: if inclusive_end {
+                EndConvention::Inclusive
+            } else {
+                EndConvention::Exclusive
+            },
+            weight_column,
+            zero_length_handling,
+            pre_merge_gap: pre_merge,
+            pre_merge_score,
+            running_max_window: running_max,
+            interpolate_gaps,
+            max_interpolate_gap,
+            unique_score_conflict,
+            extend_bp: extend,
+            strand_extend_bp: shift_extend,
+            chrom_sizes,
+            concatenate_genome: genome_offsets,
+            genome_coordinates: if per_chrom_coordinates {
+                GenomeCoordinates::PerChrom
+            } else {
+                GenomeCoordinates::Global
+            },
+            bed12,
+        })
+        .unwrap_or_exit(Some("failed to read the track"));
 
     refinery
         .write_refined_bed(&out_path, bin_size, normalize, scale, out_bedgraph)
         .unwrap_or_exit(Some("failed to bin track"));
 
+    if verify_idempotent_output {
+        verify_idempotent(&out_path, bin_size, IDEMPOTENCY_EPSILON)
+            .unwrap_or_exit(Some("the refined output is not idempotent"));
+        if !quiet {
+            eprintln!("=> refining the output again leaves it unchanged");
+        }
+    }
+
     match refinery.stats().num_duplicate_lines {
         Some(num_duplicates) => {
             println!("number of duplicate lines: {}", num_duplicates)
         }
         None => {}
     }
+
+    let num_zero_length_lines = refinery.stats().num_zero_length_lines;
+    if num_zero_length_lines > 0 {
+        eprintln!(
+            "warning: skipped {} zero-length lines, rerun with --debug to list \
+            them",
+            num_zero_length_lines
+        );
+    }
+
+    let num_bad_lines = refinery.stats().num_bad_lines;
+    if num_bad_lines > 0 {
+        eprintln!("warning: skipped {} malformed lines", num_bad_lines);
+    }
+    let num_invalid_strand_lines = refinery.stats().num_invalid_strand_lines;
+    if num_invalid_strand_lines > 0 {
+        eprintln!(
+            "warning: {} lines have an unexpected strand and were read as \
+            having an unknown strand",
+            num_invalid_strand_lines
+        );
+    }
+    let num_missing_score_lines = refinery.stats().num_missing_score_lines;
+    if num_missing_score_lines > 0 && !quiet {
+        eprintln!(
+            "=> {} lines with a missing value were {}",
+            num_missing_score_lines,
+            match missing_score {
+                MissingScore::Zero => "read as zeros",
+                MissingScore::Skip => "skipped",
+            }
+        );
+    }
+
+    if let Some(num_imprecise_intervals) =
+        refinery.stats().num_imprecise_intervals
+    {
+        if num_imprecise_intervals > 0 {
+            eprintln!(
+                "warning: {} aggregated intervals have values large enough \
+                to lose precision, rerun with --debug to list them",
+                num_imprecise_intervals
+            );
+        }
+    }
+
+    if report_skipped_chroms {
+        let skipped_chrom_line_counts =
+            &refinery.stats().skipped_chrom_line_counts;
+        eprintln!(
+            "{} chromosomes skipped by the chromosome filter (chrom, lines):",
+            skipped_chrom_line_counts.len()
+        );
+        for (chrom, num_lines) in skipped_chrom_line_counts.iter() {
+            eprintln!("{}, {}", chrom, num_lines);
+        }
+    }
+
+    if complexity {
+        let unique_position_counts = refinery.unique_position_count();
+        println!("number of distinct start positions:");
+        for chrom in get_sorted_keys(unique_position_counts) {
+            println!("{}, {}", chrom, unique_position_counts[&chrom]);
+        }
+        println!("overall, {}", unique_position_counts.values().sum::<i64>());
+    }
+
+    if center_of_mass {
+        let chrom_to_center_of_mass = refinery.center_of_mass();
+        println!("center of mass (chrom, mean position, std position):");
+        for chrom in get_sorted_keys(&chrom_to_center_of_mass) {
+            let (mean, std) = chrom_to_center_of_mass[&chrom];
+            println!("{}, {}, {}", chrom, mean, std);
+        }
+    }
 }
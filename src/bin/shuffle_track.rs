@@ -0,0 +1,149 @@
+use biofile::bed::{BedDataLine, BedWriter};
+use biostats::{
+    shuffle::shuffle_track,
+    track_conversion::{track_from_path_autodetect, TrackFormat},
+    util::{
+        get_chrom_intervals, get_chrom_sizes, get_excluded_interval_maps,
+        get_sorted_keys, ReadOptions,
+    },
+};
+use clap::{clap_app, Arg};
+use math::interval::traits::Interval;
+use program_flow::{
+    argparse::{
+        extract_boolean_flag, extract_optional_numeric_arg,
+        extract_optional_str_arg, extract_str_arg,
+    },
+    debug_eprint_named_vars, eprint_named_vars, OrExit,
+};
+
+fn main() {
+    let mut app = clap_app!(shuffle_track =>
+        (about: "Randomly repositions the intervals of a track within their \
+        chromosomes while preserving the interval lengths and values, which \
+        can be used to generate an empirical null model.")
+    );
+    app = app
+        .arg(
+            Arg::with_name("track_filepath")
+                .takes_value(true)
+                .required(true)
+                .help("filepath to the track"),
+        )
+        .arg(
+            Arg::with_name("out_path")
+                .takes_value(true)
+                .required(true)
+                .help("output BED file path"),
+        )
+        .arg(
+            Arg::with_name("chrom_sizes")
+                .long("chrom-sizes")
+                .short("g")
+                .takes_value(true)
+                .required(true)
+                .help(
+                    "Path to a file in which each line consists of the \
+                    chromosome name and the chromosome size",
+                ),
+        )
+        .arg(Arg::with_name("seed").long("seed").takes_value(true).help(
+            "Seed for the random number generator, defaults to 0. \
+                    The same seed will produce the same shuffled track.",
+        ))
+        .arg(Arg::with_name("binarize_score").long("binarize").help(
+            "Each line in the original BED files will contribute a \
+            unit score for the corresponding interval",
+        ))
+        .arg(Arg::with_name("bedgraph").long("bedgraph").help(
+            "A flag to indicate that the track is in the bedgraph format. \
+            Without it, the format is detected from the first few lines of \
+            the track.",
+        ))
+        .arg(
+            Arg::with_name("exclude")
+                .long("exclude")
+                .short("v")
+                .takes_value(true)
+                .help(
+                    "Path to a BED-like file where only the chromosome, start \
+                    and end fields are required. Lines overlapping with these \
+                    regions will be ignored, and the shuffled intervals will \
+                    not be placed in these regions.",
+                ),
+        )
+        .arg(
+            Arg::with_name("quiet")
+                .long("quiet")
+                .short("q")
+                .help("Suppresses the progress messages printed to stderr"),
+        );
+    let matches = app.get_matches();
+    let quiet = extract_boolean_flag(&matches, "quiet");
+    let track_filepath = extract_str_arg(&matches, "track_filepath");
+    let out_path = extract_str_arg(&matches, "out_path");
+    let chrom_sizes_path = extract_str_arg(&matches, "chrom_sizes");
+    let seed: u64 = extract_optional_numeric_arg(&matches, "seed")
+        .unwrap_or_exit(Some("failed to parse --seed"))
+        .unwrap_or(0);
+    let binarize_score = extract_boolean_flag(&matches, "binarize_score");
+    let bedgraph = extract_boolean_flag(&matches, "bedgraph");
+    let exclude = extract_optional_str_arg(&matches, "exclude");
+
+    if !quiet {
+        eprint_named_vars!(
+            track_filepath,
+            out_path,
+            chrom_sizes_path,
+            seed,
+            binarize_score,
+            bedgraph
+        );
+        debug_eprint_named_vars!(exclude);
+    }
+
+    let track = if bedgraph {
+        TrackFormat::BedGraph.to_track(&track_filepath, binarize_score)
+    } else {
+        track_from_path_autodetect(
+            &track_filepath,
+            binarize_score,
+            TrackFormat::Bed,
+        )
+        .unwrap_or_exit(None::<String>)
+    };
+    let chrom_sizes = get_chrom_sizes(&chrom_sizes_path).unwrap_or_exit(Some(
+        format_args!("failed to read the chromosome sizes"),
+    ));
+    let exclude = get_excluded_interval_maps(exclude);
+
+    let chrom_intervals =
+        get_chrom_intervals(&track, exclude.as_ref(), &ReadOptions::default())
+            .unwrap_or_exit(None::<String>);
+
+    let shuffled =
+        shuffle_track(&chrom_intervals, &chrom_sizes, exclude.as_ref(), seed)
+            .unwrap_or_exit(Some("failed to shuffle the track"));
+
+    let mut writer = BedWriter::new(&out_path)
+        .unwrap_or_exit(Some(format_args!("failed to create {}", out_path)));
+    for chrom in get_sorted_keys(&shuffled) {
+        let mut bed_line = BedDataLine {
+            chrom: chrom.clone(),
+            start: 0,
+            end: 0,
+            name: None,
+            score: None,
+            strand: None,
+        };
+        for (interval, value) in shuffled[&chrom].iter() {
+            bed_line.start = interval.get_start();
+            // the end coordinate is exclusive in the BED format
+            bed_line.end = interval.get_end() + 1;
+            bed_line.score = Some(*value);
+            writer
+                .write_bed_line(&bed_line)
+                .unwrap_or_exit(Some("failed to write to the output file"));
+        }
+    }
+}
@@ -0,0 +1,77 @@
+use biostats::track_zipper::{split_concatenated_track, CoordinateOrder};
+use clap::{clap_app, Arg};
+use program_flow::{
+    argparse::{extract_boolean_flag, extract_numeric_arg, extract_str_arg},
+    eprint_named_vars, OrExit,
+};
+
+fn main() {
+    let mut app = clap_app!(split_concatenated_track =>
+        (about: "Splits a multi-value file produced by zip_refined_beds or the \
+        concatenated tracks output back into one BED file per value column, \
+        named track_0.bed, track_1.bed, ... in the output directory, where \
+        each line is of the form chrom start end value")
+    );
+    app = app
+        .arg(
+            Arg::with_name("in_path")
+                .takes_value(true)
+                .required(true)
+                .help("path to the multi-value file"),
+        )
+        .arg(
+            Arg::with_name("out_dir")
+                .takes_value(true)
+                .required(true)
+                .help(
+                    "directory for the per-track BED files, created if it \
+                    does not exist",
+                ),
+        )
+        .arg(
+            Arg::with_name("num_values")
+                .long("num-values")
+                .short("n")
+                .takes_value(true)
+                .required(true)
+                .help("The number of values on each line"),
+        )
+        .arg(Arg::with_name("chrom_first").long("chrom-first").help(
+            "The lines start with chrom start end as in the output of \
+            zip_refined_beds, instead of start end chrom as in the \
+            concatenated tracks output",
+        ))
+        .arg(
+            Arg::with_name("quiet")
+                .long("quiet")
+                .short("q")
+                .help("Suppresses the progress messages printed to stderr"),
+        );
+    let matches = app.get_matches();
+    let quiet = extract_boolean_flag(&matches, "quiet");
+    let in_path = extract_str_arg(&matches, "in_path");
+    let out_dir = extract_str_arg(&matches, "out_dir");
+    let num_values: usize = extract_numeric_arg(&matches, "num_values")
+        .unwrap_or_exit(Some("failed to parse --num-values"));
+    let chrom_first = extract_boolean_flag(&matches, "chrom_first");
+    let coordinate_order = if chrom_first {
+        CoordinateOrder::ChromStartEnd
+    } else {
+        CoordinateOrder::StartEndChrom
+    };
+
+    if !quiet {
+        eprint_named_vars!(in_path, out_dir, num_values, chrom_first);
+    }
+
+    let out_paths = split_concatenated_track(
+        &in_path,
+        &out_dir,
+        num_values,
+        coordinate_order,
+    )
+    .unwrap_or_exit(Some(format_args!("failed to split {}", in_path)));
+    for path in out_paths.iter() {
+        println!("=> wrote {}", path.display());
+    }
+}
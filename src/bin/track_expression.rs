@@ -0,0 +1,186 @@
+use biofile::{bed::Bed, bedgraph::BedGraphDataLine};
+use biostats::{
+    track_expression::{compute_track_expression, MissingValue},
+    util::{
+        create_maybe_gzip, get_default_human_chrom_inclusion_set,
+        get_track_paths, write_bedgraph_line,
+    },
+};
+use clap::{clap_app, Arg};
+use math::interval::traits::Interval;
+use program_flow::{
+    argparse::{
+        extract_boolean_flag, extract_numeric_arg, extract_optional_str_arg,
+        extract_optional_str_vec_arg, extract_str_arg,
+    },
+    debug_eprint_named_vars, eprint_named_vars, OrExit,
+};
+
+fn main() {
+    let mut app = clap_app!(track_expression =>
+        (about: "Evaluates an arithmetic expression over the binned values of \
+        multiple tracks and writes the result of each bin as a bedGraph.")
+    );
+    app = app
+        .arg(
+            Arg::with_name("track_filepaths")
+                .takes_value(true)
+                .multiple(true)
+                .required_unless("tracks_filepath")
+                .help(
+                    "paths to the tracks in BED format, referred to as t0, \
+                    t1, ... in the expression in this order",
+                ),
+        )
+        .arg(
+            Arg::with_name("tracks_filepath")
+                .long("tracks")
+                .short("f")
+                .takes_value(true)
+                .conflicts_with("track_filepaths")
+                .help(
+                    "Path to a file where each line is the path to a track \
+                    in BED format, as an alternative to listing the tracks \
+                    as arguments",
+                ),
+        )
+        .arg(
+            Arg::with_name("expr")
+                .long("expr")
+                .short("e")
+                .takes_value(true)
+                .required(true)
+                .long_help(
+                    "The expression to evaluate in each bin, where t0, t1, \
+                    ... are the average values of the tracks in the bin, \
+                    e.g., '(t0 - t1) / (t0 + t1 + 1)'. Supports +, -, *, /, \
+                    parentheses and numeric constants.",
+                ),
+        )
+        .arg(
+            Arg::with_name("out_path")
+                .long("out-path")
+                .short("o")
+                .takes_value(true)
+                .required(true)
+                .help(
+                    "output bedGraph file path, compressed with gzip if it \
+                    ends in .gz.",
+                ),
+        )
+        .arg(
+            Arg::with_name("bin_size")
+                .long("bin")
+                .takes_value(true)
+                .required(true)
+                .help(
+                    "Group the base pairs into consecutive bins of size \
+                    bin_size, aligned at index 0.",
+                ),
+        )
+        .arg(
+            Arg::with_name("missing")
+                .long("missing")
+                .takes_value(true)
+                .possible_values(&["zero", "skip"])
+                .long_help(
+                    "How to handle the bins where some of the tracks have no \
+                    data: 'zero' uses 0 as the value of those tracks, while \
+                    'skip' leaves the bin out of the output. Defaults to \
+                    'zero'.",
+                ),
+        )
+        .arg(Arg::with_name("binarize_score").long("binarize").help(
+            "Each line in the original BED files will contribute a \
+            unit score for the corresponding interval",
+        ))
+        .arg(
+            Arg::with_name("default_human_chrom")
+                .long("default-human-chrom")
+                .short("d")
+                .help(
+                    "Only process chromosomes \
+                    chr1, chr2, ... chr22, chrX, chrY.",
+                ),
+        )
+        .arg(
+            Arg::with_name("quiet")
+                .long("quiet")
+                .short("q")
+                .help("Suppresses the progress messages printed to stderr"),
+        );
+    let matches = app.get_matches();
+    let quiet = extract_boolean_flag(&matches, "quiet");
+    let track_filepaths =
+        extract_optional_str_vec_arg(&matches, "track_filepaths");
+    let tracks_filepath = extract_optional_str_arg(&matches, "tracks_filepath");
+    let expr = extract_str_arg(&matches, "expr");
+    let out_path = extract_str_arg(&matches, "out_path");
+    let bin_size: i64 = extract_numeric_arg(&matches, "bin_size")
+        .unwrap_or_exit(Some("failed to parse --bin"));
+    let missing = extract_optional_str_arg(&matches, "missing");
+    let binarize_score = extract_boolean_flag(&matches, "binarize_score");
+    let default_human_chrom =
+        extract_boolean_flag(&matches, "default_human_chrom");
+
+    if !quiet {
+        eprint_named_vars!(expr, out_path, bin_size, binarize_score);
+        debug_eprint_named_vars!(
+            track_filepaths,
+            tracks_filepath,
+            missing,
+            default_human_chrom
+        );
+    }
+
+    if bin_size <= 0 {
+        eprintln!("--bin must be positive, received {}", bin_size);
+        std::process::exit(1);
+    }
+
+    let missing_value = match missing.as_ref().map(|s| s.as_str()) {
+        Some("skip") => MissingValue::Skip,
+        _ => MissingValue::Zero,
+    };
+
+    let track_filepaths = match tracks_filepath {
+        Some(path) => get_track_paths(&path).unwrap_or_exit(Some(
+            format_args!("failed to read the track paths from {}", path),
+        )),
+        None => track_filepaths.unwrap_or_else(Vec::new),
+    };
+    let beds: Vec<Bed> = track_filepaths
+        .iter()
+        .map(|path| Bed::new(path, binarize_score))
+        .collect();
+
+    let target_chroms = if default_human_chrom {
+        Some(get_default_human_chrom_inclusion_set())
+    } else {
+        None
+    };
+
+    let lines = compute_track_expression(
+        beds,
+        &expr,
+        bin_size,
+        missing_value,
+        target_chroms.as_ref(),
+    )
+    .unwrap_or_exit(Some("failed to evaluate the expression"));
+
+    let mut writer = create_maybe_gzip(&out_path, false)
+        .unwrap_or_exit(Some(format_args!("failed to create {}", out_path)));
+    for (chrom, interval, value) in lines {
+        write_bedgraph_line(&mut writer, &BedGraphDataLine {
+            chrom,
+            start: interval.get_start(),
+            end_exclusive: interval.get_end() + 1,
+            value,
+        })
+        .unwrap_or_exit(Some(format_args!("failed to write to {}", out_path)));
+    }
+    writer
+        .finish()
+        .unwrap_or_exit(Some(format_args!("failed to write to {}", out_path)));
+}
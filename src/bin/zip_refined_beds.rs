@@ -1,8 +1,12 @@
-use biostats::{refined_bed_zipper::RefinedBedZipper, util::get_track_paths};
+use biostats::{
+    refined_bed_zipper::RefinedBedZipper,
+    util::{get_track_paths, EndConvention},
+};
 use clap::{clap_app, Arg};
 use program_flow::{
     argparse::{
-        extract_numeric_arg, extract_optional_numeric_arg, extract_str_arg,
+        extract_boolean_flag, extract_numeric_arg,
+        extract_optional_numeric_arg, extract_str_arg,
     },
     eprint_named_vars, OrExit,
 };
@@ -28,7 +32,9 @@ fn main() {
             Arg::with_name("out_path")
                 .takes_value(true)
                 .required(true)
-                .help("output file path"),
+                .help(
+                    "output file path, compressed with gzip if it ends in .gz",
+                ),
         )
         .arg(
             Arg::with_name("interval_length")
@@ -58,8 +64,29 @@ fn main() {
                     interval, this default value will be used as the value \
                     for that interval for the BED file.",
                 ),
+        )
+        .arg(Arg::with_name("append").long("append").help(
+            "Appends to the output file instead of overwriting it, e.g., to \
+            combine chromosome shards. The shards must be appended in sorted \
+            order of the chromosomes.",
+        ))
+        .arg(
+            Arg::with_name("inclusive_end_input")
+                .long("inclusive-end-input")
+                .help(
+                    "Reads the end coordinates of the input files as the last \
+                    base pair of each interval instead of one past it. The \
+                    output always uses exclusive ends.",
+                ),
+        )
+        .arg(
+            Arg::with_name("quiet")
+                .long("quiet")
+                .short("q")
+                .help("Suppresses the progress messages printed to stderr"),
         );
     let matches = app.get_matches();
+    let quiet = extract_boolean_flag(&matches, "quiet");
     let refined_beds_path_file =
         extract_str_arg(&matches, "refined_beds_path_file");
 
@@ -77,25 +104,39 @@ fn main() {
             .unwrap_or_exit(Some(format!("failed to parse --default-value")))
             .unwrap_or(0.);
 
+    let append = extract_boolean_flag(&matches, "append");
+    let inclusive_end_input =
+        extract_boolean_flag(&matches, "inclusive_end_input");
+
     let refined_bed_paths: Vec<String> =
         get_track_paths(&refined_beds_path_file)
             .unwrap_or_exit(Some("failed to get the list of BED file paths"));
 
-    eprint_named_vars!(
-        refined_beds_path_file,
-        out_path,
-        interval_length,
-        alignment,
-        default_value
-    );
+    if !quiet {
+        eprint_named_vars!(
+            refined_beds_path_file,
+            out_path,
+            interval_length,
+            alignment,
+            default_value,
+            append,
+            inclusive_end_input
+        );
+    }
 
+    let input_end_convention = if inclusive_end_input {
+        EndConvention::Inclusive
+    } else {
+        EndConvention::Exclusive
+    };
     let zipper = RefinedBedZipper::new(
         refined_bed_paths,
         alignment,
         interval_length,
         default_value,
+        input_end_convention,
     );
     zipper
-        .write_to_file(&out_path)
+        .write_to_file(&out_path, append)
         .unwrap_or_exit(Some(format!("failed to write to {}", out_path)));
 }
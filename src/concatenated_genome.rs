@@ -0,0 +1,164 @@
+use crate::error::Error;
+use biofile::bed::Chrom;
+use math::interval::{traits::Interval, I64Interval};
+use std::collections::HashMap;
+
+/// The chromosome name under which the intervals of a concatenated genome are
+/// reported in the global coordinates.
+pub const GENOME_CHROM: &str = "genome";
+
+/// The chromosomes laid end to end in a single global coordinate space, in
+/// the order of the chromosome sizes, so that bins can continue across the
+/// chromosome boundaries as in tools operating on a linearized genome. The
+/// global coordinate of a position is the position plus the total size of
+/// the chromosomes before it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GenomeOffsets {
+    // (chrom, offset, size) in the global order
+    chroms: Vec<(Chrom, i64, i64)>,
+    chrom_to_index: HashMap<Chrom, usize>,
+}
+
+impl GenomeOffsets {
+    /// Returns an error if a chromosome appears more than once or has a
+    /// negative size.
+    pub fn new(chrom_sizes: Vec<(Chrom, i64)>) -> Result<GenomeOffsets, Error> {
+        let mut chroms = Vec::with_capacity(chrom_sizes.len());
+        let mut chrom_to_index = HashMap::new();
+        let mut offset = 0i64;
+        for (chrom, size) in chrom_sizes {
+            if size < 0 {
+                return Err(Error::Validation(format!(
+                    "the size of {} cannot be negative, received {}",
+                    chrom, size
+                )));
+            }
+            if chrom_to_index.insert(chrom.clone(), chroms.len()).is_some() {
+                return Err(Error::Validation(format!(
+                    "duplicate chromosome size for {}",
+                    chrom
+                )));
+            }
+            chroms.push((chrom, offset, size));
+            offset += size;
+        }
+        Ok(GenomeOffsets {
+            chroms,
+            chrom_to_index,
+        })
+    }
+
+    /// The global coordinate of the first base pair of `chrom`.
+    pub fn offset(&self, chrom: &str) -> Option<i64> {
+        self.chrom_to_index.get(chrom).map(|&i| self.chroms[i].1)
+    }
+
+    /// The total size of the chromosomes.
+    pub fn genome_size(&self) -> i64 {
+        self.chroms
+            .last()
+            .map_or(0, |(_, offset, size)| offset + size)
+    }
+
+    /// Maps the `interval` on `chrom` into the global coordinates. Returns an
+    /// error if the chromosome has no size or if the interval extends beyond
+    /// the chromosome, since it would then spill into the next chromosome.
+    pub fn to_global(
+        &self,
+        chrom: &str,
+        interval: I64Interval,
+    ) -> Result<I64Interval, Error> {
+        let &(_, offset, size) = self
+            .chrom_to_index
+            .get(chrom)
+            .map(|&i| &self.chroms[i])
+            .ok_or_else(|| {
+                Error::Validation(format!("{} has no chromosome size", chrom))
+            })?;
+        if interval.get_start() < 0 || interval.get_end() >= size {
+            return Err(Error::Validation(format!(
+                "the interval [{}, {}) extends beyond the size {} of {}",
+                interval.get_start(),
+                interval.get_end() + 1,
+                size,
+                chrom
+            )));
+        }
+        Ok(I64Interval::new(
+            interval.get_start() + offset,
+            interval.get_end() + offset,
+        ))
+    }
+
+    /// Splits the global `interval` at the chromosome boundaries into the
+    /// per-chromosome intervals, in the global order. The parts of the
+    /// interval outside the genome are dropped.
+    pub fn split(&self, interval: I64Interval) -> Vec<(Chrom, I64Interval)> {
+        self.chroms
+            .iter()
+            .filter_map(|(chrom, offset, size)| {
+                let start = interval.get_start().max(*offset);
+                let end = interval.get_end().min(offset + size - 1);
+                if start <= end {
+                    Some((
+                        chrom.clone(),
+                        I64Interval::new(start - offset, end - offset),
+                    ))
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::concatenated_genome::GenomeOffsets;
+    use math::interval::{traits::Interval, I64Interval};
+
+    #[test]
+    fn test_genome_offsets() {
+        let offsets = GenomeOffsets::new(vec![
+            ("chr1".to_string(), 100),
+            ("chr2".to_string(), 50),
+            ("chr3".to_string(), 20),
+        ])
+        .unwrap();
+        assert_eq!(offsets.offset("chr1"), Some(0));
+        assert_eq!(offsets.offset("chr2"), Some(100));
+        assert_eq!(offsets.offset("chr3"), Some(150));
+        assert_eq!(offsets.offset("chrX"), None);
+        assert_eq!(offsets.genome_size(), 170);
+
+        // the chr2 interval [10, 20) comes after the 100 bp of chr1
+        assert_eq!(
+            offsets.to_global("chr2", I64Interval::new(10, 19)).unwrap(),
+            I64Interval::new(110, 119)
+        );
+        assert_eq!(
+            offsets.to_global("chr3", I64Interval::new(0, 19)).unwrap(),
+            I64Interval::new(150, 169)
+        );
+        assert!(offsets.to_global("chr2", I64Interval::new(40, 50)).is_err());
+        assert!(offsets.to_global("chrX", I64Interval::new(0, 9)).is_err());
+
+        let split: Vec<(String, i64, i64)> = offsets
+            .split(I64Interval::new(90, 179))
+            .into_iter()
+            .map(|(chrom, i)| (chrom, i.get_start(), i.get_end()))
+            .collect();
+        assert_eq!(split, vec![
+            ("chr1".to_string(), 90, 99),
+            ("chr2".to_string(), 0, 49),
+            ("chr3".to_string(), 0, 19),
+        ]);
+
+        assert!(GenomeOffsets::new(vec![
+            ("chr1".to_string(), 100),
+            ("chr1".to_string(), 100),
+        ])
+        .is_err());
+        assert!(GenomeOffsets::new(vec![("chr1".to_string(), -1)]).is_err());
+    }
+}
@@ -0,0 +1,74 @@
+use std::{fmt, io};
+
+/// The error type of the public APIs of this crate.
+///
+/// An `Error` converts into a `String` for the callers that only print the
+/// message.
+#[derive(Debug)]
+pub enum Error {
+    /// Failure to read or write a file.
+    Io(io::Error),
+
+    /// Failure reported by the `biofile` crate.
+    Biofile(biofile::error::Error),
+
+    /// A malformed field or line in an input file.
+    Parse(String),
+
+    /// An invalid argument or an inconsistent combination of arguments.
+    Validation(String),
+
+    /// An input that is required to be sorted is not.
+    UnsortedInput(String),
+
+    /// Any other failure.
+    Generic(String),
+}
+
+impl Error {
+    /// An `Io` error whose message starts with the `context`, e.g., the file
+    /// being read.
+    pub fn io(context: String, why: io::Error) -> Error {
+        Error::Io(io::Error::new(why.kind(), format!("{}: {}", context, why)))
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(why) => write!(f, "I/O error: {}", why),
+            Error::Biofile(why) => write!(f, "{:?}", why),
+            Error::Parse(msg) => write!(f, "parse error: {}", msg),
+            Error::Validation(msg) => write!(f, "invalid input: {}", msg),
+            Error::UnsortedInput(msg) => write!(f, "unsorted input: {}", msg),
+            Error::Generic(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(why) => Some(why),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(why: io::Error) -> Error {
+        Error::Io(why)
+    }
+}
+
+impl From<biofile::error::Error> for Error {
+    fn from(why: biofile::error::Error) -> Error {
+        Error::Biofile(why)
+    }
+}
+
+impl From<Error> for String {
+    fn from(error: Error) -> String {
+        error.to_string()
+    }
+}
@@ -7,10 +7,17 @@ pub mod util;
 pub mod test_util;
 
 pub mod bed_refinery;
+pub mod concatenated_genome;
+pub mod error;
 pub mod linear_track_mixture;
 pub mod refined_bed_zipper;
+pub mod region_aggregation;
+pub mod shuffle;
 pub mod top_k;
 pub mod top_k_overlap;
+pub mod track_conversion;
 pub mod track_correlation;
+pub mod track_diff;
+pub mod track_expression;
 pub mod track_histogram;
 pub mod track_zipper;
@@ -1,19 +1,64 @@
+use crate::{
+    error::Error,
+    util::{write_bed_line, WriteOptions},
+};
 use biofile::{
-    bed::{Bed, BedDataLine, BedWriter, Chrom},
+    bed::{Bed, BedDataLine, Chrom},
     iter::ToChromIntervalValueIter,
 };
 use math::{
     interval::{traits::Interval, I64Interval},
     iter::{AggregateOp, CommonRefinementZip, IntoBinnedIntervalIter},
     partition::integer_interval_map::IntegerIntervalMap,
+    set::ordered_integer_set::OrderedIntegerSet,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    io::Write,
 };
-use std::collections::{HashMap, HashSet};
 
 type Coefficient = f64;
 type Value = f64;
+type BinnedTrack = HashMap<Chrom, Vec<(I64Interval, Value)>>;
 
 pub struct LinearTrackMixture {
     content: HashMap<Chrom, Vec<(I64Interval, Value)>>,
+    empty_output_warning: Option<String>,
+    weights: Vec<Coefficient>,
+
+    // kept for rebinning the tracks when their contributions are requested
+    beds: Vec<Bed>,
+    bin_size: i64,
+    exclude: Option<HashMap<String, OrderedIntegerSet<i64>>>,
+    target_chroms: Option<HashSet<String>>,
+}
+
+/// How the weights of the tracks are determined. `None` uses the weights
+/// provided together with the track paths. `InverseVariance` ignores the
+/// provided weights and instead weights each track proportionally to the
+/// inverse of the variance of its bin values across the genome, with the
+/// weights normalized to sum to 1, so that noisier tracks contribute less.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum AutoWeight {
+    None,
+    InverseVariance,
+}
+
+impl Default for AutoWeight {
+    fn default() -> Self {
+        AutoWeight::None
+    }
+}
+
+/// A summary of the values of the bins in a `LinearTrackMixture`. The `mean`
+/// and `max` are NaN if the mixture has no bins.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct MixtureSummary {
+    /// The sum of the values of all the bins.
+    pub total: Value,
+    pub mean: Value,
+    pub max: Value,
+    pub num_bins: usize,
 }
 
 impl LinearTrackMixture {
@@ -23,7 +68,8 @@ impl LinearTrackMixture {
         use_binary_score: bool,
         exclude_track_filepath: Option<String>,
         target_chroms: Option<HashSet<String>>,
-    ) -> Result<LinearTrackMixture, biofile::error::Error> {
+        auto_weight: AutoWeight,
+    ) -> Result<LinearTrackMixture, Error> {
         let exclude = if let Some(path) = exclude_track_filepath {
             // binarize_score is irrelevant for getting the intervals
             Some(Bed::new(&path, false).get_chrom_to_intervals())
@@ -31,8 +77,60 @@ impl LinearTrackMixture {
             None
         };
 
-        let weights: Vec<Coefficient> =
-            weighted_paths.iter().map(|(w, _)| *w).collect();
+        let beds: Vec<Bed> = weighted_paths
+            .iter()
+            .map(|(_, p)| Bed::new(p, use_binary_score))
+            .collect();
+
+        // the chromosomes with intervals before applying the chromosome filter
+        let mut chroms_read = HashSet::<Chrom>::new();
+        let mut bin_track = |bed: &Bed| {
+            get_binned_track(
+                bed,
+                bin_size,
+                exclude.as_ref(),
+                target_chroms.as_ref(),
+                &mut chroms_read,
+            )
+        };
+
+        // the variances need every track binned before the mixing, in which
+        // case the binned tracks are reused for the mixing
+        let mut binned_tracks: Vec<BinnedTrack> = Vec::new();
+        let weights: Vec<Coefficient> = match auto_weight {
+            AutoWeight::None => {
+                weighted_paths.iter().map(|(w, _)| *w).collect()
+            }
+            AutoWeight::InverseVariance => {
+                binned_tracks = beds
+                    .iter()
+                    .map(&mut bin_track)
+                    .collect::<Result<Vec<BinnedTrack>, Error>>()?;
+                let inverse_variances = binned_tracks
+                    .iter()
+                    .zip(weighted_paths.iter())
+                    .map(|(binned_track, (_, path))| {
+                        let variance = get_binned_variance(binned_track);
+                        if variance > 0. {
+                            Ok(1. / variance)
+                        } else {
+                            Err(Error::Validation(format!(
+                                "cannot weight {} by the inverse of its bin \
+                                variance {}",
+                                path, variance
+                            )))
+                        }
+                    })
+                    .collect::<Result<Vec<Value>, Error>>()?;
+                let total: Value = inverse_variances.iter().sum();
+                inverse_variances.iter().map(|v| v / total).collect()
+            }
+        };
+        let mut binned_tracks = binned_tracks.into_iter();
+        let mut next_binned_track = |bed: &Bed| match binned_tracks.next() {
+            Some(binned_track) => Ok(binned_track),
+            None => bin_track(bed),
+        };
 
         let (first_weight, rest_weights): (Coefficient, Vec<Coefficient>) = {
             let (first, rest) = weights
@@ -41,118 +139,336 @@ impl LinearTrackMixture {
             (*first, rest.to_vec())
         };
 
-        let beds: Vec<Bed> = weighted_paths
-            .iter()
-            .map(|(_, p)| Bed::new(p, use_binary_score))
-            .collect();
-
         let first_bed = beds.first().expect("weighted_paths cannot be empty");
 
         let init: HashMap<Chrom, Vec<(I64Interval, Value)>> =
-            ToChromIntervalValueIter::get_chrom_to_interval_to_val(
-                first_bed,
-                exclude.as_ref(),
-            )?
-            .into_iter()
-            .filter_map(
-                |(chrom, interval_to_val): (
-                    Chrom,
-                    IntegerIntervalMap<Value>,
-                )| {
-                    if target_chroms.is_none()
-                        || target_chroms.as_ref().unwrap().contains(&chrom)
-                    {
-                        let binned_intervals = interval_to_val
-                            .iter()
-                            .into_binned_interval_iter(
-                                bin_size,
-                                AggregateOp::Average,
-                                Box::new(|item| (*item.0, *item.1)),
-                            )
-                            .into_iter()
-                            .map(|(interval, value)| {
-                                (interval, value * first_weight)
-                            })
-                            .collect::<Vec<(I64Interval, Value)>>();
-                        Some((chrom, binned_intervals))
-                    } else {
-                        None
-                    }
-                },
-            )
-            .collect();
+            next_binned_track(first_bed)?
+                .into_iter()
+                .map(|(chrom, binned_intervals)| {
+                    let binned_intervals = binned_intervals
+                        .into_iter()
+                        .map(|(interval, value)| {
+                            (interval, value * first_weight)
+                        })
+                        .collect::<Vec<(I64Interval, Value)>>();
+                    (chrom, binned_intervals)
+                })
+                .collect();
 
         let content = beds.iter().skip(1).enumerate().try_fold(
             init,
-            |acc_chrom_to_binned_interval_values, (i, bed)| {
-                ToChromIntervalValueIter::get_chrom_to_interval_to_val(
-                    bed,
-                    exclude.as_ref(),
-                )?
-                .into_iter()
-                .filter_map(|(chrom, interval_to_val)| {
-                    if target_chroms.is_some()
-                        && !target_chroms.as_ref().unwrap().contains(&chrom)
-                    {
-                        return None;
-                    }
-                    let interval_values: Vec<(I64Interval, Value)> =
-                        interval_to_val
-                            .iter()
-                            .into_binned_interval_iter(
-                                bin_size,
-                                AggregateOp::Average,
-                                Box::new(|item| (*item.0, *item.1)),
-                            )
-                            .collect();
-
-                    let w: Coefficient = rest_weights[i];
-                    match acc_chrom_to_binned_interval_values.get(&chrom) {
-                        None => Some(Ok((chrom, interval_values))),
-
-                        Some(acc_binned_interval_values) => {
-                            let interval_values = acc_binned_interval_values
-                                .iter()
-                                .into_binned_interval_iter(
-                                    bin_size,
-                                    AggregateOp::Average,
-                                    Box::new(|&item| (item.0, item.1)),
-                                )
-                                .common_refinement_zip(
-                                    interval_values
+            |acc_chrom_to_binned_interval_values,
+             (i, bed)|
+             -> Result<BinnedTrack, Error> {
+                let binned_track = next_binned_track(bed)?;
+                Ok(binned_track
+                    .into_iter()
+                    .map(|(chrom, interval_values)| {
+                        let w: Coefficient = rest_weights[i];
+                        match acc_chrom_to_binned_interval_values.get(&chrom) {
+                            None => (chrom, interval_values),
+
+                            Some(acc_binned_interval_values) => {
+                                let interval_values =
+                                    acc_binned_interval_values
                                         .iter()
                                         .into_binned_interval_iter(
                                             bin_size,
                                             AggregateOp::Average,
                                             Box::new(|&item| (item.0, item.1)),
-                                        ),
-                                )
-                                .map(|(interval, values)| {
-                                    let acc = values[0].unwrap_or(0f64);
-                                    let val = values[1].unwrap_or(0f64);
-                                    (interval, acc + val * w)
-                                })
-                                .collect();
-                            Some(Ok((chrom, interval_values)))
+                                        )
+                                        .common_refinement_zip(
+                                            interval_values
+                                                .iter()
+                                                .into_binned_interval_iter(
+                                                    bin_size,
+                                                    AggregateOp::Average,
+                                                    Box::new(|&item| {
+                                                        (item.0, item.1)
+                                                    }),
+                                                ),
+                                        )
+                                        .map(|(interval, values)| {
+                                            let acc = values[0].unwrap_or(0f64);
+                                            let val = values[1].unwrap_or(0f64);
+                                            (interval, acc + val * w)
+                                        })
+                                        .collect();
+                                (chrom, interval_values)
+                            }
                         }
-                    }
-                })
-                .collect::<Result<
-                    HashMap<Chrom, Vec<(I64Interval, Value)>>,
-                    biofile::error::Error,
-                >>()
+                    })
+                    .collect())
             },
         )?;
+        let empty_output_warning = if content.is_empty() {
+            Some(crate::util::get_empty_output_warning(
+                chroms_read.len(),
+                target_chroms.is_some(),
+                exclude.is_some(),
+            ))
+        } else {
+            None
+        };
         Ok(LinearTrackMixture {
             content,
+            empty_output_warning,
+            weights,
+            beds,
+            bin_size,
+            exclude,
+            target_chroms,
         })
     }
 
-    pub fn write_to_bed_file(
+    /// Returns the index of the track contributing the most to the mixed
+    /// value of each bin, i.e., with the largest weighted value in the bin,
+    /// where the first of the tracks with equal contributions is returned.
+    /// Only the tracks with data in a bin are considered.
+    pub fn argmax_track(
+        &self,
+    ) -> Result<HashMap<Chrom, Vec<(I64Interval, usize)>>, Error> {
+        Ok(self
+            .get_max_contributions()?
+            .into_iter()
+            .map(|(chrom, bins)| {
+                let argmax = bins
+                    .into_iter()
+                    .map(|(interval, track_index, _)| (interval, track_index))
+                    .collect();
+                (chrom, argmax)
+            })
+            .collect())
+    }
+
+    /// Writes the result of `argmax_track` as a BED file in which the name of
+    /// each bin is the index of the dominant track and the score is its
+    /// weighted value, with the end coordinates following the
+    /// `options.end_convention`.
+    pub fn write_argmax_track(
         &self,
         path: &str,
-    ) -> Result<(), biofile::error::Error> {
-        let mut writer = BedWriter::new(path)?;
+        options: &WriteOptions,
+    ) -> Result<(), Error> {
+        let chrom_to_max_contributions = self.get_max_contributions()?;
+        let mut writer = crate::util::create_maybe_gzip(path, false)?;
+        for chrom in crate::util::get_sorted_keys(&chrom_to_max_contributions) {
+            for &(interval, track_index, contribution) in
+                chrom_to_max_contributions[&chrom].iter()
+            {
+                write_bed_line(&mut writer, &BedDataLine {
+                    chrom: chrom.clone(),
+                    start: interval.get_start(),
+                    end: options
+                        .end_convention
+                        .end_coordinate(interval.get_end()),
+                    name: Some(track_index.to_string()),
+                    score: Some(contribution),
+                    strand: None,
+                })?;
+            }
+        }
+        Ok(writer.finish()?)
+    }
+
+    /// Writes each bin of the mixture as a line of the form
+    /// `chrom start end mixed contrib_0 contrib_1 ... contrib_N`, where
+    /// `contrib_i` is the weighted value of the `i`-th track in the bin, i.e.,
+    /// its weight times its value, or 0 if the track has no data in the bin.
+    /// The contributions of a bin sum to its mixed value. The end coordinates
+    /// follow the `options.end_convention`.
+    pub fn write_with_contributions(
+        &self,
+        out_path: &str,
+        options: &WriteOptions,
+    ) -> Result<(), Error> {
+        if let Some(warning) = self.empty_output_warning() {
+            eprintln!("{}", warning);
+        }
+        let chrom_to_bin_contributions = self.get_bin_contributions()?;
+        let mut writer = crate::util::create_maybe_gzip(out_path, false)?;
+        for chrom in crate::util::get_sorted_keys(&chrom_to_bin_contributions) {
+            for (interval, value, contributions) in
+                chrom_to_bin_contributions[&chrom].iter()
+            {
+                write!(
+                    &mut writer,
+                    "{}\t{}\t{}\t{}",
+                    chrom,
+                    interval.get_start(),
+                    options.end_convention.end_coordinate(interval.get_end()),
+                    value
+                )?;
+                for contribution in contributions.iter() {
+                    write!(&mut writer, "\t{}", contribution.unwrap_or(0.))?;
+                }
+                writeln!(&mut writer)?;
+            }
+        }
+        Ok(writer.finish()?)
+    }
+
+    /// Returns the index and the weighted value of the track contributing the
+    /// most to each bin of the mixture.
+    fn get_max_contributions(
+        &self,
+    ) -> Result<HashMap<Chrom, Vec<(I64Interval, usize, Value)>>, Error> {
+        let mut chrom_to_max: HashMap<Chrom, Vec<Option<(usize, Value)>>> =
+            self.content
+                .iter()
+                .map(|(chrom, bins)| (chrom.clone(), vec![None; bins.len()]))
+                .collect();
+        // the tracks are visited in order, so that the first of the tracks
+        // with equal contributions is kept
+        self.for_each_contribution(
+            |chrom, bin_index, track_index, contribution| {
+                let max = &mut chrom_to_max.get_mut(chrom).unwrap()[bin_index];
+                if max.map_or(true, |(_, m)| contribution > m) {
+                    *max = Some((track_index, contribution));
+                }
+            },
+        )?;
+        Ok(chrom_to_max
+            .into_iter()
+            .map(|(chrom, max_contributions)| {
+                let max_contributions = self.content[&chrom]
+                    .iter()
+                    .zip(max_contributions.into_iter())
+                    .filter_map(|(&(interval, _), max)| {
+                        max.map(|(track_index, contribution)| {
+                            (interval, track_index, contribution)
+                        })
+                    })
+                    .collect();
+                (chrom, max_contributions)
+            })
+            .collect())
+    }
+
+    /// Returns the mixed value of each bin of the mixture together with the
+    /// weighted value of each track in the bin, in the order of the tracks,
+    /// which is `None` if the track has no data in the bin.
+    fn get_bin_contributions(
+        &self,
+    ) -> Result<
+        HashMap<Chrom, Vec<(I64Interval, Value, Vec<Option<Value>>)>>,
+        Error,
+    > {
+        let mut chrom_to_bin_contributions: HashMap<
+            Chrom,
+            Vec<(I64Interval, Value, Vec<Option<Value>>)>,
+        > = self
+            .content
+            .iter()
+            .map(|(chrom, bins)| {
+                let bin_contributions = bins
+                    .iter()
+                    .map(|&(interval, value)| {
+                        (interval, value, vec![None; self.beds.len()])
+                    })
+                    .collect();
+                (chrom.clone(), bin_contributions)
+            })
+            .collect();
+        self.for_each_contribution(
+            |chrom, bin_index, track_index, contribution| {
+                chrom_to_bin_contributions.get_mut(chrom).unwrap()[bin_index]
+                    .2[track_index] = Some(contribution);
+            },
+        )?;
+        Ok(chrom_to_bin_contributions)
+    }
+
+    /// Rebins the tracks one at a time and calls `visit` with the chromosome
+    /// and the index of each bin of the mixture, the index of a track with
+    /// data in the bin, and the weighted value of the track in the bin, so
+    /// that only one binned track is held in memory at a time.
+    fn for_each_contribution<F>(&self, mut visit: F) -> Result<(), Error>
+    where
+        F: FnMut(&Chrom, usize, usize, Value),
+    {
+        for (track_index, (bed, w)) in
+            self.beds.iter().zip(self.weights.iter()).enumerate()
+        {
+            let binned_track = get_binned_track(
+                bed,
+                self.bin_size,
+                self.exclude.as_ref(),
+                self.target_chroms.as_ref(),
+                &mut HashSet::new(),
+            )?;
+            for (chrom, bins) in self.content.iter() {
+                let track_bins = match binned_track.get(chrom) {
+                    Some(track_bins) => track_bins,
+                    None => continue,
+                };
+                for (bin_index, (interval, _)) in bins.iter().enumerate() {
+                    // the track bin containing the start of the mixed bin
+                    let k = track_bins.partition_point(|(i, _)| {
+                        i.get_end() < interval.get_start()
+                    });
+                    if let Some(&(i, value)) = track_bins.get(k) {
+                        if i.get_start() <= interval.get_start() {
+                            visit(chrom, bin_index, track_index, value * w);
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The weights with which the tracks were mixed, in the order of the
+    /// tracks, which differ from the provided weights under
+    /// `AutoWeight::InverseVariance`.
+    pub fn weights(&self) -> &[Coefficient] {
+        &self.weights
+    }
+
+    /// Returns a warning naming the likely cause if the mixture is empty, e.g.,
+    /// because of a chromosome filter that matches none of the chromosomes.
+    pub fn empty_output_warning(&self) -> Option<&str> {
+        self.empty_output_warning.as_deref()
+    }
+
+    /// Summarizes the values of the bins across all the chromosomes.
+    pub fn summary(&self) -> MixtureSummary {
+        let mut total = 0.;
+        let mut max = std::f64::NAN;
+        let mut num_bins = 0usize;
+        for (_interval, value) in self.content.values().flatten() {
+            total += value;
+            // `f64::max` ignores the initial NaN
+            max = max.max(*value);
+            num_bins += 1;
+        }
+        MixtureSummary {
+            total,
+            mean: if num_bins == 0 {
+                std::f64::NAN
+            } else {
+                total / num_bins as f64
+            },
+            max,
+            num_bins,
+        }
+    }
+
+    pub fn write_to_bed_file(&self, path: &str) -> Result<(), Error> {
+        self.write_to_bed_file_with_options(path, &WriteOptions::default())
+    }
+
+    /// Same as `write_to_bed_file` except that the end coordinates follow the
+    /// `options.end_convention`.
+    pub fn write_to_bed_file_with_options(
+        &self,
+        path: &str,
+        options: &WriteOptions,
+    ) -> Result<(), Error> {
+        if let Some(warning) = self.empty_output_warning() {
+            eprintln!("{}", warning);
+        }
+        let mut writer = crate::util::create_maybe_gzip(path, false)?;
 
         for chrom in crate::util::get_sorted_keys(&self.content).into_iter() {
             let mut bed_data_line = BedDataLine {
@@ -165,24 +481,91 @@ impl LinearTrackMixture {
             };
             for (interval, value) in self.content[&chrom].iter() {
                 bed_data_line.start = interval.get_start();
-                // the end coordinate is exclusive under the BED format
-                bed_data_line.end = interval.get_end() + 1;
+                bed_data_line.end =
+                    options.end_convention.end_coordinate(interval.get_end());
                 bed_data_line.score = Some(*value);
-                writer.write_bed_line(&bed_data_line)?;
+                write_bed_line(&mut writer, &bed_data_line)?;
             }
         }
-        Ok(())
+        Ok(writer.finish()?)
     }
 }
 
+/// Bins the values of the `bed` track on the `target_chroms` by averaging,
+/// adding the chromosomes read before the filter to `chroms_read`.
+fn get_binned_track(
+    bed: &Bed,
+    bin_size: i64,
+    exclude: Option<&HashMap<String, OrderedIntegerSet<i64>>>,
+    target_chroms: Option<&HashSet<String>>,
+    chroms_read: &mut HashSet<Chrom>,
+) -> Result<BinnedTrack, Error> {
+    Ok(
+        ToChromIntervalValueIter::get_chrom_to_interval_to_val(bed, exclude)?
+            .into_iter()
+            .filter_map(
+                |(chrom, interval_to_val): (
+                    Chrom,
+                    IntegerIntervalMap<Value>,
+                )| {
+                    chroms_read.insert(chrom.clone());
+                    if target_chroms.is_some()
+                        && !target_chroms.unwrap().contains(&chrom)
+                    {
+                        return None;
+                    }
+                    let binned_intervals = interval_to_val
+                        .iter()
+                        .into_binned_interval_iter(
+                            bin_size,
+                            AggregateOp::Average,
+                            Box::new(|item| (*item.0, *item.1)),
+                        )
+                        .collect::<Vec<(I64Interval, Value)>>();
+                    Some((chrom, binned_intervals))
+                },
+            )
+            .collect(),
+    )
+}
+
+/// Returns the variance of the bin values of the `binned_track`, or NaN if
+/// the track has no bins.
+fn get_binned_variance(binned_track: &BinnedTrack) -> Value {
+    let bin_values: Vec<Value> = binned_track
+        .values()
+        .flatten()
+        .map(|&(_, value)| value)
+        .collect();
+    get_variance(&bin_values)
+}
+
+/// Returns the population variance of the `values`, or NaN if `values` is
+/// empty.
+fn get_variance(values: &[Value]) -> Value {
+    let n = values.len() as Value;
+    let mean = values.iter().sum::<Value>() / n;
+    values
+        .iter()
+        .map(|v| (v - mean) * (v - mean))
+        .sum::<Value>()
+        / n
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::{check_chrom, linear_track_mixture::LinearTrackMixture};
+    use crate::{
+        assert_almost_eq, check_chrom,
+        linear_track_mixture::{AutoWeight, LinearTrackMixture},
+        test_util::create_temp_bed,
+        util::WriteOptions,
+    };
     use biofile::{bed::Bed, iter::ToChromIntervalValueIter};
     use math::interval::I64Interval;
     use std::{
         collections::HashSet,
-        io::{BufWriter, Write},
+        fs::File,
+        io::{BufRead, BufReader, BufWriter, Write},
     };
     use tempfile::NamedTempFile;
 
@@ -233,6 +616,7 @@ mod tests {
                         .into_iter()
                         .collect::<HashSet<String>>(),
                 ),
+                AutoWeight::None,
             )
             .unwrap();
 
@@ -270,6 +654,7 @@ mod tests {
                 false,
                 None,
                 None,
+                AutoWeight::None,
             )
             .unwrap();
 
@@ -315,6 +700,7 @@ mod tests {
                         .into_iter()
                         .collect::<HashSet<String>>(),
                 ),
+                AutoWeight::None,
             )
             .unwrap();
 
@@ -337,4 +723,253 @@ mod tests {
             check_chrom!(chr3_map_iter, (2500, 2549, 350.), (2550, 2599, 300.));
         }
     }
+
+    #[test]
+    fn test_mixture_summary() {
+        let bed_1_path = create_temp_bed(
+            "chr1 100 200 name_1 10\n\
+            chr1 150 250 name_2 5\n\
+            chr3 2500 2600 name_6 25\n",
+        )
+        .unwrap();
+        let bed_2_path = create_temp_bed(
+            "chr1 100 200 name_1 100\n\
+            chr3 2500 2575 name_6 1000\n",
+        )
+        .unwrap();
+        let mixture = LinearTrackMixture::create(
+            vec![
+                (0.4, bed_1_path.to_str().unwrap().to_string()),
+                (0.6, bed_2_path.to_str().unwrap().to_string()),
+            ],
+            50,
+            false,
+            None,
+            None,
+            AutoWeight::None,
+        )
+        .unwrap();
+
+        let mixed_path = NamedTempFile::new().unwrap().into_temp_path();
+        mixture
+            .write_to_bed_file(mixed_path.to_str().unwrap())
+            .unwrap();
+        let written_values: Vec<f64> = {
+            let bed = Bed::new(mixed_path.to_str().unwrap(), false);
+            ToChromIntervalValueIter::get_chrom_to_interval_to_val(&bed, None)
+                .unwrap()
+                .values()
+                .flat_map(|m| m.iter().map(|(_, &v)| v).collect::<Vec<f64>>())
+                .collect()
+        };
+
+        let summary = mixture.summary();
+        assert_eq!(summary.num_bins, written_values.len());
+        assert_almost_eq!(summary.total, written_values.iter().sum::<f64>());
+        assert_almost_eq!(summary.total, 64. + 66. + 2. + 610. + 310.);
+        assert_almost_eq!(summary.mean, summary.total / 5.);
+        assert_almost_eq!(summary.max, 610.);
+    }
+
+    #[test]
+    fn test_empty_mixture_warning() {
+        let bed_path =
+            create_temp_bed("1 100 200 name_1 10\n3 2500 2600 name_2 25\n")
+                .unwrap();
+        let get_mixture = |target_chroms: Vec<&str>| {
+            LinearTrackMixture::create(
+                vec![(1., bed_path.to_str().unwrap().to_string())],
+                10,
+                false,
+                None,
+                Some(
+                    target_chroms.into_iter().map(|c| c.to_string()).collect(),
+                ),
+                AutoWeight::None,
+            )
+            .unwrap()
+        };
+
+        assert!(get_mixture(vec!["1"]).empty_output_warning().is_none());
+
+        // the chromosome filter uses UCSC-style names unlike the track
+        let warning = get_mixture(vec!["chr1", "chr3"])
+            .empty_output_warning()
+            .unwrap()
+            .to_string();
+        assert!(warning.contains("none of the 2 chromosomes"));
+        assert!(warning.contains("chromosome filter"));
+    }
+
+    #[test]
+    fn test_argmax_track() {
+        // each of the tracks dominates in one of the two halves of [0, 200)
+        let bed_1_path =
+            create_temp_bed("chr1 0 100 name_1 10\nchr1 100 200 name_2 1\n")
+                .unwrap();
+        let bed_2_path =
+            create_temp_bed("chr1 0 100 name_1 2\nchr1 100 200 name_2 8\n")
+                .unwrap();
+        let mixture = LinearTrackMixture::create(
+            vec![
+                (0.5, bed_1_path.to_str().unwrap().to_string()),
+                (0.5, bed_2_path.to_str().unwrap().to_string()),
+            ],
+            50,
+            false,
+            None,
+            None,
+            AutoWeight::None,
+        )
+        .unwrap();
+        let argmax = mixture.argmax_track().unwrap();
+        assert_eq!(argmax.len(), 1);
+        assert_eq!(argmax["chr1"], vec![
+            (I64Interval::new(0, 49), 0),
+            (I64Interval::new(50, 99), 0),
+            (I64Interval::new(100, 149), 1),
+            (I64Interval::new(150, 199), 1),
+        ]);
+
+        let argmax_path = NamedTempFile::new().unwrap().into_temp_path();
+        mixture
+            .write_argmax_track(
+                argmax_path.to_str().unwrap(),
+                &WriteOptions::default(),
+            )
+            .unwrap();
+        let lines: Vec<Vec<String>> =
+            BufReader::new(File::open(argmax_path.to_str().unwrap()).unwrap())
+                .lines()
+                .map(|line| {
+                    line.unwrap()
+                        .split_whitespace()
+                        .map(|field| field.to_string())
+                        .collect()
+                })
+                .collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(&lines[0][..4], &["chr1", "0", "50", "0"]);
+        assert_eq!(&lines[3][..4], &["chr1", "150", "200", "1"]);
+        assert_almost_eq!(lines[3][4].parse::<f64>().unwrap(), 4.);
+    }
+
+    #[test]
+    fn test_write_with_contributions() {
+        let bed_1_path = create_temp_bed(
+            "chr1 100 200 name_1 10\n\
+            chr1 150 250 name_2 5\n\
+            chr3 2500 2600 name_6 25\n",
+        )
+        .unwrap();
+        let bed_2_path = create_temp_bed(
+            "chr1 100 200 name_1 100\n\
+            chr3 2500 2575 name_6 1000\n",
+        )
+        .unwrap();
+        let mixture = LinearTrackMixture::create(
+            vec![
+                (0.4, bed_1_path.to_str().unwrap().to_string()),
+                (0.6, bed_2_path.to_str().unwrap().to_string()),
+            ],
+            50,
+            false,
+            None,
+            None,
+            AutoWeight::None,
+        )
+        .unwrap();
+
+        let out_path = NamedTempFile::new().unwrap().into_temp_path();
+        mixture
+            .write_with_contributions(
+                out_path.to_str().unwrap(),
+                &WriteOptions::default(),
+            )
+            .unwrap();
+        let lines: Vec<Vec<String>> =
+            BufReader::new(File::open(out_path.to_str().unwrap()).unwrap())
+                .lines()
+                .map(|line| {
+                    line.unwrap()
+                        .split_whitespace()
+                        .map(|field| field.to_string())
+                        .collect()
+                })
+                .collect();
+        assert_eq!(lines.len(), 5);
+        for fields in lines.iter() {
+            assert_eq!(fields.len(), 6);
+            let mixed = fields[3].parse::<f64>().unwrap();
+            let total: f64 =
+                fields[4..].iter().map(|c| c.parse::<f64>().unwrap()).sum();
+            assert_almost_eq!(total, mixed);
+        }
+        assert_eq!(&lines[0][..3], &["chr1", "100", "150"]);
+        assert_almost_eq!(lines[0][4].parse::<f64>().unwrap(), 4.);
+        assert_almost_eq!(lines[0][5].parse::<f64>().unwrap(), 60.);
+
+        // the second track has no data in the last bin of chr1
+        assert_eq!(&lines[2][..3], &["chr1", "200", "250"]);
+        assert_almost_eq!(lines[2][3].parse::<f64>().unwrap(), 2.);
+        assert_almost_eq!(lines[2][5].parse::<f64>().unwrap(), 0.);
+    }
+
+    #[test]
+    fn test_inverse_variance_weights() {
+        // the bin values are 1 and 3 with a variance of 1
+        let bed_1_path =
+            create_temp_bed("chr1 0 10 name_1 1\nchr1 10 20 name_2 3\n")
+                .unwrap();
+        // the bin values are 0 and 4 with a variance of 4
+        let noisy_bed_path =
+            create_temp_bed("chr1 0 10 name_1 0\nchr1 10 20 name_2 4\n")
+                .unwrap();
+        let get_mixture = |auto_weight| {
+            LinearTrackMixture::create(
+                vec![
+                    (0.5, bed_1_path.to_str().unwrap().to_string()),
+                    (0.5, noisy_bed_path.to_str().unwrap().to_string()),
+                ],
+                10,
+                false,
+                None,
+                None,
+                auto_weight,
+            )
+            .unwrap()
+        };
+
+        let mixture = get_mixture(AutoWeight::None);
+        assert_eq!(mixture.weights(), &[0.5, 0.5]);
+
+        // the provided weights are ignored
+        let mixture = get_mixture(AutoWeight::InverseVariance);
+        let weights = mixture.weights();
+        assert_eq!(weights.len(), 2);
+        assert_almost_eq!(weights[0], 0.8);
+        assert_almost_eq!(weights[1], 0.2);
+        let values: Vec<f64> =
+            mixture.content["chr1"].iter().map(|(_, v)| *v).collect();
+        assert_eq!(values.len(), 2);
+        assert_almost_eq!(values[0], 0.8 * 1. + 0.2 * 0.);
+        assert_almost_eq!(values[1], 0.8 * 3. + 0.2 * 4.);
+
+        // a constant track has no variance to weight by
+        let constant_bed_path =
+            create_temp_bed("chr1 0 10 name_1 2\nchr1 10 20 name_2 2\n")
+                .unwrap();
+        assert!(LinearTrackMixture::create(
+            vec![
+                (0.5, bed_1_path.to_str().unwrap().to_string()),
+                (0.5, constant_bed_path.to_str().unwrap().to_string()),
+            ],
+            10,
+            false,
+            None,
+            None,
+            AutoWeight::InverseVariance,
+        )
+        .is_err());
+    }
 }
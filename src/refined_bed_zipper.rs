@@ -1,3 +1,7 @@
+use crate::{
+    error::Error,
+    util::{create_maybe_gzip, EndConvention},
+};
 use biofile::{
     bed::{Bed, BedDataLine, BedDataLineIter},
     bedgraph::{BedGraph, BedGraphDataLine, BedGraphDataLineIter},
@@ -5,11 +9,7 @@ use biofile::{
 };
 use enum_dispatch::enum_dispatch;
 use math::traits::ToIterator;
-use std::{
-    collections::HashSet,
-    fs::OpenOptions,
-    io::{BufWriter, Write},
-};
+use std::{collections::HashSet, io::Write};
 
 type Chrom = String;
 type Coord = i64;
@@ -29,6 +29,10 @@ pub struct RefinedBedZipper {
     interval_length: Coord,
 
     default_value: Value,
+
+    // how the end coordinates of the input files are to be read, which are
+    // converted to exclusive ends before the intervals are checked
+    input_end_convention: EndConvention,
 }
 
 impl RefinedBedZipper {
@@ -37,25 +41,29 @@ impl RefinedBedZipper {
         alignment: Coord,
         interval_length: Coord,
         default_value: Value,
+        input_end_convention: EndConvention,
     ) -> RefinedBedZipper {
         RefinedBedZipper {
             refined_bed_paths,
             alignment,
             interval_length,
             default_value,
+            input_end_convention,
         }
     }
 
+    /// Writes the zipped lines to `out_path`, compressed with gzip if the path
+    /// ends in `.gz`. If `append` is true, the lines
+    /// are appended to any existing content of `out_path` instead of
+    /// replacing it, e.g., to combine the outputs for different chromosome
+    /// shards. The caller is then responsible for appending the shards in
+    /// sorted order of the chromosomes.
     pub fn write_to_file(
         &self,
         out_path: &str,
-    ) -> Result<(), biofile::error::Error> {
-        let file = OpenOptions::new()
-            .create(true)
-            .truncate(true)
-            .write(true)
-            .open(out_path)?;
-        let mut writer = BufWriter::new(file);
+        append: bool,
+    ) -> Result<(), Error> {
+        let mut writer = create_maybe_gzip(out_path, append)?;
 
         for ZippedBedGraphLine {
             chrom,
@@ -71,7 +79,7 @@ impl RefinedBedZipper {
             }
             writeln!(&mut writer,)?;
         }
-        Ok(())
+        Ok(writer.finish()?)
     }
 }
 
@@ -85,10 +93,10 @@ pub trait TryToIter<'s, I: Iterator<Item = R>, R, E> {
     fn try_to_iter(&'s self) -> Result<I, E>;
 }
 
-impl<'s> TryToIter<'s, RefinedBedZipperIter, ZippedBedGraphLine, String>
+impl<'s> TryToIter<'s, RefinedBedZipperIter, ZippedBedGraphLine, Error>
     for RefinedBedZipper
 {
-    fn try_to_iter(&'s self) -> Result<RefinedBedZipperIter, String> {
+    fn try_to_iter(&'s self) -> Result<RefinedBedZipperIter, Error> {
         let bed_reserves: Vec<BedReserveVariant> = self
             .refined_bed_paths
             .iter()
@@ -96,18 +104,74 @@ impl<'s> TryToIter<'s, RefinedBedZipperIter, ZippedBedGraphLine, String>
                 if p.ends_with(".bed") {
                     Ok(BedReserveVariant::Bed(BedReserve::new(
                         Bed::new(p, false).to_iter(),
+                        self.input_end_convention,
                     )))
                 } else if p.ends_with(".bedgraph") {
                     Ok(BedReserveVariant::BedGraph(BedReserve::new(
                         BedGraph::new(p, false).to_iter(),
+                        self.input_end_convention,
                     )))
                 } else {
-                    Err(format!(
-                        "file names must end with either .bed or .bedgraph"
-                    ))
+                    Err(Error::Validation(format!(
+                        "file names must end with either .bed or .bedgraph, \
+                        received {}",
+                        p
+                    )))
                 }
             })
-            .collect::<Result<Vec<BedReserveVariant>, String>>()?;
+            .collect::<Result<Vec<BedReserveVariant>, Error>>()?;
+
+        // The lines after the first are checked as the files are advanced,
+        // but the first lines are checked up front so that files binned with
+        // different bin sizes are reported as such.
+        let first_interval_lengths: Vec<(&String, Coord)> = self
+            .refined_bed_paths
+            .iter()
+            .zip(bed_reserves.iter())
+            .filter_map(|(path, reserve)| {
+                reserve
+                    .current()
+                    .map(|(_, start, end, _)| (path, end - start))
+            })
+            .collect();
+        if first_interval_lengths
+            .iter()
+            .any(|&(_, length)| length != self.interval_length)
+        {
+            let lengths = first_interval_lengths
+                .iter()
+                .map(|(path, length)| format!("{} ({})", path, length))
+                .collect::<Vec<String>>()
+                .join(", ");
+            // Every length being off by one in the same direction points to
+            // files following the other end convention rather than to
+            // different bin sizes.
+            let (length_difference, likely_convention) =
+                match self.input_end_convention {
+                    EndConvention::Exclusive => (-1, EndConvention::Inclusive),
+                    EndConvention::Inclusive => (1, EndConvention::Exclusive),
+                };
+            if first_interval_lengths.iter().all(|&(_, length)| {
+                length - self.interval_length == length_difference
+            }) {
+                return Err(Error::Validation(format!(
+                    "the first intervals of the files are all off by one from \
+                    the interval_length {} when read with the {:?} end \
+                    convention, the files likely use the {:?} end \
+                    convention: {}",
+                    self.interval_length,
+                    self.input_end_convention,
+                    likely_convention,
+                    lengths
+                )));
+            }
+            return Err(Error::Validation(format!(
+                "the first intervals of the files must have the \
+                interval_length {}, the files may have been binned with \
+                different bin sizes: {}",
+                self.interval_length, lengths
+            )));
+        }
 
         RefinedBedZipperIter::new(
             bed_reserves,
@@ -144,17 +208,17 @@ impl RefinedBedZipperIter {
         alignment: Coord,
         interval_length: Coord,
         default_value: Value,
-    ) -> Result<RefinedBedZipperIter, String> {
+    ) -> Result<RefinedBedZipperIter, Error> {
         if alignment < 0 {
-            Err(format!(
+            Err(Error::Validation(format!(
                 "alignment cannot be negative, received {}",
                 alignment
-            ))
+            )))
         } else if interval_length <= 0 {
-            Err(format!(
+            Err(Error::Validation(format!(
                 "interval_legnth must be positive, received {}",
                 interval_length
-            ))
+            )))
         } else {
             Ok(RefinedBedZipperIter {
                 bed_reserves,
@@ -234,10 +298,14 @@ impl Iterator for RefinedBedZipperIter {
                     })
                     .collect();
 
+                let end_exclusive = min_start
+                    .checked_add(self.interval_length)
+                    .expect("the end coordinate overflows i64");
+
                 Some(ZippedBedGraphLine {
                     chrom: min_chrom,
                     start: min_start,
-                    end_exclusive: min_start + self.interval_length,
+                    end_exclusive,
                     values,
                 })
             }
@@ -248,6 +316,9 @@ impl Iterator for RefinedBedZipperIter {
 struct BedReserve<Iter: Iterator<Item = T>, T: ToChromStartEndVal<Value>> {
     bed_iter: Iter,
 
+    // the end convention of the lines of the bed_iter
+    end_convention: EndConvention,
+
     // (chrom, start, end_exclusive, value)
     current_chrom_coordinates: Option<(Chrom, Coord, Coord, Option<Value>)>,
 
@@ -258,15 +329,27 @@ struct BedReserve<Iter: Iterator<Item = T>, T: ToChromStartEndVal<Value>> {
 impl<Iter: Iterator<Item = T>, T: ToChromStartEndVal<Value>>
     BedReserve<Iter, T>
 {
-    fn new(mut bed_iter: Iter) -> BedReserve<Iter, T> {
-        let current_chrom_coordinates =
-            bed_iter.next().map(|x| x.to_chrom_start_end_val());
-
-        BedReserve {
+    fn new(
+        bed_iter: Iter,
+        end_convention: EndConvention,
+    ) -> BedReserve<Iter, T> {
+        let mut reserve = BedReserve {
             bed_iter,
-            current_chrom_coordinates,
+            end_convention,
+            current_chrom_coordinates: None,
             past_chroms: HashSet::new(),
-        }
+        };
+        reserve.current_chrom_coordinates = reserve.next_line();
+        reserve
+    }
+
+    /// Reads the next line as `(chrom, start, end_exclusive, value)`.
+    fn next_line(&mut self) -> Option<(Chrom, Coord, Coord, Option<Value>)> {
+        let end_convention = self.end_convention;
+        self.bed_iter.next().map(|x| {
+            let (chrom, start, end, value) = x.to_chrom_start_end_val();
+            (chrom, start, end_convention.exclusive_end(end), value)
+        })
     }
 }
 
@@ -278,7 +361,7 @@ trait BedReserveOp {
         &mut self,
         alignment: Coord,
         interval_length: Coord,
-    ) -> Result<Option<&(Chrom, Coord, Coord, Option<Value>)>, String>;
+    ) -> Result<Option<&(Chrom, Coord, Coord, Option<Value>)>, Error>;
 }
 
 impl<Iter: Iterator<Item = T>, T: ToChromStartEndVal<Value>> BedReserveOp
@@ -292,30 +375,28 @@ impl<Iter: Iterator<Item = T>, T: ToChromStartEndVal<Value>> BedReserveOp
         &mut self,
         alignment: Coord,
         interval_length: Coord,
-    ) -> Result<Option<&(Chrom, Coord, Coord, Option<Value>)>, String> {
-        if let Some((chrom, start, end, score)) =
-            self.bed_iter.next().map(|x| x.to_chrom_start_end_val())
-        {
+    ) -> Result<Option<&(Chrom, Coord, Coord, Option<Value>)>, Error> {
+        if let Some((chrom, start, end, score)) = self.next_line() {
             if self.past_chroms.contains(&chrom) {
-                return Err(format!(
+                return Err(Error::UnsortedInput(format!(
                     "different chromosomes cannot interleave, encountered \
                     chromosome {} again: ",
                     &chrom
-                ));
+                )));
             }
 
-            if end - start != interval_length {
-                return Err(format!(
-                    "end ({}) - start ({}) != interval_length ({})",
+            if end.checked_sub(start) != Some(interval_length) {
+                return Err(Error::Validation(format!(
+                    "end_exclusive ({}) - start ({}) != interval_length ({})",
                     end, start, interval_length
-                ));
+                )));
             }
 
             if start % interval_length != alignment {
-                return Err(format!(
+                return Err(Error::Validation(format!(
                     "start ({}) % interval_length ({}) != alignment ({})",
                     start, interval_length, alignment
-                ));
+                )));
             }
 
             let insert_to_past_chroms = match self.current() {
@@ -342,19 +423,19 @@ impl<Iter: Iterator<Item = T>, T: ToChromStartEndVal<Value>> BedReserveOp
             {
                 if old_chrom == &chrom {
                     if start < *old_end_exclusive {
-                        return Err(format!(
+                        return Err(Error::UnsortedInput(format!(
                             "the intervals must be sorted in increasing order, \
                             new start {} < old_end_exclusive {}",
                             start, old_end_exclusive
-                        ));
+                        )));
                     }
                 } else {
                     if old_chrom > &chrom {
-                        return Err(format!(
+                        return Err(Error::UnsortedInput(format!(
                             "the chromosomes must be sorted in increasing \
                             order, new chrom {} < old chrom {}",
                             chrom, old_chrom
-                        ));
+                        )));
                     }
                 }
             }
@@ -375,3 +456,188 @@ impl<Iter: Iterator<Item = T>, T: ToChromStartEndVal<Value>> BedReserveOp
         Ok(self.current())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        error::Error,
+        refined_bed_zipper::{RefinedBedZipper, TryToIter},
+        test_util::create_temp_bed_with_suffix,
+        util::EndConvention,
+    };
+    use std::fs::read_to_string;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn test_coordinates_beyond_i32() {
+        let bed_1_path = create_temp_bed_with_suffix(
+            "chr1 3000000000 3000000100 name_1 1\n\
+            chr1 3000000100 3000000200 name_2 2\n",
+            ".bed",
+        )
+        .unwrap();
+        let bed_2_path = create_temp_bed_with_suffix(
+            "chr1 3000000100 3000000200 name_1 5\n\
+            chr2 4000000000 4000000100 name_2 7\n",
+            ".bed",
+        )
+        .unwrap();
+
+        let zipper = RefinedBedZipper::new(
+            vec![
+                bed_1_path.to_str().unwrap().to_string(),
+                bed_2_path.to_str().unwrap().to_string(),
+            ],
+            0,
+            100,
+            0.,
+            EndConvention::Exclusive,
+        );
+        let lines: Vec<(String, i64, i64, Vec<f64>)> = zipper
+            .try_to_iter()
+            .unwrap()
+            .map(|line| {
+                (line.chrom, line.start, line.end_exclusive, line.values)
+            })
+            .collect();
+
+        let expected: Vec<(String, i64, i64, Vec<f64>)> = vec![
+            ("chr1".into(), 3000000000, 3000000100, vec![1., 0.]),
+            ("chr1".into(), 3000000100, 3000000200, vec![2., 5.]),
+            ("chr2".into(), 4000000000, 4000000100, vec![0., 7.]),
+        ];
+        assert_eq!(lines.len(), expected.len());
+        for (actual, expected) in lines.into_iter().zip(expected.into_iter()) {
+            assert_eq!(actual.0, expected.0);
+            assert_eq!(actual.1, expected.1);
+            assert_eq!(actual.2, expected.2);
+            assert_vec_almost_eq!(actual.3, expected.3);
+        }
+    }
+
+    #[test]
+    fn test_append_to_file() {
+        let chr1_path = create_temp_bed_with_suffix(
+            "chr1 0 100 name_1 1\n\
+            chr1 100 200 name_2 2\n",
+            ".bed",
+        )
+        .unwrap();
+        let chr2_path =
+            create_temp_bed_with_suffix("chr2 200 300 name_1 3\n", ".bed")
+                .unwrap();
+        let get_zipper = |path: &str| {
+            RefinedBedZipper::new(
+                vec![path.to_string()],
+                0,
+                100,
+                0.,
+                EndConvention::Exclusive,
+            )
+        };
+
+        let out_path = NamedTempFile::new().unwrap().into_temp_path();
+        let out_path = out_path.to_str().unwrap();
+        get_zipper(chr1_path.to_str().unwrap())
+            .write_to_file(out_path, false)
+            .unwrap();
+        get_zipper(chr2_path.to_str().unwrap())
+            .write_to_file(out_path, true)
+            .unwrap();
+        assert_eq!(
+            read_to_string(out_path).unwrap(),
+            "chr1\t0\t100\t1\nchr1\t100\t200\t2\nchr2\t200\t300\t3\n"
+        );
+
+        // writing without appending replaces the shards
+        get_zipper(chr2_path.to_str().unwrap())
+            .write_to_file(out_path, false)
+            .unwrap();
+        assert_eq!(read_to_string(out_path).unwrap(), "chr2\t200\t300\t3\n");
+    }
+
+    #[test]
+    fn test_mismatched_bin_sizes() {
+        let bin_100_path = create_temp_bed_with_suffix(
+            "chr1 0 100 name_1 1\n\
+            chr1 100 200 name_2 2\n",
+            ".bed",
+        )
+        .unwrap();
+        let bin_50_path = create_temp_bed_with_suffix(
+            "chr1 0 50 name_1 1\n\
+            chr1 50 100 name_2 2\n",
+            ".bed",
+        )
+        .unwrap();
+        let get_zipper = |paths: Vec<&str>, interval_length| {
+            RefinedBedZipper::new(
+                paths.into_iter().map(|p| p.to_string()).collect(),
+                0,
+                interval_length,
+                0.,
+                EndConvention::Exclusive,
+            )
+        };
+        let bin_100_path = bin_100_path.to_str().unwrap();
+        let bin_50_path = bin_50_path.to_str().unwrap();
+        assert!(get_zipper(vec![bin_100_path, bin_100_path], 100)
+            .try_to_iter()
+            .is_ok());
+        assert!(get_zipper(vec![bin_100_path, bin_50_path], 100)
+            .try_to_iter()
+            .is_err());
+        assert!(get_zipper(vec![bin_50_path, bin_100_path], 50)
+            .try_to_iter()
+            .is_err());
+    }
+
+    #[test]
+    fn test_inclusive_end_input() {
+        let bed_1_path = create_temp_bed_with_suffix(
+            "chr1 0 99 name_1 1\n\
+            chr1 100 199 name_2 2\n",
+            ".bed",
+        )
+        .unwrap();
+        let bed_2_path = create_temp_bed_with_suffix(
+            "chr1 100 199 name_1 5\n\
+            chr2 0 99 name_2 7\n",
+            ".bed",
+        )
+        .unwrap();
+        let get_zipper = |input_end_convention| {
+            RefinedBedZipper::new(
+                vec![
+                    bed_1_path.to_str().unwrap().to_string(),
+                    bed_2_path.to_str().unwrap().to_string(),
+                ],
+                0,
+                100,
+                0.,
+                input_end_convention,
+            )
+        };
+
+        let out_path = NamedTempFile::new().unwrap().into_temp_path();
+        let out_path = out_path.to_str().unwrap();
+        get_zipper(EndConvention::Inclusive)
+            .write_to_file(out_path, false)
+            .unwrap();
+        // the output follows the exclusive end convention of the BED format
+        assert_eq!(
+            read_to_string(out_path).unwrap(),
+            "chr1\t0\t100\t1\t0\n\
+            chr1\t100\t200\t2\t5\n\
+            chr2\t0\t100\t0\t7\n"
+        );
+
+        // reading the same files with exclusive ends points to the convention
+        match get_zipper(EndConvention::Exclusive).try_to_iter() {
+            Err(Error::Validation(msg)) => {
+                assert!(msg.contains("likely use the Inclusive end convention"))
+            }
+            _ => panic!("expected a validation error"),
+        }
+    }
+}
@@ -0,0 +1,477 @@
+use crate::{
+    error::Error,
+    util::{get_chrom_interval_map, is_bed_non_data_line},
+};
+use biofile::{
+    bed::{Bed, Chrom},
+    util::TrackVariant,
+};
+use math::{
+    interval::{traits::Interval, I64Interval},
+    iter::{AggregateOp, IntoBinnedIntervalIter},
+    partition::integer_interval_map::IntegerIntervalMap,
+    set::traits::{Finite, Intersect},
+};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufRead, BufReader},
+};
+
+/// How the values of the base pairs in a region are aggregated. The base
+/// pairs in the region not covered by the track have a value of 0.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum RegionAggregateOp {
+    Mean,
+    Max,
+    Sum,
+    Min,
+}
+
+impl Default for RegionAggregateOp {
+    fn default() -> Self {
+        RegionAggregateOp::Mean
+    }
+}
+
+impl RegionAggregateOp {
+    /// Parses the op from the name field of a region, i.e., one of `mean`,
+    /// `max`, `sum` and `min`, returning `None` for any other name.
+    pub fn from_name(name: &str) -> Option<RegionAggregateOp> {
+        match name {
+            "mean" => Some(RegionAggregateOp::Mean),
+            "max" => Some(RegionAggregateOp::Max),
+            "sum" => Some(RegionAggregateOp::Sum),
+            "min" => Some(RegionAggregateOp::Min),
+            _ => None,
+        }
+    }
+}
+
+/// The aggregated value of the track over a region.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RegionValue {
+    pub chrom: Chrom,
+    pub interval: I64Interval,
+    pub op: RegionAggregateOp,
+    pub value: f64,
+}
+
+/// Aggregates the values of the track over each of the regions in the BED
+/// file at `regions_path`, in the order of the regions. The op for each
+/// region is read from its name field, e.g., `max` over peaks and `mean` over
+/// gene bodies, so that a single pass can produce mixed statistics. The
+/// `default_op` is used for the regions without a name field or with a name
+/// not recognized by `RegionAggregateOp::from_name`.
+///
+/// Returns an error if a region has malformed coordinates or is empty.
+pub fn aggregate_over_regions(
+    chrom_to_interval_map: &HashMap<Chrom, IntegerIntervalMap<f64>>,
+    regions_path: &str,
+    default_op: RegionAggregateOp,
+) -> Result<Vec<RegionValue>, Error> {
+    let file = File::open(regions_path).map_err(|why| {
+        Error::io(format!("failed to open {}", regions_path), why)
+    })?;
+    // the intervals of each chromosome in sorted order, collected at the first
+    // region on the chromosome so that each region is located by a binary
+    // search instead of a scan from the start of the chromosome
+    let mut chrom_to_intervals: HashMap<Chrom, Vec<(I64Interval, f64)>> =
+        HashMap::new();
+    let mut region_values = Vec::new();
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|why| {
+            Error::io(format!("failed to read {}", regions_path), why)
+        })?;
+        if is_bed_non_data_line(&line) {
+            continue;
+        }
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let parse_coordinate = |field: Option<&&str>| {
+            field.and_then(|f| f.parse::<i64>().ok()).ok_or_else(|| {
+                Error::Parse(format!(
+                    "invalid coordinates in line {} of {}: {}",
+                    i + 1,
+                    regions_path,
+                    line
+                ))
+            })
+        };
+        let start = parse_coordinate(fields.get(1))?;
+        let end = parse_coordinate(fields.get(2))?;
+        if end <= start {
+            return Err(Error::Parse(format!(
+                "empty region in line {} of {}: {}",
+                i + 1,
+                regions_path,
+                line
+            )));
+        }
+        let chrom = fields[0].to_string();
+        let interval = I64Interval::new(start, end - 1);
+        let op = fields
+            .get(3)
+            .and_then(|name| RegionAggregateOp::from_name(name))
+            .unwrap_or(default_op);
+        let intervals =
+            chrom_to_intervals.entry(chrom.clone()).or_insert_with(|| {
+                chrom_to_interval_map
+                    .get(&chrom)
+                    .map_or_else(Vec::new, get_sorted_intervals)
+            });
+        region_values.push(RegionValue {
+            value: aggregate_over_interval(intervals, interval, op),
+            chrom,
+            interval,
+            op,
+        });
+    }
+    Ok(region_values)
+}
+
+/// The prefix sums of the binned signal of a track on each chromosome, which
+/// answer the total signal over a region with two binary searches instead of
+/// a scan over the intervals of the region, e.g., when aggregating over many
+/// regions. Only the bins with signal are stored, at the memory cost of one
+/// `i64` bin start and one `f64` prefix sum per bin, so a small `bin_size`
+/// over a densely covered genome costs about 16 bytes per base pair.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PrefixSumTrack {
+    bin_size: i64,
+    // the sorted starts of the bins with signal, and the prefix sums where
+    // `prefix_sums[i]` is the total signal in the first `i` bins
+    chrom_to_prefix_sums: HashMap<Chrom, (Vec<i64>, Vec<f64>)>,
+}
+
+impl PrefixSumTrack {
+    /// Bins the signal of each chromosome into consecutive bins of size
+    /// `bin_size` aligned at index 0, where the signal of a bin is the sum of
+    /// the values of its base pairs.
+    pub fn from_map(
+        chrom_map: &HashMap<Chrom, IntegerIntervalMap<f64>>,
+        bin_size: i64,
+    ) -> Result<PrefixSumTrack, Error> {
+        if bin_size <= 0 {
+            return Err(Error::Validation(format!(
+                "the bin size must be positive, received {}",
+                bin_size
+            )));
+        }
+        let chrom_to_prefix_sums = chrom_map
+            .iter()
+            .map(|(chrom, interval_map)| {
+                let mut bin_starts = Vec::new();
+                let mut prefix_sums = vec![0.];
+                for (bin, sum) in interval_map.iter().into_binned_interval_iter(
+                    bin_size,
+                    AggregateOp::Sum,
+                    Box::new(|item| (*item.0, *item.1)),
+                ) {
+                    bin_starts.push(bin.get_start());
+                    prefix_sums.push(prefix_sums[prefix_sums.len() - 1] + sum);
+                }
+                (chrom.clone(), (bin_starts, prefix_sums))
+            })
+            .collect();
+        Ok(PrefixSumTrack {
+            bin_size,
+            chrom_to_prefix_sums,
+        })
+    }
+
+    pub fn bin_size(&self) -> i64 {
+        self.bin_size
+    }
+
+    /// The total signal over the bins whose starts are in the 0-based
+    /// half-open region `[start, end)` on `chrom`, which is the exact total
+    /// signal over the region if `start` and `end` are multiples of the bin
+    /// size. Returns 0 for a chromosome without signal.
+    pub fn sum_over(&self, chrom: &str, start: i64, end: i64) -> f64 {
+        match self.chrom_to_prefix_sums.get(chrom) {
+            Some((bin_starts, prefix_sums)) => {
+                let first = bin_starts
+                    .binary_search(&start)
+                    .unwrap_or_else(|index| index);
+                let last = bin_starts
+                    .binary_search(&end)
+                    .unwrap_or_else(|index| index);
+                if last > first {
+                    prefix_sums[last] - prefix_sums[first]
+                } else {
+                    0.
+                }
+            }
+            None => 0.,
+        }
+    }
+}
+
+/// The fraction of reads in peaks (FRiP), i.e., the fraction of the total
+/// signal of the `reads` track over all the chromosomes that falls within the
+/// intervals of the `peaks`. The signal of a line is its value times the
+/// number of its base pairs, so a read partially overlapping with a peak
+/// contributes in proportion to the overlap. Returns 0 if the total signal is
+/// 0, e.g., for an empty reads track.
+pub fn compute_frip(reads: &TrackVariant, peaks: &Bed) -> Result<f64, Error> {
+    let (in_peaks, total) =
+        get_chrom_signal_in_peaks(reads, peaks)?.values().fold(
+            (0., 0.),
+            |(in_peaks, total), &(chrom_in_peaks, chrom_total)| {
+                (in_peaks + chrom_in_peaks, total + chrom_total)
+            },
+        );
+    Ok(get_frip(in_peaks, total))
+}
+
+/// Same as `compute_frip` but for each chromosome of the `reads` track
+/// separately.
+pub fn compute_chrom_frip(
+    reads: &TrackVariant,
+    peaks: &Bed,
+) -> Result<HashMap<Chrom, f64>, Error> {
+    Ok(get_chrom_signal_in_peaks(reads, peaks)?
+        .into_iter()
+        .map(|(chrom, (in_peaks, total))| (chrom, get_frip(in_peaks, total)))
+        .collect())
+}
+
+/// Returns `(signal within the peaks, total signal)` of the `reads` track for
+/// each chromosome of the track.
+fn get_chrom_signal_in_peaks(
+    reads: &TrackVariant,
+    peaks: &Bed,
+) -> Result<HashMap<Chrom, (f64, f64)>, Error> {
+    let chrom_to_peaks = peaks.get_chrom_to_intervals();
+    Ok(get_chrom_interval_map(reads, None)?
+        .into_iter()
+        .map(|(chrom, interval_map)| {
+            let chrom_peaks = chrom_to_peaks.get(&chrom);
+            let signals = interval_map.iter().fold(
+                (0., 0.),
+                |(in_peaks, total), (interval, &value)| {
+                    let overlap = chrom_peaks.map_or(0, |chrom_peaks| {
+                        interval.intersect(chrom_peaks).size()
+                    });
+                    (
+                        in_peaks + value * overlap as f64,
+                        total + value * interval.size() as f64,
+                    )
+                },
+            );
+            (chrom, signals)
+        })
+        .collect())
+}
+
+fn get_frip(in_peaks: f64, total: f64) -> f64 {
+    if total == 0. {
+        0.
+    } else {
+        in_peaks / total
+    }
+}
+
+fn get_sorted_intervals(
+    interval_map: &IntegerIntervalMap<f64>,
+) -> Vec<(I64Interval, f64)> {
+    interval_map
+        .iter()
+        .map(|(&interval, &value)| (interval, value))
+        .collect()
+}
+
+/// Aggregates the values of the sorted disjoint `intervals` over `interval`.
+fn aggregate_over_interval(
+    intervals: &[(I64Interval, f64)],
+    interval: I64Interval,
+    op: RegionAggregateOp,
+) -> f64 {
+    let mut sum = 0.;
+    let mut num_covered = 0usize;
+    let mut max = std::f64::NEG_INFINITY;
+    let mut min = std::f64::INFINITY;
+    let first =
+        intervals.partition_point(|(i, _)| i.get_end() < interval.get_start());
+    for &(i, value) in intervals[first..]
+        .iter()
+        .take_while(|(i, _)| i.get_start() <= interval.get_end())
+    {
+        let overlap = I64Interval::new(
+            i.get_start().max(interval.get_start()),
+            i.get_end().min(interval.get_end()),
+        )
+        .size();
+        sum += value * overlap as f64;
+        num_covered += overlap;
+        max = max.max(value);
+        min = min.min(value);
+    }
+    if num_covered < interval.size() {
+        // the uncovered base pairs have a value of 0
+        max = max.max(0.);
+        min = min.min(0.);
+    }
+    match op {
+        RegionAggregateOp::Mean => sum / interval.size() as f64,
+        RegionAggregateOp::Max => max,
+        RegionAggregateOp::Sum => sum,
+        RegionAggregateOp::Min => min,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        assert_almost_eq,
+        region_aggregation::{
+            aggregate_over_interval, aggregate_over_regions,
+            compute_chrom_frip, compute_frip, get_sorted_intervals,
+            PrefixSumTrack, RegionAggregateOp,
+        },
+        test_util::create_temp_bed,
+        util::get_chrom_interval_map,
+    };
+    use biofile::{bed::Bed, util::TrackVariant};
+    use math::interval::I64Interval;
+
+    #[test]
+    fn test_aggregate_over_regions() {
+        let track_path =
+            create_temp_bed("chr1 0 10 id0 2\nchr1 10 20 id1 6\n").unwrap();
+        let chrom_to_interval_map = get_chrom_interval_map(
+            &TrackVariant::Bed(Bed::new(track_path.to_str().unwrap(), false)),
+            None,
+        )
+        .unwrap();
+        let regions_path = create_temp_bed(
+            "chr1 0 20 mean\n\
+            chr1 0 20 max\n\
+            chr1 0 20 sum\n\
+            chr1 5 25 min\n\
+            chr1 5 25 peak\n\
+            chr1 0 20\n\
+            chr2 0 10 max\n",
+        )
+        .unwrap();
+
+        let region_values = aggregate_over_regions(
+            &chrom_to_interval_map,
+            regions_path.to_str().unwrap(),
+            RegionAggregateOp::Sum,
+        )
+        .unwrap();
+        let ops: Vec<RegionAggregateOp> =
+            region_values.iter().map(|r| r.op).collect();
+        assert_eq!(ops, vec![
+            RegionAggregateOp::Mean,
+            RegionAggregateOp::Max,
+            RegionAggregateOp::Sum,
+            RegionAggregateOp::Min,
+            RegionAggregateOp::Sum,
+            RegionAggregateOp::Sum,
+            RegionAggregateOp::Max,
+        ]);
+        let values: Vec<f64> = region_values.iter().map(|r| r.value).collect();
+        let expected = vec![4., 6., 80., 0., 70., 80., 0.];
+        assert_eq!(values.len(), expected.len());
+        for (value, expected) in values.into_iter().zip(expected.into_iter()) {
+            assert_almost_eq!(value, expected);
+        }
+
+        let bad_regions_path = create_temp_bed("chr1 10 10 mean\n").unwrap();
+        assert!(aggregate_over_regions(
+            &chrom_to_interval_map,
+            bad_regions_path.to_str().unwrap(),
+            RegionAggregateOp::Mean,
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_prefix_sum_track() {
+        let track_path = create_temp_bed(
+            "chr1 0 10 id0 2\n\
+            chr1 10 25 id1 6\n\
+            chr1 40 45 id2 -1.5\n\
+            chr1 95 130 id3 3\n\
+            chr2 5 15 id4 1\n",
+        )
+        .unwrap();
+        let chrom_to_interval_map = get_chrom_interval_map(
+            &TrackVariant::Bed(Bed::new(track_path.to_str().unwrap(), false)),
+            None,
+        )
+        .unwrap();
+        let prefix_sum_track =
+            PrefixSumTrack::from_map(&chrom_to_interval_map, 5).unwrap();
+        for &(chrom, start, end) in [
+            ("chr1", 0, 5),
+            ("chr1", 0, 200),
+            ("chr1", 5, 40),
+            ("chr1", 20, 45),
+            ("chr1", 45, 95),
+            ("chr1", 100, 125),
+            ("chr2", 0, 10),
+            ("chr2", 10, 20),
+            ("chr3", 0, 100),
+        ]
+        .iter()
+        {
+            let brute_force_sum =
+                chrom_to_interval_map.get(chrom).map_or(0., |interval_map| {
+                    aggregate_over_interval(
+                        &get_sorted_intervals(interval_map),
+                        I64Interval::new(start, end - 1),
+                        RegionAggregateOp::Sum,
+                    )
+                });
+            assert_almost_eq!(
+                prefix_sum_track.sum_over(chrom, start, end),
+                brute_force_sum
+            );
+        }
+        assert_almost_eq!(prefix_sum_track.sum_over("chr1", 30, 30), 0.);
+        assert!(PrefixSumTrack::from_map(&chrom_to_interval_map, 0).is_err());
+    }
+
+    #[test]
+    fn test_frip() {
+        let reads_path = create_temp_bed(
+            "chr1 10 20 read0\n\
+            chr1 30 40 read1\n\
+            chr2 0 10 read2\n",
+        )
+        .unwrap();
+        let reads =
+            TrackVariant::Bed(Bed::new(reads_path.to_str().unwrap(), true));
+        let get_peaks = |content: &str| {
+            let path = create_temp_bed(content).unwrap();
+            (Bed::new(path.to_str().unwrap(), false), path)
+        };
+
+        // all the reads fall inside the peaks
+        let (peaks, _path) = get_peaks("chr1 0 50 peak0\nchr2 0 100 peak1\n");
+        assert_almost_eq!(compute_frip(&reads, &peaks).unwrap(), 1.);
+
+        // half of each of the first two reads overlaps with the peak
+        let (peaks, _path) = get_peaks("chr1 15 35 peak0\nchr3 0 100 peak1\n");
+        assert_almost_eq!(compute_frip(&reads, &peaks).unwrap(), 10. / 30.);
+        let chrom_frip = compute_chrom_frip(&reads, &peaks).unwrap();
+        assert_eq!(chrom_frip.len(), 2);
+        assert_almost_eq!(chrom_frip["chr1"], 0.5);
+        assert_almost_eq!(chrom_frip["chr2"], 0.);
+
+        // zero total reads gives 0 instead of NaN
+        let empty_reads_path = create_temp_bed("chr1 0 10 read0 0\n").unwrap();
+        let empty_reads = TrackVariant::Bed(Bed::new(
+            empty_reads_path.to_str().unwrap(),
+            false,
+        ));
+        assert_eq!(compute_frip(&empty_reads, &peaks).unwrap(), 0.);
+        assert!(compute_chrom_frip(&empty_reads, &peaks)
+            .unwrap()
+            .values()
+            .all(|&frip| frip == 0.));
+    }
+}
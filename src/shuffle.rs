@@ -0,0 +1,160 @@
+use crate::error::Error;
+use math::{
+    interval::I64Interval,
+    partition::integer_interval_map::IntegerIntervalMap,
+    set::{
+        ordered_integer_set::OrderedIntegerSet,
+        traits::{Finite, Intersect},
+    },
+};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::collections::HashMap;
+
+type Chrom = String;
+type Coord = i64;
+type Value = f64;
+
+/// The maximum number of random positions to try for each interval before
+/// giving up on finding one that avoids the excluded regions.
+const MAX_PLACEMENT_ATTEMPTS: usize = 10000;
+
+/// Randomly repositions each interval within its chromosome while preserving
+/// the interval lengths, their values and the number of intervals, which can
+/// be used to generate an empirical null model. The shuffled intervals are
+/// aggregated into the returned maps as in `get_chrom_interval_map`.
+///
+/// * `chrom_intervals`: the intervals of the input lines with their values on
+///   each chromosome, e.g., as returned by `get_chrom_intervals`. The lines are
+///   shuffled as they are rather than the refined intervals of a
+///   `get_chrom_interval_map`, which would split the overlapping lines.
+/// * `chrom_sizes`: maps each chromosome to its size. The shuffled intervals
+///   will lie within `[0, size)`.
+/// * `exclude`: the shuffled intervals will not overlap with these regions.
+/// * `seed`: the same seed will produce the same shuffled track.
+pub fn shuffle_track(
+    chrom_intervals: &HashMap<Chrom, Vec<(I64Interval, Value)>>,
+    chrom_sizes: &HashMap<Chrom, Coord>,
+    exclude: Option<&HashMap<Chrom, OrderedIntegerSet<Coord>>>,
+    seed: u64,
+) -> Result<HashMap<Chrom, IntegerIntervalMap<Value>>, Error> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    shuffle_track_with_rng(chrom_intervals, chrom_sizes, exclude, &mut rng)
+}
+
+/// Same as `shuffle_track` except that the random positions are drawn from
+/// `rng`, so that multiple shuffles can share a single random sequence.
+pub fn shuffle_track_with_rng<R: Rng>(
+    chrom_intervals: &HashMap<Chrom, Vec<(I64Interval, Value)>>,
+    chrom_sizes: &HashMap<Chrom, Coord>,
+    exclude: Option<&HashMap<Chrom, OrderedIntegerSet<Coord>>>,
+    rng: &mut R,
+) -> Result<HashMap<Chrom, IntegerIntervalMap<Value>>, Error> {
+    let mut shuffled = HashMap::new();
+
+    // the chromosomes are visited in sorted order for reproducibility
+    for chrom in crate::util::get_sorted_keys(chrom_intervals) {
+        let chrom_size = *chrom_sizes.get(&chrom).ok_or_else(|| {
+            Error::Validation(format!(
+                "the size of chromosome {} is not provided",
+                chrom
+            ))
+        })?;
+        let excluded_intervals = exclude.and_then(|e| e.get(&chrom));
+
+        let mut shuffled_map = IntegerIntervalMap::new();
+        for &(interval, value) in chrom_intervals[&chrom].iter() {
+            let length = interval.size() as Coord;
+            if length > chrom_size {
+                return Err(Error::Validation(format!(
+                    "interval {:?} is longer than chromosome {} of size {}",
+                    interval, chrom, chrom_size
+                )));
+            }
+            let max_start = chrom_size - length;
+            let mut placement = None;
+            for _ in 0..MAX_PLACEMENT_ATTEMPTS {
+                let start = rng.gen_range(0, max_start + 1);
+                let candidate = I64Interval::new(start, start + length - 1);
+                match excluded_intervals {
+                    Some(excluded) => {
+                        if !candidate.has_non_empty_intersection_with(excluded)
+                        {
+                            placement = Some(candidate);
+                            break;
+                        }
+                    }
+                    None => {
+                        placement = Some(candidate);
+                        break;
+                    }
+                }
+            }
+            match placement {
+                Some(candidate) => shuffled_map.aggregate(candidate, value),
+                None => {
+                    return Err(Error::Generic(format!(
+                        "failed to place the interval {:?} on chromosome {} \
+                        outside of the excluded regions after {} attempts",
+                        interval, chrom, MAX_PLACEMENT_ATTEMPTS
+                    )))
+                }
+            }
+        }
+        shuffled.insert(chrom, shuffled_map);
+    }
+    Ok(shuffled)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::shuffle::shuffle_track;
+    use math::{
+        interval::{traits::Interval, I64Interval},
+        set::{ordered_integer_set::OrderedIntegerSet, traits::Finite},
+    };
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_shuffle_track() {
+        // the two overlapping intervals are shuffled as they are
+        let mut chrom_intervals = HashMap::new();
+        chrom_intervals.insert("chr1".to_string(), vec![
+            (I64Interval::new(0, 9), 1.),
+            (I64Interval::new(5, 24), 2.),
+        ]);
+
+        let mut chrom_sizes = HashMap::new();
+        chrom_sizes.insert("chr1".to_string(), 100);
+
+        let mut exclude = HashMap::new();
+        exclude.insert(
+            "chr1".to_string(),
+            OrderedIntegerSet::from_slice(&[[50, 99]]),
+        );
+
+        let shuffled =
+            shuffle_track(&chrom_intervals, &chrom_sizes, Some(&exclude), 7)
+                .unwrap();
+        let mut total = 0.;
+        for (interval, value) in shuffled["chr1"].iter() {
+            assert!(interval.get_start() >= 0);
+            assert!(interval.get_end() < 50);
+            total += value * interval.size() as f64;
+        }
+        // the length-weighted sum of the values is preserved
+        assert_almost_eq!(total, 50.);
+
+        let shuffled_again =
+            shuffle_track(&chrom_intervals, &chrom_sizes, Some(&exclude), 7)
+                .unwrap();
+        assert_eq!(
+            shuffled["chr1"].iter().collect::<Vec<_>>(),
+            shuffled_again["chr1"].iter().collect::<Vec<_>>()
+        );
+
+        // the chromosome size must be provided
+        assert!(
+            shuffle_track(&chrom_intervals, &HashMap::new(), None, 7).is_err()
+        );
+    }
+}
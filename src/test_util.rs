@@ -1,5 +1,10 @@
+use crate::track_conversion::{detect_track_format, TrackFormat};
+use biofile::{
+    bed::{Bed, BedDataLineIter},
+    bedgraph::{BedGraph, BedGraphDataLineIter},
+};
 use std::io::{BufWriter, Write};
-use tempfile::{NamedTempFile, TempPath};
+use tempfile::{Builder, NamedTempFile, TempPath};
 #[macro_export]
 macro_rules! check_chrom {
     (
@@ -23,6 +28,75 @@ macro_rules! check_chrom {
     };
 }
 
+/// Asserts that the track written at `$path`, in either the BED or the
+/// bedGraph format, consists of the `$expected` lines
+/// `(chrom, start, end_exclusive, value)` in order, where the values are
+/// compared within `$epsilon`, or 1e-8 by default. The value of a BED line is
+/// its score.
+#[macro_export]
+macro_rules! assert_track_eq {
+    ($path:expr, $expected:expr) => {
+        $crate::assert_track_eq!($path, $expected, 1e-8)
+    };
+    ($path:expr, $expected:expr, $epsilon:expr) => {{
+        let lines = $crate::test_util::read_track_lines($path);
+        assert_eq!(
+            lines.len(),
+            $expected.len(),
+            "number of lines in {}",
+            $path
+        );
+        for ((chrom, start, end, value), &(e_chrom, e_start, e_end, e_value)) in
+            lines.iter().zip($expected.iter())
+        {
+            assert_eq!(
+                (chrom.as_str(), *start, *end),
+                (e_chrom, e_start, e_end)
+            );
+            assert!(
+                (value - e_value).abs() < $epsilon,
+                "{}:{}-{} has the value {}, expected {}",
+                chrom,
+                start,
+                end,
+                value,
+                e_value
+            );
+        }
+    }};
+}
+
+/// Reads the `(chrom, start, end_exclusive, value)` lines of the track at
+/// `path`, whose format is detected by `detect_track_format`. Panics if the
+/// track cannot be read, its format cannot be detected, e.g., because it has
+/// no data line, or a BED line has no score.
+pub fn read_track_lines(path: &str) -> Vec<(String, i64, i64, f64)> {
+    match detect_track_format(path).unwrap() {
+        Some(TrackFormat::BedGraph) => {
+            let iter: BedGraphDataLineIter<f64> =
+                BedGraph::new(path, false).to_iter();
+            iter.map(|line| {
+                (line.chrom, line.start, line.end_exclusive, line.value)
+            })
+            .collect()
+        }
+        Some(TrackFormat::Bed) => {
+            let iter: BedDataLineIter<f64> = Bed::new(path, false).to_iter();
+            iter.map(|line| {
+                let score = line.score.unwrap_or_else(|| {
+                    panic!(
+                        "no score for {}:{}-{}",
+                        line.chrom, line.start, line.end
+                    )
+                });
+                (line.chrom, line.start, line.end, score)
+            })
+            .collect()
+        }
+        None => panic!("cannot detect the format of {}", path),
+    }
+}
+
 pub fn create_temp_bed(content: &str) -> std::io::Result<TempPath> {
     let file = NamedTempFile::new().unwrap();
     {
@@ -31,3 +105,17 @@ pub fn create_temp_bed(content: &str) -> std::io::Result<TempPath> {
     }
     Ok(file.into_temp_path())
 }
+
+/// Same as `create_temp_bed` except that the file name will end with `suffix`,
+/// e.g. ".bed" or ".bedgraph", for readers that dispatch on the file extension.
+pub fn create_temp_bed_with_suffix(
+    content: &str,
+    suffix: &str,
+) -> std::io::Result<TempPath> {
+    let file = Builder::new().suffix(suffix).tempfile()?;
+    {
+        let mut writer = BufWriter::new(&file);
+        writer.write_fmt(format_args!("{}", content))?;
+    }
+    Ok(file.into_temp_path())
+}
@@ -1,3 +1,4 @@
+use crate::error::Error;
 use math::{
     interval::I64Interval,
     iter::{AggregateOp, IntoBinnedIntervalIter},
@@ -12,11 +13,13 @@ use std::{
 struct HeapItem {
     interval: I64Interval,
     val: f64,
+    // the items are ordered by this key derived from `val`
+    key: f64,
 }
 
 impl PartialOrd for HeapItem {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        self.val.partial_cmp(&other.val)
+        self.key.partial_cmp(&other.key)
     }
 }
 
@@ -28,23 +31,57 @@ impl Ord for HeapItem {
 
 impl Eq for HeapItem {}
 
+/// Returns the `k` bins of size `bin_size` with the largest average values in
+/// the `interval_map`. A `bin_size` of 0 uses the intervals of the map as the
+/// bins, e.g., for tracks of variable-size intervals.
 pub fn get_top_k_bin_map(
     interval_map: &IntegerIntervalMap<f64>,
     k: i64,
     bin_size: i64,
-) -> Result<IntegerIntervalMap<f64>, String> {
-    let binned_iter = interval_map.iter().into_binned_interval_iter(
-        bin_size,
-        AggregateOp::Average,
-        Box::new(|item| (*item.0, *item.1)),
-    );
+) -> Result<IntegerIntervalMap<f64>, Error> {
+    get_top_k_bin_map_by_key(interval_map, k, bin_size, |val| val)
+}
+
+/// Same as `get_top_k_bin_map` except that the top `k` bins are those with
+/// the largest absolute values, e.g., the strongly up and the strongly down
+/// bins of a signed track. The bins keep their signed values.
+pub fn get_top_k_abs_bin_map(
+    interval_map: &IntegerIntervalMap<f64>,
+    k: i64,
+    bin_size: i64,
+) -> Result<IntegerIntervalMap<f64>, Error> {
+    get_top_k_bin_map_by_key(interval_map, k, bin_size, f64::abs)
+}
+
+fn get_top_k_bin_map_by_key<F: Fn(f64) -> f64>(
+    interval_map: &IntegerIntervalMap<f64>,
+    k: i64,
+    bin_size: i64,
+    key: F,
+) -> Result<IntegerIntervalMap<f64>, Error> {
+    let bins: Vec<(I64Interval, f64)> = if bin_size == 0 {
+        interval_map
+            .iter()
+            .map(|(&interval, &val)| (interval, val))
+            .collect()
+    } else {
+        interval_map
+            .iter()
+            .into_binned_interval_iter(
+                bin_size,
+                AggregateOp::Average,
+                Box::new(|item| (*item.0, *item.1)),
+            )
+            .collect()
+    };
 
     let mut heap = BinaryHeap::new();
 
-    for (interval, val) in binned_iter {
+    for (interval, val) in bins {
         heap.push(Reverse(HeapItem {
             interval,
             val,
+            key: key(val),
         }));
         if (heap.len() as i64) > k {
             heap.pop();
@@ -57,10 +94,48 @@ pub fn get_top_k_bin_map(
     Ok(top_interval_map)
 }
 
+/// Returns the fraction of the total signal of `interval_map` that is
+/// concentrated in its top `k` bins, where the value of each bin is the
+/// average over its base pairs as in `get_top_k_bin_map`. Returns 1 if `k` is
+/// at least the number of bins, and 0 if there are no bins or if the total
+/// signal is 0.
+pub fn top_k_signal_fraction(
+    interval_map: &IntegerIntervalMap<f64>,
+    k: i64,
+    bin_size: i64,
+) -> Result<f64, Error> {
+    let (num_bins, total) = interval_map
+        .iter()
+        .into_binned_interval_iter(
+            bin_size,
+            AggregateOp::Average,
+            Box::new(|item| (*item.0, *item.1)),
+        )
+        .fold((0i64, 0.), |(num_bins, total), (_interval, val)| {
+            (num_bins + 1, total + val)
+        });
+    if num_bins == 0 {
+        return Ok(0.);
+    }
+    if k >= num_bins {
+        return Ok(1.);
+    }
+    if total == 0. {
+        return Ok(0.);
+    }
+    let top_k_total: f64 = get_top_k_bin_map(interval_map, k, bin_size)?
+        .iter()
+        .map(|(_interval, val)| val)
+        .sum();
+    Ok(top_k_total / total)
+}
+
 #[cfg(test)]
 mod tests {
     use crate::{
-        check_chrom, top_k::get_top_k_bin_map, util::get_chrom_interval_map,
+        assert_almost_eq, check_chrom,
+        top_k::{get_top_k_bin_map, top_k_signal_fraction},
+        util::get_chrom_interval_map,
     };
     use biofile::{bed::Bed, util::TrackVariant};
     use math::{
@@ -126,5 +201,27 @@ mod tests {
             let mut chr3_map_iter = top_k_map["chr3"].iter();
             check_chrom!(chr3_map_iter, (2000, 2049, 25.), (2050, 2099, 25.));
         }
+
+        // the bin values on chr1 are 10, 10, 75, 125, 25 and 500
+        let chr1_map = &chrom_to_interval_map["chr1"];
+        assert_almost_eq!(
+            top_k_signal_fraction(chr1_map, 3, 50).unwrap(),
+            700. / 745.
+        );
+        assert_almost_eq!(
+            top_k_signal_fraction(chr1_map, 1, 50).unwrap(),
+            500. / 745.
+        );
+        assert_eq!(top_k_signal_fraction(chr1_map, 0, 50).unwrap(), 0.);
+        assert_eq!(top_k_signal_fraction(chr1_map, 6, 50).unwrap(), 1.);
+        assert_eq!(
+            top_k_signal_fraction(&chrom_to_interval_map["chr3"], 3, 50)
+                .unwrap(),
+            1.
+        );
+        assert_eq!(
+            top_k_signal_fraction(&IntegerIntervalMap::new(), 3, 50).unwrap(),
+            0.
+        );
     }
 }
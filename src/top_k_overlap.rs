@@ -1,32 +1,96 @@
-use crate::{top_k::get_top_k_bin_map, util::get_common_refined_binned_iter};
+use crate::{
+    error::Error,
+    shuffle::shuffle_track_with_rng,
+    top_k::{get_top_k_abs_bin_map, get_top_k_bin_map},
+    util::{
+        get_common_refined_binned_iter, get_sorted_keys, to_chrom_interval_map,
+    },
+};
+use biofile::bed::Chrom;
 use math::{
-    iter::UnionZip, partition::integer_interval_map::IntegerIntervalMap,
+    interval::{traits::Interval, I64Interval},
+    iter::{
+        AggregateOp, CommonRefinementZip, IntoBinnedIntervalIter, UnionZip,
+    },
+    partition::integer_interval_map::IntegerIntervalMap,
+    set::{ordered_integer_set::OrderedIntegerSet, traits::Finite},
 };
-use std::collections::HashMap;
+use rand::{rngs::StdRng, SeedableRng};
+use std::{cmp::Ordering, collections::HashMap, fs, path::Path};
+
+/// How the bins in the top K sets are counted by the overlap ratios. `Bins`
+/// counts each bin as a unit as in `get_top_k_overlap_ratio`, while
+/// `BasePairs` weights each bin by its size as in
+/// `get_top_k_bp_overlap_ratio`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum OverlapWeight {
+    Bins,
+    BasePairs,
+}
+
+impl Default for OverlapWeight {
+    fn default() -> Self {
+        OverlapWeight::Bins
+    }
+}
 
+/// The options for `get_top_k_fraction_overlap_ratio` and
+/// `get_top_k_fraction_overlap_ratio_across_chroms`.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct TopKOverlapOptions {
+    pub weight: OverlapWeight,
+
+    /// Prints the number of bins corresponding to the top K fraction.
+    pub verbose: bool,
+}
+
+/// Same as `get_top_k_overlap_ratio` where K is the `top_k_fraction` of the
+/// bins in which either map has data, as computed by `get_k`. Returns NaN if
+/// neither map has any bin, in which case there is no overlap to measure. The
+/// overlap is measured according to the `options.weight`.
 pub fn get_top_k_fraction_overlap_ratio(
     map1: &IntegerIntervalMap<f64>,
     map2: &IntegerIntervalMap<f64>,
     top_k_fraction: f64,
     bin_size: i64,
-) -> Result<f64, String> {
+    options: &TopKOverlapOptions,
+) -> Result<f64, Error> {
     let k = get_k(map1, map2, top_k_fraction, bin_size);
-    eprintln!(
-        "=> top {} fraction corresponds to {} bins",
-        top_k_fraction, k
-    );
-    get_top_k_overlap_ratio(map1, map2, k, bin_size)
+    if options.verbose {
+        eprintln!(
+            "=> top {} fraction corresponds to {} bins",
+            top_k_fraction, k
+        );
+    }
+    match options.weight {
+        OverlapWeight::Bins => get_top_k_overlap_ratio(map1, map2, k, bin_size),
+        OverlapWeight::BasePairs => {
+            get_top_k_bp_overlap_ratio(map1, map2, k, bin_size)
+        }
+    }
 }
 
+/// The number of bins corresponding to the `top_k_fraction` of the bins in
+/// which either map has data, rounded to the nearest integer. A positive
+/// fraction selects at least one bin as long as there is a bin, so that a tiny
+/// fraction does not select zero bins and make the overlap ratio `0 / 0`.
 fn get_k(
     map1: &IntegerIntervalMap<f64>,
     map2: &IntegerIntervalMap<f64>,
     top_k_fraction: f64,
     bin_size: i64,
 ) -> i64 {
-    let count =
-        get_common_refined_binned_iter(&map1, &map2, bin_size).count() as f64;
-    (count * top_k_fraction) as i64
+    let count = if bin_size == 0 {
+        map1.iter().common_refinement_zip(map2.iter()).count()
+    } else {
+        get_common_refined_binned_iter(&map1, &map2, bin_size).count()
+    };
+    let k = (count as f64 * top_k_fraction).round() as i64;
+    if top_k_fraction > 0. && count > 0 {
+        k.max(1)
+    } else {
+        k
+    }
 }
 
 pub fn get_top_k_overlap_ratio(
@@ -34,32 +98,189 @@ pub fn get_top_k_overlap_ratio(
     map2: &IntegerIntervalMap<f64>,
     k: i64,
     bin_size: i64,
-) -> Result<f64, String> {
+) -> Result<f64, Error> {
+    let (num_overlapped_bins, count) =
+        get_top_k_overlap_counts(map1, map2, k, bin_size)?;
+    Ok((num_overlapped_bins as f64) / (count as f64))
+}
+
+/// Returns the number of bins in both top K sets and that in either top K set.
+fn get_top_k_overlap_counts(
+    map1: &IntegerIntervalMap<f64>,
+    map2: &IntegerIntervalMap<f64>,
+    k: i64,
+    bin_size: i64,
+) -> Result<(usize, usize), Error> {
     let top_k_1 = get_top_k_bin_map(map1, k, bin_size)?;
     let top_k_2 = get_top_k_bin_map(map2, k, bin_size)?;
 
     let iter = get_common_refined_binned_iter(&top_k_1, &top_k_2, bin_size);
 
-    let mut count = 0i64;
-    let mut num_overlapped_bins = 0i64;
+    let mut count = 0usize;
+    let mut num_overlapped_bins = 0usize;
     for (_interval, values) in iter {
         count += 1;
         if values[0].is_some() && values[1].is_some() {
             num_overlapped_bins += 1;
         }
     }
+    Ok((num_overlapped_bins, count))
+}
 
-    Ok((num_overlapped_bins as f64) / (count as f64))
+/// Same as `get_top_k_overlap_ratio` except that each bin is weighted by its
+/// size, i.e., returns the fraction of the base pairs in either top K set that
+/// are in both sets. The two ratios differ when the bins differ in size, e.g.,
+/// for a `bin_size` of 0, which uses the intervals of the maps as the bins.
+pub fn get_top_k_bp_overlap_ratio(
+    map1: &IntegerIntervalMap<f64>,
+    map2: &IntegerIntervalMap<f64>,
+    k: i64,
+    bin_size: i64,
+) -> Result<f64, Error> {
+    let (num_overlapped_bp, num_bp) =
+        get_top_k_bp_overlap_sizes(map1, map2, k, bin_size)?;
+    Ok((num_overlapped_bp as f64) / (num_bp as f64))
+}
+
+/// Returns the number of base pairs in both top K sets and that in either top
+/// K set.
+fn get_top_k_bp_overlap_sizes(
+    map1: &IntegerIntervalMap<f64>,
+    map2: &IntegerIntervalMap<f64>,
+    k: i64,
+    bin_size: i64,
+) -> Result<(usize, usize), Error> {
+    let top_k_1 = get_top_k_bin_map(map1, k, bin_size)?;
+    let top_k_2 = get_top_k_bin_map(map2, k, bin_size)?;
+
+    // the pieces of the common refinement of the top K maps are either in
+    // both sets or in exactly one of them
+    let mut num_bp = 0usize;
+    let mut num_overlapped_bp = 0usize;
+    for (interval, values) in
+        top_k_1.iter().common_refinement_zip(top_k_2.iter())
+    {
+        num_bp += interval.size();
+        if values[0].is_some() && values[1].is_some() {
+            num_overlapped_bp += interval.size();
+        }
+    }
+    Ok((num_overlapped_bp, num_bp))
+}
+
+/// The signed counterpart of `get_top_k_overlap_ratio` for signed tracks,
+/// where the top K bins of each map are those with the largest absolute
+/// values. Returns `(concordant_ratio, discordant_ratio)`, i.e., the fractions
+/// of the bins in either top K set that are in both sets with values of the
+/// same sign and of opposite signs respectively. An overlapping bin with a
+/// value of 0 in either map is neither concordant nor discordant.
+pub fn get_signed_top_k_overlap(
+    map1: &IntegerIntervalMap<f64>,
+    map2: &IntegerIntervalMap<f64>,
+    k: i64,
+    bin_size: i64,
+) -> Result<(f64, f64), Error> {
+    let top_k_1 = get_top_k_abs_bin_map(map1, k, bin_size)?;
+    let top_k_2 = get_top_k_abs_bin_map(map2, k, bin_size)?;
+
+    let iter = get_common_refined_binned_iter(&top_k_1, &top_k_2, bin_size);
+
+    let mut count = 0i64;
+    let mut num_concordant_bins = 0i64;
+    let mut num_discordant_bins = 0i64;
+    for (_interval, values) in iter {
+        count += 1;
+        if let (Some(v1), Some(v2)) = (values[0], values[1]) {
+            let product = v1 * v2;
+            if product > 0. {
+                num_concordant_bins += 1;
+            } else if product < 0. {
+                num_discordant_bins += 1;
+            }
+        }
+    }
+
+    Ok((
+        (num_concordant_bins as f64) / (count as f64),
+        (num_discordant_bins as f64) / (count as f64),
+    ))
+}
+
+/// Same as `get_top_k_overlap_ratio` for each K in `ks`, returning the
+/// `(k, ratio)` pairs in the order of `ks`. Both maps are binned and their
+/// bins sorted by value only once, and the top K bins for each K are a prefix
+/// of the sorted bins, so that a sweep over K costs about as much as a single
+/// K. The ties at the K-th largest value may be broken differently than in
+/// `get_top_k_overlap_ratio`. The `bin_size` must be positive, since the bins
+/// of the two maps are matched by their start coordinates.
+pub fn get_top_k_overlap_ratios(
+    map1: &IntegerIntervalMap<f64>,
+    map2: &IntegerIntervalMap<f64>,
+    ks: &[i64],
+    bin_size: i64,
+) -> Vec<(i64, f64)> {
+    // maps the start of each bin to its 0-based rank in decreasing value
+    let get_bin_ranks = |map: &IntegerIntervalMap<f64>| {
+        let mut bins: Vec<(i64, f64)> = map
+            .iter()
+            .into_binned_interval_iter(
+                bin_size,
+                AggregateOp::Average,
+                Box::new(|item| (*item.0, *item.1)),
+            )
+            .map(|(interval, val)| (interval.get_start(), val))
+            .collect();
+        bins.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(Ordering::Equal));
+        bins.into_iter()
+            .enumerate()
+            .map(|(rank, (start, _))| (start, rank))
+            .collect::<HashMap<i64, usize>>()
+    };
+    let ranks_1 = get_bin_ranks(map1);
+    let ranks_2 = get_bin_ranks(map2);
+
+    // a bin in both maps is in both top K sets once K exceeds both its ranks
+    let mut overlap_ranks: Vec<usize> = ranks_1
+        .iter()
+        .filter_map(|(start, &rank_1)| {
+            ranks_2.get(start).map(|&rank_2| rank_1.max(rank_2))
+        })
+        .collect();
+    overlap_ranks.sort();
+
+    ks.iter()
+        .map(|&k| {
+            let top_k = k.max(0) as usize;
+            let num_overlapped_bins =
+                match overlap_ranks.binary_search_by(|&rank| {
+                    if rank < top_k {
+                        Ordering::Less
+                    } else {
+                        Ordering::Greater
+                    }
+                }) {
+                    Ok(i) | Err(i) => i,
+                };
+            let num_bins = top_k.min(ranks_1.len()) + top_k.min(ranks_2.len())
+                - num_overlapped_bins;
+            (k, (num_overlapped_bins as f64) / (num_bins as f64))
+        })
+        .collect()
 }
 
+/// Same as `get_top_k_fraction_overlap_ratio` except that the top K bins are
+/// selected on each chromosome and the overlapping bins, or base pairs under
+/// `OverlapWeight::BasePairs`, are counted across all the chromosomes. Returns
+/// NaN if neither map has any bin. The `options.verbose` has no effect.
 pub fn get_top_k_fraction_overlap_ratio_across_chroms(
     chrom_to_int_interval_map_1: &HashMap<String, IntegerIntervalMap<f64>>,
     chrom_to_int_interval_map_2: &HashMap<String, IntegerIntervalMap<f64>>,
     top_k_fraction: f64,
     bin_size: i64,
-) -> Result<f64, String> {
-    let mut total_num_bins = 0i64;
-    let mut num_overlapped_bins = 0i64;
+    options: &TopKOverlapOptions,
+) -> Result<f64, Error> {
+    let mut total_num_bins = 0usize;
+    let mut num_overlapped_bins = 0usize;
 
     let union_zipped_iter = chrom_to_int_interval_map_1
         .union_zip(&chrom_to_int_interval_map_2)
@@ -70,29 +291,468 @@ pub fn get_top_k_fraction_overlap_ratio_across_chroms(
         let map1 = map_list[0].unwrap_or_else(|| &empty_interval_map);
         let map2 = map_list[1].unwrap_or_else(|| &empty_interval_map);
         let k = get_k(&map1, &map2, top_k_fraction, bin_size);
-        let top_k_1 = get_top_k_bin_map(map1, k, bin_size)?;
-        let top_k_2 = get_top_k_bin_map(map2, k, bin_size)?;
-        let iter = get_common_refined_binned_iter(&top_k_1, &top_k_2, bin_size);
-
-        for (_interval, values) in iter {
-            total_num_bins += 1;
-            if values[0].is_some() && values[1].is_some() {
-                num_overlapped_bins += 1;
+        let (overlap, total) = match options.weight {
+            OverlapWeight::Bins => {
+                get_top_k_overlap_counts(map1, map2, k, bin_size)?
             }
-        }
+            OverlapWeight::BasePairs => {
+                get_top_k_bp_overlap_sizes(map1, map2, k, bin_size)?
+            }
+        };
+        num_overlapped_bins += overlap;
+        total_num_bins += total;
     }
 
     Ok((num_overlapped_bins as f64) / (total_num_bins as f64))
 }
 
+/// For each fraction `f` in `fractions`, selects the top `f` fraction of the
+/// bins in `map_a` by value and computes the fraction of the total signal in
+/// `map_b` lying in those bins, where the signal of a bin is its average value
+/// times its size. Returns the `(fraction, recovered_fraction)` pairs in the
+/// order of `fractions`. The recovered fractions are NaN if `map_b` has no
+/// signal.
+///
+/// The number of bins is counted over the bins in which either map has data,
+/// as in `get_top_k_fraction_overlap_ratio`.
+pub fn compute_recovery_curve(
+    map_a: &IntegerIntervalMap<f64>,
+    map_b: &IntegerIntervalMap<f64>,
+    bin_size: i64,
+    fractions: &[f64],
+) -> Result<Vec<(f64, f64)>, Error> {
+    let total_signal: f64 =
+        get_common_refined_binned_iter(map_a, map_b, bin_size)
+            .map(|(interval, values)| {
+                values[1].unwrap_or(0.) * interval.size() as f64
+            })
+            .sum();
+
+    fractions
+        .iter()
+        .map(|&fraction| {
+            let k = get_k(map_a, map_b, fraction, bin_size);
+            let top_k_a = get_top_k_bin_map(map_a, k, bin_size)?;
+            let recovered_signal: f64 =
+                get_common_refined_binned_iter(&top_k_a, map_b, bin_size)
+                    .filter(|(_, values)| values[0].is_some())
+                    .map(|(interval, values)| {
+                        values[1].unwrap_or(0.) * interval.size() as f64
+                    })
+                    .sum();
+            Ok((fraction, recovered_signal / total_signal))
+        })
+        .collect()
+}
+
+/// Returns a map from each chromosome to the number of base pairs covered by
+/// `(only the first track, only the second track, both tracks)`. The lengths
+/// are computed from the common refinement of the raw intervals, so the
+/// values of the tracks are ignored.
+pub fn overlap_lengths(
+    map_a: &HashMap<Chrom, IntegerIntervalMap<f64>>,
+    map_b: &HashMap<Chrom, IntegerIntervalMap<f64>>,
+) -> HashMap<Chrom, (usize, usize, usize)> {
+    let empty_interval_map = IntegerIntervalMap::new();
+    map_a
+        .union_zip(map_b)
+        .into_iter()
+        .map(|(chrom, map_list)| {
+            let a = map_list[0].unwrap_or_else(|| &empty_interval_map);
+            let b = map_list[1].unwrap_or_else(|| &empty_interval_map);
+            let mut lengths = (0, 0, 0);
+            for (interval, values) in a.iter().common_refinement_zip(b.iter()) {
+                match (values[0], values[1]) {
+                    (Some(_), Some(_)) => lengths.2 += interval.size(),
+                    (Some(_), None) => lengths.0 += interval.size(),
+                    (None, Some(_)) => lengths.1 += interval.size(),
+                    (None, None) => {}
+                }
+            }
+            (chrom.to_string(), lengths)
+        })
+        .collect()
+}
+
+/// The result of a permutation test for the top K overlap ratio.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OverlapEnrichment {
+    /// The top K overlap ratio between the original tracks.
+    pub observed_ratio: f64,
+    /// The mean of the top K overlap ratios over the shuffles.
+    pub null_mean: f64,
+    /// The fraction of shuffles whose top K overlap ratio is at least the
+    /// observed ratio.
+    pub p_value: f64,
+}
+
+/// Tests whether the overlap between the top K bins of two tracks is larger
+/// than expected by chance. The intervals of the first track are shuffled
+/// `n_perm` times with `shuffle_track_with_rng`, and the top K overlap ratio
+/// across chromosomes is recomputed for each shuffle to form the null
+/// distribution.
+///
+/// * `intervals_a`: the intervals of the lines of the first track, e.g., as
+///   returned by `get_chrom_intervals`, so that the lines rather than their
+///   refined pieces are shuffled. The observed ratio is computed from their
+///   aggregation by `to_chrom_interval_map`.
+/// * `top_k_fraction`: the fraction of bins on each chromosome that are
+///   considered the top K bins, as in
+///   `get_top_k_fraction_overlap_ratio_across_chroms`.
+/// * `seed`: the same seed will produce the same shuffles.
+pub fn overlap_enrichment_pvalue(
+    intervals_a: &HashMap<String, Vec<(I64Interval, f64)>>,
+    map_b: &HashMap<String, IntegerIntervalMap<f64>>,
+    chrom_sizes: &HashMap<String, i64>,
+    n_perm: usize,
+    top_k_fraction: f64,
+    bin_size: i64,
+    seed: u64,
+) -> Result<OverlapEnrichment, Error> {
+    overlap_enrichment_pvalue_with_options(
+        intervals_a,
+        map_b,
+        chrom_sizes,
+        top_k_fraction,
+        bin_size,
+        &PermutationOptions {
+            n_perm,
+            seed,
+            checkpoint_path: None,
+            checkpoint_interval: 1,
+            exclude: None,
+        },
+    )
+}
+
+/// The options for `overlap_enrichment_pvalue_with_options`. See
+/// `overlap_enrichment_pvalue` for the meaning of `n_perm` and `seed`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PermutationOptions {
+    pub n_perm: usize,
+    pub seed: u64,
+
+    /// If provided, the null distribution accumulated so far is written to
+    /// this path after every `checkpoint_interval` shuffles. If the path
+    /// already exists, the shuffles recorded in it are reused and the run
+    /// resumes from the next shuffle, which yields the same result as an
+    /// uninterrupted run.
+    pub checkpoint_path: Option<String>,
+    pub checkpoint_interval: usize,
+
+    /// If provided, the shuffled intervals of `intervals_a` avoid these
+    /// regions.
+    /// This should be the exclusion applied when reading the tracks, so that
+    /// the null ratios are drawn from the same regions as the observed ratio.
+    pub exclude: Option<HashMap<Chrom, OrderedIntegerSet<i64>>>,
+}
+
+pub fn overlap_enrichment_pvalue_with_options(
+    intervals_a: &HashMap<String, Vec<(I64Interval, f64)>>,
+    map_b: &HashMap<String, IntegerIntervalMap<f64>>,
+    chrom_sizes: &HashMap<String, i64>,
+    top_k_fraction: f64,
+    bin_size: i64,
+    options: &PermutationOptions,
+) -> Result<OverlapEnrichment, Error> {
+    let n_perm = options.n_perm;
+    let seed = options.seed;
+    if n_perm == 0 {
+        return Err(Error::Validation(
+            "the number of permutations must be positive".to_string(),
+        ));
+    }
+    if options.checkpoint_interval == 0 {
+        return Err(Error::Validation(
+            "the checkpoint interval must be positive".to_string(),
+        ));
+    }
+    let map_a = to_chrom_interval_map(intervals_a);
+    let observed_ratio = get_top_k_fraction_overlap_ratio_across_chroms(
+        &map_a,
+        map_b,
+        top_k_fraction,
+        bin_size,
+        &TopKOverlapOptions::default(),
+    )?;
+
+    let new_checkpoint = PermutationCheckpoint {
+        seed,
+        tracks_fingerprint: get_tracks_fingerprint(&[&map_a, map_b]),
+        top_k_fraction,
+        bin_size,
+        null_ratios: Vec::new(),
+    };
+    let mut checkpoint = match options.checkpoint_path.as_ref() {
+        Some(path) if Path::new(path).exists() => {
+            let checkpoint = PermutationCheckpoint::read_from_file(path)?;
+            checkpoint.check_parameters(&new_checkpoint, path)?;
+            checkpoint
+        }
+        _ => new_checkpoint,
+    };
+    for index in checkpoint.null_ratios.len()..n_perm {
+        checkpoint.null_ratios.push(get_shuffled_ratio(
+            intervals_a,
+            map_b,
+            chrom_sizes,
+            top_k_fraction,
+            bin_size,
+            options,
+            index,
+        )?);
+        if let Some(path) = options.checkpoint_path.as_ref() {
+            if (index + 1) % options.checkpoint_interval == 0
+                || index + 1 == n_perm
+            {
+                checkpoint.write_to_file(path)?;
+            }
+        }
+    }
+    Ok(get_enrichment(
+        observed_ratio,
+        &checkpoint.null_ratios[..n_perm],
+    ))
+}
+
+/// The progress of a permutation test, i.e., the parameters of the test and
+/// the top K overlap ratios of the shuffles completed so far. The `i`-th
+/// shuffle draws from a random number generator seeded by both `seed` and `i`,
+/// so the state of the random sequence is fully described by `seed` and the
+/// number of completed shuffles.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PermutationCheckpoint {
+    pub seed: u64,
+    /// Identifies the two tracks being compared, see `get_tracks_fingerprint`.
+    pub tracks_fingerprint: u64,
+    pub top_k_fraction: f64,
+    pub bin_size: i64,
+    pub null_ratios: Vec<f64>,
+}
+
+impl PermutationCheckpoint {
+    /// The first line holds the tab-separated seed, tracks fingerprint, top K
+    /// fraction and bin size, followed by one null ratio per line. The file is
+    /// first written to a temporary path and then renamed so that a crash
+    /// while writing cannot corrupt an existing checkpoint.
+    pub fn write_to_file(&self, path: &str) -> Result<(), Error> {
+        let temp_path = format!("{}.tmp", path);
+        let mut content = format!(
+            "{}\t{}\t{}\t{}\n",
+            self.seed,
+            self.tracks_fingerprint,
+            self.top_k_fraction,
+            self.bin_size
+        );
+        for ratio in self.null_ratios.iter() {
+            content.push_str(&format!("{}\n", ratio));
+        }
+        fs::write(&temp_path, content)
+            .and_then(|_| fs::rename(&temp_path, path))
+            .map_err(|why| {
+                Error::io(
+                    format!("failed to write the checkpoint {}", path),
+                    why,
+                )
+            })
+    }
+
+    pub fn read_from_file(path: &str) -> Result<PermutationCheckpoint, Error> {
+        let content = fs::read_to_string(path).map_err(|why| {
+            Error::io(format!("failed to read the checkpoint {}", path), why)
+        })?;
+        let mut lines = content.lines();
+        let header: Vec<&str> = lines
+            .next()
+            .ok_or_else(|| {
+                Error::Parse(format!("the checkpoint {} is empty", path))
+            })?
+            .split('\t')
+            .collect();
+        if header.len() != 4 {
+            return Err(Error::Parse(format!(
+                "expected 4 fields in the first line of the checkpoint {}, \
+                found {}",
+                path,
+                header.len()
+            )));
+        }
+        let invalid_field = |name: &str, why: &dyn std::fmt::Display| {
+            Error::Parse(format!(
+                "invalid {} in the checkpoint {}: {}",
+                name, path, why
+            ))
+        };
+        let seed = header[0]
+            .parse::<u64>()
+            .map_err(|why| invalid_field("seed", &why))?;
+        let tracks_fingerprint = header[1]
+            .parse::<u64>()
+            .map_err(|why| invalid_field("tracks fingerprint", &why))?;
+        let top_k_fraction = header[2]
+            .parse::<f64>()
+            .map_err(|why| invalid_field("top K fraction", &why))?;
+        let bin_size = header[3]
+            .parse::<i64>()
+            .map_err(|why| invalid_field("bin size", &why))?;
+        let null_ratios = lines
+            .map(|line| {
+                line.parse::<f64>().map_err(|why| {
+                    Error::Parse(format!(
+                        "invalid ratio {} in the checkpoint {}: {}",
+                        line, path, why
+                    ))
+                })
+            })
+            .collect::<Result<Vec<f64>, Error>>()?;
+        Ok(PermutationCheckpoint {
+            seed,
+            tracks_fingerprint,
+            top_k_fraction,
+            bin_size,
+            null_ratios,
+        })
+    }
+
+    /// Returns a validation error naming every parameter of the test that
+    /// differs from the `expected` one, so that a checkpoint is never resumed
+    /// with different tracks or settings.
+    fn check_parameters(
+        &self,
+        expected: &PermutationCheckpoint,
+        path: &str,
+    ) -> Result<(), Error> {
+        let mut mismatches = Vec::new();
+        if self.seed != expected.seed {
+            mismatches.push(format!(
+                "seed {} instead of {}",
+                self.seed, expected.seed
+            ));
+        }
+        if self.tracks_fingerprint != expected.tracks_fingerprint {
+            mismatches.push("different tracks".to_string());
+        }
+        if self.top_k_fraction != expected.top_k_fraction {
+            mismatches.push(format!(
+                "top K fraction {} instead of {}",
+                self.top_k_fraction, expected.top_k_fraction
+            ));
+        }
+        if self.bin_size != expected.bin_size {
+            mismatches.push(format!(
+                "bin size {} instead of {}",
+                self.bin_size, expected.bin_size
+            ));
+        }
+        if mismatches.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::Validation(format!(
+                "the checkpoint {} was created with {}",
+                path,
+                mismatches.join(", ")
+            )))
+        }
+    }
+}
+
+/// Computes the 64-bit FNV-1a hash of the chromosomes, intervals and values of
+/// the tracks. Unlike the hashers in the standard library, FNV-1a is stable
+/// across compiler versions, so a checkpoint can be resumed by a newer build.
+fn get_tracks_fingerprint(
+    maps: &[&HashMap<String, IntegerIntervalMap<f64>>],
+) -> u64 {
+    const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const FNV_PRIME: u64 = 0x0100_0000_01b3;
+    let mut hash = FNV_OFFSET_BASIS;
+    let mut update = |bytes: &[u8]| {
+        for &byte in bytes {
+            hash ^= u64::from(byte);
+            hash = hash.wrapping_mul(FNV_PRIME);
+        }
+    };
+    for &map in maps {
+        update(&(map.len() as u64).to_le_bytes());
+        for chrom in get_sorted_keys(map) {
+            update(&(chrom.len() as u64).to_le_bytes());
+            update(chrom.as_bytes());
+            for (interval, value) in map[&chrom].iter() {
+                update(&interval.get_start().to_le_bytes());
+                update(&interval.get_end().to_le_bytes());
+                update(&value.to_bits().to_le_bytes());
+            }
+        }
+    }
+    hash
+}
+
+/// Computes the top K overlap ratio after the `index`-th shuffle of
+/// `intervals_a`.
+fn get_shuffled_ratio(
+    intervals_a: &HashMap<String, Vec<(I64Interval, f64)>>,
+    map_b: &HashMap<String, IntegerIntervalMap<f64>>,
+    chrom_sizes: &HashMap<String, i64>,
+    top_k_fraction: f64,
+    bin_size: i64,
+    options: &PermutationOptions,
+    index: usize,
+) -> Result<f64, Error> {
+    let mut rng_seed = [0u8; 32];
+    rng_seed[..8].copy_from_slice(&options.seed.to_le_bytes());
+    rng_seed[8..16].copy_from_slice(&(index as u64).to_le_bytes());
+    let mut rng = StdRng::from_seed(rng_seed);
+
+    let shuffled_a = shuffle_track_with_rng(
+        intervals_a,
+        chrom_sizes,
+        options.exclude.as_ref(),
+        &mut rng,
+    )?;
+    get_top_k_fraction_overlap_ratio_across_chroms(
+        &shuffled_a,
+        map_b,
+        top_k_fraction,
+        bin_size,
+        &TopKOverlapOptions::default(),
+    )
+}
+
+fn get_enrichment(
+    observed_ratio: f64,
+    null_ratios: &[f64],
+) -> OverlapEnrichment {
+    let num_at_least_observed =
+        null_ratios.iter().filter(|&&r| r >= observed_ratio).count();
+    OverlapEnrichment {
+        observed_ratio,
+        null_mean: null_ratios.iter().sum::<f64>() / null_ratios.len() as f64,
+        p_value: num_at_least_observed as f64 / null_ratios.len() as f64,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::{
-        check_chrom, test_util::create_temp_bed,
-        top_k_overlap::get_top_k_overlap_ratio, util::get_chrom_interval_map,
+        assert_almost_eq, check_chrom,
+        test_util::create_temp_bed,
+        top_k_overlap::{
+            compute_recovery_curve, get_k, get_signed_top_k_overlap,
+            get_top_k_bp_overlap_ratio, get_top_k_fraction_overlap_ratio,
+            get_top_k_fraction_overlap_ratio_across_chroms,
+            get_top_k_overlap_ratio, get_top_k_overlap_ratios,
+            overlap_enrichment_pvalue, overlap_enrichment_pvalue_with_options,
+            overlap_lengths, OverlapWeight, PermutationCheckpoint,
+            PermutationOptions, TopKOverlapOptions,
+        },
+        util::{
+            get_chrom_interval_map, get_chrom_intervals, to_chrom_interval_map,
+            ReadOptions,
+        },
     };
     use biofile::{bed::Bed, util::TrackVariant};
-    use math::iter::UnionZip;
+    use math::{
+        iter::UnionZip, partition::integer_interval_map::IntegerIntervalMap,
+    };
     use std::{
         collections::HashMap,
         io::{BufWriter, Write},
@@ -156,4 +816,475 @@ mod tests {
         assert_eq!(chrom_to_overlap_ratio["chr1"], 150f64 / 400f64);
         assert_eq!(chrom_to_overlap_ratio["chr3"], 1f64);
     }
+
+    #[test]
+    fn test_top_k_bp_overlap_ratio() {
+        let bed_1_path = create_temp_bed(
+            "chr1 0 100 name_1 10\n\
+            chr1 100 110 name_2 5\n\
+            chr1 500 600 name_3 1\n",
+        )
+        .unwrap();
+        let bed_2_path = create_temp_bed(
+            "chr1 0 10 name_1 10\n\
+            chr1 100 200 name_2 5\n\
+            chr1 600 700 name_3 1\n",
+        )
+        .unwrap();
+        let map_1 = get_chrom_interval_map(
+            &TrackVariant::Bed(Bed::new(bed_1_path.to_str().unwrap(), false)),
+            None,
+        )
+        .unwrap();
+        let map_2 = get_chrom_interval_map(
+            &TrackVariant::Bed(Bed::new(bed_2_path.to_str().unwrap(), false)),
+            None,
+        )
+        .unwrap();
+
+        // With the intervals as the bins, the top 2 sets are [0, 100) and
+        // [100, 110), and [0, 10) and [100, 200), whose common refinement
+        // consists of [0, 10) and [100, 110) in both sets, and [10, 100) and
+        // [110, 200) in one of them, i.e., only 20 of the 200 base pairs
+        // overlap.
+        assert_almost_eq!(
+            get_top_k_bp_overlap_ratio(&map_1["chr1"], &map_2["chr1"], 2, 0)
+                .unwrap(),
+            0.1
+        );
+        let bp_options = TopKOverlapOptions {
+            weight: OverlapWeight::BasePairs,
+            ..TopKOverlapOptions::default()
+        };
+        // 0.3 of the 6 pieces of the common refinement rounds to K = 2
+        assert_almost_eq!(
+            get_top_k_fraction_overlap_ratio_across_chroms(
+                &map_1,
+                &map_2,
+                0.3,
+                0,
+                &bp_options
+            )
+            .unwrap(),
+            0.1
+        );
+
+        // the two ratios agree when the bins have the same size
+        for k in 1..=4 {
+            assert_almost_eq!(
+                get_top_k_overlap_ratio(&map_1["chr1"], &map_2["chr1"], k, 10)
+                    .unwrap(),
+                get_top_k_bp_overlap_ratio(
+                    &map_1["chr1"],
+                    &map_2["chr1"],
+                    k,
+                    10
+                )
+                .unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn test_signed_top_k_overlap() {
+        let map_1 = get_chrom_interval_map(
+            &TrackVariant::Bed(Bed::new(
+                create_temp_bed(
+                    "chr1 0 10 a 9\n\
+                    chr1 10 20 a -8\n\
+                    chr1 20 30 a 7\n\
+                    chr1 30 40 a 1\n\
+                    chr1 40 50 a -6\n",
+                )
+                .unwrap()
+                .to_str()
+                .unwrap(),
+                false,
+            )),
+            None,
+        )
+        .unwrap();
+        // the strong bins at 10 and 20 are discordant with the first map
+        let map_2 = get_chrom_interval_map(
+            &TrackVariant::Bed(Bed::new(
+                create_temp_bed(
+                    "chr1 0 10 b 5\n\
+                    chr1 10 20 b 9\n\
+                    chr1 20 30 b -7\n\
+                    chr1 30 40 b 1\n\
+                    chr1 40 50 b -8\n",
+                )
+                .unwrap()
+                .to_str()
+                .unwrap(),
+                false,
+            )),
+            None,
+        )
+        .unwrap();
+
+        // both top 4 sets are the bins at 0, 10, 20 and 40
+        let (concordant_ratio, discordant_ratio) =
+            get_signed_top_k_overlap(&map_1["chr1"], &map_2["chr1"], 4, 10)
+                .unwrap();
+        assert_almost_eq!(concordant_ratio, 0.5);
+        assert_almost_eq!(discordant_ratio, 0.5);
+
+        // the top 2 sets are the bins at 0 and 10, and at 10 and 40
+        let (concordant_ratio, discordant_ratio) =
+            get_signed_top_k_overlap(&map_1["chr1"], &map_2["chr1"], 2, 10)
+                .unwrap();
+        assert_almost_eq!(concordant_ratio, 0.);
+        assert_almost_eq!(discordant_ratio, 1. / 3.);
+
+        // the unsigned top K ignores the strongly negative bins, i.e., the
+        // top 2 sets are the bins at 0 and 20, and at 0 and 10
+        assert_almost_eq!(
+            get_top_k_overlap_ratio(&map_1["chr1"], &map_2["chr1"], 2, 10)
+                .unwrap(),
+            1. / 3.
+        );
+    }
+
+    #[test]
+    fn test_top_k_overlap_ratios() {
+        let map_1 = get_chrom_interval_map(
+            &TrackVariant::Bed(Bed::new(
+                create_temp_bed(
+                    "chr1 0 10 a 5\n\
+                    chr1 10 20 a 3\n\
+                    chr1 20 30 a 8\n\
+                    chr1 30 40 a 1\n\
+                    chr1 40 50 a 6\n",
+                )
+                .unwrap()
+                .to_str()
+                .unwrap(),
+                false,
+            )),
+            None,
+        )
+        .unwrap();
+        let map_2 = get_chrom_interval_map(
+            &TrackVariant::Bed(Bed::new(
+                create_temp_bed(
+                    "chr1 0 10 b 2\n\
+                    chr1 10 20 b 9\n\
+                    chr1 30 40 b 4\n\
+                    chr1 40 50 b 7\n\
+                    chr1 60 70 b 5\n",
+                )
+                .unwrap()
+                .to_str()
+                .unwrap(),
+                false,
+            )),
+            None,
+        )
+        .unwrap();
+        let ks = vec![3, 1, 2, 4, 5, 6];
+        let bin_size = 10;
+        let ratios = get_top_k_overlap_ratios(
+            &map_1["chr1"],
+            &map_2["chr1"],
+            &ks,
+            bin_size,
+        );
+        assert_eq!(ratios.len(), ks.len());
+        for ((k, ratio), &expected_k) in ratios.into_iter().zip(ks.iter()) {
+            assert_eq!(k, expected_k);
+            assert_almost_eq!(
+                ratio,
+                get_top_k_overlap_ratio(
+                    &map_1["chr1"],
+                    &map_2["chr1"],
+                    k,
+                    bin_size
+                )
+                .unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn test_get_k() {
+        let map = get_chrom_interval_map(
+            &TrackVariant::Bed(Bed::new(
+                create_temp_bed(
+                    "chr1 0 10 a 5\n\
+                    chr1 10 20 a 3\n\
+                    chr1 20 30 a 8\n",
+                )
+                .unwrap()
+                .to_str()
+                .unwrap(),
+                false,
+            )),
+            None,
+        )
+        .unwrap();
+        let map = &map["chr1"];
+        let empty_map = IntegerIntervalMap::new();
+
+        // 0.5 of the 3 bins rounds to 2 instead of truncating to 1
+        assert_eq!(get_k(map, map, 0.5, 10), 2);
+        assert_eq!(get_k(map, map, 0.4, 10), 1);
+        // a tiny fraction still selects one bin
+        assert_eq!(get_k(map, map, 0.01, 10), 1);
+        assert_eq!(get_k(map, map, 0., 10), 0);
+        assert_eq!(get_k(&empty_map, &empty_map, 0.5, 10), 0);
+
+        assert_almost_eq!(
+            get_top_k_fraction_overlap_ratio(
+                map,
+                map,
+                0.01,
+                10,
+                &TopKOverlapOptions::default()
+            )
+            .unwrap(),
+            1.
+        );
+        assert!(get_top_k_fraction_overlap_ratio(
+            &empty_map,
+            &empty_map,
+            0.5,
+            10,
+            &TopKOverlapOptions::default()
+        )
+        .unwrap()
+        .is_nan());
+        let empty_chrom_map = HashMap::new();
+        assert!(get_top_k_fraction_overlap_ratio_across_chroms(
+            &empty_chrom_map,
+            &empty_chrom_map,
+            0.5,
+            10,
+            &TopKOverlapOptions::default()
+        )
+        .unwrap()
+        .is_nan());
+    }
+
+    #[test]
+    fn test_recovery_curve() {
+        let bed_path = create_temp_bed(
+            "chr1 0 100 name_1 1\n\
+            chr1 100 200 name_2 1\n\
+            chr1 300 500 name_3 1\n\
+            chr1 600 800 name_4 1\n\
+            chr1 800 900 name_5 1\n\
+            chr1 1000 1100 name_6 1\n",
+        )
+        .unwrap();
+        let map = get_chrom_interval_map(
+            &TrackVariant::Bed(Bed::new(bed_path.to_str().unwrap(), false)),
+            None,
+        )
+        .unwrap();
+
+        // the 8 bins of the identical tracks carry the same signal
+        let fractions = vec![0.25, 0.5, 0.75, 1.];
+        let curve =
+            compute_recovery_curve(&map["chr1"], &map["chr1"], 100, &fractions)
+                .unwrap();
+        assert_eq!(curve.len(), fractions.len());
+        for ((fraction, recovered), &expected) in
+            curve.into_iter().zip(fractions.iter())
+        {
+            assert_eq!(fraction, expected);
+            assert_almost_eq!(recovered, expected);
+        }
+    }
+
+    #[test]
+    fn test_overlap_lengths() {
+        let bed_a_path = create_temp_bed(
+            "chr1 100 200 name_1 10\n\
+            chr1 300 400 name_2 75\n\
+            chr2 0 50 name_3 5\n",
+        )
+        .unwrap();
+
+        let bed_b_path = create_temp_bed(
+            "chr1 150 320 name_1 2\n\
+            chr3 10 20 name_2 8\n",
+        )
+        .unwrap();
+
+        let map_a = get_chrom_interval_map(
+            &TrackVariant::Bed(Bed::new(bed_a_path.to_str().unwrap(), false)),
+            None,
+        )
+        .unwrap();
+        let map_b = get_chrom_interval_map(
+            &TrackVariant::Bed(Bed::new(bed_b_path.to_str().unwrap(), false)),
+            None,
+        )
+        .unwrap();
+
+        let lengths = overlap_lengths(&map_a, &map_b);
+        assert_eq!(lengths.len(), 3);
+        // a only: [100, 150) and [320, 400), b only: [200, 300),
+        // both: [150, 200) and [300, 320)
+        assert_eq!(lengths["chr1"], (130, 100, 70));
+        assert_eq!(lengths["chr2"], (50, 0, 0));
+        assert_eq!(lengths["chr3"], (0, 10, 0));
+    }
+
+    #[test]
+    fn test_overlap_enrichment_pvalue() {
+        let bed_path = create_temp_bed(
+            "chr1 1000 1100 name_1 10\n\
+            chr1 5000 5100 name_2 75\n\
+            chr1 20000 20100 name_3 125\n\
+            chr1 40000 40100 name_4 25\n\
+            chr2 3000 3100 name_5 500\n\
+            chr2 60000 60100 name_6 50\n",
+        )
+        .unwrap();
+
+        let chrom_to_intervals = get_chrom_intervals(
+            &TrackVariant::Bed(Bed::new(bed_path.to_str().unwrap(), false)),
+            None,
+            &ReadOptions::default(),
+        )
+        .unwrap();
+        let chrom_to_interval_map = to_chrom_interval_map(&chrom_to_intervals);
+
+        let chrom_sizes: HashMap<String, i64> = vec![
+            ("chr1".to_string(), 1_000_000),
+            ("chr2".to_string(), 800_000),
+        ]
+        .into_iter()
+        .collect();
+
+        let result = overlap_enrichment_pvalue(
+            &chrom_to_intervals,
+            &chrom_to_interval_map,
+            &chrom_sizes,
+            20,
+            0.5,
+            50,
+            7,
+        )
+        .unwrap();
+        assert_eq!(result.observed_ratio, 1f64);
+        assert!(result.null_mean < result.observed_ratio);
+        assert!(result.p_value < 0.1);
+
+        // the same seed produces the same null distribution
+        let same_seed = overlap_enrichment_pvalue(
+            &chrom_to_intervals,
+            &chrom_to_interval_map,
+            &chrom_sizes,
+            20,
+            0.5,
+            50,
+            7,
+        )
+        .unwrap();
+        assert_eq!(result, same_seed);
+    }
+
+    #[test]
+    fn test_overlap_enrichment_checkpoint() {
+        let bed_path = create_temp_bed(
+            "chr1 1000 1100 name_1 10\n\
+            chr1 5000 5100 name_2 75\n\
+            chr1 20000 20100 name_3 125\n\
+            chr2 3000 3100 name_5 500\n",
+        )
+        .unwrap();
+        let chrom_to_intervals = get_chrom_intervals(
+            &TrackVariant::Bed(Bed::new(bed_path.to_str().unwrap(), false)),
+            None,
+            &ReadOptions::default(),
+        )
+        .unwrap();
+        let chrom_to_interval_map = to_chrom_interval_map(&chrom_to_intervals);
+        let chrom_sizes: HashMap<String, i64> =
+            vec![("chr1".to_string(), 100_000), ("chr2".to_string(), 80_000)]
+                .into_iter()
+                .collect();
+
+        let checkpoint_dir = tempfile::tempdir().unwrap();
+        let get_path = |name: &str| {
+            checkpoint_dir
+                .path()
+                .join(name)
+                .to_str()
+                .unwrap()
+                .to_string()
+        };
+        let run_with = |map_b: &HashMap<String, IntegerIntervalMap<f64>>,
+                        bin_size,
+                        n_perm,
+                        path: &str| {
+            overlap_enrichment_pvalue_with_options(
+                &chrom_to_intervals,
+                map_b,
+                &chrom_sizes,
+                0.5,
+                bin_size,
+                &PermutationOptions {
+                    n_perm,
+                    seed: 11,
+                    checkpoint_path: Some(path.to_string()),
+                    checkpoint_interval: 10,
+                    exclude: None,
+                },
+            )
+        };
+        let run = |n_perm, path: &str| {
+            run_with(&chrom_to_interval_map, 50, n_perm, path).unwrap()
+        };
+
+        // running 100 permutations in two halves
+        let resumed_path = get_path("resumed");
+        run(50, &resumed_path);
+        assert_eq!(
+            PermutationCheckpoint::read_from_file(&resumed_path)
+                .unwrap()
+                .null_ratios
+                .len(),
+            50
+        );
+        let resumed = run(100, &resumed_path);
+
+        // a checkpoint cannot be resumed with different parameters
+        let error = run_with(&chrom_to_interval_map, 25, 100, &resumed_path)
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("bin size 50 instead of 25"), "{}", error);
+        let mut other_map = chrom_to_interval_map.clone();
+        other_map.remove("chr2");
+        let error = run_with(&other_map, 50, 100, &resumed_path)
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("different tracks"), "{}", error);
+
+        // running 100 permutations at once
+        let full_path = get_path("full");
+        let full = run(100, &full_path);
+
+        assert_eq!(
+            PermutationCheckpoint::read_from_file(&resumed_path).unwrap(),
+            PermutationCheckpoint::read_from_file(&full_path).unwrap()
+        );
+        assert_eq!(resumed, full);
+        assert_eq!(
+            full,
+            overlap_enrichment_pvalue(
+                &chrom_to_intervals,
+                &chrom_to_interval_map,
+                &chrom_sizes,
+                100,
+                0.5,
+                50,
+                11,
+            )
+            .unwrap()
+        );
+    }
 }
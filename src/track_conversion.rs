@@ -0,0 +1,334 @@
+use crate::{
+    error::Error,
+    util::{create_maybe_gzip, is_bed_non_data_line},
+};
+use biofile::{bed::Bed, bedgraph::BedGraph, util::TrackVariant};
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, Write},
+};
+
+/// The format of a track file, which determines how the columns after the
+/// coordinates are interpreted.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TrackFormat {
+    Bed,
+    BedGraph,
+}
+
+impl TrackFormat {
+    pub fn other(self) -> TrackFormat {
+        match self {
+            TrackFormat::Bed => TrackFormat::BedGraph,
+            TrackFormat::BedGraph => TrackFormat::Bed,
+        }
+    }
+
+    /// Returns the track at `path` read in this format.
+    pub fn to_track(self, path: &str, binarize_score: bool) -> TrackVariant {
+        match self {
+            TrackFormat::Bed => {
+                TrackVariant::Bed(Bed::new(path, binarize_score))
+            }
+            TrackFormat::BedGraph => {
+                TrackVariant::BedGraph(BedGraph::new(path, binarize_score))
+            }
+        }
+    }
+}
+
+/// The number of data lines inspected by `detect_track_format`.
+const NUM_FORMAT_DETECTION_LINES: usize = 10;
+
+/// Detects the format of the track at `path` from its first few data lines. A
+/// line with exactly four fields whose fourth field is numeric is a bedGraph
+/// line, while any other line is a BED line, e.g., with a name in the fourth
+/// field, with only the coordinates or with five or more fields, even if the
+/// fourth field is a numeric name. Returns `None` if there is no data line,
+/// and a validation error if the lines disagree, in which case the format has
+/// to be given explicitly.
+pub fn detect_track_format(path: &str) -> Result<Option<TrackFormat>, Error> {
+    let file = File::open(path)
+        .map_err(|why| Error::io(format!("failed to open {}", path), why))?;
+    let mut detected = None;
+    let mut num_lines = 0;
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|why| {
+            Error::io(format!("failed to read {}", path), why)
+        })?;
+        if is_bed_non_data_line(&line) {
+            continue;
+        }
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let format = if fields.len() == 4 && fields[3].parse::<f64>().is_ok() {
+            TrackFormat::BedGraph
+        } else {
+            TrackFormat::Bed
+        };
+        match detected {
+            Some(detected) if detected != format => {
+                return Err(Error::Validation(format!(
+                    "cannot detect the format of {}: line {} looks like {:?} \
+                    while the earlier lines look like {:?}, specify the \
+                    format explicitly, e.g., with the bedgraph flag",
+                    path,
+                    i + 1,
+                    format,
+                    detected
+                )));
+            }
+            _ => detected = Some(format),
+        }
+        num_lines += 1;
+        if num_lines == NUM_FORMAT_DETECTION_LINES {
+            break;
+        }
+    }
+    Ok(detected)
+}
+
+/// Returns the track at `path` read in the format detected by
+/// `detect_track_format`, or in the `fallback` format if the track has no
+/// data line, for which the format makes no difference. This keeps a bedGraph
+/// passed without the corresponding format flag from silently being read as
+/// BED, whose value column is different. Returns an error if the format is
+/// ambiguous, i.e., the lines disagree.
+pub fn track_from_path_autodetect(
+    path: &str,
+    binarize_score: bool,
+    fallback: TrackFormat,
+) -> Result<TrackVariant, Error> {
+    let format = detect_track_format(path)?.unwrap_or(fallback);
+    Ok(format.to_track(path, binarize_score))
+}
+
+/// Converts the track at `in_path` from the `from` format into the other
+/// format line by line, without aggregating overlapping lines, and returns
+/// the number of data lines converted. Both formats use 0-based half-open
+/// coordinates, so the coordinates are copied verbatim, and so are the values
+/// to avoid any loss of precision. The comment and header lines are copied as
+/// they are, and the output fields are separated by tabs.
+///
+/// A BED line is converted into a bedGraph line with the score as the value,
+/// dropping the name, the strand and any later columns, while a bedGraph line
+/// is converted into a BED line with "." as the name and the value as the
+/// score. Converting a bedGraph into BED and back is therefore lossless, and
+/// so is converting a BED with only "." names and no strand into bedGraph and
+/// back.
+///
+/// Returns an error if a line does not match the `from` format, e.g., a BED
+/// line without a score or a line that looks like bedGraph when `from` is
+/// `Bed`. The output is compressed with gzip if `out_path` ends in `.gz`.
+pub fn convert_track(
+    in_path: &str,
+    out_path: &str,
+    from: TrackFormat,
+) -> Result<usize, Error> {
+    let file = File::open(in_path)
+        .map_err(|why| Error::io(format!("failed to open {}", in_path), why))?;
+    let mut writer = create_maybe_gzip(out_path, false).map_err(|why| {
+        Error::io(format!("failed to create {}", out_path), why)
+    })?;
+    let write_error =
+        |why| Error::io(format!("failed to write to {}", out_path), why);
+
+    let mut num_lines = 0;
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|why| {
+            Error::io(format!("failed to read {}", in_path), why)
+        })?;
+        if is_bed_non_data_line(&line) {
+            writeln!(writer, "{}", line).map_err(write_error)?;
+            continue;
+        }
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let format_error = |reason: &str| {
+            Error::Parse(format!(
+                "line {} of {} is not a valid {:?} line: {}: {}",
+                i + 1,
+                in_path,
+                from,
+                reason,
+                line
+            ))
+        };
+        if fields.len() < 3 {
+            return Err(format_error("expected the chrom, start and end"));
+        }
+        let converted = match from {
+            TrackFormat::Bed => {
+                if fields.len() == 4 && fields[3].parse::<f64>().is_ok() {
+                    return Err(format_error(
+                        "the fourth field is a value as in bedGraph",
+                    ));
+                }
+                if fields.len() < 5 {
+                    return Err(format_error("missing the score column"));
+                }
+                if fields[4].parse::<f64>().is_err() {
+                    return Err(format_error("the score is not numeric"));
+                }
+                format!(
+                    "{}\t{}\t{}\t{}",
+                    fields[0], fields[1], fields[2], fields[4]
+                )
+            }
+            TrackFormat::BedGraph => {
+                if fields.len() != 4 {
+                    return Err(format_error("expected exactly four fields"));
+                }
+                if fields[3].parse::<f64>().is_err() {
+                    return Err(format_error("the value is not numeric"));
+                }
+                format!(
+                    "{}\t{}\t{}\t.\t{}",
+                    fields[0], fields[1], fields[2], fields[3]
+                )
+            }
+        };
+        writeln!(writer, "{}", converted).map_err(write_error)?;
+        num_lines += 1;
+    }
+    writer.finish().map_err(write_error)?;
+    Ok(num_lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        error::Error,
+        test_util::create_temp_bed,
+        track_conversion::{
+            convert_track, detect_track_format, track_from_path_autodetect,
+            TrackFormat,
+        },
+    };
+    use biofile::util::TrackVariant;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn test_round_trip() {
+        // overlapping lines are kept as they are
+        let bed = "track name=test\n\
+            chr1\t0\t10\t.\t1.5\n\
+            chr1\t5\t20\t.\t2\n\
+            chr2\t100\t200\t.\t0.000001\n";
+        let bed_path = create_temp_bed(bed).unwrap();
+        let bedgraph_path = NamedTempFile::new().unwrap().into_temp_path();
+        let round_trip_path = NamedTempFile::new().unwrap().into_temp_path();
+
+        assert_eq!(
+            convert_track(
+                bed_path.to_str().unwrap(),
+                bedgraph_path.to_str().unwrap(),
+                TrackFormat::Bed
+            )
+            .unwrap(),
+            3
+        );
+        assert_eq!(
+            std::fs::read_to_string(&bedgraph_path).unwrap(),
+            "track name=test\n\
+            chr1\t0\t10\t1.5\n\
+            chr1\t5\t20\t2\n\
+            chr2\t100\t200\t0.000001\n"
+        );
+        assert_eq!(
+            convert_track(
+                bedgraph_path.to_str().unwrap(),
+                round_trip_path.to_str().unwrap(),
+                TrackFormat::BedGraph
+            )
+            .unwrap(),
+            3
+        );
+        assert_eq!(std::fs::read_to_string(&round_trip_path).unwrap(), bed);
+    }
+
+    #[test]
+    fn test_format_mismatch() {
+        let out_path = NamedTempFile::new().unwrap().into_temp_path();
+        let bedgraph_path = create_temp_bed("chr1 0 10 1.5\n").unwrap();
+        assert!(convert_track(
+            bedgraph_path.to_str().unwrap(),
+            out_path.to_str().unwrap(),
+            TrackFormat::Bed
+        )
+        .is_err());
+
+        let bed_path = create_temp_bed("chr1 0 10 id0 1.5 +\n").unwrap();
+        assert!(convert_track(
+            bed_path.to_str().unwrap(),
+            out_path.to_str().unwrap(),
+            TrackFormat::BedGraph
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_detect_track_format() {
+        let detect = |content: &str| {
+            let path = create_temp_bed(content).unwrap();
+            detect_track_format(path.to_str().unwrap()).unwrap()
+        };
+        assert_eq!(
+            detect("track name=test\nchr1 0 10 1.5\nchr1 10 20 -2\n"),
+            Some(TrackFormat::BedGraph)
+        );
+        assert_eq!(
+            detect("chr1 0 10 id0 1.5\nchr1 10 20 id1 2\n"),
+            Some(TrackFormat::Bed)
+        );
+        assert_eq!(detect("chr1 0 10\nchr1 10 20\n"), Some(TrackFormat::Bed));
+        assert_eq!(detect("chr1 0 10 peak1\n"), Some(TrackFormat::Bed));
+        // a numeric name is only read as a bedGraph value in the last column
+        assert_eq!(
+            detect("chr1 0 10 7 0.5 +\nchr1 10 20 8 1 -\n"),
+            Some(TrackFormat::Bed)
+        );
+        assert_eq!(detect("# comment\n"), None);
+
+        // lines of different formats are ambiguous
+        let ambiguous_path =
+            create_temp_bed("chr1 0 10 1.5\nchr1 10 20 id1 2\n").unwrap();
+        match detect_track_format(ambiguous_path.to_str().unwrap()) {
+            Err(Error::Validation(why)) => assert!(why.contains("line 2")),
+            _ => panic!("expected a validation error"),
+        }
+
+        let bedgraph_path = create_temp_bed("chr1 0 10 1.5\n").unwrap();
+        match track_from_path_autodetect(
+            bedgraph_path.to_str().unwrap(),
+            false,
+            TrackFormat::Bed,
+        )
+        .unwrap()
+        {
+            TrackVariant::BedGraph(_) => {}
+            _ => panic!("expected the track to be read as a bedGraph"),
+        }
+        assert!(track_from_path_autodetect(
+            ambiguous_path.to_str().unwrap(),
+            false,
+            TrackFormat::Bed,
+        )
+        .is_err());
+        let empty_path = create_temp_bed("# comment\n").unwrap();
+        match track_from_path_autodetect(
+            empty_path.to_str().unwrap(),
+            false,
+            TrackFormat::Bed,
+        )
+        .unwrap()
+        {
+            TrackVariant::Bed(_) => {}
+            _ => panic!("expected the fallback format"),
+        }
+        assert!(track_from_path_autodetect(
+            "/nonexistent",
+            false,
+            TrackFormat::Bed
+        )
+        .is_err());
+    }
+}
@@ -1,29 +1,57 @@
 use crate::{
+    error::Error,
     top_k::get_top_k_bin_map,
-    util::{get_chrom_interval_map, get_common_refined_binned_iter},
+    util::{
+        get_aliased_excluded_interval_maps, get_chrom_interval_map,
+        get_chrom_start_end_val_iter_with_options,
+        get_common_refined_binned_iter,
+        get_common_refined_binned_iter_with_bin_sizes, get_read_count_map,
+        get_single_chrom_interval_map, get_stranded_chrom_interval_maps,
+        get_target_chrom_interval_map, get_target_chrom_read_intervals,
+        ApproxQuantiles, ReadOptions, ReadStats, UnknownStrand,
+    },
 };
-use biofile::{bed::Bed, util::TrackVariant};
+use biofile::{bed::BedWriter, bedgraph::BedGraphDataLine, util::TrackVariant};
 use math::{
-    interval::I64Interval,
+    interval::{traits::Interval, I64Interval},
     iter::{
-        CommonRefinementZip, CommonRefinementZipped, ConcatenatedIter, UnionZip,
+        AggregateOp, CommonRefinementZip, CommonRefinementZipped,
+        ConcatenatedIter, IntoBinnedIntervalIter, UnionZip,
     },
     partition::integer_interval_map::IntegerIntervalMap,
-    set::traits::Finite,
+    set::{
+        ordered_integer_set::OrderedIntegerSet,
+        traits::{Finite, Intersect},
+    },
     stats::correlation::weighted_correlation,
 };
-use std::collections::HashSet;
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Write},
+    iter::Peekable,
+    path::Path,
+};
 
 /// The weight is the reciprocal of the interval size so as to produce the mean
 /// of the values in the interval. Each bin is considered a single entity of
-/// "weight" 1 when taking correlations.
+/// "weight" 1 when taking correlations. A `BinWeight` other than `Size` can be
+/// passed as the third argument to change the weight. A pair with a NaN or
+/// infinite value is skipped by giving it zero values and zero weight.
 macro_rules! binned_extractor {
     ($transform: expr, $transform_type: expr) => {
+        binned_extractor!($transform, $transform_type, BinWeight::Size)
+    };
+    ($transform: expr, $transform_type: expr, $bin_weight: expr) => {
         |(interval, v)| {
+            if !is_finite_pair(&v) {
+                return (0., 0., 0.);
+            }
             (
                 $transform(v[0].unwrap_or(0.), $transform_type),
                 $transform(v[1].unwrap_or(0.), $transform_type),
-                interval.size() as f64,
+                apply_bin_weight(interval.size(), $bin_weight),
             )
         }
     };
@@ -31,24 +59,317 @@ macro_rules! binned_extractor {
 
 /// The weight is the size of the interval since each value has to be multiplied
 /// by the number of elements in the interval as the proper weighting in the
-/// correlation calculation.
+/// correlation calculation. A `BinWeight` other than `Size` can be passed as
+/// the third argument to change the weight. As in `binned_extractor`, a pair
+/// with a NaN or infinite value is skipped.
 macro_rules! non_binned_extractor {
     ($transform: expr, $transform_type: expr) => {
+        non_binned_extractor!($transform, $transform_type, BinWeight::Size)
+    };
+    ($transform: expr, $transform_type: expr, $bin_weight: expr) => {
         |(interval, v)| {
+            if !is_finite_pair(&v) {
+                return (0., 0., 0.);
+            }
             (
                 $transform(v[0].unwrap_or(0.), $transform_type),
                 $transform(v[1].unwrap_or(0.), $transform_type),
-                interval.size() as f64,
+                apply_bin_weight(interval.size(), $bin_weight),
             )
         }
     };
 }
 
+/// Computes the correlation with `phi_coefficient` when all the values are
+/// binary, and falls back to the general `weighted_correlation` otherwise.
+/// A `QuantileBinning` can be passed as the third argument to correlate the
+/// quantile group means instead, in which case there is no fast path, and
+/// whether to find the groups by approximate quantiles as the fourth.
+macro_rules! correlation_with_binary_fast_path {
+    ($get_iter: expr, $extractor: expr) => {{
+        let get_iter = $get_iter;
+        let extractor = $extractor;
+        phi_coefficient(get_iter().map(extractor))
+            .unwrap_or_else(|| weighted_correlation(get_iter, extractor))
+    }};
+    (
+        $get_iter: expr,
+        $extractor: expr,
+        $quantile_binning: expr,
+        $approx_quantiles: expr
+    ) => {{
+        match $quantile_binning.num_groups() {
+            Some(num_groups) => {
+                let get_iter = $get_iter;
+                let extractor = $extractor;
+                if $approx_quantiles {
+                    get_approx_quantile_group_correlation(
+                        || get_iter().map(extractor),
+                        num_groups,
+                    )
+                } else {
+                    get_quantile_group_correlation(
+                        get_iter().map(extractor).collect(),
+                        num_groups,
+                    )
+                }
+            }
+            None => correlation_with_binary_fast_path!($get_iter, $extractor),
+        }
+    }};
+}
+
 pub type ChromCorrelations = Vec<(String, Vec<f64>)>;
 pub type OverallCorrelations = Vec<f64>;
 
+/// `(correlation, slope, intercept)` of the least-squares regression of the
+/// second track on the first track.
+pub type Regression = (f64, f64, f64);
+pub type ChromRegressions = Vec<(String, Vec<Regression>)>;
+
+/// The per-chromosome and the overall correlations between the signals of
+/// the two tracks on one strand.
+pub type StrandCorrelations = (ChromCorrelations, OverallCorrelations);
+
+/// Statistics collected by `compute_track_correlations_with_stats`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CorrelationStats {
+    /// The number of bins skipped in the per-chromosome correlations because
+    /// the value of either track is NaN or infinite, one for each bin size,
+    /// summed over the chromosomes.
+    pub num_skipped_pairs: Vec<usize>,
+    /// The number of bins used as the sample size of the overall p-values,
+    /// one for each bin size, counted over the bins of the per-chromosome
+    /// correlations after the coverage and zero-fill filters and without the
+    /// skipped pairs. Empty if `CorrelationOptions::p_values` is
+    /// `PValueMode::None`.
+    pub num_effective_bins: Vec<f64>,
+    /// The two-sided p-values of the overall correlations, one for each bin
+    /// size. Empty if `CorrelationOptions::p_values` is `PValueMode::None`.
+    pub p_values: Vec<f64>,
+    /// The regressions of the second track on the first track computed from
+    /// the same bins as the per-chromosome correlations, in the order of the
+    /// per-chromosome correlations.
+    pub chrom_regressions: ChromRegressions,
+    /// The regressions over the bins of all the chromosomes, one for each bin
+    /// size.
+    pub overall_regressions: Vec<Regression>,
+}
+
 type Coord = i64;
 
+/// The options for `compute_track_correlations_with_options`. See
+/// `compute_track_correlations` for the meaning of the first few fields.
+#[derive(Clone)]
+pub struct CorrelationOptions {
+    pub target_chroms: Option<HashSet<String>>,
+    pub value_transform: ValueTransform,
+    pub top_k: Option<i64>,
+    pub exclude_track_filepath: Option<String>,
+    pub read_options: ReadOptions,
+    /// Whether to print the progress messages to stderr.
+    pub verbose: bool,
+    /// Whether to correlate the differences between adjacent bins instead of
+    /// the values in the bins.
+    pub difference_order: DifferenceOrder,
+    /// The weight of each bin in the correlation.
+    pub bin_weight: BinWeight,
+    /// If true, the chromosomes skipped because they are not in the
+    /// `target_chroms` are printed to stderr together with their line counts.
+    pub report_skipped_chroms: bool,
+    /// Whether to correlate the means of quantile groups of the bins instead
+    /// of the bins themselves.
+    pub quantile_binning: QuantileBinning,
+    /// If true, the `quantile_binning` groups are bounded by the approximate
+    /// quantiles from an `ApproxQuantiles` sketch of the bins instead of
+    /// sorting all of them, so that the overall correlations group the bins
+    /// across the genome in bounded memory. The rank of each group boundary
+    /// is within `APPROX_QUANTILE_EPSILON` of the number of bins, and the
+    /// bins tied at a boundary fall into the same group.
+    pub approx_quantiles: bool,
+    /// Where a bin without data in one of the tracks counts as zero in that
+    /// track.
+    pub zero_fill_mode: ZeroFillMode,
+    /// The per-chromosome correlation for a bin size is reported as NaN if
+    /// fewer than this many bins are correlated on the chromosome, since a
+    /// correlation over a couple of bins is a meaningless +/-1. Defaults to
+    /// 0, which reports every correlation.
+    pub min_bins: usize,
+    /// If not `None`, only the bins where the binned values of both tracks
+    /// are at least this value are correlated, which keeps the noise in the
+    /// weakly covered bins out of the correlation. A bin without data in a
+    /// track has a value of 0 in that track. The threshold is applied to the
+    /// binned values before the value transform.
+    pub min_coverage: Option<f64>,
+    /// Which bins are correlated based on which of the tracks have data in
+    /// them. Defaults to `CoveragePredicate::Any`, which keeps every bin.
+    pub coverage_predicate: CoveragePredicate,
+    /// If true, the value of each bin is the number of lines, e.g., reads or
+    /// peaks, overlapping the bin instead of the aggregated values of the
+    /// lines, so that tracks without meaningful scores can be compared. Each
+    /// bin is still weighted by its size. A bin size of 0 correlates the
+    /// depths of the base pairs.
+    pub count_reads: bool,
+    /// Whether to compute the p-values of the overall correlations into the
+    /// `CorrelationStats`.
+    pub p_values: PValueMode,
+}
+
+/// `None` correlates the values in the bins. `First` replaces the values in
+/// each bin by their differences from the values in the preceding bin before
+/// correlating, which compares where the tracks rise and fall rather than
+/// their levels. A bin that is not contiguous with the preceding bin, e.g.,
+/// the first bin after a gap or on a new chromosome, has no difference and is
+/// left out. The value transform is applied to the differences.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DifferenceOrder {
+    None,
+    First,
+}
+
+impl Default for DifferenceOrder {
+    fn default() -> Self {
+        DifferenceOrder::None
+    }
+}
+
+/// The rank error of the group boundaries under
+/// `CorrelationOptions::approx_quantiles` as a fraction of the number of bins.
+pub const APPROX_QUANTILE_EPSILON: f64 = 0.001;
+
+/// `None` correlates the bins directly. Otherwise, the bins are sorted by their
+/// values in the first track and divided into equal-count quantile groups,
+/// ten for `Deciles` and `n` for `Custom(n)`, and the correlation is computed
+/// between the weighted means of the two tracks within each group. This is a
+/// coarser, robustness-oriented correlation that is insensitive to how the
+/// bins are distributed within each group, at the cost of discarding most of
+/// the variation between individual bins. The per-chromosome correlations
+/// group the bins of each chromosome, while the overall correlations group
+/// the bins across all the chromosomes.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum QuantileBinning {
+    None,
+    Deciles,
+    Custom(usize),
+}
+
+impl Default for QuantileBinning {
+    fn default() -> Self {
+        QuantileBinning::None
+    }
+}
+
+impl QuantileBinning {
+    fn num_groups(self) -> Option<usize> {
+        match self {
+            QuantileBinning::None => None,
+            QuantileBinning::Deciles => Some(10),
+            QuantileBinning::Custom(n) => Some(n),
+        }
+    }
+}
+
+/// `WholeChromosome` treats a bin without data in one of the tracks as zero in
+/// that track wherever it is on the chromosome. `WithinCoveredSpan` does so
+/// only within the span covered by both tracks on the chromosome, i.e., the
+/// intersection of the spans from the start of the first interval to the end
+/// of the last interval of each track, and leaves out the bins extending
+/// beyond it as unobserved. This keeps the flanking regions covered by only
+/// one of the tracks, e.g., when the other track was assayed on a smaller
+/// region, from counting as zero signal in the other track.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ZeroFillMode {
+    WholeChromosome,
+    WithinCoveredSpan,
+}
+
+impl Default for ZeroFillMode {
+    fn default() -> Self {
+        ZeroFillMode::WholeChromosome
+    }
+}
+
+/// Decides whether a bin is correlated based on which of the tracks have data
+/// in the bin. `All` requires every track to have data, `Any` requires at
+/// least one track to, which keeps every bin of the union of the tracks,
+/// `First` and `Second` require the first and the second track to,
+/// respectively, and `Count(n)` requires at least `n` of the tracks to.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CoveragePredicate {
+    All,
+    Any,
+    First,
+    Second,
+    Count(usize),
+}
+
+impl Default for CoveragePredicate {
+    fn default() -> Self {
+        CoveragePredicate::Any
+    }
+}
+
+impl CoveragePredicate {
+    /// Whether a bin with the `values` of the tracks is to be correlated,
+    /// where a track has data in the bin if its value is not `None`.
+    pub fn is_satisfied(self, values: &[Option<f64>]) -> bool {
+        let is_covered =
+            |i: usize| values.get(i).map_or(false, Option::is_some);
+        match self {
+            CoveragePredicate::All => values.iter().all(Option::is_some),
+            CoveragePredicate::Any => values.iter().any(Option::is_some),
+            CoveragePredicate::First => is_covered(0),
+            CoveragePredicate::Second => is_covered(1),
+            CoveragePredicate::Count(n) => {
+                values.iter().filter(|v| v.is_some()).count() >= n
+            }
+        }
+    }
+}
+
+/// `None` skips the p-values. `Naive` uses the number of bins entering the
+/// correlations as the sample size, which overstates the degrees of freedom
+/// since adjacent bins are correlated. `AutocorrCorrected` scales the number
+/// of bins on each chromosome by `(1 - ra * rb) / (1 + ra * rb)`, where `ra`
+/// and `rb` are the lag-1 autocorrelations of the two tracks over the same
+/// bins.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PValueMode {
+    None,
+    Naive,
+    AutocorrCorrected,
+}
+
+impl Default for PValueMode {
+    fn default() -> Self {
+        PValueMode::None
+    }
+}
+
+impl Default for CorrelationOptions {
+    fn default() -> Self {
+        CorrelationOptions {
+            target_chroms: None,
+            value_transform: ValueTransform::Identity,
+            top_k: None,
+            exclude_track_filepath: None,
+            read_options: ReadOptions::default(),
+            verbose: true,
+            difference_order: DifferenceOrder::None,
+            bin_weight: BinWeight::Size,
+            report_skipped_chroms: false,
+            quantile_binning: QuantileBinning::None,
+            approx_quantiles: false,
+            zero_fill_mode: ZeroFillMode::WholeChromosome,
+            min_bins: 0,
+            min_coverage: None,
+            coverage_predicate: CoveragePredicate::Any,
+            count_reads: false,
+            p_values: PValueMode::None,
+        }
+    }
+}
+
 pub fn compute_track_correlations(
     first_track: &TrackVariant,
     second_track: &TrackVariant,
@@ -57,26 +378,251 @@ pub fn compute_track_correlations(
     value_transform: ValueTransform,
     top_k: Option<i64>,
     exclude_track_filepath: Option<String>,
-) -> Result<(ChromCorrelations, OverallCorrelations), String> {
-    let exclude = if let Some(path) = exclude_track_filepath {
-        // binarize_score is irrelevant for getting the intervals
-        Some(Bed::new(&path, false).get_chrom_to_intervals())
-    } else {
-        None
+) -> Result<(ChromCorrelations, OverallCorrelations), Error> {
+    compute_track_correlations_with_options(
+        first_track,
+        second_track,
+        bin_sizes,
+        &CorrelationOptions {
+            target_chroms,
+            value_transform,
+            top_k,
+            exclude_track_filepath,
+            ..CorrelationOptions::default()
+        },
+    )
+}
+
+pub fn compute_track_correlations_with_options(
+    first_track: &TrackVariant,
+    second_track: &TrackVariant,
+    bin_sizes: &Vec<Coord>,
+    options: &CorrelationOptions,
+) -> Result<(ChromCorrelations, OverallCorrelations), Error> {
+    compute_track_correlations_with_stats(
+        first_track,
+        second_track,
+        bin_sizes,
+        options,
+    )
+    .map(|(chrom_correlations, overall_correlations, _)| {
+        (chrom_correlations, overall_correlations)
+    })
+}
+
+/// Same as `compute_track_correlations_with_options` except that the
+/// `CorrelationStats` are returned as well. The bins where either value is NaN
+/// or infinite are left out of the correlations instead of poisoning them.
+pub fn compute_track_correlations_with_stats(
+    first_track: &TrackVariant,
+    second_track: &TrackVariant,
+    bin_sizes: &Vec<Coord>,
+    options: &CorrelationOptions,
+) -> Result<(ChromCorrelations, OverallCorrelations, CorrelationStats), Error> {
+    options.value_transform.validate()?;
+    let target_chroms = &options.target_chroms;
+    let verbose = options.verbose;
+    let exclude = get_aliased_excluded_interval_maps(
+        options.exclude_track_filepath.as_deref(),
+        &options.read_options.chrom_alias,
+    )?;
+
+    let report_read_stats = |read_stats: ReadStats, track_name: &str| {
+        if verbose && read_stats.num_bad_lines > 0 {
+            eprintln!(
+                "warning: skipped {} malformed lines in the {} track",
+                read_stats.num_bad_lines, track_name
+            );
+        }
+        if options.report_skipped_chroms {
+            eprintln!(
+                "=> {} chromosomes skipped in the {} track (chrom, lines):",
+                read_stats.skipped_chroms.len(),
+                track_name
+            );
+            for (chrom, num_lines) in read_stats.skipped_chroms.iter() {
+                eprintln!("{}, {}", chrom, num_lines);
+            }
+        }
+    };
+    let eprint_reading = |track_name: &str| {
+        if verbose {
+            eprintln!(
+                "=> Constructing chrom interval map for the {} track",
+                track_name
+            );
+        }
+    };
+
+    if !options.count_reads {
+        let read = |track: &TrackVariant, track_name: &str| {
+            eprint_reading(track_name);
+            let (chrom_interval_map, read_stats) =
+                get_target_chrom_interval_map(
+                    track,
+                    exclude.as_ref(),
+                    &options.read_options,
+                    target_chroms.as_ref(),
+                )?;
+            report_read_stats(read_stats, track_name);
+            Ok::<_, Error>(chrom_interval_map)
+        };
+        let chrom_interval_map_a = read(first_track, "first")?;
+        let chrom_interval_map_b = read(second_track, "second")?;
+        return compute_chrom_interval_map_correlations(
+            &chrom_interval_map_a,
+            &chrom_interval_map_b,
+            bin_sizes,
+            options,
+        );
+    }
+
+    // the read counts depend on the bin size, so the intervals of the lines
+    // are read once and counted in memory for each bin size
+    let read = |track: &TrackVariant, track_name: &str| {
+        eprint_reading(track_name);
+        let (chrom_to_intervals, read_stats) = get_target_chrom_read_intervals(
+            track,
+            exclude.as_ref(),
+            &options.read_options,
+            target_chroms.as_ref(),
+        )?;
+        report_read_stats(read_stats, track_name);
+        Ok::<_, Error>(chrom_to_intervals)
     };
+    let chrom_to_intervals_a = read(first_track, "first")?;
+    let chrom_to_intervals_b = read(second_track, "second")?;
+
+    let mut chrom_correlations: ChromCorrelations = Vec::new();
+    let mut overall_correlations: OverallCorrelations = Vec::new();
+    let mut stats = CorrelationStats::default();
+    for (i, &bin_size) in bin_sizes.iter().enumerate() {
+        let (bin_chrom_correlations, bin_overall_correlations, bin_stats) =
+            compute_chrom_interval_map_correlations(
+                &get_read_count_map(&chrom_to_intervals_a, bin_size),
+                &get_read_count_map(&chrom_to_intervals_b, bin_size),
+                &vec![bin_size],
+                options,
+            )?;
+        if i == 0 {
+            chrom_correlations = bin_chrom_correlations;
+            stats.chrom_regressions = bin_stats.chrom_regressions;
+        } else {
+            append_chrom_values(
+                &mut chrom_correlations,
+                bin_chrom_correlations,
+                std::f64::NAN,
+            );
+            append_chrom_values(
+                &mut stats.chrom_regressions,
+                bin_stats.chrom_regressions,
+                (std::f64::NAN, std::f64::NAN, std::f64::NAN),
+            );
+        }
+        overall_correlations.extend(bin_overall_correlations);
+        stats.num_skipped_pairs.extend(bin_stats.num_skipped_pairs);
+        stats
+            .num_effective_bins
+            .extend(bin_stats.num_effective_bins);
+        stats.p_values.extend(bin_stats.p_values);
+        stats
+            .overall_regressions
+            .extend(bin_stats.overall_regressions);
+    }
+    Ok((chrom_correlations, overall_correlations, stats))
+}
 
-    eprintln!("=> Constructing chrom interval map for the first track");
-    let chrom_interval_map_a =
-        get_chrom_interval_map(first_track, exclude.as_ref())?;
+/// Appends the single value of each chromosome in `bin_chrom_values`, computed
+/// for one more bin size, to the values of the chromosome in `chrom_values`,
+/// appending `missing` for the chromosomes without a value.
+fn append_chrom_values<T: Copy>(
+    chrom_values: &mut Vec<(String, Vec<T>)>,
+    bin_chrom_values: Vec<(String, Vec<T>)>,
+    missing: T,
+) {
+    let chrom_to_value: HashMap<String, T> = bin_chrom_values
+        .into_iter()
+        .map(|(chrom, values)| (chrom, values[0]))
+        .collect();
+    for (chrom, values) in chrom_values.iter_mut() {
+        values.push(chrom_to_value.get(chrom).cloned().unwrap_or(missing));
+    }
+}
 
-    eprintln!("=> Constructing chrom interval map for the second track");
-    let chrom_interval_map_b =
-        get_chrom_interval_map(second_track, exclude.as_ref())?;
+/// Same as `compute_track_correlations_with_options` except that the lines of
+/// the two BED tracks are split by their strands, and the plus strand signals
+/// and the minus strand signals are correlated separately, returning the
+/// correlations of the plus strand and of the minus strand in this order.
+/// The lines without a known strand are counted on the strands according to
+/// `unknown_strand`. The `count_reads` option is not supported and is
+/// ignored.
+pub fn compute_stranded_correlations(
+    first_track_path: &str,
+    second_track_path: &str,
+    binarize_score: bool,
+    bin_sizes: &Vec<Coord>,
+    options: &CorrelationOptions,
+    unknown_strand: UnknownStrand,
+) -> Result<(StrandCorrelations, StrandCorrelations), Error> {
+    options.value_transform.validate()?;
+    let exclude = get_aliased_excluded_interval_maps(
+        options.exclude_track_filepath.as_deref(),
+        &options.read_options.chrom_alias,
+    )?;
+    let read = |path: &str| {
+        if options.verbose {
+            eprintln!(
+                "=> Constructing stranded chrom interval maps for {}",
+                path
+            );
+        }
+        get_stranded_chrom_interval_maps(
+            path,
+            binarize_score,
+            exclude.as_ref(),
+            &options.read_options,
+            unknown_strand,
+        )
+    };
+    let (plus_map_a, minus_map_a) = read(first_track_path)?;
+    let (plus_map_b, minus_map_b) = read(second_track_path)?;
+    let correlate = |map_a, map_b| {
+        compute_chrom_interval_map_correlations(
+            map_a, map_b, bin_sizes, options,
+        )
+        .map(|(chrom_correlations, overall_correlations, _)| {
+            (chrom_correlations, overall_correlations)
+        })
+    };
+    Ok((
+        correlate(&plus_map_a, &plus_map_b)?,
+        correlate(&minus_map_a, &minus_map_b)?,
+    ))
+}
+
+/// Correlates the aggregated interval maps of the two tracks at each of the
+/// `bin_sizes`.
+fn compute_chrom_interval_map_correlations(
+    chrom_interval_map_a: &HashMap<String, IntegerIntervalMap<f64>>,
+    chrom_interval_map_b: &HashMap<String, IntegerIntervalMap<f64>>,
+    bin_sizes: &Vec<Coord>,
+    options: &CorrelationOptions,
+) -> Result<(ChromCorrelations, OverallCorrelations, CorrelationStats), Error> {
+    let target_chroms = &options.target_chroms;
+    let value_transform = options.value_transform;
+    let verbose = options.verbose;
+    let difference_order = options.difference_order;
+    let bin_weight = options.bin_weight;
+    let quantile_binning = options.quantile_binning;
+    let approx_quantiles = options.approx_quantiles;
+    let zero_fill_mode = options.zero_fill_mode;
+    let min_coverage = options.min_coverage;
+    let coverage_predicate = options.coverage_predicate;
 
     let empty_interval_map = IntegerIntervalMap::new();
     let get_target_interval_maps = || {
         chrom_interval_map_a
-            .union_zip(&chrom_interval_map_b)
+            .union_zip(chrom_interval_map_b)
             .into_iter()
             .filter_map(|(chrom, map_list)| {
                 if target_chroms.is_none()
@@ -93,11 +639,609 @@ pub fn compute_track_correlations(
             })
     };
 
+    let mut num_skipped_pairs = vec![0usize; bin_sizes.len()];
+    let mut num_bins = vec![0.; bin_sizes.len()];
+    let mut num_autocorr_corrected_bins = vec![0.; bin_sizes.len()];
+    let mut overall_moments = vec![WeightedMoments::default(); bin_sizes.len()];
+    let mut chrom_regressions: ChromRegressions = Vec::new();
+    let chrom_correlations: Vec<(String, Vec<f64>)> =
+        get_target_interval_maps()
+            .map(|(chrom, map_a, map_b)| {
+                if verbose {
+                    eprintln!("=> Computing correlations for {}", chrom);
+                }
+                let correlations = compute_chrom_correlations(
+                    map_a, map_b, bin_sizes, options,
+                )?;
+                for (i, correlation) in correlations.iter().enumerate() {
+                    num_skipped_pairs[i] += correlation.num_skipped;
+                    num_bins[i] += correlation.num_bins;
+                    num_autocorr_corrected_bins[i] +=
+                        correlation.num_autocorr_corrected_bins;
+                    overall_moments[i].merge(&correlation.moments);
+                }
+                chrom_regressions.push((
+                    chrom.clone(),
+                    correlations.iter().map(|c| c.regression).collect(),
+                ));
+                Ok((
+                    chrom,
+                    correlations.iter().map(|c| c.correlation).collect(),
+                ))
+            })
+            .collect::<Result<Vec<(String, Vec<f64>)>, Error>>()?;
+
+    if verbose {
+        eprintln!("=> Computing overall correlations");
+    }
+    let overall_correlations: Vec<f64> = bin_sizes
+        .iter()
+        .map(|&s| match (s, difference_order) {
+            (0, DifferenceOrder::First) => {
+                let differences: Vec<Vec<(I64Interval, Vec<Option<f64>>)>> =
+                    get_target_interval_maps()
+                        .map(|(_, map_a, map_b)| {
+                            get_first_differences(
+                                a_common_refine_b(map_a, map_b).filter(
+                                    get_observed_bin_filter(
+                                        map_a,
+                                        map_b,
+                                        zero_fill_mode,
+                                        min_coverage,
+                                        coverage_predicate,
+                                    ),
+                                ),
+                            )
+                        })
+                        .collect();
+                correlation_with_binary_fast_path!(
+                    || differences.iter().flatten(),
+                    non_binned_extractor!(
+                        apply_transform,
+                        value_transform,
+                        bin_weight
+                    ),
+                    quantile_binning,
+                    approx_quantiles
+                )
+            }
+            (non_zero, DifferenceOrder::First) => {
+                let differences: Vec<Vec<(I64Interval, Vec<Option<f64>>)>> =
+                    get_target_interval_maps()
+                        .map(|(_, map_a, map_b)| {
+                            get_first_differences(
+                                get_common_refined_binned_iter(
+                                    map_a, map_b, non_zero,
+                                )
+                                .filter(
+                                    get_observed_bin_filter(
+                                        map_a,
+                                        map_b,
+                                        zero_fill_mode,
+                                        min_coverage,
+                                        coverage_predicate,
+                                    ),
+                                ),
+                            )
+                        })
+                        .collect();
+                correlation_with_binary_fast_path!(
+                    || differences.iter().flatten(),
+                    binned_extractor!(
+                        apply_transform,
+                        value_transform,
+                        bin_weight
+                    ),
+                    quantile_binning,
+                    approx_quantiles
+                )
+            }
+            (0, DifferenceOrder::None) => correlation_with_binary_fast_path!(
+                || {
+                    ConcatenatedIter::from_iters(
+                        get_target_interval_maps()
+                            .map(|(_, map_a, map_b)| {
+                                a_common_refine_b(map_a, map_b).filter(
+                                    get_observed_bin_filter(
+                                        map_a,
+                                        map_b,
+                                        zero_fill_mode,
+                                        min_coverage,
+                                        coverage_predicate,
+                                    ),
+                                )
+                            })
+                            .collect(),
+                    )
+                },
+                non_binned_extractor!(
+                    apply_transform,
+                    value_transform,
+                    bin_weight
+                ),
+                quantile_binning,
+                approx_quantiles
+            ),
+            (non_zero, DifferenceOrder::None) => {
+                correlation_with_binary_fast_path!(
+                    || {
+                        ConcatenatedIter::from_iters(
+                            get_target_interval_maps()
+                                .map(|(_, map_a, map_b)| {
+                                    get_common_refined_binned_iter(
+                                        map_a, map_b, non_zero,
+                                    )
+                                    .filter(get_observed_bin_filter(
+                                        map_a,
+                                        map_b,
+                                        zero_fill_mode,
+                                        min_coverage,
+                                        coverage_predicate,
+                                    ))
+                                })
+                                .collect(),
+                        )
+                    },
+                    binned_extractor!(
+                        apply_transform,
+                        value_transform,
+                        bin_weight
+                    ),
+                    quantile_binning,
+                    approx_quantiles
+                )
+            }
+        })
+        .collect();
+
+    let num_effective_bins: Vec<f64> = match options.p_values {
+        PValueMode::None => Vec::new(),
+        PValueMode::Naive => num_bins,
+        PValueMode::AutocorrCorrected => num_autocorr_corrected_bins,
+    };
+    let p_values = num_effective_bins
+        .iter()
+        .zip(overall_correlations.iter())
+        .map(|(&n, &correlation)| correlation_p_value(correlation, n))
+        .collect();
+    Ok(
+        (chrom_correlations, overall_correlations, CorrelationStats {
+            num_skipped_pairs,
+            num_effective_bins,
+            p_values,
+            chrom_regressions,
+            overall_regressions: overall_moments
+                .iter()
+                .map(WeightedMoments::regression)
+                .collect(),
+        }),
+    )
+}
+
+/// Estimates the number of independent bins of size `bin_size` in the
+/// `map` as `n * (1 - r1) / (1 + r1)`, where `n` is the number of bins with
+/// data and `r1` is the lag-1 autocorrelation between the adjacent bins. A
+/// negative `r1` is taken as 0 so that the estimate never exceeds `n`. A
+/// `bin_size` of 0 uses the intervals of the map as the bins.
+pub fn effective_sample_size(
+    map: &IntegerIntervalMap<f64>,
+    bin_size: Coord,
+) -> f64 {
+    let bins = get_bin_values(map, bin_size);
+    let r1 = get_lag_one_autocorrelation(&bins).max(0.);
+    bins.len() as f64 * (1. - r1) / (1. + r1)
+}
+
+/// Returns the two-sided p-value of the Pearson `correlation` over
+/// `num_samples` independent samples under the null hypothesis of no
+/// correlation, using the normal approximation to the Fisher transformation of
+/// the correlation. Returns NaN if there are at most 3 samples.
+pub fn correlation_p_value(correlation: f64, num_samples: f64) -> f64 {
+    if num_samples <= 3. || correlation.is_nan() {
+        return std::f64::NAN;
+    }
+    let z = correlation.max(-1.).min(1.).atanh() * (num_samples - 3.).sqrt();
+    erfc(z.abs() / std::f64::consts::SQRT_2)
+}
+
+/// Given the overall correlations paired with the bin sizes at which they are
+/// computed, returns the smallest bin size from which the correlation changes
+/// by less than `epsilon` between every two consecutive bin sizes, i.e., the
+/// finest resolution beyond which coarser bins no longer change the
+/// correlation. The pairs need not be sorted by the bin size. Returns `None`
+/// if there are fewer than two bin sizes or if the correlation still changes
+/// by at least `epsilon` between the two largest bin sizes. A NaN correlation
+/// is never stable.
+pub fn find_stable_bin_size(
+    correlations_by_binsize: &[(Coord, f64)],
+    epsilon: f64,
+) -> Option<Coord> {
+    let mut sorted = correlations_by_binsize.to_vec();
+    sorted.sort_by_key(|&(bin_size, _)| bin_size);
+    let mut stable_bin_size = None;
+    for pair in sorted.windows(2).rev() {
+        if (pair[1].1 - pair[0].1).abs() < epsilon {
+            stable_bin_size = Some(pair[0].0);
+        } else {
+            break;
+        }
+    }
+    stable_bin_size
+}
+
+/// The values of the bins with data in the `map`, in order, together with
+/// whether each bin is adjacent to the next one.
+fn get_bin_values(
+    map: &IntegerIntervalMap<f64>,
+    bin_size: Coord,
+) -> Vec<(f64, bool)> {
+    let bins: Vec<(I64Interval, f64)> = if bin_size == 0 {
+        map.iter()
+            .map(|(&interval, &val)| (interval, val))
+            .collect()
+    } else {
+        map.iter()
+            .into_binned_interval_iter(
+                bin_size,
+                AggregateOp::Average,
+                Box::new(|item| (*item.0, *item.1)),
+            )
+            .collect()
+    };
+    bins.iter()
+        .enumerate()
+        .map(|(i, (interval, val))| {
+            let is_adjacent_to_next = bins.get(i + 1).map_or(false, |next| {
+                interval.get_end() + 1 == next.0.get_start()
+            });
+            (*val, is_adjacent_to_next)
+        })
+        .collect()
+}
+
+/// The lag-1 autocorrelation of the values of the `bins`, each paired with
+/// whether it is adjacent to the next bin as returned by `get_bin_values`,
+/// over the pairs of adjacent bins. Returns 0 if there are no adjacent bins
+/// or the values are constant.
+fn get_lag_one_autocorrelation(bins: &[(f64, bool)]) -> f64 {
+    let n = bins.len() as f64;
+    let mean = bins.iter().map(|(val, _)| val).sum::<f64>() / n;
+    let variance = bins
+        .iter()
+        .map(|(val, _)| (val - mean).powi(2))
+        .sum::<f64>()
+        / n;
+    let (num_pairs, covariance_sum) = bins
+        .windows(2)
+        .filter(|pair| pair[0].1)
+        .fold((0usize, 0.), |(num_pairs, sum), pair| {
+            (num_pairs + 1, sum + (pair[0].0 - mean) * (pair[1].0 - mean))
+        });
+    if num_pairs == 0 || !(variance > 0.) {
+        return 0.;
+    }
+    (covariance_sum / num_pairs as f64 / variance)
+        .max(-1.)
+        .min(1.)
+}
+
+/// The number of the `bins` entering a correlation, i.e., those without a NaN
+/// or infinite value, followed by that number scaled by `(1 - ra * rb) / (1 +
+/// ra * rb)`, where `ra` and `rb` are the lag-1 autocorrelations of the
+/// transformed values of the two tracks over the adjacent bins, with a
+/// negative product taken as 0 so that the scaled number never exceeds the
+/// number of bins.
+fn get_num_correlated_bins(
+    bins: &[(I64Interval, Vec<Option<f64>>)],
+    value_transform: ValueTransform,
+) -> (f64, f64) {
+    let finite_bins: Vec<&(I64Interval, Vec<Option<f64>>)> =
+        bins.iter().filter(|(_, v)| is_finite_pair(v)).collect();
+    let get_track_values = |track: usize| -> Vec<(f64, bool)> {
+        finite_bins
+            .iter()
+            .enumerate()
+            .map(|(i, (interval, v))| {
+                let is_adjacent_to_next =
+                    finite_bins.get(i + 1).map_or(false, |next| {
+                        interval.get_end() + 1 == next.0.get_start()
+                    });
+                (
+                    value_transform.apply(v[track].unwrap_or(0.)),
+                    is_adjacent_to_next,
+                )
+            })
+            .collect()
+    };
+    let autocorr_product = (get_lag_one_autocorrelation(&get_track_values(0))
+        * get_lag_one_autocorrelation(&get_track_values(1)))
+    .max(0.)
+    .min(1.);
+    let num_bins = finite_bins.len() as f64;
+    (
+        num_bins,
+        num_bins * (1. - autocorr_product) / (1. + autocorr_product),
+    )
+}
+
+/// The coefficients of the polynomial in `erfc`, from the lowest degree.
+const ERFC_COEFFICIENTS: [f64; 10] = [
+    -1.26551223,
+    1.00002368,
+    0.37409196,
+    0.09678418,
+    -0.18628806,
+    0.27886807,
+    -1.13520398,
+    1.48851587,
+    -0.82215223,
+    0.17087277,
+];
+
+/// The complementary error function, with a fractional error below 1.2e-7,
+/// from Numerical Recipes.
+fn erfc(x: f64) -> f64 {
+    let z = x.abs();
+    let t = 1. / (1. + 0.5 * z);
+    let polynomial = ERFC_COEFFICIENTS
+        .iter()
+        .rev()
+        .fold(0., |sum, &coefficient| sum * t + coefficient);
+    let y = t * (-z * z + polynomial).exp();
+    if x >= 0. {
+        y
+    } else {
+        2. - y
+    }
+}
+
+/// Same as `compute_track_correlations_with_options` except that the
+/// weighted least-squares slope and intercept of the second track against the
+/// first track are computed alongside each correlation, from the same weighted
+/// sums of the same bins, e.g., a slope of 2 means that the second track is
+/// systematically twice the first track. Returns the per-chromosome
+/// regressions, sorted by the chromosome names, and the overall regressions,
+/// one for each bin size. The regressions are over the individual bins even
+/// under a `quantile_binning`.
+pub fn compute_track_regressions(
+    first_track: &TrackVariant,
+    second_track: &TrackVariant,
+    bin_sizes: &[Coord],
+    options: &CorrelationOptions,
+) -> Result<(ChromRegressions, Vec<Regression>), Error> {
+    let (_, _, stats) = compute_track_correlations_with_stats(
+        first_track,
+        second_track,
+        &bin_sizes.to_vec(),
+        options,
+    )?;
+    let mut chrom_regressions = stats.chrom_regressions;
+    chrom_regressions.sort_by(|(a, _), (b, _)| a.cmp(b));
+    Ok((chrom_regressions, stats.overall_regressions))
+}
+
+/// Writes the correlations of each chromosome to `chroms/<chrom>.corr.tsv` and
+/// the overall correlations to `overall.corr.tsv` under `out_dir`, creating
+/// the directories if they do not exist. The chromosome files are kept in
+/// their own subdirectory so that a chromosome named "overall" cannot
+/// overwrite the overall correlations. Each file consists of a header line
+/// with the bin sizes as the columns, followed by a line with the name of the
+/// chromosome, or "overall", and the correlation for each bin size. Writing
+/// one small file per chromosome makes it easy to compute the chromosomes in
+/// parallel and collect the results afterwards.
+pub fn write_correlations_to_dir(
+    chrom_correlations: &ChromCorrelations,
+    overall_correlations: &OverallCorrelations,
+    bin_sizes: &[Coord],
+    out_dir: &str,
+) -> Result<(), Error> {
+    let chrom_dir = Path::new(out_dir).join("chroms");
+    std::fs::create_dir_all(&chrom_dir)?;
+    let header = bin_sizes
+        .iter()
+        .fold("chrom".to_string(), |acc, s| format!("{}\t{}", acc, s));
+    let write_file = |dir: &Path, name: &str, correlations: &[f64]| {
+        let mut writer = BufWriter::new(File::create(
+            dir.join(format!("{}.corr.tsv", name)),
+        )?);
+        writeln!(writer, "{}", header)?;
+        write!(writer, "{}", name)?;
+        for c in correlations.iter() {
+            write!(writer, "\t{}", c)?;
+        }
+        writeln!(writer)
+    };
+    for (chrom, correlations) in chrom_correlations.iter() {
+        write_file(&chrom_dir, chrom, correlations)?;
+    }
+    write_file(Path::new(out_dir), "overall", overall_correlations)?;
+    Ok(())
+}
+
+/// The correlations between pairs of named tracks, where `names` labels both
+/// the rows and the columns of the symmetric `matrix`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CorrelationMatrix {
+    pub names: Vec<String>,
+    pub matrix: Vec<Vec<f64>>,
+}
+
+/// Reads the overall correlations from a correlation output file, which is
+/// either the `overall.corr.tsv` written by `write_correlations_to_dir` or the
+/// stdout of `compute_track_correlation`, whose last line is of the form
+/// `overall, 0.12345, 0.23456, `. The last line labeled "overall" is read,
+/// which always follows the lines of the chromosomes, so that a chromosome
+/// named "overall" is not mistaken for the overall correlations. Returns the
+/// bin sizes read from the header
+/// of `overall.corr.tsv`, or `None` for the stdout format, which has no
+/// header, together with the correlations.
+pub fn read_overall_correlations(
+    path: &str,
+) -> Result<(Option<Vec<Coord>>, Vec<f64>), Error> {
+    let file = File::open(path)
+        .map_err(|why| Error::io(format!("failed to open {}", path), why))?;
+    let mut bin_sizes = None;
+    let mut correlations = None;
+    for line in BufReader::new(file).lines() {
+        let line = line.map_err(|why| {
+            Error::io(format!("failed to read {}", path), why)
+        })?;
+        let fields: Vec<&str> = line
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .map(|field| field.trim())
+            .filter(|field| !field.is_empty())
+            .collect();
+        let parse_error = |field: &str| {
+            Error::Parse(format!(
+                "invalid field {} in {}: {}",
+                field, path, line
+            ))
+        };
+        match fields.first() {
+            Some(&"chrom") => {
+                bin_sizes = Some(
+                    fields[1..]
+                        .iter()
+                        .map(|s| s.parse::<Coord>().map_err(|_| parse_error(s)))
+                        .collect::<Result<Vec<Coord>, Error>>()?,
+                );
+            }
+            Some(&"overall") => {
+                correlations = Some(
+                    fields[1..]
+                        .iter()
+                        .map(|s| s.parse::<f64>().map_err(|_| parse_error(s)))
+                        .collect::<Result<Vec<f64>, Error>>()?,
+                );
+            }
+            _ => {}
+        }
+    }
+    match correlations {
+        Some(correlations) => Ok((bin_sizes, correlations)),
+        None => Err(Error::Parse(format!(
+            "no overall correlations found in {}",
+            path
+        ))),
+    }
+}
+
+/// Assembles the correlation files of separately computed pairs of tracks
+/// into a symmetric matrix at `bin_size`. Each of the `pairs` consists of the
+/// names of the two tracks and the path to their correlation file as read by
+/// `read_overall_correlations`. For a file with a header, the correlation is
+/// selected by the bin size in the header, while for a file in the stdout
+/// format, it is selected by the position of `bin_size` in
+/// `stdout_bin_sizes`, i.e., the bin sizes in the order passed to
+/// `compute_track_correlation`. An empty `stdout_bin_sizes` selects the only
+/// correlation of a file with a single bin size.
+///
+/// The tracks are ordered by their first appearance in `pairs`. The diagonal
+/// is 1 and the pairs not in `pairs` are NaN. Returns an error if a pair
+/// appears more than once or if `bin_size` cannot be found in a file.
+pub fn collect_correlation_matrix(
+    pairs: &[(String, String, String)],
+    bin_size: Coord,
+    stdout_bin_sizes: &[Coord],
+) -> Result<CorrelationMatrix, Error> {
+    let mut names: Vec<String> = Vec::new();
+    for (first, second, _) in pairs.iter() {
+        for name in [first, second].iter() {
+            if !names.contains(*name) {
+                names.push(name.to_string());
+            }
+        }
+    }
+    let index_of =
+        |name: &String| names.iter().position(|n| n == name).unwrap();
+    let mut matrix = vec![vec![std::f64::NAN; names.len()]; names.len()];
+    for (i, row) in matrix.iter_mut().enumerate() {
+        row[i] = 1.;
+    }
+    let mut visited = HashSet::new();
+    for (first, second, path) in pairs.iter() {
+        let (i, j) = (index_of(first), index_of(second));
+        if !visited.insert((i.min(j), i.max(j))) {
+            return Err(Error::Validation(format!(
+                "the pair ({}, {}) appears more than once",
+                first, second
+            )));
+        }
+        let (file_bin_sizes, correlations) = read_overall_correlations(path)?;
+        let position = match file_bin_sizes {
+            Some(sizes) => sizes.iter().position(|&s| s == bin_size),
+            None if stdout_bin_sizes.is_empty() && correlations.len() == 1 => {
+                Some(0)
+            }
+            None => stdout_bin_sizes.iter().position(|&s| s == bin_size),
+        };
+        let correlation =
+            position.and_then(|k| correlations.get(k)).ok_or_else(|| {
+                Error::Validation(format!(
+                    "bin size {} not found in {}",
+                    bin_size, path
+                ))
+            })?;
+        matrix[i][j] = *correlation;
+        matrix[j][i] = *correlation;
+    }
+    Ok(CorrelationMatrix {
+        names,
+        matrix,
+    })
+}
+
+/// The correlation of a single chromosome at one bin size together with the
+/// counts of the bins behind it.
+struct ChromCorrelation {
+    correlation: f64,
+    /// The number of bins skipped for having a NaN or infinite value.
+    num_skipped: usize,
+    /// The number of bins entering the correlation.
+    num_bins: f64,
+    /// `num_bins` corrected for the autocorrelation of the two tracks as in
+    /// `get_num_correlated_bins`.
+    num_autocorr_corrected_bins: f64,
+    /// The weighted sums of the bins entering the correlation.
+    moments: WeightedMoments,
+    /// The regression computed from the `moments`, which is NaN under the
+    /// same condition as the `correlation`.
+    regression: Regression,
+}
+
+/// Computes the correlations between the two interval maps of a single
+/// chromosome, one for each of the `bin_sizes`.
+fn compute_chrom_correlations(
+    map_a: &IntegerIntervalMap<f64>,
+    map_b: &IntegerIntervalMap<f64>,
+    bin_sizes: &[Coord],
+    options: &CorrelationOptions,
+) -> Result<Vec<ChromCorrelation>, Error> {
+    let value_transform = options.value_transform;
+    let top_k = options.top_k;
+    let difference_order = options.difference_order;
+    let bin_weight = options.bin_weight;
+    let quantile_binning = options.quantile_binning;
+    let approx_quantiles = options.approx_quantiles;
+    let is_observed = get_observed_bin_filter(
+        map_a,
+        map_b,
+        options.zero_fill_mode,
+        options.min_coverage,
+        options.coverage_predicate,
+    );
+    let apply_difference_order =
+        |mut bins: Vec<(I64Interval, Vec<Option<f64>>)>| {
+            bins.retain(&is_observed);
+            match difference_order {
+                DifferenceOrder::None => bins,
+                DifferenceOrder::First => {
+                    get_first_differences(bins.into_iter())
+                }
+            }
+        };
     let get_a_bin_b_zipped =
-        |map_a,
-         map_b,
-         bin_size|
-         -> Result<Vec<(I64Interval, Vec<Option<f64>>)>, String> {
+        |bin_size| -> Result<Vec<(I64Interval, Vec<Option<f64>>)>, Error> {
             if let Some(k) = top_k {
                 let map_a_top_k = get_top_k_bin_map(map_a, k, bin_size)?;
                 let map_b_top_k = get_top_k_bin_map(map_b, k, bin_size)?;
@@ -112,107 +1256,1146 @@ pub fn compute_track_correlations(
                     .collect())
             }
         };
+    let has_too_few_bins = |num_bins: usize, bin_size: Coord| {
+        let too_few = num_bins < options.min_bins;
+        if too_few && options.verbose {
+            eprintln!(
+                "=> reporting NaN for bin size {} because only {} bins are \
+                correlated, fewer than min_bins ({})",
+                bin_size, num_bins, options.min_bins
+            );
+        }
+        too_few
+    };
 
-    let chrom_correlations: Vec<(String, Vec<f64>)> =
-        get_target_interval_maps()
-            .map(|(chrom, map_a, map_b)| {
-                eprintln!("=> Computing correlations for {}", chrom);
+    bin_sizes
+        .iter()
+        .map(|&s| {
+            let vec = match s {
+                0 => apply_difference_order(
+                    a_common_refine_b(map_a, map_b).collect(),
+                ),
+                non_zero => {
+                    apply_difference_order(get_a_bin_b_zipped(non_zero)?)
+                }
+            };
+            let num_skipped = count_non_finite_pairs(&vec);
+            let (num_bins, num_autocorr_corrected_bins) =
+                get_num_correlated_bins(&vec, value_transform);
+            let mut moments = WeightedMoments::default();
+            for (interval, v) in vec.iter().filter(|(_, v)| is_finite_pair(v)) {
+                moments.add(
+                    apply_transform(v[0].unwrap_or(0.), value_transform),
+                    apply_transform(v[1].unwrap_or(0.), value_transform),
+                    apply_bin_weight(interval.size(), bin_weight),
+                );
+            }
+            let too_few_bins = has_too_few_bins(vec.len() - num_skipped, s);
+            let correlation = if too_few_bins {
+                f64::NAN
+            } else if s == 0 {
+                correlation_with_binary_fast_path!(
+                    || vec.iter(),
+                    non_binned_extractor!(
+                        apply_transform,
+                        value_transform,
+                        bin_weight
+                    ),
+                    quantile_binning,
+                    approx_quantiles
+                )
+            } else {
+                correlation_with_binary_fast_path!(
+                    || vec.iter(),
+                    binned_extractor!(
+                        apply_transform,
+                        value_transform,
+                        bin_weight
+                    ),
+                    quantile_binning,
+                    approx_quantiles
+                )
+            };
+            Ok(ChromCorrelation {
+                correlation,
+                num_skipped,
+                num_bins,
+                num_autocorr_corrected_bins,
+                moments,
+                regression: if too_few_bins {
+                    (f64::NAN, f64::NAN, f64::NAN)
+                } else {
+                    moments.regression()
+                },
+            })
+        })
+        .collect()
+}
+
+/// Whether neither of the values of a bin is NaN or infinite. A missing value
+/// counts as finite.
+fn is_finite_pair(values: &[Option<f64>]) -> bool {
+    values.iter().all(|v| v.map_or(true, f64::is_finite))
+}
+
+/// Returns the number of bins with a NaN or infinite value, which are skipped
+/// by the extractors.
+fn count_non_finite_pairs(bins: &[(I64Interval, Vec<Option<f64>>)]) -> usize {
+    bins.iter()
+        .filter(|(_, values)| !is_finite_pair(values))
+        .count()
+}
+
+/// Returns the closed interval from the start of the first interval to the end
+/// of the last interval in `map`, or `None` if `map` is empty.
+fn get_covered_span(map: &IntegerIntervalMap<f64>) -> Option<I64Interval> {
+    let start = map.iter().next()?.0.get_start();
+    let end = map.iter().next_back()?.0.get_end();
+    Some(I64Interval::new(start, end))
+}
+
+/// Returns the intersection of the covered spans of `map_a` and `map_b`, or
+/// `None` if either is empty or the spans are disjoint.
+fn get_shared_covered_span(
+    map_a: &IntegerIntervalMap<f64>,
+    map_b: &IntegerIntervalMap<f64>,
+) -> Option<I64Interval> {
+    let a = get_covered_span(map_a)?;
+    let b = get_covered_span(map_b)?;
+    let start = a.get_start().max(b.get_start());
+    let end = a.get_end().min(b.get_end());
+    if start <= end {
+        Some(I64Interval::new(start, end))
+    } else {
+        None
+    }
+}
 
-                let correlations: Result<Vec<f64>, String> = bin_sizes
+/// Returns a predicate on the zipped bins of `map_a` and `map_b` that is false
+/// for the bins left out as unobserved under the `zero_fill_mode`, i.e., the
+/// bins without data in a track that extend beyond the span covered by both
+/// tracks, for the bins where the value of either track is below
+/// the `min_coverage`, and for the bins not satisfying the
+/// `coverage_predicate`.
+fn get_observed_bin_filter(
+    map_a: &IntegerIntervalMap<f64>,
+    map_b: &IntegerIntervalMap<f64>,
+    zero_fill_mode: ZeroFillMode,
+    min_coverage: Option<f64>,
+    coverage_predicate: CoveragePredicate,
+) -> impl Fn(&(I64Interval, Vec<Option<f64>>)) -> bool {
+    let span = match zero_fill_mode {
+        ZeroFillMode::WholeChromosome => None,
+        ZeroFillMode::WithinCoveredSpan => {
+            Some(get_shared_covered_span(map_a, map_b))
+        }
+    };
+    move |(interval, values)| {
+        let is_covered = min_coverage.map_or(true, |min| {
+            values.iter().all(|value| value.unwrap_or(0.) >= min)
+        });
+        is_covered
+            && coverage_predicate.is_satisfied(values)
+            && match &span {
+                None => true,
+                Some(span) => {
+                    values.iter().all(|value| value.is_some())
+                        || span.as_ref().map_or(false, |span| {
+                            span.get_start() <= interval.get_start()
+                                && interval.get_end() <= span.get_end()
+                        })
+                }
+            }
+    }
+}
+
+/// Replaces the values in each bin by their differences from the values in the
+/// preceding bin, leaving out the bins that are not contiguous with the
+/// preceding bin. A missing value is taken to be zero.
+fn get_first_differences<
+    I: Iterator<Item = (I64Interval, Vec<Option<f64>>)>,
+>(
+    bins: I,
+) -> Vec<(I64Interval, Vec<Option<f64>>)> {
+    let mut differences = Vec::new();
+    let mut prev: Option<(I64Interval, Vec<Option<f64>>)> = None;
+    for (interval, values) in bins {
+        if let Some((prev_interval, prev_values)) = prev.as_ref() {
+            if prev_interval.get_end() + 1 == interval.get_start() {
+                let diff = values
                     .iter()
-                    .map(|&s| match s {
-                        0 => {
-                            let vec: Vec<(I64Interval, Vec<Option<f64>>)> =
-                                a_common_refine_b(map_a, map_b).collect();
-
-                            Ok(weighted_correlation(
-                                || vec.iter(),
-                                non_binned_extractor!(
-                                    apply_transform,
-                                    value_transform
-                                ),
-                            ))
-                        }
-                        non_zero => {
-                            let vec: Vec<(I64Interval, Vec<Option<f64>>)> =
-                                get_a_bin_b_zipped(map_a, map_b, non_zero)?;
-
-                            Ok(weighted_correlation(
-                                || vec.iter(),
-                                binned_extractor!(
-                                    apply_transform,
-                                    value_transform
-                                ),
-                            ))
-                        }
-                    })
+                    .zip(prev_values.iter())
+                    .map(|(v, p)| Some(v.unwrap_or(0.) - p.unwrap_or(0.)))
                     .collect();
+                differences.push((interval, diff));
+            }
+        }
+        prev = Some((interval, values));
+    }
+    differences
+}
+
+/// Sorts the `(a, b, weight)` triples by `a` and divides them into
+/// `num_groups` groups of equal counts, ties being broken by the original
+/// order. Returns the correlation between the weighted means of `a` and `b`
+/// over the groups. Empty groups, e.g., when there are fewer triples than
+/// groups, are left out.
+fn get_quantile_group_correlation(
+    mut values: Vec<(f64, f64, f64)>,
+    num_groups: usize,
+) -> f64 {
+    // a single group has no correlation either way
+    let num_groups = num_groups.max(1);
+    values.sort_by(|x, y| x.0.partial_cmp(&y.0).unwrap_or(Ordering::Equal));
+    let n = values.len();
+    let mut sums = vec![(0f64, 0f64, 0f64); num_groups];
+    for (i, (a, b, weight)) in values.into_iter().enumerate() {
+        add_to_group_sum(&mut sums[i * num_groups / n], a, b, weight);
+    }
+    get_group_mean_correlation(sums)
+}
+
+/// Same as `get_quantile_group_correlation` except that the groups are
+/// bounded by the approximate quantiles of `a` from an `ApproxQuantiles`
+/// sketch, so that the triples are iterated twice from `get_iter` instead of
+/// being collected and sorted. The triples tied at a group boundary fall into
+/// the same group.
+fn get_approx_quantile_group_correlation<I, F>(
+    get_iter: F,
+    num_groups: usize,
+) -> f64
+where
+    I: Iterator<Item = (f64, f64, f64)>,
+    F: Fn() -> I,
+{
+    let num_groups = num_groups.max(1);
+    let mut sketch = ApproxQuantiles::new(APPROX_QUANTILE_EPSILON)
+        .expect("APPROX_QUANTILE_EPSILON must be in (0, 1)");
+    for (a, ..) in get_iter() {
+        sketch.add(a);
+    }
+    // the largest value of each group except the last one
+    let boundaries: Vec<f64> = (1..num_groups)
+        .map(|i| sketch.quantile(i as f64 / num_groups as f64))
+        .collect();
+    let mut sums = vec![(0f64, 0f64, 0f64); num_groups];
+    for (a, b, weight) in get_iter() {
+        let group = boundaries.partition_point(|&boundary| boundary < a);
+        add_to_group_sum(&mut sums[group], a, b, weight);
+    }
+    get_group_mean_correlation(sums)
+}
+
+fn add_to_group_sum(sum: &mut (f64, f64, f64), a: f64, b: f64, weight: f64) {
+    sum.0 += a * weight;
+    sum.1 += b * weight;
+    sum.2 += weight;
+}
+
+/// Returns the correlation between the weighted means of `a` and `b` over the
+/// groups from their weighted sums, leaving out the empty groups.
+fn get_group_mean_correlation(sums: Vec<(f64, f64, f64)>) -> f64 {
+    let means: Vec<(f64, f64)> = sums
+        .into_iter()
+        .filter(|&(_, _, weight)| weight > 0.)
+        .map(|(a, b, weight)| (a / weight, b / weight))
+        .collect();
+    weighted_correlation(|| means.iter(), |&(a, b)| (a, b, 1.))
+}
+
+/// Computes the correlations between the two tracks on a single chromosome,
+/// one for each of the `bin_sizes`, which gives the same result as the entry
+/// for `chrom` from `compute_track_correlations`. Only the lines on `chrom`
+/// are aggregated, so this is much cheaper when a single chromosome is of
+/// interest. Returns NaN correlations if neither track has data on `chrom`.
+pub fn compute_single_chrom_correlation(
+    first_track: &TrackVariant,
+    second_track: &TrackVariant,
+    chrom: &str,
+    bin_sizes: &[Coord],
+    value_transform: ValueTransform,
+    exclude_track_filepath: Option<String>,
+) -> Result<Vec<f64>, Error> {
+    value_transform.validate()?;
+    let exclude = get_aliased_excluded_interval_maps(
+        exclude_track_filepath.as_deref(),
+        &ReadOptions::default().chrom_alias,
+    )?;
+    let map_a =
+        get_single_chrom_interval_map(first_track, chrom, exclude.as_ref())?;
+    let map_b =
+        get_single_chrom_interval_map(second_track, chrom, exclude.as_ref())?;
+    compute_chrom_correlations(&map_a, &map_b, bin_sizes, &CorrelationOptions {
+        value_transform,
+        ..CorrelationOptions::default()
+    })
+    .map(|correlations| correlations.iter().map(|c| c.correlation).collect())
+}
+
+/// Computes the same correlations as `compute_track_correlations` without
+/// loading either track fully into memory. Both tracks must be sorted by
+/// chromosome in lexicographic order and then by the start coordinate, e.g.,
+/// with `sort -k1,1 -k2,2n`. The two tracks are walked with a merge cursor one
+/// chromosome at a time, so at most one chromosome's data from each track is
+/// held in memory. Returns an error as soon as either track is found to be
+/// unsorted.
+pub fn compute_track_correlations_streaming(
+    first_track: &TrackVariant,
+    second_track: &TrackVariant,
+    bin_sizes: &Vec<Coord>,
+    target_chroms: Option<HashSet<String>>,
+    value_transform: ValueTransform,
+    exclude_track_filepath: Option<String>,
+) -> Result<(ChromCorrelations, OverallCorrelations), Error> {
+    compute_track_correlations_streaming_with_options(
+        first_track,
+        second_track,
+        bin_sizes,
+        &StreamingOptions {
+            target_chroms,
+            value_transform,
+            exclude_track_filepath,
+            ..StreamingOptions::default()
+        },
+    )
+}
+
+/// The options for `compute_track_correlations_streaming_with_options`. See
+/// `compute_track_correlations_streaming` for the meaning of the first few
+/// fields.
+#[derive(Clone)]
+pub struct StreamingOptions {
+    pub target_chroms: Option<HashSet<String>>,
+    pub value_transform: ValueTransform,
+    pub exclude_track_filepath: Option<String>,
+    /// How the lines of both tracks are read, as in
+    /// `CorrelationOptions::read_options`.
+    pub read_options: ReadOptions,
+    /// Whether to print the progress messages to stderr.
+    pub verbose: bool,
+    /// The number of base pairs by which the start coordinate of a line may
+    /// precede the largest start coordinate seen so far on its chromosome
+    /// without the track being considered unsorted. This tolerates nearly
+    /// sorted files, e.g., from parallel writers. Defaults to 0, which
+    /// requires the start coordinates to be sorted.
+    pub max_buffer_bp: Coord,
+}
+
+impl Default for StreamingOptions {
+    fn default() -> Self {
+        StreamingOptions {
+            target_chroms: None,
+            value_transform: ValueTransform::Identity,
+            exclude_track_filepath: None,
+            read_options: ReadOptions::default(),
+            verbose: true,
+            max_buffer_bp: 0,
+        }
+    }
+}
+
+/// Same as `compute_track_correlations_streaming` except that the lines on a
+/// chromosome may be out of order by up to `options.max_buffer_bp` base
+/// pairs. Since the lines of a chromosome are aggregated into an interval map
+/// before the correlations are computed, the buffered lines need no explicit
+/// reordering. The chromosomes must still be sorted. As in
+/// `compute_track_correlations_with_stats`, the bins where either value is
+/// NaN or infinite are left out, and their number is reported in a warning if
+/// `options.verbose` is true.
+pub fn compute_track_correlations_streaming_with_options(
+    first_track: &TrackVariant,
+    second_track: &TrackVariant,
+    bin_sizes: &Vec<Coord>,
+    options: &StreamingOptions,
+) -> Result<(ChromCorrelations, OverallCorrelations), Error> {
+    options.value_transform.validate()?;
+    let target_chroms = &options.target_chroms;
+    let value_transform = options.value_transform;
+    let verbose = options.verbose;
+    let exclude = get_aliased_excluded_interval_maps(
+        options.exclude_track_filepath.as_deref(),
+        &options.read_options.chrom_alias,
+    )?;
+
+    let mut cursor_a = SortedChromCursor::new(
+        get_chrom_start_end_val_iter_with_options(
+            first_track,
+            &options.read_options,
+        ),
+        "first",
+        options.max_buffer_bp,
+    );
+    let mut cursor_b = SortedChromCursor::new(
+        get_chrom_start_end_val_iter_with_options(
+            second_track,
+            &options.read_options,
+        ),
+        "second",
+        options.max_buffer_bp,
+    );
+
+    let mut chrom_correlations: ChromCorrelations = Vec::new();
+    let mut overall_moments: Vec<WeightedMoments> = bin_sizes
+        .iter()
+        .map(|_| WeightedMoments::default())
+        .collect();
+    let mut num_skipped_pairs = vec![0usize; bin_sizes.len()];
+
+    let empty_interval_map = IntegerIntervalMap::new();
+    loop {
+        let next_chrom = match (
+            cursor_a.current_chrom()?.cloned(),
+            cursor_b.current_chrom()?.cloned(),
+        ) {
+            (None, None) => break,
+            (Some(a), None) => a,
+            (None, Some(b)) => b,
+            (Some(a), Some(b)) => std::cmp::min(a, b),
+        };
+        let map_a =
+            cursor_a.next_chrom_map_if(&next_chrom, exclude.as_ref())?;
+        let map_b =
+            cursor_b.next_chrom_map_if(&next_chrom, exclude.as_ref())?;
+
+        if target_chroms.is_some()
+            && !target_chroms.as_ref().unwrap().contains(&next_chrom)
+        {
+            continue;
+        }
+        if verbose {
+            eprintln!("=> Computing correlations for {}", next_chrom);
+        }
 
-                correlations.map(|c| (chrom, c))
+        let map_a = map_a.as_ref().unwrap_or(&empty_interval_map);
+        let map_b = map_b.as_ref().unwrap_or(&empty_interval_map);
+        let correlations: Vec<f64> = bin_sizes
+            .iter()
+            .zip(overall_moments.iter_mut())
+            .zip(num_skipped_pairs.iter_mut())
+            .map(|((&s, moments), num_skipped)| {
+                let vec: Vec<(I64Interval, Vec<Option<f64>>)> = match s {
+                    0 => a_common_refine_b(map_a, map_b).collect(),
+                    non_zero => {
+                        get_common_refined_binned_iter(map_a, map_b, non_zero)
+                            .collect()
+                    }
+                };
+                *num_skipped += count_non_finite_pairs(&vec);
+                for (interval, v) in
+                    vec.iter().filter(|(_, v)| is_finite_pair(v))
+                {
+                    moments.add(
+                        apply_transform(v[0].unwrap_or(0.), value_transform),
+                        apply_transform(v[1].unwrap_or(0.), value_transform),
+                        interval.size() as f64,
+                    );
+                }
+                match s {
+                    0 => weighted_correlation(
+                        || vec.iter(),
+                        non_binned_extractor!(apply_transform, value_transform),
+                    ),
+                    _ => weighted_correlation(
+                        || vec.iter(),
+                        binned_extractor!(apply_transform, value_transform),
+                    ),
+                }
             })
-            .collect::<Result<Vec<(String, Vec<f64>)>, String>>()?;
+            .collect();
+        chrom_correlations.push((next_chrom, correlations));
+    }
 
-    eprintln!("=> Computing overall correlations");
-    let overall_correlations: Vec<f64> = bin_sizes
+    if verbose {
+        for (bin_size, num_skipped) in
+            bin_sizes.iter().zip(num_skipped_pairs.iter())
+        {
+            if *num_skipped > 0 {
+                eprintln!(
+                    "warning: skipped {} bins with NaN or infinite values for \
+                    bin size {}",
+                    num_skipped, bin_size
+                );
+            }
+        }
+    }
+    let overall_correlations = overall_moments
         .iter()
-        .map(|&s| match s {
-            0 => weighted_correlation(
-                || {
-                    ConcatenatedIter::from_iters(
-                        get_target_interval_maps()
-                            .map(|(_, map_a, map_b)| {
-                                a_common_refine_b(map_a, map_b)
-                            })
-                            .collect(),
+        .map(|moments| moments.correlation())
+        .collect();
+    Ok((chrom_correlations, overall_correlations))
+}
+
+/// For each pair of bin sizes `(bin_sizes_a[i], bin_sizes_b[j])`, bins the
+/// first track with `bin_sizes_a[i]` and the second track with
+/// `bin_sizes_b[j]`, then computes the overall correlation across chromosomes
+/// over the common refinement of the two binned tracks. The returned matrix has
+/// `bin_sizes_a.len()` rows and `bin_sizes_b.len()` columns, where the entry
+/// at `[i][j]` is the correlation for the pair `(bin_sizes_a[i],
+/// bin_sizes_b[j])`. This shows how the correlation degrades when the two
+/// tracks are compared at mismatched resolutions.
+pub fn compute_cross_resolution_correlations(
+    first_track: &TrackVariant,
+    second_track: &TrackVariant,
+    bin_sizes_a: &[Coord],
+    bin_sizes_b: &[Coord],
+) -> Result<Vec<Vec<f64>>, Error> {
+    let invalid_bin_sizes: Vec<Coord> = bin_sizes_a
+        .iter()
+        .chain(bin_sizes_b.iter())
+        .filter(|&&s| s <= 0)
+        .cloned()
+        .collect();
+    if !invalid_bin_sizes.is_empty() {
+        return Err(Error::Validation(format!(
+            "bin sizes must be positive, but these are not: {:?}",
+            invalid_bin_sizes
+        )));
+    }
+    let chrom_interval_map_a = get_chrom_interval_map(first_track, None)?;
+    let chrom_interval_map_b = get_chrom_interval_map(second_track, None)?;
+
+    let empty_interval_map = IntegerIntervalMap::new();
+    let maps: Vec<(&IntegerIntervalMap<f64>, &IntegerIntervalMap<f64>)> =
+        chrom_interval_map_a
+            .union_zip(&chrom_interval_map_b)
+            .into_iter()
+            .map(|(_chrom, map_list)| {
+                (
+                    map_list[0].unwrap_or_else(|| &empty_interval_map),
+                    map_list[1].unwrap_or_else(|| &empty_interval_map),
+                )
+            })
+            .collect();
+    let get_zipped_iter = |size_a, size_b| {
+        ConcatenatedIter::from_iters(
+            maps.iter()
+                .map(|&(map_a, map_b)| {
+                    get_common_refined_binned_iter_with_bin_sizes(
+                        map_a, map_b, size_a, size_b,
                     )
-                },
-                non_binned_extractor!(apply_transform, value_transform),
-            ),
-            non_zero => weighted_correlation(
+                })
+                .collect(),
+        )
+    };
+
+    Ok(bin_sizes_a
+        .iter()
+        .map(|&size_a| {
+            bin_sizes_b
+                .iter()
+                .map(|&size_b| {
+                    correlation_with_binary_fast_path!(
+                        || get_zipped_iter(size_a, size_b),
+                        binned_extractor!(
+                            apply_transform,
+                            ValueTransform::Identity
+                        )
+                    )
+                })
+                .collect()
+        })
+        .collect())
+}
+
+/// Slides a window of `window_bp` base pairs along each chromosome in steps of
+/// `step_bp` and computes the Pearson correlation between the binned values of
+/// the two tracks within each window. Each returned item is
+/// `(chrom, window_start, window_end_exclusive, correlation)`. A bin belongs to
+/// a window if its start coordinate lies in the window. Windows with fewer than
+/// `MIN_LOCAL_CORRELATION_BINS` bins have a NaN correlation.
+pub fn compute_windowed_local_correlation(
+    first_track: &TrackVariant,
+    second_track: &TrackVariant,
+    window_bp: Coord,
+    step_bp: Coord,
+    bin_size: Coord,
+) -> Result<Vec<(String, Coord, Coord, f64)>, Error> {
+    if window_bp <= 0 || step_bp <= 0 || bin_size <= 0 {
+        return Err(Error::Validation(format!(
+            "window_bp ({}), step_bp ({}) and bin_size ({}) must be positive",
+            window_bp, step_bp, bin_size
+        )));
+    }
+    let chrom_interval_map_a = get_chrom_interval_map(first_track, None)?;
+    let chrom_interval_map_b = get_chrom_interval_map(second_track, None)?;
+
+    let empty_interval_map = IntegerIntervalMap::new();
+    let mut chrom_to_maps: Vec<(
+        String,
+        Vec<Option<&IntegerIntervalMap<f64>>>,
+    )> = chrom_interval_map_a
+        .union_zip(&chrom_interval_map_b)
+        .into_iter()
+        .collect();
+    chrom_to_maps.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut local_correlations = Vec::new();
+    for (chrom, map_list) in chrom_to_maps.into_iter() {
+        let map_a = map_list[0].unwrap_or_else(|| &empty_interval_map);
+        let map_b = map_list[1].unwrap_or_else(|| &empty_interval_map);
+        let bins: Vec<(I64Interval, Vec<Option<f64>>)> =
+            get_common_refined_binned_iter(map_a, map_b, bin_size).collect();
+        let (first_start, last_end) = match (bins.first(), bins.last()) {
+            (Some(first), Some(last)) => {
+                (first.0.get_start(), last.0.get_end())
+            }
+            _ => continue,
+        };
+
+        let mut lo = 0;
+        let mut hi = 0;
+        let mut window_start = (first_start / step_bp) * step_bp;
+        while window_start <= last_end {
+            let window_end_exclusive = window_start + window_bp;
+            while lo < bins.len() && bins[lo].0.get_start() < window_start {
+                lo += 1;
+            }
+            hi = hi.max(lo);
+            while hi < bins.len()
+                && bins[hi].0.get_start() < window_end_exclusive
+            {
+                hi += 1;
+            }
+            let correlation = if hi - lo < MIN_LOCAL_CORRELATION_BINS {
+                std::f64::NAN
+            } else {
+                weighted_correlation(
+                    || bins[lo..hi].iter(),
+                    binned_extractor!(
+                        apply_transform,
+                        ValueTransform::Identity
+                    ),
+                )
+            };
+            local_correlations.push((
+                chrom.clone(),
+                window_start,
+                window_end_exclusive,
+                correlation,
+            ));
+            window_start += step_bp;
+        }
+    }
+    Ok(local_correlations)
+}
+
+/// Computes the correlation between `track` and each of the reference tracks in
+/// a matrix written by `TrackZipper::write_concatenated_tracks` with
+/// `EndConvention::Exclusive`, in which each line consists of the start, the
+/// end and the chromosome of a bin followed by the values of the reference
+/// tracks. The matrix is read only once however many reference tracks it has,
+/// and each of its columns is binned together with the track by
+/// `get_common_refined_binned_iter` with `bin_size`, which should be the bin
+/// size of the matrix. As in `compute_track_correlations`, the correlations
+/// are taken over the bins in either the matrix or the track, a bin missing
+/// from one of them counting as zero.
+pub fn correlate_against_matrix(
+    track: &TrackVariant,
+    matrix_path: &str,
+    bin_size: Coord,
+) -> Result<Vec<f64>, Error> {
+    if bin_size <= 0 {
+        return Err(Error::Validation(format!(
+            "bin_size ({}) must be positive",
+            bin_size
+        )));
+    }
+    let chrom_to_track_map = get_chrom_interval_map(track, None)?;
+
+    let file = File::open(matrix_path).map_err(|why| {
+        Error::io(format!("failed to open {}", matrix_path), why)
+    })?;
+    // the interval map of each reference track on each chromosome
+    let mut chrom_to_column_maps =
+        HashMap::<String, Vec<IntegerIntervalMap<f64>>>::new();
+    let mut num_columns = None;
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|why| {
+            Error::io(format!("failed to read {}", matrix_path), why)
+        })?;
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.is_empty() {
+            continue;
+        }
+        let parse_error = || {
+            Error::Parse(format!(
+                "failed to parse line {} of {}: {}",
+                i + 1,
+                matrix_path,
+                line
+            ))
+        };
+        if fields.len() < 4 {
+            return Err(parse_error());
+        }
+        let start: Coord = fields[0].parse().map_err(|_| parse_error())?;
+        let end: Coord = fields[1].parse().map_err(|_| parse_error())?;
+        let values = fields[3..]
+            .iter()
+            .map(|v| v.parse::<f64>())
+            .collect::<Result<Vec<f64>, _>>()
+            .map_err(|_| parse_error())?;
+        let num_columns = *num_columns.get_or_insert(values.len());
+        if num_columns != values.len() {
+            return Err(Error::Parse(format!(
+                "line {} of {} has {} values while the previous lines have {}",
+                i + 1,
+                matrix_path,
+                values.len(),
+                num_columns
+            )));
+        }
+        if end <= start {
+            return Err(parse_error());
+        }
+        let column_maps = chrom_to_column_maps
+            .entry(fields[2].to_string())
+            .or_insert_with(|| {
+                (0..num_columns)
+                    .map(|_| IntegerIntervalMap::new())
+                    .collect()
+            });
+        for (map, value) in column_maps.iter_mut().zip(values.into_iter()) {
+            map.aggregate(I64Interval::new(start, end - 1), value);
+        }
+    }
+    let num_columns = num_columns.ok_or_else(|| {
+        Error::Validation(format!("the matrix {} has no bins", matrix_path))
+    })?;
+
+    // the chromosomes in either the matrix or the track
+    let mut chroms: Vec<&String> = chrom_to_track_map
+        .keys()
+        .chain(chrom_to_column_maps.keys())
+        .collect();
+    chroms.sort();
+    chroms.dedup();
+    let empty_interval_map = IntegerIntervalMap::new();
+    Ok((0..num_columns)
+        .map(|j| {
+            correlation_with_binary_fast_path!(
                 || {
                     ConcatenatedIter::from_iters(
-                        get_target_interval_maps()
-                            .map(|(_, map_a, map_b)| {
+                        chroms
+                            .iter()
+                            .map(|&chrom| {
                                 get_common_refined_binned_iter(
-                                    map_a, map_b, non_zero,
+                                    chrom_to_track_map
+                                        .get(chrom)
+                                        .unwrap_or(&empty_interval_map),
+                                    chrom_to_column_maps
+                                        .get(chrom)
+                                        .map_or(&empty_interval_map, |maps| {
+                                            &maps[j]
+                                        }),
+                                    bin_size,
                                 )
                             })
                             .collect(),
                     )
                 },
-                binned_extractor!(apply_transform, value_transform),
-            ),
+                binned_extractor!(apply_transform, ValueTransform::Identity)
+            )
         })
-        .collect();
-    Ok((chrom_correlations, overall_correlations))
+        .collect())
+}
+
+/// Writes a bedGraph in which each bin carries `z_a * z_b`, where `z_a` and
+/// `z_b` are the values of the two tracks in the bin z-scored genome-wide.
+/// Positive values are where the tracks co-vary and negative values are where
+/// they anti-vary. The average of the contributions weighted by the bin sizes
+/// equals the overall correlation for `bin_size`, so the track decomposes the
+/// correlation spatially.
+pub fn write_correlation_contribution_track(
+    first_track: &TrackVariant,
+    second_track: &TrackVariant,
+    bin_size: Coord,
+    out_path: &str,
+) -> Result<(), Error> {
+    if bin_size <= 0 {
+        return Err(Error::Validation(format!(
+            "bin_size ({}) must be positive",
+            bin_size
+        )));
+    }
+    let chrom_interval_map_a = get_chrom_interval_map(first_track, None)?;
+    let chrom_interval_map_b = get_chrom_interval_map(second_track, None)?;
+
+    let empty_interval_map = IntegerIntervalMap::new();
+    let mut chrom_to_bins: Vec<(String, Vec<(I64Interval, f64, f64)>)> =
+        chrom_interval_map_a
+            .union_zip(&chrom_interval_map_b)
+            .into_iter()
+            .map(|(chrom, map_list)| {
+                let map_a = map_list[0].unwrap_or_else(|| &empty_interval_map);
+                let map_b = map_list[1].unwrap_or_else(|| &empty_interval_map);
+                let bins =
+                    get_common_refined_binned_iter(map_a, map_b, bin_size)
+                        .map(|(interval, v)| {
+                            (interval, v[0].unwrap_or(0.), v[1].unwrap_or(0.))
+                        })
+                        .collect();
+                (chrom, bins)
+            })
+            .collect();
+    chrom_to_bins.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut moments = WeightedMoments::default();
+    for (_, bins) in chrom_to_bins.iter() {
+        for (interval, a, b) in bins.iter() {
+            moments.add(*a, *b, interval.size() as f64);
+        }
+    }
+    let (mean_a, mean_b) = (moments.mean_x, moments.mean_y);
+    let sd_a = moments.variance_x().sqrt();
+    let sd_b = moments.variance_y().sqrt();
+    if !(sd_a > 0. && sd_b > 0.) {
+        return Err(Error::Validation(format!(
+            "cannot z-score a track with zero variance (sd_a: {}, sd_b: {})",
+            sd_a, sd_b
+        )));
+    }
+
+    let mut writer = BedWriter::new(out_path)?;
+    for (chrom, bins) in chrom_to_bins.into_iter() {
+        for (interval, a, b) in bins.into_iter() {
+            writer.write_bedgraph_line(&BedGraphDataLine {
+                chrom: chrom.clone(),
+                start: interval.get_start(),
+                // the end coordinate is exclusive under the bedGraph format
+                end_exclusive: interval.get_end() + 1,
+                value: ((a - mean_a) / sd_a) * ((b - mean_b) / sd_b),
+            })?;
+        }
+    }
+    Ok(())
+}
+
+/// The minimum number of bins in a window for the local correlation to be
+/// computed.
+pub const MIN_LOCAL_CORRELATION_BINS: usize = 3;
+
+type ChromStartEndValResult =
+    Result<(String, Coord, Coord, Option<f64>), Error>;
+
+/// Walks a chromosome-sorted track one chromosome at a time.
+struct SortedChromCursor<I: Iterator<Item = ChromStartEndValResult>> {
+    iter: Peekable<I>,
+    past_chroms: HashSet<String>,
+    track_name: &'static str,
+    // how far a start coordinate may fall behind the largest one seen so far
+    max_buffer_bp: Coord,
+}
+
+impl<I: Iterator<Item = ChromStartEndValResult>> SortedChromCursor<I> {
+    fn new(
+        iter: I,
+        track_name: &'static str,
+        max_buffer_bp: Coord,
+    ) -> SortedChromCursor<I> {
+        SortedChromCursor {
+            iter: iter.peekable(),
+            past_chroms: HashSet::new(),
+            track_name,
+            max_buffer_bp,
+        }
+    }
+
+    /// Returns the chromosome of the next line, or the error in reading the
+    /// next line.
+    fn current_chrom(&mut self) -> Result<Option<&String>, Error> {
+        if self.iter.peek().map_or(false, Result::is_err) {
+            return Err(self.iter.next().unwrap().unwrap_err());
+        }
+        Ok(self.iter.peek().map(|line| &line.as_ref().unwrap().0))
+    }
+
+    /// Consumes all the lines of the current chromosome if the current
+    /// chromosome is `chrom`, returning `None` otherwise.
+    fn next_chrom_map_if(
+        &mut self,
+        chrom: &str,
+        exclude: Option<&HashMap<String, OrderedIntegerSet<Coord>>>,
+    ) -> Result<Option<IntegerIntervalMap<f64>>, Error> {
+        match self.current_chrom()? {
+            Some(current) if current == chrom => {}
+            _ => return Ok(None),
+        }
+        if self.past_chroms.contains(chrom)
+            || self.past_chroms.iter().any(|c| c.as_str() > chrom)
+        {
+            return Err(Error::UnsortedInput(format!(
+                "the {} track is not sorted by chromosome, encountered {} \
+                after chromosomes that should come later",
+                self.track_name, chrom
+            )));
+        }
+        let excluded_intervals = exclude.and_then(|e| e.get(chrom));
+
+        let mut interval_map = IntegerIntervalMap::new();
+        let mut max_start = None;
+        while let Some(next_chrom) = self.current_chrom()? {
+            if next_chrom != chrom {
+                break;
+            }
+            let (_, start, end, value) = self.iter.next().unwrap()?;
+            if let Some(max_start) = max_start {
+                if start < max_start - self.max_buffer_bp {
+                    return Err(Error::UnsortedInput(format!(
+                        "the {} track is not sorted by coordinates, \
+                        encountered start {} after start {} on {}, which is \
+                        more than the buffer of {} bp",
+                        self.track_name,
+                        start,
+                        max_start,
+                        chrom,
+                        self.max_buffer_bp
+                    )));
+                }
+            }
+            max_start = Some(max_start.map_or(start, |m: Coord| m.max(start)));
+
+            let interval = I64Interval::new(start, end - 1);
+            if let Some(excluded_intervals) = excluded_intervals {
+                if interval.has_non_empty_intersection_with(excluded_intervals)
+                {
+                    continue;
+                }
+            }
+            interval_map.aggregate(interval, value.unwrap_or(0.));
+        }
+        self.past_chroms.insert(chrom.to_string());
+        Ok(Some(interval_map))
+    }
+}
+
+/// Computes the weighted Pearson correlation between binary values, i.e., the
+/// phi coefficient, from the 2x2 contingency table of the total weights of
+/// each `(x, y)` combination. The table is accumulated with integer
+/// arithmetic, which is faster and numerically more stable than the general
+/// `weighted_correlation`.
+///
+/// Each item is `(x, y, weight)` where the weight must be an integer, e.g., an
+/// interval size. Returns `None` if any value is neither 0 nor 1 or if any
+/// weight is not an integer, in which case the general `weighted_correlation`
+/// should be used instead.
+pub fn phi_coefficient<I: Iterator<Item = (f64, f64, f64)>>(
+    items: I,
+) -> Option<f64> {
+    fn to_binary_index(value: f64) -> Option<usize> {
+        if value == 0. {
+            Some(0)
+        } else if value == 1. {
+            Some(1)
+        } else {
+            None
+        }
+    }
+    // counts[x][y] is the total weight of the items with values (x, y)
+    let mut counts = [[0i64; 2]; 2];
+    for (x, y, weight) in items {
+        if weight.fract() != 0. {
+            return None;
+        }
+        counts[to_binary_index(x)?][to_binary_index(y)?] += weight as i64;
+    }
+    let x_0 = (counts[0][0] + counts[0][1]) as f64;
+    let x_1 = (counts[1][0] + counts[1][1]) as f64;
+    let y_0 = (counts[0][0] + counts[1][0]) as f64;
+    let y_1 = (counts[0][1] + counts[1][1]) as f64;
+
+    // the products can exceed i64 for genome-wide counts
+    let numerator = counts[1][1] as i128 * counts[0][0] as i128
+        - counts[1][0] as i128 * counts[0][1] as i128;
+    Some(numerator as f64 / (x_0 * x_1 * y_0 * y_1).sqrt())
+}
+
+/// Running weighted means and sums of squared deviations from the means, from
+/// which the weighted Pearson correlation can be computed in a single pass.
+/// They are updated with Welford's algorithm, which unlike the raw sums of
+/// squares does not lose precision to cancellation when the values are large
+/// relative to their spread, e.g., for genomic coverage.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct WeightedMoments {
+    pub sum_weight: f64,
+    pub mean_x: f64,
+    pub mean_y: f64,
+    /// The weighted sum of the squared deviations of `x` from `mean_x`.
+    pub m2_x: f64,
+    /// The weighted sum of the squared deviations of `y` from `mean_y`.
+    pub m2_y: f64,
+    /// The weighted sum of the products of the deviations of `x` and `y`.
+    pub c_xy: f64,
+}
+
+impl WeightedMoments {
+    pub fn add(&mut self, x: f64, y: f64, weight: f64) {
+        if weight == 0. {
+            return;
+        }
+        self.sum_weight += weight;
+        let dx = x - self.mean_x;
+        let dy = y - self.mean_y;
+        self.mean_x += weight / self.sum_weight * dx;
+        self.mean_y += weight / self.sum_weight * dy;
+        self.m2_x += weight * dx * (x - self.mean_x);
+        self.m2_y += weight * dy * (y - self.mean_y);
+        self.c_xy += weight * dx * (y - self.mean_y);
+    }
+
+    /// Combines the moments of `other`, e.g., of another chromosome, with the
+    /// pairwise update of Chan et al.
+    pub fn merge(&mut self, other: &WeightedMoments) {
+        if other.sum_weight == 0. {
+            return;
+        }
+        if self.sum_weight == 0. {
+            *self = *other;
+            return;
+        }
+        let sum_weight = self.sum_weight + other.sum_weight;
+        let dx = other.mean_x - self.mean_x;
+        let dy = other.mean_y - self.mean_y;
+        let cross_weight = self.sum_weight * other.sum_weight / sum_weight;
+        self.mean_x += dx * other.sum_weight / sum_weight;
+        self.mean_y += dy * other.sum_weight / sum_weight;
+        self.m2_x += other.m2_x + dx * dx * cross_weight;
+        self.m2_y += other.m2_y + dy * dy * cross_weight;
+        self.c_xy += other.c_xy + dx * dy * cross_weight;
+        self.sum_weight = sum_weight;
+    }
+
+    /// The weighted population variance of `x`.
+    pub fn variance_x(&self) -> f64 {
+        self.m2_x / self.sum_weight
+    }
+
+    /// The weighted population variance of `y`.
+    pub fn variance_y(&self) -> f64 {
+        self.m2_y / self.sum_weight
+    }
+
+    pub fn correlation(&self) -> f64 {
+        self.c_xy / (self.m2_x * self.m2_y).sqrt()
+    }
+
+    /// Returns `(correlation, slope, intercept)`, where the slope and the
+    /// intercept are those of the weighted least-squares line `y = slope * x +
+    /// intercept`. The slope and the intercept are NaN if `x` is constant.
+    pub fn regression(&self) -> Regression {
+        let slope = if self.m2_x == 0. {
+            f64::NAN
+        } else {
+            self.c_xy / self.m2_x
+        };
+        (self.correlation(), slope, self.mean_y - slope * self.mean_x)
+    }
 }
 
 /// `Idenitty` does not change the value.
-/// `Log` transforms any value x into sign(x) * ln(|x| + 1)
 /// `Thresholding(t)` will restrict the absolute value to less than or equal to
-/// `t`. `LogThresholding(t)` will first apply the thresholding and then apply
-/// the log transform.
+/// `t`. `Log { base, pseudocount }` transforms any value x into
+/// sign(x) * log_base(|x| + pseudocount), so that zero stays zero when the
+/// pseudocount is 1 and the sign of negative values is preserved. The base
+/// must be positive and different from 1, see `ValueTransform::validate`.
 #[derive(Copy, Clone, PartialEq)]
 pub enum ValueTransform {
     Identity,
     Thresholding(f64),
+    Log { base: f64, pseudocount: f64 },
 }
 
-fn apply_transform(value: f64, transform: ValueTransform) -> f64 {
-    match transform {
-        ValueTransform::Identity => value,
-        ValueTransform::Thresholding(t) => {
-            if value > t {
-                t
-            } else if value < -t {
-                -t
-            } else {
-                value
+impl ValueTransform {
+    /// The signed log2 transform with a pseudocount of 1.
+    pub fn log2() -> ValueTransform {
+        ValueTransform::Log {
+            base: 2.,
+            pseudocount: 1.,
+        }
+    }
+
+    /// The signed log10 transform with a pseudocount of 1.
+    pub fn log10() -> ValueTransform {
+        ValueTransform::Log {
+            base: 10.,
+            pseudocount: 1.,
+        }
+    }
+
+    /// Returns a validation error if the base of a `Log` transform is not
+    /// positive or is 1, for which the logarithm is undefined.
+    pub fn validate(self) -> Result<(), Error> {
+        match self {
+            ValueTransform::Log {
+                base, ..
+            } if !(base > 0. && base != 1.) => Err(Error::Validation(format!(
+                "the base of the log transform must be positive and \
+                    different from 1, received {}",
+                base
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn apply(self, value: f64) -> f64 {
+        match self {
+            ValueTransform::Identity => value,
+            ValueTransform::Thresholding(t) => {
+                if value > t {
+                    t
+                } else if value < -t {
+                    -t
+                } else {
+                    value
+                }
+            }
+            ValueTransform::Log {
+                base,
+                pseudocount,
+            } => {
+                if value == 0. {
+                    0.
+                } else {
+                    value.signum() * (value.abs() + pseudocount).log(base)
+                }
             }
         }
     }
 }
 
+/// The weight of each bin, or of each interval when not binning, in the
+/// correlation. `Size` weights by the number of base pairs. `SqrtSize` weights
+/// by the square root of the number of base pairs and `Capped(c)` by the number
+/// of base pairs capped at `c`, both of which limit the influence of a few
+/// giant intervals. `Uniform` gives every bin the same weight regardless of
+/// its size.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum BinWeight {
+    Size,
+    SqrtSize,
+    Capped(i64),
+    Uniform,
+}
+
+impl Default for BinWeight {
+    fn default() -> Self {
+        BinWeight::Size
+    }
+}
+
+fn apply_bin_weight(size: usize, bin_weight: BinWeight) -> f64 {
+    match bin_weight {
+        BinWeight::Size => size as f64,
+        BinWeight::SqrtSize => (size as f64).sqrt(),
+        BinWeight::Capped(cap) => (size as f64).min(cap as f64),
+        BinWeight::Uniform => 1.,
+    }
+}
+
+fn apply_transform(value: f64, transform: ValueTransform) -> f64 {
+    transform.apply(value)
+}
+
 type Boundary = i64;
 type Value = f64;
 type BtreeMapIter<'a> = std::collections::btree_map::Iter<'a, I64Interval, f64>;
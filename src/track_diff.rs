@@ -0,0 +1,143 @@
+use crate::{
+    error::Error,
+    track_conversion::{track_from_path_autodetect, TrackFormat},
+    util::{get_chrom_interval_map, get_common_refined_binned_iter},
+};
+use math::{
+    interval::I64Interval, iter::CommonRefinementZip,
+    partition::integer_interval_map::IntegerIntervalMap,
+};
+use std::collections::HashSet;
+
+/// Each item is `(chrom, interval, value_a, value_b)`, where a value is `None`
+/// if the corresponding track has no data in the interval.
+pub type TrackDiff = Vec<(String, I64Interval, Option<f64>, Option<f64>)>;
+
+/// Compares two refined tracks bin by bin and returns the bins where the
+/// absolute difference between the values exceeds `tolerance`, together with
+/// the bins where only one of the tracks has data. A bin size of 0 means to
+/// compare the common refinement of the two tracks without binning.
+///
+/// The format of each track is detected from its lines by
+/// `track_from_path_autodetect` regardless of the file name.
+pub fn diff_tracks(
+    path_a: &str,
+    path_b: &str,
+    bin_size: i64,
+    tolerance: f64,
+) -> Result<TrackDiff, Error> {
+    if bin_size < 0 {
+        return Err(Error::Validation(format!(
+            "the bin size must be non-negative, got {}",
+            bin_size
+        )));
+    }
+    let chrom_interval_map_a = get_chrom_interval_map(
+        &track_from_path_autodetect(path_a, false, TrackFormat::Bed)?,
+        None,
+    )?;
+    let chrom_interval_map_b = get_chrom_interval_map(
+        &track_from_path_autodetect(path_b, false, TrackFormat::Bed)?,
+        None,
+    )?;
+
+    let mut chroms: Vec<String> = chrom_interval_map_a
+        .keys()
+        .chain(chrom_interval_map_b.keys())
+        .cloned()
+        .collect::<HashSet<String>>()
+        .into_iter()
+        .collect();
+    chroms.sort();
+
+    let empty_interval_map = IntegerIntervalMap::new();
+    let mut diff = Vec::new();
+    for chrom in chroms {
+        let map_a = chrom_interval_map_a
+            .get(&chrom)
+            .unwrap_or(&empty_interval_map);
+        let map_b = chrom_interval_map_b
+            .get(&chrom)
+            .unwrap_or(&empty_interval_map);
+        let zipped: Box<dyn Iterator<Item = (I64Interval, Vec<Option<f64>>)>> =
+            if bin_size == 0 {
+                Box::new(map_a.iter().common_refinement_zip(map_b.iter()))
+            } else {
+                Box::new(get_common_refined_binned_iter(map_a, map_b, bin_size))
+            };
+        for (interval, values) in zipped {
+            let is_different = match (values[0], values[1]) {
+                (Some(a), Some(b)) => (a - b).abs() > tolerance,
+                (None, None) => false,
+                _ => true,
+            };
+            if is_different {
+                diff.push((chrom.clone(), interval, values[0], values[1]));
+            }
+        }
+    }
+    Ok(diff)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        test_util::create_temp_bed_with_suffix, track_diff::diff_tracks,
+    };
+    use math::interval::I64Interval;
+
+    #[test]
+    fn test_diff_tracks() {
+        let original_path = create_temp_bed_with_suffix(
+            "chr1 0 10 id0 3\n\
+            chr1 10 20 id1 5\n\
+            chr1 20 30 id2 8\n\
+            chr2 0 10 id3 1\n",
+            ".bed",
+        )
+        .unwrap();
+        let perturbed_path = create_temp_bed_with_suffix(
+            "chr1 0 10 id0 3.0000001\n\
+            chr1 10 20 id1 5.5\n\
+            chr1 20 30 id2 8\n\
+            chr3 0 10 id3 1\n",
+            ".bed",
+        )
+        .unwrap();
+        let original = original_path.to_str().unwrap();
+        let perturbed = perturbed_path.to_str().unwrap();
+
+        assert!(diff_tracks(original, original, 0, 0.).unwrap().is_empty());
+
+        let diff = diff_tracks(original, perturbed, 10, 1e-3).unwrap();
+        assert_eq!(diff, vec![
+            (
+                "chr1".to_string(),
+                I64Interval::new(10, 19),
+                Some(5.),
+                Some(5.5)
+            ),
+            ("chr2".to_string(), I64Interval::new(0, 9), Some(1.), None),
+            ("chr3".to_string(), I64Interval::new(0, 9), None, Some(1.)),
+        ]);
+
+        // the perturbation on the first bin exceeds a zero tolerance
+        assert_eq!(diff_tracks(original, perturbed, 0, 0.).unwrap().len(), 4);
+
+        // the same values in a bedGraph are detected as such whatever the
+        // suffix
+        let bedgraph_path = create_temp_bed_with_suffix(
+            "chr1 0 10 3\n\
+            chr1 10 20 5\n\
+            chr1 20 30 8\n\
+            chr2 0 10 1\n",
+            ".bg",
+        )
+        .unwrap();
+        assert!(
+            diff_tracks(original, bedgraph_path.to_str().unwrap(), 0, 0.)
+                .unwrap()
+                .is_empty()
+        );
+    }
+}
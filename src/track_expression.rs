@@ -0,0 +1,459 @@
+use crate::{error::Error, track_zipper::TrackZipper};
+use biofile::bed::{Bed, Chrom};
+use math::interval::I64Interval;
+use std::{collections::HashSet, iter::Peekable, str::Chars};
+
+type Value = f64;
+
+/// An arithmetic expression over the values of the tracks in a bin, where
+/// `t0`, `t1`, ... refer to the tracks in order, e.g.,
+/// `(t0 - t1) / (t0 + t1 + 1)`. Supports `+`, `-`, `*`, `/`, the unary minus,
+/// parentheses and numeric constants, with the usual precedence.
+#[derive(Clone, Debug, PartialEq)]
+pub enum TrackExpression {
+    Constant(Value),
+    Track(usize),
+    Negate(Box<TrackExpression>),
+    Add(Box<TrackExpression>, Box<TrackExpression>),
+    Subtract(Box<TrackExpression>, Box<TrackExpression>),
+    Multiply(Box<TrackExpression>, Box<TrackExpression>),
+    Divide(Box<TrackExpression>, Box<TrackExpression>),
+}
+
+impl TrackExpression {
+    pub fn parse(expr: &str) -> Result<TrackExpression, Error> {
+        let tokens = tokenize(expr)?;
+        let mut parser = Parser {
+            tokens: &tokens,
+            pos: 0,
+            depth: 0,
+        };
+        let expression = parser.parse_sum()?;
+        match parser.peek() {
+            None => Ok(expression),
+            Some(token) => Err(Error::Parse(format!(
+                "unexpected {:?} after the end of the expression {}",
+                token, expr
+            ))),
+        }
+    }
+
+    /// The largest track index referred to, or `None` if the expression does
+    /// not refer to any track.
+    pub fn max_track_index(&self) -> Option<usize> {
+        match self {
+            TrackExpression::Constant(_) => None,
+            TrackExpression::Track(i) => Some(*i),
+            TrackExpression::Negate(e) => e.max_track_index(),
+            TrackExpression::Add(a, b)
+            | TrackExpression::Subtract(a, b)
+            | TrackExpression::Multiply(a, b)
+            | TrackExpression::Divide(a, b) => {
+                a.max_track_index().max(b.max_track_index())
+            }
+        }
+    }
+
+    /// Evaluates the expression with `values[i]` as the value of `ti`. A
+    /// division by zero follows the floating point semantics, e.g., `1 / 0`
+    /// is infinite. Panics if a track index is out of bounds.
+    pub fn evaluate(&self, values: &[Value]) -> Value {
+        match self {
+            TrackExpression::Constant(c) => *c,
+            TrackExpression::Track(i) => values[*i],
+            TrackExpression::Negate(e) => -e.evaluate(values),
+            TrackExpression::Add(a, b) => {
+                a.evaluate(values) + b.evaluate(values)
+            }
+            TrackExpression::Subtract(a, b) => {
+                a.evaluate(values) - b.evaluate(values)
+            }
+            TrackExpression::Multiply(a, b) => {
+                a.evaluate(values) * b.evaluate(values)
+            }
+            TrackExpression::Divide(a, b) => {
+                a.evaluate(values) / b.evaluate(values)
+            }
+        }
+    }
+}
+
+/// How a bin without data in some of the tracks is evaluated. `Zero` uses 0
+/// as the value of those tracks, while `Skip` leaves the bin out of the
+/// output.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum MissingValue {
+    Zero,
+    Skip,
+}
+
+impl Default for MissingValue {
+    fn default() -> Self {
+        MissingValue::Zero
+    }
+}
+
+/// Evaluates the `expr` parsed by `TrackExpression::parse` in each bin of
+/// size `bin_size` of the `beds` zipped by a `TrackZipper`, where `ti` is the
+/// average value of the `i`-th track in the bin. Returns the
+/// `(chrom, bin, value)` of the bins in the sorted order of the chromosomes
+/// and the bins, which can be written as a bedGraph.
+pub fn compute_track_expression(
+    beds: Vec<Bed>,
+    expr: &str,
+    bin_size: i64,
+    missing_value: MissingValue,
+    target_chroms: Option<&HashSet<Chrom>>,
+) -> Result<Vec<(Chrom, I64Interval, Value)>, Error> {
+    let expression = TrackExpression::parse(expr)?;
+    if let Some(i) = expression.max_track_index() {
+        if i >= beds.len() {
+            return Err(Error::Validation(format!(
+                "the expression refers to t{} but there are only {} tracks",
+                i,
+                beds.len()
+            )));
+        }
+    }
+    let zipper = TrackZipper::new(beds, None)?;
+    let chrom_to_binned_zipped_values =
+        zipper.chrom_to_binned_zipped_values(target_chroms, bin_size)?;
+    let mut lines = Vec::new();
+    for chrom in crate::util::get_sorted_keys(&chrom_to_binned_zipped_values) {
+        for (interval, values) in &chrom_to_binned_zipped_values[&chrom] {
+            if missing_value == MissingValue::Skip
+                && values.iter().any(Option::is_none)
+            {
+                continue;
+            }
+            let values: Vec<Value> =
+                values.iter().map(|v| v.unwrap_or(0.)).collect();
+            lines.push((
+                chrom.clone(),
+                *interval,
+                expression.evaluate(&values),
+            ));
+        }
+    }
+    Ok(lines)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(Value),
+    Track(usize),
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    LeftParen,
+    RightParen,
+}
+
+fn tokenize(expr: &str) -> Result<Vec<Token>, Error> {
+    let mut chars = expr.chars().peekable();
+    let mut tokens = Vec::new();
+    while let Some(&c) = chars.peek() {
+        let token = match c {
+            ' ' | '\t' => {
+                chars.next();
+                continue;
+            }
+            '+' => Token::Plus,
+            '-' => Token::Minus,
+            '*' => Token::Star,
+            '/' => Token::Slash,
+            '(' => Token::LeftParen,
+            ')' => Token::RightParen,
+            't' => {
+                chars.next();
+                let index = take_number_chars(&mut chars);
+                tokens.push(Token::Track(index.parse::<usize>().map_err(
+                    |_| {
+                        Error::Parse(format!(
+                            "invalid track reference t{}",
+                            index
+                        ))
+                    },
+                )?));
+                continue;
+            }
+            c if c.is_ascii_digit() || c == '.' => {
+                let number = take_number_chars(&mut chars);
+                tokens.push(Token::Number(number.parse::<Value>().map_err(
+                    |_| Error::Parse(format!("invalid number {}", number)),
+                )?));
+                continue;
+            }
+            c => {
+                return Err(Error::Parse(format!(
+                    "unexpected character {} in the expression {}",
+                    c, expr
+                )));
+            }
+        };
+        chars.next();
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+fn take_number_chars(chars: &mut Peekable<Chars>) -> String {
+    let mut number = String::new();
+    while let Some(&c) = chars.peek() {
+        if c.is_ascii_digit() || c == '.' {
+            number.push(c);
+            chars.next();
+        } else {
+            break;
+        }
+    }
+    number
+}
+
+/// A recursive descent parser over the grammar
+/// ```text
+/// sum     := product (('+' | '-') product)*
+/// product := factor (('*' | '/') factor)*
+/// factor  := '-' factor | number | track | '(' sum ')'
+/// ```
+/// where the nesting of the unary minuses and the parentheses is limited to
+/// `MAX_NESTING_DEPTH` levels so that the recursion cannot overflow the stack.
+struct Parser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+    depth: usize,
+}
+
+const MAX_NESTING_DEPTH: usize = 256;
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&'a Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<&'a Token> {
+        let token = self.tokens.get(self.pos);
+        self.pos += 1;
+        token
+    }
+
+    fn parse_sum(&mut self) -> Result<TrackExpression, Error> {
+        let mut expression = self.parse_product()?;
+        loop {
+            match self.peek() {
+                Some(Token::Plus) => {
+                    self.next();
+                    expression = TrackExpression::Add(
+                        Box::new(expression),
+                        Box::new(self.parse_product()?),
+                    );
+                }
+                Some(Token::Minus) => {
+                    self.next();
+                    expression = TrackExpression::Subtract(
+                        Box::new(expression),
+                        Box::new(self.parse_product()?),
+                    );
+                }
+                _ => return Ok(expression),
+            }
+        }
+    }
+
+    fn parse_product(&mut self) -> Result<TrackExpression, Error> {
+        let mut expression = self.parse_factor()?;
+        loop {
+            match self.peek() {
+                Some(Token::Star) => {
+                    self.next();
+                    expression = TrackExpression::Multiply(
+                        Box::new(expression),
+                        Box::new(self.parse_factor()?),
+                    );
+                }
+                Some(Token::Slash) => {
+                    self.next();
+                    expression = TrackExpression::Divide(
+                        Box::new(expression),
+                        Box::new(self.parse_factor()?),
+                    );
+                }
+                _ => return Ok(expression),
+            }
+        }
+    }
+
+    fn parse_factor(&mut self) -> Result<TrackExpression, Error> {
+        match self.next() {
+            Some(Token::Minus) => Ok(TrackExpression::Negate(Box::new(
+                self.parse_nested(Self::parse_factor)?,
+            ))),
+            Some(Token::Number(c)) => Ok(TrackExpression::Constant(*c)),
+            Some(Token::Track(i)) => Ok(TrackExpression::Track(*i)),
+            Some(Token::LeftParen) => {
+                let expression = self.parse_nested(Self::parse_sum)?;
+                match self.next() {
+                    Some(Token::RightParen) => Ok(expression),
+                    _ => Err(Error::Parse(
+                        "missing a closing parenthesis".to_string(),
+                    )),
+                }
+            }
+            Some(token) => Err(Error::Parse(format!("unexpected {:?}", token))),
+            None => Err(Error::Parse(
+                "the expression ended unexpectedly".to_string(),
+            )),
+        }
+    }
+
+    /// Runs `parse` one nesting level deeper, failing if that exceeds
+    /// `MAX_NESTING_DEPTH`.
+    fn parse_nested<F>(&mut self, parse: F) -> Result<TrackExpression, Error>
+    where
+        F: FnOnce(&mut Self) -> Result<TrackExpression, Error>,
+    {
+        if self.depth == MAX_NESTING_DEPTH {
+            return Err(Error::Parse(format!(
+                "the expression is nested more than {} levels deep",
+                MAX_NESTING_DEPTH
+            )));
+        }
+        self.depth += 1;
+        let expression = parse(self);
+        self.depth -= 1;
+        expression
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        assert_almost_eq,
+        error::Error,
+        test_util::create_temp_bed,
+        track_expression::{
+            compute_track_expression, MissingValue, TrackExpression,
+        },
+    };
+    use biofile::bed::Bed;
+    use math::interval::{traits::Interval, I64Interval};
+
+    #[test]
+    fn test_parse() {
+        let expression =
+            TrackExpression::parse("(t0 - t1) / (t0 + t1 + 1)").unwrap();
+        assert_eq!(expression.max_track_index(), Some(1));
+        assert_almost_eq!(expression.evaluate(&[3., 1.]), 2. / 5.);
+
+        // the precedence of the operators and the unary minus
+        let expression =
+            TrackExpression::parse("-t0 + 2 * t1 - 6 / 3").unwrap();
+        assert_almost_eq!(expression.evaluate(&[1., 4.]), 5.);
+        assert_almost_eq!(
+            TrackExpression::parse("2.5 * -(t2 - 1)")
+                .unwrap()
+                .evaluate(&[0., 0., 3.]),
+            -5.
+        );
+        assert_eq!(
+            TrackExpression::parse("1.5").unwrap().max_track_index(),
+            None
+        );
+
+        for invalid in vec!["", "t0 +", "(t0", "t0 t1", "t", "t0 % 2", "1..2"] {
+            assert!(TrackExpression::parse(invalid).is_err(), "{}", invalid);
+        }
+
+        // the nesting depth is bounded
+        let nested = |depth: usize| {
+            format!("{}t0{}", "(-".repeat(depth), ")".repeat(depth))
+        };
+        assert_almost_eq!(
+            TrackExpression::parse(&nested(100))
+                .unwrap()
+                .evaluate(&[2.]),
+            2.
+        );
+        match TrackExpression::parse(&nested(100_000)) {
+            Err(Error::Parse(why)) => assert!(why.contains("nested")),
+            _ => panic!("expected a parse error"),
+        }
+    }
+
+    #[test]
+    fn test_compute_track_expression() {
+        let bed_0_path = create_temp_bed(
+            "chr1 0 10 a 3\n\
+            chr1 10 20 a 1\n\
+            chr1 20 30 a 2\n",
+        )
+        .unwrap();
+        let bed_1_path = create_temp_bed(
+            "chr1 0 10 b 1\n\
+            chr1 10 20 b 1\n",
+        )
+        .unwrap();
+        let get_beds = || {
+            vec![
+                Bed::new(bed_0_path.to_str().unwrap(), false),
+                Bed::new(bed_1_path.to_str().unwrap(), false),
+            ]
+        };
+        let expr = "(t0 - t1) / (t0 + t1 + 1)";
+        let to_tuples = |lines: Vec<(String, I64Interval, f64)>| {
+            lines
+                .into_iter()
+                .map(|(chrom, interval, value)| {
+                    (chrom, interval.get_start(), interval.get_end(), value)
+                })
+                .collect::<Vec<(String, i64, i64, f64)>>()
+        };
+
+        let lines = to_tuples(
+            compute_track_expression(
+                get_beds(),
+                expr,
+                10,
+                MissingValue::Zero,
+                None,
+            )
+            .unwrap(),
+        );
+        let expected = vec![
+            ("chr1".to_string(), 0, 9, 2. / 5.),
+            ("chr1".to_string(), 10, 19, 0.),
+            ("chr1".to_string(), 20, 29, 2. / 3.),
+        ];
+        assert_eq!(lines.len(), expected.len());
+        for (line, expected) in lines.into_iter().zip(expected.into_iter()) {
+            assert_eq!(
+                (&line.0, line.1, line.2),
+                (&expected.0, expected.1, expected.2)
+            );
+            assert_almost_eq!(line.3, expected.3);
+        }
+
+        // the last bin has no data in the second track
+        let lines = to_tuples(
+            compute_track_expression(
+                get_beds(),
+                expr,
+                10,
+                MissingValue::Skip,
+                None,
+            )
+            .unwrap(),
+        );
+        assert_eq!(lines.len(), 2);
+
+        assert!(compute_track_expression(
+            get_beds(),
+            "t2 - t0",
+            10,
+            MissingValue::Zero,
+            None
+        )
+        .is_err());
+    }
+}
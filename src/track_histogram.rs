@@ -1,15 +1,39 @@
-use biofile::{bed::Bed, iter::ToChromIntervalValueIter};
+use crate::{
+    error::Error,
+    util::{
+        create_maybe_gzip, get_chrom_interval_map_with_options,
+        get_chrom_start_end_val_iter_with_options, ReadOptions,
+    },
+};
+use biofile::{bed::Bed, util::TrackVariant};
 use math::{
     histogram::Histogram,
+    interval::{traits::Interval, I64Interval},
     iter::{AggregateOp, IntoBinnedIntervalIter},
     partition::integer_interval_map::IntegerIntervalMap,
     traits::Collecting,
 };
-use std::collections::{HashMap, HashSet};
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+use std::{
+    collections::{HashMap, HashSet},
+    io::Write,
+};
 
 type Chrom = String;
 type Value = f64;
 
+/// The histograms of the binned values of a track.
+pub struct TrackHistograms {
+    pub overall: Histogram<Value>,
+    pub chrom_to_histogram: HashMap<Chrom, Histogram<Value>>,
+
+    /// The number of bins in each bucket across all the chromosomes, followed
+    /// by the number of bins that are out of range, in the same layout as
+    /// the counts returned by `write_bucket_labels`.
+    pub bucket_counts: Vec<usize>,
+}
+
 /// `bed_track_filepath`: BED file path.
 /// `histogram_num_buckets`: the number of buckets evenly partitioning the range
 /// `[min, max]`                          for the histogram.
@@ -19,6 +43,14 @@ type Value = f64;
 /// will aggergate             the average values of each of the bins. Bin
 /// coordinates not overlapping the BED             file coordinates are not
 /// considered.
+/// `max_lines`: if not `None`, only the first `max_lines` lines of the BED
+/// file are read.
+///
+/// If the lines of each chromosome are contiguous in the BED file, e.g., when
+/// the file is sorted, the chromosomes are read one at a time so that only
+/// the interval map of a single chromosome is held in memory. Otherwise, the
+/// whole file is read into memory first.
+#[allow(clippy::too_many_arguments)]
 pub fn generate_track_histograms(
     bed_track_filepath: &str,
     histogram_num_buckets: usize,
@@ -27,55 +59,807 @@ pub fn generate_track_histograms(
     bin_size: i64,
     binarize_score: bool,
     filter_chroms: Option<HashSet<Chrom>>,
-) -> Result<(Histogram<Value>, HashMap<Chrom, Histogram<Value>>), String> {
-    let bed = Bed::new(bed_track_filepath, binarize_score);
-    let chrom_interval_map =
-        match ToChromIntervalValueIter::get_chrom_to_interval_to_val(
-            &bed, None,
-        ):
-            Result<
-                HashMap<Chrom, IntegerIntervalMap<Value>>,
-                biofile::error::Error,
-            > {
-            Ok(map) => map,
-            Err(why) => {
-                return Err(format!(
-                    "failed to get chrom interval map for {}: {}",
-                    bed_track_filepath, why
-                ))
-            }
-        };
-    let mut overall_histogram =
-        Histogram::new(None, histogram_num_buckets, min, max).unwrap();
+    max_lines: Option<usize>,
+) -> Result<TrackHistograms, Error> {
+    generate_track_histograms_with_labels(
+        bed_track_filepath,
+        HistogramBuckets {
+            num_buckets: histogram_num_buckets,
+            min,
+            max,
+        },
+        bin_size,
+        binarize_score,
+        filter_chroms,
+        max_lines,
+        None,
+    )
+}
 
-    let bin_size_f64 = bin_size as Value;
+/// Same as `generate_track_histograms` except that, if `label_out_path` is
+/// provided, the bucket labels of the bins as described in
+/// `write_bucket_labels` are written to it in the same pass over the track.
+pub fn generate_track_histograms_with_labels(
+    bed_track_filepath: &str,
+    buckets: HistogramBuckets,
+    bin_size: i64,
+    binarize_score: bool,
+    filter_chroms: Option<HashSet<Chrom>>,
+    max_lines: Option<usize>,
+    label_out_path: Option<&str>,
+) -> Result<TrackHistograms, Error> {
+    buckets.validate()?;
+    let mut label_writer = match label_out_path {
+        Some(out_path) => Some((
+            out_path,
+            create_maybe_gzip(out_path, false).map_err(|why| {
+                Error::io(format!("failed to create {}", out_path), why)
+            })?,
+        )),
+        None => None,
+    };
+    let mut overall_histogram =
+        Histogram::new(None, buckets.num_buckets, buckets.min, buckets.max)
+            .unwrap();
     let mut chrom_to_histogram: HashMap<Chrom, Histogram<Value>> =
         HashMap::new();
+    let mut bucket_counts = vec![0; buckets.num_buckets + 1];
+
+    let mut add_chrom_histogram =
+        |chrom: Chrom,
+         (chrom_histogram, chrom_bucket_counts): ChromHistogram,
+         bins: &[(I64Interval, Value)]|
+         -> Result<(), Error> {
+            if let Some((out_path, writer)) = label_writer.as_mut() {
+                write_chrom_bucket_labels(
+                    writer, out_path, &chrom, bins, buckets,
+                )?;
+            }
+            overall_histogram
+                .add_counts(&chrom_histogram)
+                .expect("the histograms share the same buckets");
+            for (total, count) in
+                bucket_counts.iter_mut().zip(chrom_bucket_counts)
+            {
+                *total += count;
+            }
+            chrom_to_histogram.insert(chrom, chrom_histogram);
+            Ok(())
+        };
+    let track = TrackVariant::Bed(Bed::new(bed_track_filepath, binarize_score));
+    let read_options = ReadOptions {
+        max_lines,
+        ..ReadOptions::default()
+    };
+    if has_contiguous_chroms(&track, &read_options)? {
+        stream_binned_averages(
+            &track,
+            &read_options,
+            bin_size,
+            &filter_chroms,
+            |chrom, bins| {
+                let chrom_histogram = get_chrom_histogram(&bins, buckets);
+                add_chrom_histogram(chrom, chrom_histogram, &bins)
+            },
+        )?;
+    } else {
+        // the bins are only kept after the parallel map if they are labeled
+        let keep_bins = label_out_path.is_some();
+        for (chrom, chrom_histogram, bins) in map_binned_averages(
+            bed_track_filepath,
+            bin_size,
+            binarize_score,
+            &filter_chroms,
+            max_lines,
+            cfg!(feature = "parallel"),
+            |chrom, bins| {
+                let chrom_histogram = get_chrom_histogram(&bins, buckets);
+                (
+                    chrom,
+                    chrom_histogram,
+                    if keep_bins { bins } else { vec![] },
+                )
+            },
+        )? {
+            add_chrom_histogram(chrom, chrom_histogram, &bins)?;
+        }
+    }
+    if let Some((out_path, writer)) = label_writer {
+        writer.finish().map_err(|why| {
+            Error::io(format!("failed to write to {}", out_path), why)
+        })?;
+    }
+    Ok(TrackHistograms {
+        overall: overall_histogram,
+        chrom_to_histogram,
+        bucket_counts,
+    })
+}
+
+/// The histogram of the bins of a chromosome together with the number of bins
+/// in each bucket, followed by the number of bins that are out of range.
+type ChromHistogram = (Histogram<Value>, Vec<usize>);
+
+/// Builds the `ChromHistogram` of the average values of the `bins` of a
+/// chromosome.
+fn get_chrom_histogram(
+    bins: &[(I64Interval, Value)],
+    buckets: HistogramBuckets,
+) -> ChromHistogram {
+    let mut histogram =
+        Histogram::new(None, buckets.num_buckets, buckets.min, buckets.max)
+            .unwrap();
+    let mut bucket_counts = vec![0; buckets.num_buckets + 1];
+    for &(_interval, average) in bins {
+        histogram.collect(average);
+        let index = buckets
+            .get_bucket_index(average)
+            .unwrap_or(buckets.num_buckets);
+        bucket_counts[index] += 1;
+    }
+    (histogram, bucket_counts)
+}
+
+/// Returns `log2(observed / expected)` for each bucket, where `observed` is
+/// the number of bins in the bucket from the `bucket_counts` of
+/// `TrackHistograms`, and `expected` is the number of in-range bins
+/// distributed over the buckets in proportion to the `expected_weights`,
+/// e.g., all ones for a uniform expectation. The out-of-range bins are not
+/// counted. An empty bucket has an enrichment of negative infinity.
+pub fn get_bucket_enrichment(
+    bucket_counts: &[usize],
+    expected_weights: &[f64],
+) -> Result<Vec<f64>, Error> {
+    if bucket_counts.len() != expected_weights.len() + 1 {
+        return Err(Error::Validation(format!(
+            "expected weights for {} buckets, received {}",
+            bucket_counts.len() - 1,
+            expected_weights.len()
+        )));
+    }
+    if expected_weights.iter().any(|&w| !(w > 0.)) {
+        return Err(Error::Validation(
+            "the expected weights must be positive".to_string(),
+        ));
+    }
+    let num_in_range: usize =
+        bucket_counts[..expected_weights.len()].iter().sum();
+    let total_weight: f64 = expected_weights.iter().sum();
+    Ok(bucket_counts
+        .iter()
+        .zip(expected_weights.iter())
+        .map(|(&observed, &weight)| {
+            let expected = num_in_range as f64 * weight / total_weight;
+            (observed as f64 / expected).log2()
+        })
+        .collect())
+}
+
+/// Reads the expected weight of each of the `num_buckets` buckets for
+/// `get_bucket_enrichment` from the last field of each line of the file at
+/// `path`, e.g., the bucket counts of a reference track. Empty lines and
+/// lines starting with `#` are skipped.
+pub fn read_expected_weights(
+    path: &str,
+    num_buckets: usize,
+) -> Result<Vec<f64>, Error> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|why| Error::io(format!("failed to read {}", path), why))?;
+    let weights = content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let field = line.split_whitespace().last().unwrap();
+            field.parse::<f64>().map_err(|why| {
+                Error::Parse(format!(
+                    "failed to parse the weight {}: {}",
+                    field, why
+                ))
+            })
+        })
+        .collect::<Result<Vec<f64>, Error>>()?;
+    if weights.len() != num_buckets {
+        return Err(Error::Validation(format!(
+            "{} has {} weights but there are {} buckets",
+            path,
+            weights.len(),
+            num_buckets
+        )));
+    }
+    Ok(weights)
+}
+
+/// The histogram buckets evenly partitioning the range `[min, max]`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct HistogramBuckets {
+    pub num_buckets: usize,
+    pub min: f64,
+    pub max: f64,
+}
+
+impl HistogramBuckets {
+    /// The label given to the bins whose values are outside `[min, max]`.
+    pub const OUT_OF_RANGE_LABEL: &'static str = "-1";
+
+    /// Returns a validation error unless there is at least one bucket and
+    /// `min < max` are finite.
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.num_buckets == 0 {
+            return Err(Error::Validation(
+                "the number of histogram buckets must be positive".to_string(),
+            ));
+        }
+        if !(self.min.is_finite()
+            && self.max.is_finite()
+            && self.min < self.max)
+        {
+            return Err(Error::Validation(format!(
+                "the histogram range must be finite with min < max, received \
+                [{}, {}]",
+                self.min, self.max
+            )));
+        }
+        Ok(())
+    }
+
+    /// Returns the 0-based index of the bucket containing `value`, where the
+    /// last bucket also contains `max`, or `None` if `value` is outside
+    /// `[min, max]`, is NaN, or there are no buckets.
+    pub fn get_bucket_index(&self, value: f64) -> Option<usize> {
+        if self.num_buckets == 0 || !(value >= self.min && value <= self.max) {
+            return None;
+        }
+        let index = ((value - self.min) / (self.max - self.min)
+            * self.num_buckets as f64) as usize;
+        Some(index.min(self.num_buckets - 1))
+    }
+}
+
+/// Bins the track in the same way as `generate_track_histograms` and writes a
+/// BED file to `out_path` in which each line is a bin, with the index of its
+/// histogram bucket as the name and its average value as the score. The bins
+/// with values outside the range of the buckets are labeled
+/// `HistogramBuckets::OUT_OF_RANGE_LABEL`. The output is compressed with gzip
+/// if `out_path` ends in `.gz`. The chromosomes are written in the order of
+/// the track if the lines of each chromosome are contiguous, and sorted by
+/// name otherwise.
+///
+/// Returns the number of bins in each bucket, followed by the number of bins
+/// that are out of range.
+pub fn write_bucket_labels(
+    bed_track_filepath: &str,
+    out_path: &str,
+    buckets: HistogramBuckets,
+    bin_size: i64,
+    binarize_score: bool,
+    filter_chroms: Option<HashSet<Chrom>>,
+    max_lines: Option<usize>,
+) -> Result<Vec<usize>, Error> {
+    Ok(generate_track_histograms_with_labels(
+        bed_track_filepath,
+        buckets,
+        bin_size,
+        binarize_score,
+        filter_chroms,
+        max_lines,
+        Some(out_path),
+    )?
+    .bucket_counts)
+}
+
+/// Writes a line for each of the `bins` of `chrom` in the format described in
+/// `write_bucket_labels`.
+fn write_chrom_bucket_labels<W: Write>(
+    writer: &mut W,
+    out_path: &str,
+    chrom: &str,
+    bins: &[(I64Interval, Value)],
+    buckets: HistogramBuckets,
+) -> Result<(), Error> {
+    for &(interval, average) in bins {
+        let label = match buckets.get_bucket_index(average) {
+            Some(index) => index.to_string(),
+            None => HistogramBuckets::OUT_OF_RANGE_LABEL.to_string(),
+        };
+        writeln!(
+            writer,
+            "{}\t{}\t{}\t{}\t{}",
+            chrom,
+            interval.get_start(),
+            interval.get_end() + 1,
+            label,
+            average
+        )
+        .map_err(|why| {
+            Error::io(format!("failed to write to {}", out_path), why)
+        })?;
+    }
+    Ok(())
+}
 
-    let mut keys: Vec<Chrom> =
-        chrom_interval_map.keys().map(|k| k.to_string()).collect();
+/// Returns the bins of each chromosome passing `filter_chroms` together with
+/// the average value of each bin, sorted by the chromosome name. The
+/// chromosomes are binned in parallel if `parallel` is true and the
+/// `parallel` feature is enabled. Only the first `max_lines` lines of the
+/// track are read if provided.
+fn get_binned_averages(
+    bed_track_filepath: &str,
+    bin_size: i64,
+    binarize_score: bool,
+    filter_chroms: &Option<HashSet<Chrom>>,
+    max_lines: Option<usize>,
+    parallel: bool,
+) -> Result<Vec<(Chrom, Vec<(I64Interval, Value)>)>, Error> {
+    map_binned_averages(
+        bed_track_filepath,
+        bin_size,
+        binarize_score,
+        filter_chroms,
+        max_lines,
+        parallel,
+        |chrom, bins| (chrom, bins),
+    )
+}
+
+/// Same as `get_binned_averages` except that the bins of each chromosome are
+/// mapped by `f` together with the chromosome, within the parallel map when
+/// `parallel` is true.
+fn map_binned_averages<F, T>(
+    bed_track_filepath: &str,
+    bin_size: i64,
+    binarize_score: bool,
+    filter_chroms: &Option<HashSet<Chrom>>,
+    max_lines: Option<usize>,
+    parallel: bool,
+    f: F,
+) -> Result<Vec<T>, Error>
+where
+    F: Fn(Chrom, Vec<(I64Interval, Value)>) -> T + Sync + Send,
+    T: Send,
+{
+    let track = TrackVariant::Bed(Bed::new(bed_track_filepath, binarize_score));
+    let chrom_interval_map: HashMap<Chrom, IntegerIntervalMap<Value>> =
+        get_chrom_interval_map_with_options(&track, None, &ReadOptions {
+            max_lines,
+            ..ReadOptions::default()
+        })?;
+
+    let mut keys: Vec<Chrom> = chrom_interval_map
+        .keys()
+        .filter(|chrom| match filter_chroms {
+            Some(filter) => filter.contains(*chrom),
+            None => true,
+        })
+        .map(|k| k.to_string())
+        .collect();
     keys.sort();
 
-    for chrom in keys.iter() {
-        if let Some(filter) = &filter_chroms {
-            if !filter.contains(chrom) {
-                continue;
+    let bin_chrom = |chrom: Chrom| {
+        let bins = get_bin_averages(&chrom_interval_map[&chrom], bin_size);
+        f(chrom, bins)
+    };
+    Ok(map_chroms(keys, bin_chrom, parallel))
+}
+
+/// Returns whether all the lines of each chromosome are contiguous in the
+/// track, i.e., no chromosome reappears after the lines of another
+/// chromosome, which holds for sorted tracks.
+fn has_contiguous_chroms(
+    track: &TrackVariant,
+    read_options: &ReadOptions,
+) -> Result<bool, Error> {
+    let mut finished_chroms = HashSet::new();
+    let mut current_chrom: Option<Chrom> = None;
+    for line in get_chrom_start_end_val_iter_with_options(track, read_options) {
+        let (chrom, ..) = line?;
+        if current_chrom.as_ref() == Some(&chrom) {
+            continue;
+        }
+        if finished_chroms.contains(&chrom) {
+            return Ok(false);
+        }
+        if let Some(finished) = current_chrom.replace(chrom) {
+            finished_chroms.insert(finished);
+        }
+    }
+    Ok(true)
+}
+
+/// Same as `get_binned_averages` except that the track must have contiguous
+/// chromosomes as checked by `has_contiguous_chroms`, and that the bins of
+/// each chromosome are passed to `f` in the order of the track as soon as the
+/// chromosome has been read, after which its interval map is dropped. Stops
+/// at the first error returned by `f`.
+fn stream_binned_averages<F>(
+    track: &TrackVariant,
+    read_options: &ReadOptions,
+    bin_size: i64,
+    filter_chroms: &Option<HashSet<Chrom>>,
+    mut f: F,
+) -> Result<(), Error>
+where
+    F: FnMut(Chrom, Vec<(I64Interval, Value)>) -> Result<(), Error>,
+{
+    let mut current: Option<(Chrom, IntegerIntervalMap<Value>)> = None;
+    for line in get_chrom_start_end_val_iter_with_options(track, read_options) {
+        let (chrom, start, end, value) = line?;
+        if filter_chroms
+            .as_ref()
+            .map_or(false, |filter| !filter.contains(&chrom))
+        {
+            continue;
+        }
+        if current.as_ref().map_or(true, |(c, _)| *c != chrom) {
+            if let Some((finished, interval_map)) =
+                current.replace((chrom, IntegerIntervalMap::new()))
+            {
+                f(finished, get_bin_averages(&interval_map, bin_size))?;
             }
         }
-        let mut chrom_histogram =
-            Histogram::new(None, histogram_num_buckets, min, max).unwrap();
+        if let Some((_, interval_map)) = current.as_mut() {
+            interval_map.aggregate(
+                I64Interval::new(start, end - 1),
+                value.unwrap_or(0.),
+            );
+        }
+    }
+    if let Some((finished, interval_map)) = current {
+        f(finished, get_bin_averages(&interval_map, bin_size))?;
+    }
+    Ok(())
+}
 
-        let interval_map = chrom_interval_map.get(chrom).unwrap();
-        for (_interval, v) in interval_map.iter().into_binned_interval_iter(
+/// The average value of each bin of size `bin_size` overlapping with the
+/// `interval_map`, where the base pairs without a value count as zero.
+fn get_bin_averages(
+    interval_map: &IntegerIntervalMap<Value>,
+    bin_size: i64,
+) -> Vec<(I64Interval, Value)> {
+    let bin_size_f64 = bin_size as Value;
+    interval_map
+        .iter()
+        .into_binned_interval_iter(
             bin_size,
             AggregateOp::Sum,
             Box::new(|item| (*item.0, *item.1)),
-        ) {
-            let average = v / bin_size_f64;
-            overall_histogram.collect(average);
-            chrom_histogram.collect(average);
+        )
+        .map(|(interval, v)| (interval, v / bin_size_f64))
+        .collect()
+}
+
+/// Maps each of the `chroms` by `f` in order, in parallel if `parallel` is
+/// true. The order of the chromosomes is preserved when collecting.
+#[cfg(feature = "parallel")]
+fn map_chroms<F, T>(chroms: Vec<Chrom>, f: F, parallel: bool) -> Vec<T>
+where
+    F: Fn(Chrom) -> T + Sync + Send,
+    T: Send,
+{
+    if parallel {
+        chroms.into_par_iter().map(f).collect()
+    } else {
+        chroms.into_iter().map(f).collect()
+    }
+}
+
+/// Maps each of the `chroms` by `f` in order. `parallel` has no effect
+/// without the `parallel` feature.
+#[cfg(not(feature = "parallel"))]
+fn map_chroms<F, T>(chroms: Vec<Chrom>, f: F, _parallel: bool) -> Vec<T>
+where
+    F: Fn(Chrom) -> T,
+{
+    chroms.into_iter().map(f).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        assert_almost_eq,
+        test_util::create_temp_bed,
+        track_histogram::{
+            generate_track_histograms, get_binned_averages,
+            get_bucket_enrichment, get_chrom_histogram, has_contiguous_chroms,
+            map_binned_averages, stream_binned_averages, write_bucket_labels,
+            HistogramBuckets,
+        },
+        util::ReadOptions,
+    };
+    use biofile::{bed::Bed, util::TrackVariant};
+    use std::collections::HashSet;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn test_write_bucket_labels() {
+        let track_path = create_temp_bed(
+            "chr1 0 10 id0 1\n\
+            chr1 10 20 id1 4\n\
+            chr1 20 30 id2 10\n\
+            chr2 0 10 id3 15\n\
+            chr2 10 20 id4 3\n",
+        )
+        .unwrap();
+        let out_path = NamedTempFile::new().unwrap().into_temp_path();
+        let buckets = HistogramBuckets {
+            num_buckets: 5,
+            min: 0.,
+            max: 10.,
+        };
+        let counts = write_bucket_labels(
+            track_path.to_str().unwrap(),
+            out_path.to_str().unwrap(),
+            buckets,
+            10,
+            false,
+            None,
+            None,
+        )
+        .unwrap();
+        assert_eq!(counts, vec![1, 1, 1, 0, 1, 1]);
+        assert_eq!(
+            std::fs::read_to_string(&out_path).unwrap(),
+            "chr1\t0\t10\t0\t1\n\
+            chr1\t10\t20\t2\t4\n\
+            chr1\t20\t30\t4\t10\n\
+            chr2\t0\t10\t-1\t15\n\
+            chr2\t10\t20\t1\t3\n"
+        );
+
+        // each bucket has a width of 2 and the last one also contains the max
+        assert_eq!(buckets.get_bucket_index(0.), Some(0));
+        assert_eq!(buckets.get_bucket_index(1.99), Some(0));
+        assert_eq!(buckets.get_bucket_index(2.), Some(1));
+        assert_eq!(buckets.get_bucket_index(7.5), Some(3));
+        assert_eq!(buckets.get_bucket_index(9.99), Some(4));
+        assert_eq!(buckets.get_bucket_index(10.), Some(4));
+        assert_eq!(buckets.get_bucket_index(10.01), None);
+        assert_eq!(buckets.get_bucket_index(-0.1), None);
+        assert_eq!(buckets.get_bucket_index(f64::NAN), None);
+
+        let no_buckets = HistogramBuckets {
+            num_buckets: 0,
+            ..buckets
+        };
+        assert_eq!(no_buckets.get_bucket_index(5.), None);
+        assert!(write_bucket_labels(
+            track_path.to_str().unwrap(),
+            out_path.to_str().unwrap(),
+            no_buckets,
+            10,
+            false,
+            None,
+            None,
+        )
+        .is_err());
+        assert!(HistogramBuckets {
+            min: 10.,
+            max: 0.,
+            ..buckets
         }
-        chrom_to_histogram.insert(chrom.clone(), chrom_histogram);
+        .validate()
+        .is_err());
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn test_parallel_binning() {
+        let track_path = create_temp_bed(
+            "chr1 0 10 id0 1\n\
+            chr1 5 30 id1 4\n\
+            chr2 0 10 id2 15\n\
+            chr3 10 20 id3 3\n\
+            chr3 100 137 id4 7\n\
+            chrX 0 25 id5 2\n",
+        )
+        .unwrap();
+        let track_path = track_path.to_str().unwrap();
+        for &bin_size in [1, 7, 10].iter() {
+            let sequential = get_binned_averages(
+                track_path, bin_size, false, &None, None, false,
+            )
+            .unwrap();
+            let parallel = get_binned_averages(
+                track_path, bin_size, false, &None, None, true,
+            )
+            .unwrap();
+            assert_eq!(sequential.len(), 4);
+            assert_eq!(sequential, parallel);
+        }
+    }
+
+    #[test]
+    fn test_chrom_histograms() {
+        let sorted_path = create_temp_bed(
+            "chr1 0 10 id0 1\n\
+            chr1 5 30 id1 4\n\
+            chr2 0 10 id2 15\n\
+            chr3 10 20 id3 3\n\
+            chr3 100 137 id4 7\n",
+        )
+        .unwrap();
+        let unsorted_path = create_temp_bed(
+            "chr3 10 20 id3 3\n\
+            chr1 0 10 id0 1\n\
+            chr2 0 10 id2 15\n\
+            chr1 5 30 id1 4\n\
+            chr3 100 137 id4 7\n",
+        )
+        .unwrap();
+        let buckets = HistogramBuckets {
+            num_buckets: 4,
+            min: 0.,
+            max: 8.,
+        };
+        let sorted_path = sorted_path.to_str().unwrap();
+        let unsorted_path = unsorted_path.to_str().unwrap();
+        for &bin_size in [1, 7, 10].iter() {
+            let get_chrom_histograms = |path: &str, parallel: bool| {
+                map_binned_averages(
+                    path,
+                    bin_size,
+                    false,
+                    &None,
+                    None,
+                    parallel,
+                    |chrom, bins| {
+                        let (histogram, counts) =
+                            get_chrom_histogram(&bins, buckets);
+                        (chrom, histogram.to_string(), counts)
+                    },
+                )
+                .unwrap()
+            };
+            let sequential = get_chrom_histograms(sorted_path, false);
+            assert_eq!(sequential.len(), 3);
+            assert_eq!(sequential, get_chrom_histograms(sorted_path, true));
+            assert_eq!(sequential, get_chrom_histograms(unsorted_path, true));
+
+            // the streamed and the merged in-memory histograms are the same
+            let get_histograms = |path: &str| {
+                generate_track_histograms(
+                    path,
+                    buckets.num_buckets,
+                    buckets.min,
+                    buckets.max,
+                    bin_size,
+                    false,
+                    None,
+                    None,
+                )
+                .unwrap()
+            };
+            let streamed = get_histograms(sorted_path);
+            let in_memory = get_histograms(unsorted_path);
+            assert_eq!(
+                streamed.overall.to_string(),
+                in_memory.overall.to_string()
+            );
+            assert_eq!(streamed.bucket_counts, in_memory.bucket_counts);
+            for (chrom, histogram, counts) in sequential.iter() {
+                assert_eq!(
+                    &streamed.chrom_to_histogram[chrom].to_string(),
+                    histogram
+                );
+                assert_eq!(
+                    &in_memory.chrom_to_histogram[chrom].to_string(),
+                    histogram
+                );
+                assert!(counts.iter().sum::<usize>() > 0);
+            }
+        }
+    }
+
+    #[test]
+    fn test_stream_binned_averages() {
+        let sorted_path = create_temp_bed(
+            "chr1 0 10 id0 1\n\
+            chr1 5 30 id1 4\n\
+            chr1 28 40 id2 2\n\
+            chr2 0 10 id3 15\n\
+            chr3 10 20 id4 3\n\
+            chr3 100 137 id5 7\n",
+        )
+        .unwrap();
+        let sorted_path = sorted_path.to_str().unwrap();
+        let track = TrackVariant::Bed(Bed::new(sorted_path, false));
+        let read_options = ReadOptions::default();
+        assert!(has_contiguous_chroms(&track, &read_options).unwrap());
+
+        let filter_chroms: Option<HashSet<String>> = Some(
+            vec!["chr1".to_string(), "chr3".to_string()]
+                .into_iter()
+                .collect(),
+        );
+        for filter in vec![None, filter_chroms] {
+            for &bin_size in [1, 7, 10].iter() {
+                let mut streamed = Vec::new();
+                stream_binned_averages(
+                    &track,
+                    &read_options,
+                    bin_size,
+                    &filter,
+                    |chrom, bins| {
+                        streamed.push((chrom, bins));
+                        Ok(())
+                    },
+                )
+                .unwrap();
+                let in_memory = get_binned_averages(
+                    sorted_path,
+                    bin_size,
+                    false,
+                    &filter,
+                    None,
+                    false,
+                )
+                .unwrap();
+                assert_eq!(streamed, in_memory);
+            }
+        }
+
+        let unsorted_path = create_temp_bed(
+            "chr1 0 10 id0 1\n\
+            chr2 0 10 id1 15\n\
+            chr1 20 30 id2 4\n",
+        )
+        .unwrap();
+        let unsorted =
+            TrackVariant::Bed(Bed::new(unsorted_path.to_str().unwrap(), false));
+        assert!(!has_contiguous_chroms(&unsorted, &read_options).unwrap());
+    }
+
+    #[test]
+    fn test_bucket_enrichment() {
+        // one bin in each of the 10 buckets and one bin out of range
+        let track: String = (0..10)
+            .map(|i| {
+                format!(
+                    "chr1 {} {} id{} {}\n",
+                    i * 10,
+                    i * 10 + 10,
+                    i,
+                    i as f64 + 0.5
+                )
+            })
+            .chain(std::iter::once("chr2 0 10 id10 20\n".to_string()))
+            .collect();
+        let track_path = create_temp_bed(&track).unwrap();
+        let histograms = generate_track_histograms(
+            track_path.to_str().unwrap(),
+            10,
+            0.,
+            10.,
+            10,
+            false,
+            None,
+            None,
+        )
+        .unwrap();
+        let mut expected_counts = vec![1; 10];
+        expected_counts.push(1);
+        assert_eq!(histograms.bucket_counts, expected_counts);
+
+        // a flat distribution is not enriched in any bucket
+        let enrichment =
+            get_bucket_enrichment(&histograms.bucket_counts, &[1.; 10])
+                .unwrap();
+        assert_eq!(enrichment.len(), 10);
+        for e in enrichment {
+            assert_almost_eq!(e, 0.);
+        }
+
+        // twice the weight on the first bucket
+        let mut weights = vec![1.; 10];
+        weights[0] = 2.;
+        let enrichment =
+            get_bucket_enrichment(&histograms.bucket_counts, &weights).unwrap();
+        assert_almost_eq!(enrichment[0], (11f64 / 20.).log2());
+        assert_almost_eq!(enrichment[1], (11f64 / 10.).log2());
+
+        assert!(
+            get_bucket_enrichment(&histograms.bucket_counts, &[1.; 9]).is_err()
+        );
+        assert!(get_bucket_enrichment(&[1, 0], &[0.]).is_err());
     }
-    Ok((overall_histogram, chrom_to_histogram))
 }
@@ -1,3 +1,7 @@
+use crate::{
+    error::Error,
+    util::{create_maybe_gzip, is_bed_non_data_line, EndConvention},
+};
 use biofile::{
     bed::{Bed, Chrom},
     iter::ToChromIntervalValueIter,
@@ -7,24 +11,57 @@ use math::{
     iter::{AggregateOp, CommonRefinementZip, IntoBinnedIntervalIter},
     partition::integer_interval_map::IntegerIntervalMap,
 };
+use rand::{rngs::StdRng, Rng, SeedableRng};
 use std::{
     collections::{HashMap, HashSet},
-    fs::OpenOptions,
-    io::{BufWriter, Write},
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
 };
 
 type Value = f64;
 
+/// The options for `TrackZipper::write_concatenated_tracks_with_options`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ConcatenatedTracksOptions {
+    /// How the end coordinates are written.
+    pub end_convention: EndConvention,
+
+    /// If true, the lines are appended to any existing content of the output
+    /// path, and the caller is responsible for keeping the chromosomes sorted
+    /// across the appends.
+    pub append: bool,
+
+    /// If true, a sidecar file `<out_path>.index` is written as well, with a
+    /// line `chrom\tnum_rows\tbyte_offset` for each chromosome, where
+    /// `byte_offset` is the position of the first row of the chromosome in the
+    /// output, so that a reader can seek directly to the rows of a
+    /// chromosome. The index is appended to as well under `append`. The index
+    /// cannot be written for a gzip output, which is not seekable.
+    pub write_index: bool,
+}
+
+impl Default for ConcatenatedTracksOptions {
+    fn default() -> Self {
+        ConcatenatedTracksOptions {
+            end_convention: EndConvention::Exclusive,
+            append: false,
+            write_index: false,
+        }
+    }
+}
+
 pub struct TrackZipper {
     pub bed_files: Vec<Bed>,
     list_of_chrom_interval_maps: Vec<HashMap<Chrom, IntegerIntervalMap<Value>>>,
+    has_exclude: bool,
 }
 
 impl TrackZipper {
     pub fn new(
         bed_files: Vec<Bed>,
         exclude_track_filepath: Option<&str>,
-    ) -> Result<Self, biofile::error::Error> {
+    ) -> Result<Self, Error> {
         let exclude = if let Some(path) = exclude_track_filepath {
             // binarize_score is irrelevant for getting the intervals
             Some(Bed::new(path, false).get_chrom_to_intervals())
@@ -50,6 +87,7 @@ impl TrackZipper {
         Ok(TrackZipper {
             bed_files,
             list_of_chrom_interval_maps,
+            has_exclude: exclude.is_some(),
         })
     }
 
@@ -57,14 +95,60 @@ impl TrackZipper {
         self.list_of_chrom_interval_maps.len()
     }
 
+    /// Returns a warning naming the likely cause if none of the tracks has
+    /// any interval on the `target_chroms`, in which case the zipped output
+    /// will be empty. Returns `None` otherwise.
+    pub fn empty_output_warning(
+        &self,
+        target_chroms: Option<&HashSet<Chrom>>,
+    ) -> Option<String> {
+        let chroms_read: HashSet<&Chrom> = self
+            .list_of_chrom_interval_maps
+            .iter()
+            .flat_map(|chrom_to_interval_map| chrom_to_interval_map.keys())
+            .collect();
+        let has_target_chrom = chroms_read.iter().any(|chrom| {
+            target_chroms.map_or(true, |target| target.contains(*chrom))
+        });
+        if has_target_chrom {
+            None
+        } else {
+            Some(crate::util::get_empty_output_warning(
+                chroms_read.len(),
+                target_chroms.is_some(),
+                self.has_exclude,
+            ))
+        }
+    }
+
     pub fn chrom_to_binned_zipped_values(
         &self,
         target_chroms: Option<&HashSet<Chrom>>,
         bin_size: i64,
-    ) -> Result<
-        HashMap<Chrom, Vec<(I64Interval, Vec<Option<Value>>)>>,
-        biofile::error::Error,
-    > {
+    ) -> Result<HashMap<Chrom, Vec<(I64Interval, Vec<Option<Value>>)>>, Error>
+    {
+        let mut chrom_to_binned_zipped_values = HashMap::new();
+        self.for_each_chrom_binned_zipped_values(
+            target_chroms,
+            bin_size,
+            |chrom, binned_values| {
+                chrom_to_binned_zipped_values.insert(chrom, binned_values);
+            },
+        );
+        Ok(chrom_to_binned_zipped_values)
+    }
+
+    /// Passes the bins of each chromosome together with the values of each
+    /// track to `f`, one chromosome at a time in the order of the chromosome
+    /// names, so that only the bins of a single chromosome are held at once.
+    fn for_each_chrom_binned_zipped_values<F>(
+        &self,
+        target_chroms: Option<&HashSet<Chrom>>,
+        bin_size: i64,
+        mut f: F,
+    ) where
+        F: FnMut(Chrom, Vec<(I64Interval, Vec<Option<Value>>)>),
+    {
         let empty_interval_map = IntegerIntervalMap::new();
         let union_zipped_chrom_interval_maps: HashMap<
             Chrom,
@@ -77,52 +161,166 @@ impl TrackZipper {
         let chroms =
             crate::util::get_sorted_keys(&union_zipped_chrom_interval_maps);
 
-        Ok(chroms
-            .into_iter()
-            .map(|chrom| {
-                let interval_maps = &union_zipped_chrom_interval_maps[&chrom];
-
-                let binned_values: Vec<(I64Interval, Vec<Option<Value>>)> =
-                    interval_maps
-                        .iter()
-                        .skip(1)
-                        .fold(
-                            interval_maps
-                                .first()
-                                .expect("interval maps cannot be empty")
-                                .iter()
-                                .into_binned_interval_iter(
+        for chrom in chroms.into_iter() {
+            let interval_maps = &union_zipped_chrom_interval_maps[&chrom];
+
+            let binned_values: Vec<(I64Interval, Vec<Option<Value>>)> =
+                interval_maps
+                    .iter()
+                    .skip(1)
+                    .fold(
+                        interval_maps
+                            .first()
+                            .expect("interval maps cannot be empty")
+                            .iter()
+                            .into_binned_interval_iter(
+                                bin_size,
+                                AggregateOp::Average,
+                                Box::new(|item| (*item.0, *item.1)),
+                            )
+                            .into_common_refinement_zipped(),
+                        |common_refinement, map| {
+                            common_refinement.common_refinement_flat_zip(
+                                map.iter().into_binned_interval_iter(
                                     bin_size,
                                     AggregateOp::Average,
                                     Box::new(|item| (*item.0, *item.1)),
-                                )
-                                .into_common_refinement_zipped(),
-                            |common_refinement, map| {
-                                common_refinement.common_refinement_flat_zip(
-                                    map.iter().into_binned_interval_iter(
-                                        bin_size,
-                                        AggregateOp::Average,
-                                        Box::new(|item| (*item.0, *item.1)),
-                                    ),
-                                )
-                            },
-                        )
-                        .collect();
-                (chrom, binned_values)
+                                ),
+                            )
+                        },
+                    )
+                    .collect();
+            f(chrom, binned_values);
+        }
+    }
+
+    /// Returns the bins on `chrom` overlapping with the 0-based half-open
+    /// region `[start, end)` together with the values of each track, where
+    /// the value of a track is `None` if it has no interval in the bin. The
+    /// bins are computed in the same way as in
+    /// `chrom_to_binned_zipped_values` and are not clipped to the region.
+    pub fn dump_region(
+        &self,
+        chrom: &str,
+        start: i64,
+        end: i64,
+        bin_size: i64,
+    ) -> Result<Vec<(I64Interval, Vec<Option<Value>>)>, Error> {
+        let target_chroms: HashSet<Chrom> =
+            std::iter::once(chrom.to_string()).collect();
+        let mut chrom_to_binned_zipped_values =
+            self.chrom_to_binned_zipped_values(Some(&target_chroms), bin_size)?;
+        Ok(chrom_to_binned_zipped_values
+            .remove(chrom)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|(interval, _)| {
+                interval.get_start() < end && interval.get_end() >= start
             })
             .collect())
     }
 
+    /// Projects the bins × tracks matrix of the binned values onto a random
+    /// subspace of `n_components` dimensions in the Johnson-Lindenstrauss
+    /// manner, e.g., to cluster the bins of many tracks, returning one row of
+    /// `n_components` values for each bin in the order of
+    /// `write_concatenated_tracks`. A track without an interval in a bin has
+    /// a value of 0 there. The entries of the projection matrix are `+/-1 /
+    /// sqrt(n_components)` with equal probabilities, drawn from a random
+    /// number generator seeded by `seed`, so that the same seed gives the
+    /// same projection. The bins are computed one chromosome at a time, so
+    /// that only the binned values of a single chromosome are held alongside
+    /// the projected rows.
+    pub fn random_projection(
+        &self,
+        target_chroms: Option<&HashSet<Chrom>>,
+        bin_size: i64,
+        n_components: usize,
+        seed: u64,
+    ) -> Result<Vec<Vec<Value>>, Error> {
+        if n_components == 0 {
+            return Err(Error::Validation(
+                "the number of components must be positive".into(),
+            ));
+        }
+        let mut rng = StdRng::seed_from_u64(seed);
+        let scale = 1. / (n_components as Value).sqrt();
+        // the row of the projection matrix for each track
+        let projection: Vec<Vec<Value>> = (0..self.num_tracks())
+            .map(|_| {
+                (0..n_components)
+                    .map(|_| if rng.gen::<bool>() { scale } else { -scale })
+                    .collect()
+            })
+            .collect();
+
+        let mut projected_rows = Vec::new();
+        self.for_each_chrom_binned_zipped_values(
+            target_chroms,
+            bin_size,
+            |_, binned_values| {
+                for (_, values) in binned_values.iter() {
+                    let mut row = vec![0.; n_components];
+                    for (value, projection_row) in
+                        values.iter().zip(projection.iter())
+                    {
+                        let value = value.unwrap_or(0.);
+                        for (r, p) in row.iter_mut().zip(projection_row.iter())
+                        {
+                            *r += value * p;
+                        }
+                    }
+                    projected_rows.push(row);
+                }
+            },
+        );
+        Ok(projected_rows)
+    }
+
+    /// Writes each bin as a line consisting of the start, the end and the
+    /// chromosome followed by the values of the tracks. The output is
+    /// compressed with gzip if `out_path` ends in `.gz`.
     pub fn write_concatenated_tracks(
         &self,
         target_chroms: Option<&HashSet<Chrom>>,
         bin_size: i64,
         out_path: &str,
-    ) -> Result<(), biofile::error::Error> {
+    ) -> Result<(), Error> {
+        self.write_concatenated_tracks_with_options(
+            target_chroms,
+            bin_size,
+            out_path,
+            &ConcatenatedTracksOptions::default(),
+        )
+    }
+
+    /// Same as `write_concatenated_tracks` except that the lines are written
+    /// according to the `options`.
+    pub fn write_concatenated_tracks_with_options(
+        &self,
+        target_chroms: Option<&HashSet<Chrom>>,
+        bin_size: i64,
+        out_path: &str,
+        options: &ConcatenatedTracksOptions,
+    ) -> Result<(), Error> {
+        let ConcatenatedTracksOptions {
+            end_convention,
+            append,
+            write_index,
+        } = *options;
+        if write_index && out_path.ends_with(".gz") {
+            return Err(Error::Validation(format!(
+                "cannot write an index for the gzip output {}",
+                out_path
+            )));
+        }
         let chrom_to_binned_zipped_values: HashMap<
             Chrom,
             Vec<(I64Interval, Vec<Option<Value>>)>,
         > = self.chrom_to_binned_zipped_values(target_chroms, bin_size)?;
+        if let Some(warning) = self.empty_output_warning(target_chroms) {
+            eprintln!("{}", warning);
+        }
 
         let chroms: Vec<String> = {
             let mut keys: Vec<String> =
@@ -131,42 +329,169 @@ impl TrackZipper {
             keys
         };
 
-        let file = OpenOptions::new()
-            .create(true)
-            .truncate(true)
-            .write(true)
-            .open(out_path)?;
-        let mut writer = BufWriter::new(file);
+        let mut writer = create_maybe_gzip(out_path, append)?;
+        let mut index_writer = if write_index {
+            Some(create_maybe_gzip(&format!("{}.index", out_path), append)?)
+        } else {
+            None
+        };
+        // the rows are appended after any existing content
+        let mut byte_offset = if append && write_index {
+            std::fs::metadata(out_path)?.len()
+        } else {
+            0
+        };
 
         for c in chroms.into_iter() {
-            for (interval, values) in &chrom_to_binned_zipped_values[&c] {
-                // note that the end coordinate is exclusive in the BED format
-                write!(
-                    &mut writer,
+            let rows = &chrom_to_binned_zipped_values[&c];
+            if let Some(index_writer) = index_writer.as_mut() {
+                writeln!(
+                    index_writer,
+                    "{}\t{}\t{}",
+                    c,
+                    rows.len(),
+                    byte_offset
+                )?;
+            }
+            for (interval, values) in rows {
+                let mut line = format!(
                     "{} {} {}",
                     interval.get_start(),
-                    interval.get_end() + 1,
+                    end_convention.end_coordinate(interval.get_end()),
                     c,
-                )?;
+                );
                 for v in values.iter() {
-                    write!(&mut writer, " {}", v.unwrap_or(0.))?;
+                    line.push_str(&format!(" {}", v.unwrap_or(0.)));
                 }
-                writeln!(&mut writer,)?;
+                writeln!(&mut writer, "{}", line)?;
+                byte_offset += line.len() as u64 + 1;
             }
         }
+        writer.finish()?;
+        if let Some(index_writer) = index_writer {
+            index_writer.finish()?;
+        }
         Ok(())
     }
 }
 
+/// The order of the three coordinate fields at the start of each line of a
+/// multi-value file. `StartEndChrom` is the order of
+/// `TrackZipper::write_concatenated_tracks` and `ChromStartEnd` is the order
+/// of `RefinedBedZipper::write_to_file`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CoordinateOrder {
+    StartEndChrom,
+    ChromStartEnd,
+}
+
+impl Default for CoordinateOrder {
+    fn default() -> Self {
+        CoordinateOrder::StartEndChrom
+    }
+}
+
+/// The inverse of `write_concatenated_tracks` and
+/// `RefinedBedZipper::write_to_file`: splits each line of the multi-value file
+/// at `in_path` into the files `track_0.bed`, ..., `track_{N-1}.bed` under
+/// `out_dir`, where `N` is `num_values`, so that `track_i.bed` consists of the
+/// lines `chrom start end value_i`. The directory is created if it does not
+/// exist. The coordinate fields of each line are in the `coordinate_order`,
+/// and a line whose start or end is not an integer is an error. Returns the
+/// paths of the per-track files.
+pub fn split_concatenated_track(
+    in_path: &str,
+    out_dir: &str,
+    num_values: usize,
+    coordinate_order: CoordinateOrder,
+) -> Result<Vec<PathBuf>, Error> {
+    if num_values == 0 {
+        return Err(Error::Validation(
+            "the number of values must be positive".to_string(),
+        ));
+    }
+    let reader =
+        BufReader::new(OpenOptions::new().read(true).open(in_path).map_err(
+            |why| Error::io(format!("failed to open {}", in_path), why),
+        )?);
+    std::fs::create_dir_all(out_dir).map_err(|why| {
+        Error::io(format!("failed to create {}", out_dir), why)
+    })?;
+    let out_paths: Vec<PathBuf> = (0..num_values)
+        .map(|i| Path::new(out_dir).join(format!("track_{}.bed", i)))
+        .collect();
+    let mut writers = out_paths
+        .iter()
+        .map(|path| {
+            File::create(path).map(BufWriter::new).map_err(|why| {
+                Error::io(format!("failed to create {}", path.display()), why)
+            })
+        })
+        .collect::<Result<Vec<BufWriter<File>>, Error>>()?;
+
+    let write_error = |why: std::io::Error| {
+        Error::io("failed to write a split track".to_string(), why)
+    };
+    for (i, line) in reader.lines().enumerate() {
+        let line = line.map_err(|why| {
+            Error::io(format!("failed to read {}", in_path), why)
+        })?;
+        if is_bed_non_data_line(&line) {
+            continue;
+        }
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() != 3 + num_values {
+            return Err(Error::Parse(format!(
+                "line {} of {} has {} fields, expected 3 coordinate fields \
+                followed by {} values",
+                i + 1,
+                in_path,
+                fields.len(),
+                num_values
+            )));
+        }
+        let (chrom, start, end) = match coordinate_order {
+            CoordinateOrder::StartEndChrom => (fields[2], fields[0], fields[1]),
+            CoordinateOrder::ChromStartEnd => (fields[0], fields[1], fields[2]),
+        };
+        if start.parse::<i64>().is_err() || end.parse::<i64>().is_err() {
+            return Err(Error::Parse(format!(
+                "line {} of {} does not have integer coordinates in the {:?} \
+                order",
+                i + 1,
+                in_path,
+                coordinate_order
+            )));
+        }
+        for (writer, value) in writers.iter_mut().zip(fields[3..].iter()) {
+            writeln!(writer, "{}\t{}\t{}\t{}", chrom, start, end, value)
+                .map_err(write_error)?;
+        }
+    }
+    for writer in writers.iter_mut() {
+        writer.flush().map_err(write_error)?;
+    }
+    Ok(out_paths)
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::track_zipper::TrackZipper;
+    use crate::{
+        refined_bed_zipper::RefinedBedZipper,
+        test_util::{create_temp_bed, create_temp_bed_with_suffix},
+        track_zipper::{
+            split_concatenated_track, ConcatenatedTracksOptions,
+            CoordinateOrder, TrackZipper,
+        },
+        util::EndConvention,
+    };
     use biofile::bed::Bed;
+    use math::interval::{traits::Interval, I64Interval};
     use std::{
-        fs::OpenOptions,
-        io::{BufRead, BufReader, BufWriter, Write},
+        fs::{read_to_string, OpenOptions},
+        io::{BufRead, BufReader, BufWriter, Seek, SeekFrom, Write},
     };
-    use tempfile::NamedTempFile;
+    use tempfile::{tempdir, NamedTempFile};
 
     #[test]
     fn test_write_concatenated_tracks() {
@@ -204,7 +529,8 @@ mod tests {
                 writer
                     .write_fmt(format_args!(
                         "chr2 10 20 name_1 10\n\
-                        chr1 90 105 1000 7\n"
+                        chr1 90 105 1000 7\n\
+                        chr4 3000000000 3000000010 name_2 10\n"
                     ))
                     .unwrap();
             }
@@ -232,13 +558,13 @@ mod tests {
                 .open(out_path.to_str().unwrap())
                 .unwrap(),
         );
-        let lines: Vec<(i32, i32, String, Vec<f64>)> = reader
+        let lines: Vec<(i64, i64, String, Vec<f64>)> = reader
             .lines()
             .map(|line| {
                 let line = line.unwrap();
                 let mut iter = line.split_whitespace();
-                let start: i32 = iter.next().unwrap().parse().unwrap();
-                let end: i32 = iter.next().unwrap().parse().unwrap();
+                let start: i64 = iter.next().unwrap().parse().unwrap();
+                let end: i64 = iter.next().unwrap().parse().unwrap();
                 let chrom = iter.next().unwrap().to_string();
                 let values: Vec<f64> =
                     iter.map(|val| val.parse::<f64>().unwrap()).collect();
@@ -246,7 +572,7 @@ mod tests {
             })
             .collect();
 
-        let expected: Vec<(i32, i32, String, Vec<f64>)> = vec![
+        let expected: Vec<(i64, i64, String, Vec<f64>)> = vec![
             (75, 100, "chr1".into(), vec![0., 0., 2.8]),
             (100, 125, "chr1".into(), vec![6.8, 15., 1.4]),
             (125, 150, "chr1".into(), vec![0., 15., 0.]),
@@ -255,6 +581,7 @@ mod tests {
             (0, 25, "chr2".into(), vec![0., 0., 4.]),
             (1000, 1025, "chr3".into(), vec![250., 0., 0.]),
             (1025, 1050, "chr3".into(), vec![0., 0.8, 0.]),
+            (3000000000, 3000000025, "chr4".into(), vec![0., 0., 4.]),
         ];
 
         assert_eq!(lines.len(), expected.len());
@@ -268,4 +595,260 @@ mod tests {
             assert_vec_almost_eq!(values, expected_values);
         }
     }
+
+    #[test]
+    fn test_dump_region() {
+        let bed_1_path = create_temp_bed(
+            "chr1 100 110 name_1 17\n\
+            chr1 200 212 name_2 50\n\
+            chr3 1000 1025 name_6 250\n",
+        )
+        .unwrap();
+        let bed_2_path = create_temp_bed(
+            "chr1 100 175 name_1 15\n\
+            chr3 1025 1026 name_6 20\n",
+        )
+        .unwrap();
+        let bed_3_path = create_temp_bed(
+            "chr2 10 20 name_1 10\n\
+            chr1 90 105 1000 7\n\
+            chr4 3000000000 3000000010 name_2 10\n",
+        )
+        .unwrap();
+        let zipper = TrackZipper::new(
+            vec![
+                Bed::new(bed_1_path.to_str().unwrap(), false),
+                Bed::new(bed_2_path.to_str().unwrap(), false),
+                Bed::new(bed_3_path.to_str().unwrap(), false),
+            ],
+            None,
+        )
+        .unwrap();
+
+        let region = zipper.dump_region("chr1", 110, 150, 25).unwrap();
+        let intervals: Vec<(i64, i64)> = region
+            .iter()
+            .map(|(interval, _)| (interval.get_start(), interval.get_end()))
+            .collect();
+        assert_eq!(intervals, vec![(100, 124), (125, 149)]);
+        let values: Vec<&Vec<Option<f64>>> =
+            region.iter().map(|(_, values)| values).collect();
+        assert_eq!(values[0].len(), 3);
+        assert_almost_eq!(values[0][0].unwrap(), 6.8);
+        assert_almost_eq!(values[0][1].unwrap(), 15.);
+        assert_almost_eq!(values[0][2].unwrap(), 1.4);
+        assert_eq!(values[1], &vec![None, Some(15.), None]);
+
+        assert_eq!(zipper.dump_region("chr2", 0, 5, 25).unwrap(), vec![(
+            I64Interval::new(0, 24),
+            vec![None, None, Some(4.)]
+        )]);
+        assert!(zipper.dump_region("chr1", 180, 200, 25).unwrap().is_empty());
+        assert!(zipper.dump_region("chr5", 0, 100, 25).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_random_projection() {
+        let bed_1_path = create_temp_bed(
+            "chr1 100 110 name_1 17
+            chr1 200 212 name_2 50
+            chr3 1000 1025 name_6 250
+",
+        )
+        .unwrap();
+        let bed_2_path = create_temp_bed(
+            "chr1 100 175 name_1 15
+            chr3 1025 1026 name_6 20
+",
+        )
+        .unwrap();
+        let bed_3_path = create_temp_bed(
+            "chr2 10 20 name_1 10
+            chr1 90 105 1000 7
+",
+        )
+        .unwrap();
+        let zipper = TrackZipper::new(
+            vec![
+                Bed::new(bed_1_path.to_str().unwrap(), false),
+                Bed::new(bed_2_path.to_str().unwrap(), false),
+                Bed::new(bed_3_path.to_str().unwrap(), false),
+            ],
+            None,
+        )
+        .unwrap();
+
+        let projection = zipper.random_projection(None, 25, 2, 7).unwrap();
+        assert_eq!(projection.len(), 8);
+        assert!(projection.iter().all(|row| row.len() == 2));
+        assert_eq!(
+            zipper.random_projection(None, 25, 2, 7).unwrap(),
+            projection
+        );
+
+        // the bin (1000, 1025) on chr3 only has a value of 250 in the first
+        // track
+        let scale = 1. / 2f64.sqrt();
+        for &value in projection[6].iter() {
+            assert_almost_eq!(value.abs(), 250. * scale);
+        }
+
+        assert!(zipper.random_projection(None, 25, 0, 7).is_err());
+    }
+
+    #[test]
+    fn test_split_concatenated_track() {
+        let inputs = vec![
+            "chr1\t0\t25\t1\nchr1\t25\t50\t2\nchr2\t50\t75\t3\n",
+            "chr1\t0\t25\t4\nchr1\t25\t50\t5\nchr2\t50\t75\t6\n",
+        ];
+        let bed_paths: Vec<_> = inputs
+            .iter()
+            .map(|content| {
+                create_temp_bed_with_suffix(content, ".bed").unwrap()
+            })
+            .collect();
+        let bed_paths: Vec<String> = bed_paths
+            .iter()
+            .map(|path| path.to_str().unwrap().to_string())
+            .collect();
+
+        // the chromosome is the third column
+        let zipper = TrackZipper::new(
+            bed_paths.iter().map(|path| Bed::new(path, false)).collect(),
+            None,
+        )
+        .unwrap();
+        let out_path = NamedTempFile::new().unwrap().into_temp_path();
+        let out_path = out_path.to_str().unwrap();
+        zipper
+            .write_concatenated_tracks(None, 25, out_path)
+            .unwrap();
+        let out_dir = tempdir().unwrap();
+        let out_dir = out_dir.path().to_str().unwrap();
+        let split_paths = split_concatenated_track(
+            out_path,
+            out_dir,
+            2,
+            CoordinateOrder::StartEndChrom,
+        )
+        .unwrap();
+        assert_eq!(split_paths.len(), 2);
+        for (path, &expected) in split_paths.iter().zip(inputs.iter()) {
+            assert_eq!(read_to_string(path).unwrap(), expected);
+        }
+
+        // the chromosome is the first column
+        RefinedBedZipper::new(bed_paths, 0, 25, 0., EndConvention::Exclusive)
+            .write_to_file(out_path, false)
+            .unwrap();
+        let split_paths = split_concatenated_track(
+            out_path,
+            out_dir,
+            2,
+            CoordinateOrder::ChromStartEnd,
+        )
+        .unwrap();
+        for (path, &expected) in split_paths.iter().zip(inputs.iter()) {
+            assert_eq!(read_to_string(path).unwrap(), expected);
+        }
+
+        // the order is not guessed from the fields
+        assert!(split_concatenated_track(
+            out_path,
+            out_dir,
+            2,
+            CoordinateOrder::StartEndChrom
+        )
+        .is_err());
+        assert!(split_concatenated_track(
+            out_path,
+            out_dir,
+            3,
+            CoordinateOrder::ChromStartEnd
+        )
+        .is_err());
+        assert!(split_concatenated_track(
+            out_path,
+            out_dir,
+            0,
+            CoordinateOrder::ChromStartEnd
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_concatenated_tracks_index() {
+        let bed_1_path = create_temp_bed(
+            "chr1 0 50 name_1 1\n\
+            chr2 0 25 name_2 2\n\
+            chr3 100 150 name_3 3\n",
+        )
+        .unwrap();
+        let bed_2_path = create_temp_bed("chr3 125 150 name_1 4\n").unwrap();
+        let zipper = TrackZipper::new(
+            vec![
+                Bed::new(bed_1_path.to_str().unwrap(), false),
+                Bed::new(bed_2_path.to_str().unwrap(), false),
+            ],
+            None,
+        )
+        .unwrap();
+        let out_path = NamedTempFile::new().unwrap().into_temp_path();
+        let out_path = out_path.to_str().unwrap();
+        let index_path = format!("{}.index", out_path);
+        zipper
+            .write_concatenated_tracks_with_options(
+                None,
+                25,
+                out_path,
+                &ConcatenatedTracksOptions {
+                    write_index: true,
+                    ..ConcatenatedTracksOptions::default()
+                },
+            )
+            .unwrap();
+
+        let index: Vec<(String, usize, u64)> = read_to_string(&index_path)
+            .unwrap()
+            .lines()
+            .map(|line| {
+                let fields: Vec<&str> = line.split('\t').collect();
+                (
+                    fields[0].to_string(),
+                    fields[1].parse().unwrap(),
+                    fields[2].parse().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(index, vec![
+            ("chr1".to_string(), 2, 0),
+            ("chr2".to_string(), 1, 29),
+            ("chr3".to_string(), 2, 43),
+        ]);
+
+        // seeks directly to the rows of chr3
+        let (_, num_rows, byte_offset) = &index[2];
+        let mut file = OpenOptions::new().read(true).open(out_path).unwrap();
+        file.seek(SeekFrom::Start(*byte_offset)).unwrap();
+        let rows: Vec<String> = BufReader::new(file)
+            .lines()
+            .take(*num_rows)
+            .map(|line| line.unwrap())
+            .collect();
+        assert_eq!(rows, vec!["100 125 chr3 3 0", "125 150 chr3 3 4"]);
+
+        assert!(zipper
+            .write_concatenated_tracks_with_options(
+                None,
+                25,
+                &format!("{}.gz", out_path),
+                &ConcatenatedTracksOptions {
+                    write_index: true,
+                    ..ConcatenatedTracksOptions::default()
+                },
+            )
+            .is_err());
+        std::fs::remove_file(index_path).unwrap();
+    }
 }
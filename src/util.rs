@@ -1,24 +1,31 @@
+use crate::{error::Error, track_conversion::TrackFormat};
 use biofile::{
-    bed::{Bed, Chrom},
-    iter::ToChromIntervalValueIter,
+    bed::{Bed, BedDataLine, BedDataLineIter, Chrom, Strand},
+    bedgraph::{BedGraphDataLine, BedGraphDataLineIter},
+    traits::ToChromStartEndVal,
     util::TrackVariant,
 };
+use flate2::{write::GzEncoder, Compression};
 use math::{
-    interval::I64Interval,
+    interval::{traits::Interval, I64Interval},
     iter::{
         AggregateOp, AsUnionZipped, BinnedIntervalIter, CommonRefinementZip,
         CommonRefinementZipped, IntoBinnedIntervalIter, IntoUnionZip,
     },
     partition::integer_interval_map::IntegerIntervalMap,
-    set::ordered_integer_set::OrderedIntegerSet,
+    set::{ordered_integer_set::OrderedIntegerSet, traits::Intersect},
+    traits::ToIterator,
 };
-use num::Num;
+use num::{Float, Num};
 use std::{
-    collections::{HashMap, HashSet},
-    fs::OpenOptions,
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt::{Debug, Display},
+    fs::{File, OpenOptions},
     io,
-    io::{BufRead, BufReader},
+    io::{BufRead, BufReader, BufWriter, Write},
+    marker::PhantomData,
     path::PathBuf,
+    str::FromStr,
 };
 
 #[macro_export]
@@ -49,6 +56,8 @@ macro_rules! assert_vec_almost_eq {
 
 type Boundary = i64;
 type Value = f64;
+type ChromStartEndValResult =
+    Result<(Chrom, Boundary, Boundary, Option<Value>), Error>;
 type BinnedIter<'a> = BinnedIntervalIter<
     std::collections::btree_map::Iter<'a, I64Interval, f64>,
     f64,
@@ -64,21 +73,64 @@ pub fn get_common_refined_binned_iter<'a>(
     <BinnedIter<'a> as Iterator>::Item,
     I64Interval,
     Value,
+> {
+    get_common_refined_binned_iter_with_bin_sizes(
+        map_a, map_b, bin_size, bin_size,
+    )
+}
+
+/// Same as `get_common_refined_binned_iter` except that `map_a` is binned with
+/// `bin_size_a` and `map_b` is binned with `bin_size_b` before the common
+/// refinement.
+pub fn get_common_refined_binned_iter_with_bin_sizes<'a>(
+    map_a: &'a IntegerIntervalMap<f64>,
+    map_b: &'a IntegerIntervalMap<f64>,
+    bin_size_a: i64,
+    bin_size_b: i64,
+) -> CommonRefinementZipped<
+    Boundary,
+    BinnedIter<'a>,
+    <BinnedIter<'a> as Iterator>::Item,
+    I64Interval,
+    Value,
 > {
     map_a
         .iter()
         .into_binned_interval_iter(
-            bin_size,
+            bin_size_a,
             AggregateOp::Average,
             Box::new(|item| (*item.0, *item.1)),
         )
         .common_refinement_zip(map_b.iter().into_binned_interval_iter(
-            bin_size,
+            bin_size_b,
             AggregateOp::Average,
             Box::new(|item| (*item.0, *item.1)),
         ))
 }
 
+/// Re-aggregates `map` into consecutive bins of size `target_bin_size`,
+/// aligned at index 0, so that tracks binned at different sizes can be
+/// compared on a common grid. The values of `map` are per base pair, so a
+/// target bin larger than the source bins aggregates the base pairs of the
+/// source bins it overlaps with `op`, while a source bin larger than the
+/// target bins is split, with each target bin aggregating its own part of the
+/// source bin. The target bins not overlapping with `map` are left out.
+pub fn resample_to_grid(
+    map: &IntegerIntervalMap<f64>,
+    target_bin_size: i64,
+    op: AggregateOp,
+) -> IntegerIntervalMap<f64> {
+    let mut resampled = IntegerIntervalMap::new();
+    for (bin, value) in map.iter().into_binned_interval_iter(
+        target_bin_size,
+        op,
+        Box::new(|item| (*item.0, *item.1)),
+    ) {
+        resampled.aggregate(bin, value);
+    }
+    resampled
+}
+
 /// Returns chr1 to chr22 inclusive, together with chrX and chrY.
 pub fn get_default_human_chrom_inclusion_set() -> HashSet<String> {
     let mut chrom_list: HashSet<String> = (1..=22)
@@ -107,6 +159,396 @@ pub fn extract_chrom_names(filepath: &str) -> Result<Vec<String>, io::Error> {
         .collect())
 }
 
+/// Reads a file in which each line consists of two fields, the chromosome name
+/// and the chromosome size, e.g., the `.chrom.sizes` files from UCSC.
+pub fn get_chrom_sizes(
+    filepath: &str,
+) -> Result<HashMap<String, i64>, std::io::Error> {
+    Ok(get_chrom_size_list(filepath)?.into_iter().collect())
+}
+
+/// Same as `get_chrom_sizes` except that the chromosomes are kept in the order
+/// of the file.
+pub fn get_chrom_size_list(
+    filepath: &str,
+) -> Result<Vec<(String, i64)>, std::io::Error> {
+    let buf_reader =
+        BufReader::new(OpenOptions::new().read(true).open(filepath)?);
+
+    let mut chrom_sizes = Vec::new();
+    for line in buf_reader.lines() {
+        let line = line?;
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        if tokens.is_empty() {
+            continue;
+        }
+        if tokens.len() < 2 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("expected two fields in the line: {}", line),
+            ));
+        }
+        let size = tokens[1].parse::<i64>().map_err(|why| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "failed to parse the chromosome size {}: {}",
+                    line, why
+                ),
+            )
+        })?;
+        chrom_sizes.push((tokens[0].to_string(), size));
+    }
+    Ok(chrom_sizes)
+}
+
+/// Reads a file in which each line consists of two fields, a chromosome name
+/// and the canonical name it should be renamed to, e.g., `1 chr1`.
+pub fn get_chrom_aliases(
+    filepath: &str,
+) -> Result<HashMap<String, String>, std::io::Error> {
+    let buf_reader =
+        BufReader::new(OpenOptions::new().read(true).open(filepath)?);
+
+    let mut aliases = HashMap::new();
+    for line in buf_reader.lines() {
+        let line = line?;
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        if tokens.is_empty() {
+            continue;
+        }
+        if tokens.len() < 2 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("expected two fields in the line: {}", line),
+            ));
+        }
+        aliases.insert(tokens[0].to_string(), tokens[1].to_string());
+    }
+    Ok(aliases)
+}
+
+/// Whether a line of a BED file holds no data, i.e., is empty, a comment, or a
+/// track or browser header line.
+pub fn is_bed_non_data_line(line: &str) -> bool {
+    let trimmed = line.trim();
+    trimmed.is_empty()
+        || trimmed.starts_with('#')
+        || trimmed.starts_with("track")
+        || trimmed.starts_with("browser")
+}
+
+/// Splits a BED line into its fields, where the fields can be separated by
+/// tabs, spaces or a mix of both. An empty field between two consecutive tabs
+/// is kept as an empty string, so that the columns after it stay aligned in a
+/// tab-separated line, while a run of spaces is a single separator.
+pub fn split_bed_fields(line: &str) -> Vec<&str> {
+    if !line.contains('\t') {
+        return line.split_whitespace().collect();
+    }
+    line.trim_end_matches(|c| c == '\r' || c == '\n')
+        .split('\t')
+        .flat_map(|piece| {
+            let tokens: Vec<&str> = piece.split_whitespace().collect();
+            if tokens.is_empty() {
+                vec![""]
+            } else {
+                tokens
+            }
+        })
+        .collect()
+}
+
+/// A data line of a BED or bedGraph file read by `TrackLineIter`, with the
+/// chromosome canonicalized and the coordinates converted into the 0-based
+/// half-open convention according to the `ReadOptions`. The line is kept as
+/// read so that any other column can be taken from the same line.
+pub struct TrackLine<D> {
+    pub chrom: Chrom,
+    pub start: i64,
+    pub end: i64,
+
+    /// The value read from the value column after `transform_value`, or
+    /// `None` if the line does not have the column, e.g., a BED3 line.
+    pub value: Option<D>,
+
+    /// The 1-based number of the line in the file, counting all the lines.
+    pub line_number: usize,
+
+    line: String,
+}
+
+impl<D> TrackLine<D> {
+    /// Returns the field in the 1-based `column`, or `None` if the line has
+    /// fewer columns.
+    pub fn field(&self, column: usize) -> Option<&str> {
+        let index = column.checked_sub(1)?;
+        split_bed_fields(&self.line).get(index).copied()
+    }
+
+    /// Converts the line into `(chrom, start, end_exclusive, value)`.
+    pub fn to_chrom_start_end_val(self) -> (Chrom, i64, i64, Option<D>) {
+        (self.chrom, self.start, self.end, self.value)
+    }
+
+    /// The strand of the line normalized by `normalize_strand`.
+    pub fn strand(&self) -> Result<Option<Strand>, Error> {
+        normalize_strand(self.field(STRAND_COLUMN))
+    }
+}
+
+/// Reads the data lines of a BED or bedGraph file in a single pass, skipping
+/// the empty, comment and header lines. Each line is checked as it is parsed,
+/// so that a line without integer start and end fields, e.g., with `1e5` as a
+/// coordinate, or with a value that is not numeric is reported as a
+/// `Error::Parse` naming the offending line instead of failing inside the
+/// reader. Up to `read_options.max_bad_lines` such malformed lines are skipped
+/// and counted instead. A `.` or empty value is read according to
+/// `read_options.missing_score`. The iteration stops after
+/// `read_options.max_lines` data lines if provided, so that the lines beyond
+/// are neither read nor checked.
+pub struct TrackLineIter<'a, D> {
+    path: String,
+    format: TrackFormat,
+    binarize_score: bool,
+    read_options: &'a ReadOptions,
+    lines: io::Lines<BufReader<File>>,
+    line_number: usize,
+    num_data_lines: usize,
+    num_bad_lines: usize,
+    num_missing_score_lines: usize,
+    phantom: PhantomData<D>,
+}
+
+impl<'a, D, E> TrackLineIter<'a, D>
+where
+    D: Float + FromStr<Err = E>,
+    E: Debug,
+{
+    /// Reads the file at `path` in the `format`, where each value is read
+    /// according to the `read_options`, or is 1 if `binarize_score` is true.
+    /// Returns an error if the file cannot be opened or if the values of a
+    /// bedGraph file are to be read from the name column.
+    pub fn new(
+        path: &str,
+        format: TrackFormat,
+        binarize_score: bool,
+        read_options: &'a ReadOptions,
+    ) -> Result<TrackLineIter<'a, D>, Error> {
+        if format == TrackFormat::BedGraph
+            && read_options.value_column == ValueColumn::Name
+        {
+            return Err(Error::Validation(
+                "bedGraph tracks do not have a name column".to_string(),
+            ));
+        }
+        let file = File::open(path).map_err(|why| {
+            Error::io(format!("failed to open {}", path), why)
+        })?;
+        Ok(TrackLineIter {
+            path: path.to_string(),
+            format,
+            binarize_score,
+            read_options,
+            lines: BufReader::new(file).lines(),
+            line_number: 0,
+            num_data_lines: 0,
+            num_bad_lines: 0,
+            num_missing_score_lines: 0,
+            phantom: PhantomData,
+        })
+    }
+
+    /// Same as `new` for the file and the format of the `track`.
+    pub fn from_track(
+        track: &TrackVariant,
+        read_options: &'a ReadOptions,
+    ) -> Result<TrackLineIter<'a, D>, Error> {
+        match track {
+            TrackVariant::Bed(bed) => TrackLineIter::new(
+                bed.get_filepath(),
+                TrackFormat::Bed,
+                bed.is_score_binarized(),
+                read_options,
+            ),
+            TrackVariant::BedGraph(bedgraph) => TrackLineIter::new(
+                bedgraph.get_filepath(),
+                TrackFormat::BedGraph,
+                bedgraph.is_score_binarized(),
+                read_options,
+            ),
+        }
+    }
+
+    /// The number of malformed lines skipped so far under
+    /// `ReadOptions::max_bad_lines`.
+    pub fn num_bad_lines(&self) -> usize {
+        self.num_bad_lines
+    }
+
+    /// The number of lines read so far whose values are missing, which were
+    /// either read as zeros or skipped.
+    pub fn num_missing_score_lines(&self) -> usize {
+        self.num_missing_score_lines
+    }
+
+    /// Returns `None` for a line whose value is missing under
+    /// `MissingScore::Skip`.
+    fn parse_line(
+        &mut self,
+        line: String,
+    ) -> Result<Option<TrackLine<D>>, Error> {
+        let fields = split_bed_fields(&line);
+        if fields.len() < 3 {
+            return Err(Error::Parse(format!(
+                "line {} of {} has {} fields but at least the chrom, start \
+                and end fields are required: {}",
+                self.line_number,
+                self.path,
+                fields.len(),
+                line
+            )));
+        }
+        let parse_coordinate = |name: &str, token: &str| {
+            token.parse::<i64>().map_err(|_| {
+                Error::Parse(format!(
+                    "line {} of {}: the {} field {:?} is not an integer \
+                    coordinate",
+                    self.line_number, self.path, name, token
+                ))
+            })
+        };
+        let start = parse_coordinate("start", fields[1])?;
+        let end = parse_coordinate("end", fields[2])?;
+
+        // the value of a bedGraph line is in the fourth column, which is the
+        // name column of a BED line
+        let value_column = match self.format {
+            TrackFormat::Bed => self.read_options.value_column,
+            TrackFormat::BedGraph => ValueColumn::Name,
+        };
+        let value = if self.binarize_score {
+            Some(D::one())
+        } else {
+            match fields.get(value_column.column_index() - 1) {
+                Some(&token) if is_missing_value_field(Some(token)) => {
+                    self.num_missing_score_lines += 1;
+                    match self.read_options.missing_score {
+                        MissingScore::Zero => Some(D::zero()),
+                        MissingScore::Skip => return Ok(None),
+                    }
+                }
+                Some(token) => Some(token.parse::<D>().map_err(|why| {
+                    Error::Parse(format!(
+                        "line {} of {}: failed to parse the value {:?}: {:?}",
+                        self.line_number, self.path, token, why
+                    ))
+                })?),
+                None if self.format == TrackFormat::Bed
+                    && value_column == ValueColumn::Name =>
+                {
+                    return Err(Error::Parse(format!(
+                        "line {} of {} has no name column while the values \
+                        are expected to be in the name column",
+                        self.line_number, self.path
+                    )));
+                }
+                None => None,
+            }
+        };
+        let chrom = self
+            .read_options
+            .chrom_alias
+            .canonicalize(fields[0].to_string());
+        let (start, end) = self.read_options.to_half_open(start, end);
+        Ok(Some(TrackLine {
+            chrom,
+            start,
+            end,
+            value: value.map(|v| self.read_options.transform_value(v)),
+            line_number: self.line_number,
+            line,
+        }))
+    }
+}
+
+impl<'a, D, E> Iterator for TrackLineIter<'a, D>
+where
+    D: Float + FromStr<Err = E>,
+    E: Debug,
+{
+    type Item = Result<TrackLine<D>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(max_lines) = self.read_options.max_lines {
+            if self.num_data_lines >= max_lines {
+                return None;
+            }
+        }
+        loop {
+            let line = match self.lines.next()? {
+                Ok(line) => line,
+                Err(why) => {
+                    return Some(Err(Error::io(
+                        format!("failed to read {}", self.path),
+                        why,
+                    )))
+                }
+            };
+            self.line_number += 1;
+            if !is_bed_non_data_line(&line) {
+                self.num_data_lines += 1;
+                match (self.parse_line(line), self.read_options.max_bad_lines) {
+                    (Ok(Some(line)), _) => return Some(Ok(line)),
+                    // the value is missing under `MissingScore::Skip`
+                    (Ok(None), _) => {}
+                    (Err(Error::Parse(why)), Some(max_bad_lines)) => {
+                        self.num_bad_lines += 1;
+                        if self.num_bad_lines > max_bad_lines {
+                            return Some(Err(Error::Parse(format!(
+                                "more than {} malformed lines, the last one \
+                                being {}",
+                                max_bad_lines, why
+                            ))));
+                        }
+                    }
+                    (Err(error), _) => return Some(Err(error)),
+                }
+            }
+        }
+    }
+}
+
+/// Whether a value field is the `.` placeholder or empty, i.e., the value of
+/// the line is missing. A line without the field at all is not considered to
+/// have a missing value, since BED3 lines simply have no score.
+pub fn is_missing_value_field(field: Option<&str>) -> bool {
+    match field {
+        Some(field) => {
+            let field = field.trim();
+            field.is_empty() || field == "."
+        }
+        None => false,
+    }
+}
+
+/// The 1-based column of the strand in the BED6 format.
+const STRAND_COLUMN: usize = 6;
+
+/// Normalizes the strand field of a BED line, where `+` and `-` are the plus
+/// and the minus strands, while `.`, an empty field and a missing field leave
+/// the strand unknown. Returns an error for any other token.
+pub fn normalize_strand(field: Option<&str>) -> Result<Option<Strand>, Error> {
+    match field {
+        Some("+") => Ok(Some(Strand::Positive)),
+        Some("-") => Ok(Some(Strand::Negative)),
+        None | Some("") | Some(".") => Ok(None),
+        Some(token) => {
+            Err(Error::Parse(format!("unexpected strand {}", token)))
+        }
+    }
+}
+
 pub fn get_track_paths(
     track_paths_file: &str,
 ) -> Result<Vec<String>, std::io::Error> {
@@ -159,25 +601,680 @@ pub fn get_weighted_track_paths(
         .collect())
 }
 
+/// Options controlling how the value of each line is interpreted when reading
+/// a track.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ReadOptions {
+    /// If true, each value is the `log2` of the signal and will be
+    /// exponentiated, i.e., `x => 2^x`, before being aggregated, so that the
+    /// aggregation happens in the linear space.
+    pub input_log2: bool,
+
+    /// The BED column from which the value of each line is read.
+    pub value_column: ValueColumn,
+
+    /// The coordinate convention of the start and end fields.
+    pub coordinate_system: CoordinateSystem,
+
+    /// How the chromosome names are canonicalized when read, so that tracks
+    /// with different naming conventions refer to the same chromosomes.
+    pub chrom_alias: ChromAlias,
+
+    /// If not `None`, only the first `max_lines` data lines of the track are
+    /// read, e.g., for a quick smoke test on a huge file. The limit is applied
+    /// by `TrackLineIter`, so the lines beyond are not read at all.
+    pub max_lines: Option<usize>,
+
+    /// How the lines with a `.` or an empty value field are read.
+    pub missing_score: MissingScore,
+
+    /// If set, up to this many malformed lines, e.g., with a non-integer
+    /// coordinate, are skipped and counted by `TrackLineIter` instead of
+    /// failing the read. More malformed lines than this are still an error.
+    pub max_bad_lines: Option<usize>,
+}
+
+/// `Original` keeps the chromosome names as they are. `Ucsc` converts
+/// Ensembl-style names into UCSC-style names by adding the `chr` prefix when
+/// it is missing, e.g., `1` becomes `chr1`, and by mapping the mitochondrial
+/// chromosome `MT`, `M` or `chrMT` to `chrM`. `Map` renames the chromosomes
+/// found in the map and keeps the other names as they are.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ChromAlias {
+    Original,
+    Ucsc,
+    Map(HashMap<String, String>),
+}
+
+impl Default for ChromAlias {
+    fn default() -> Self {
+        ChromAlias::Original
+    }
+}
+
+impl ChromAlias {
+    pub fn canonicalize(&self, chrom: String) -> String {
+        match self {
+            ChromAlias::Original => chrom,
+            ChromAlias::Ucsc => match chrom.as_str() {
+                "MT" | "M" | "chrMT" => "chrM".to_string(),
+                _ if chrom.starts_with("chr") => chrom,
+                _ => format!("chr{}", chrom),
+            },
+            ChromAlias::Map(aliases) => match aliases.get(&chrom) {
+                Some(alias) => alias.clone(),
+                None => chrom,
+            },
+        }
+    }
+}
+
+/// `HalfOpen0` is the standard BED convention where the coordinates are
+/// 0-based and the end is exclusive. `Closed1` is used by some annotation
+/// sources, e.g., GFF-derived BED files, where the coordinates are 1-based and
+/// the end is inclusive, so that the feature `[start, end]` in `Closed1`
+/// corresponds to `[start - 1, end)` in `HalfOpen0`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CoordinateSystem {
+    HalfOpen0,
+    Closed1,
+}
+
+impl Default for CoordinateSystem {
+    fn default() -> Self {
+        CoordinateSystem::HalfOpen0
+    }
+}
+
+/// How the end coordinate of an interval is written. `Exclusive` is the
+/// genomics standard followed by the BED and bedGraph formats, where the end is
+/// one past the last base pair, and is the default. `Inclusive` writes the
+/// last base pair itself for downstream tools expecting inclusive ends.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum EndConvention {
+    Exclusive,
+    Inclusive,
+}
+
+impl Default for EndConvention {
+    fn default() -> Self {
+        EndConvention::Exclusive
+    }
+}
+
+impl EndConvention {
+    /// Returns the end coordinate to be written for an interval whose last
+    /// base pair is `last`.
+    pub fn end_coordinate(self, last: i64) -> i64 {
+        match self {
+            EndConvention::Exclusive => last + 1,
+            EndConvention::Inclusive => last,
+        }
+    }
+
+    /// Returns the exclusive end of an interval read from a file following
+    /// this convention, whose end coordinate is `end`.
+    pub fn exclusive_end(self, end: i64) -> i64 {
+        match self {
+            EndConvention::Exclusive => end,
+            EndConvention::Inclusive => end + 1,
+        }
+    }
+}
+
+/// The options for the writers of the tracks computed in memory, e.g.,
+/// `LinearTrackMixture::write_to_bed_file_with_options`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct WriteOptions {
+    pub end_convention: EndConvention,
+}
+
+impl Default for WriteOptions {
+    fn default() -> Self {
+        WriteOptions {
+            end_convention: EndConvention::default(),
+        }
+    }
+}
+
+/// Some tools store a numeric value in the `name` column of a BED file while
+/// leaving the `score` column as a placeholder. `Name` reads the value from
+/// the `name` column in that case. BedGraph tracks only support `Score`,
+/// which refers to their value column.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ValueColumn {
+    Score,
+    Name,
+}
+
+impl Default for ValueColumn {
+    fn default() -> Self {
+        ValueColumn::Score
+    }
+}
+
+impl ValueColumn {
+    /// The 1-based column of the value in a BED line.
+    pub fn column_index(self) -> usize {
+        match self {
+            ValueColumn::Score => 5,
+            ValueColumn::Name => 4,
+        }
+    }
+}
+
+/// How a missing value, i.e., a `.` or an empty value field, is read. `Zero`
+/// reads it as `0`, which is the default, while `Skip` leaves the line out.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum MissingScore {
+    Zero,
+    Skip,
+}
+
+impl Default for MissingScore {
+    fn default() -> Self {
+        MissingScore::Zero
+    }
+}
+
+impl ReadOptions {
+    /// Transforms a value read from a track line into the value to be
+    /// aggregated.
+    pub fn transform_value<D: Float>(&self, value: D) -> D {
+        if self.input_log2 {
+            value.exp2()
+        } else {
+            value
+        }
+    }
+
+    /// Converts the start and end fields of a line into the 0-based half-open
+    /// `(start, end_exclusive)` used internally.
+    pub fn to_half_open(&self, start: i64, end: i64) -> (i64, i64) {
+        match self.coordinate_system {
+            CoordinateSystem::HalfOpen0 => (start, end),
+            CoordinateSystem::Closed1 => (start - 1, end),
+        }
+    }
+}
+
 pub fn get_chrom_interval_map(
     track: &TrackVariant,
     exclude: Option<&HashMap<String, OrderedIntegerSet<i64>>>,
-) -> Result<HashMap<String, IntegerIntervalMap<f64>>, String> {
-    let result = match track {
-        TrackVariant::Bed(bed) => {
-            ToChromIntervalValueIter::get_chrom_to_interval_to_val(bed, exclude)
+) -> Result<HashMap<String, IntegerIntervalMap<f64>>, Error> {
+    get_chrom_interval_map_with_options(track, exclude, &ReadOptions::default())
+}
+
+/// Returns the intervals of the lines on each chromosome together with their
+/// values in file order, leaving out the lines overlapping with the `exclude`
+/// intervals as in `get_chrom_interval_map`. Unlike the refined intervals of
+/// `get_chrom_interval_map`, overlapping lines are kept as separate
+/// intervals, e.g., for shuffling the lines of a track. Each line is read
+/// according to the `read_options`.
+pub fn get_chrom_intervals(
+    track: &TrackVariant,
+    exclude: Option<&HashMap<String, OrderedIntegerSet<i64>>>,
+    read_options: &ReadOptions,
+) -> Result<HashMap<String, Vec<(I64Interval, f64)>>, Error> {
+    let mut chrom_to_intervals =
+        HashMap::<String, Vec<(I64Interval, f64)>>::new();
+    for line in get_chrom_start_end_val_iter_with_options(track, read_options) {
+        let (chrom, start, end, value) = line?;
+        if end <= start {
+            continue;
         }
-        TrackVariant::BedGraph(bedgraph) => {
-            ToChromIntervalValueIter::get_chrom_to_interval_to_val(
-                bedgraph, exclude,
+        let interval = I64Interval::new(start, end - 1);
+        if !is_excluded(&chrom, &interval, exclude) {
+            chrom_to_intervals
+                .entry(chrom)
+                .or_insert_with(Vec::new)
+                .push((interval, value.unwrap_or(0.)));
+        }
+    }
+    Ok(chrom_to_intervals)
+}
+
+/// Aggregates the intervals returned by `get_chrom_intervals` into the same
+/// maps as those returned by `get_chrom_interval_map`.
+pub fn to_chrom_interval_map(
+    chrom_to_intervals: &HashMap<String, Vec<(I64Interval, f64)>>,
+) -> HashMap<String, IntegerIntervalMap<f64>> {
+    chrom_to_intervals
+        .iter()
+        .map(|(chrom, intervals)| {
+            let mut interval_map = IntegerIntervalMap::new();
+            for &(interval, value) in intervals.iter() {
+                interval_map.aggregate(interval, value);
+            }
+            (chrom.clone(), interval_map)
+        })
+        .collect()
+}
+
+/// Returns the base pairs covered by the lines of either a BED or a bedGraph
+/// track on each chromosome, leaving out the lines overlapping with the
+/// `exclude` intervals as in `get_chrom_interval_map`. The values of the lines
+/// are ignored, so this serves as the primitive for set operations between
+/// tracks.
+pub fn get_covered_positions(
+    track: &TrackVariant,
+    exclude: Option<&HashMap<String, OrderedIntegerSet<i64>>>,
+) -> Result<HashMap<Chrom, OrderedIntegerSet<i64>>, Error> {
+    Ok(get_chrom_interval_map(track, exclude)?
+        .into_iter()
+        .map(|(chrom, interval_map)| {
+            // coalesces the contiguous intervals of the sorted refinement
+            let mut intervals: Vec<[i64; 2]> = Vec::new();
+            for (interval, _) in interval_map.iter() {
+                match intervals.last_mut() {
+                    Some(last) if last[1] + 1 >= interval.get_start() => {
+                        last[1] = last[1].max(interval.get_end());
+                    }
+                    _ => intervals
+                        .push([interval.get_start(), interval.get_end()]),
+                }
+            }
+            (chrom, OrderedIntegerSet::from_slice(&intervals))
+        })
+        .collect())
+}
+
+/// Merges the intervals on each chromosome into maximal intervals, like
+/// `bedtools merge -d max_gap`, where two intervals are merged if they overlap
+/// or if there are at most `max_gap` base pairs between them. In particular,
+/// book-ended intervals are always merged, and two intervals exactly
+/// `max_gap` base pairs apart are merged. The merged intervals on each
+/// chromosome are sorted by their start coordinates.
+pub fn merge_intervals(
+    chrom_to_intervals: &HashMap<Chrom, Vec<I64Interval>>,
+    max_gap: i64,
+) -> HashMap<Chrom, Vec<I64Interval>> {
+    chrom_to_intervals
+        .iter()
+        .map(|(chrom, intervals)| {
+            let mut sorted = intervals.clone();
+            sorted.sort_by_key(|interval| interval.get_start());
+            let mut merged: Vec<I64Interval> = Vec::new();
+            for interval in sorted {
+                match merged.last_mut() {
+                    // the number of base pairs strictly between the two
+                    // intervals is the difference minus 1
+                    Some(last)
+                        if interval.get_start() - last.get_end() - 1
+                            <= max_gap =>
+                    {
+                        if interval.get_end() > last.get_end() {
+                            *last = I64Interval::new(
+                                last.get_start(),
+                                interval.get_end(),
+                            );
+                        }
+                    }
+                    _ => merged.push(interval),
+                }
+            }
+            (chrom.clone(), merged)
+        })
+        .collect()
+}
+
+/// Same as `get_chrom_interval_map` except that each line is read according to
+/// the `read_options`.
+pub fn get_chrom_interval_map_with_options(
+    track: &TrackVariant,
+    exclude: Option<&HashMap<String, OrderedIntegerSet<i64>>>,
+    read_options: &ReadOptions,
+) -> Result<HashMap<String, IntegerIntervalMap<f64>>, Error> {
+    aggregate_chrom_intervals(
+        get_chrom_start_end_val_iter_with_options(track, read_options),
+        exclude,
+        None,
+    )
+}
+
+/// The number of lines skipped on each chromosome that is not targeted.
+pub type SkippedChroms = BTreeMap<String, usize>;
+
+/// The lines left out while reading a track by `get_target_chrom_interval_map`
+/// or `get_target_chrom_read_count_map`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ReadStats {
+    pub skipped_chroms: SkippedChroms,
+
+    /// The number of malformed lines skipped under
+    /// `ReadOptions::max_bad_lines`.
+    pub num_bad_lines: usize,
+
+    /// The number of lines whose values are missing, which were read as
+    /// zeros or skipped according to `ReadOptions::missing_score`.
+    pub num_missing_score_lines: usize,
+}
+
+/// Same as `get_chrom_interval_map_with_options` except that only the lines on
+/// the `target_chroms` are aggregated if provided. Also returns the number of
+/// lines skipped on each of the other chromosomes and the other `ReadStats`.
+pub fn get_target_chrom_interval_map(
+    track: &TrackVariant,
+    exclude: Option<&HashMap<String, OrderedIntegerSet<i64>>>,
+    read_options: &ReadOptions,
+    target_chroms: Option<&HashSet<String>>,
+) -> Result<(HashMap<String, IntegerIntervalMap<f64>>, ReadStats), Error> {
+    get_target_chrom_map(track, exclude, read_options, target_chroms, None)
+}
+
+/// Same as `get_target_chrom_interval_map` except that the value of each bin
+/// of size `bin_size`, aligned at index 0, is the number of lines overlapping
+/// the bin regardless of their values, i.e., the read count of the bin, which
+/// is spread over the whole bin so that the binned average of the bin is the
+/// count. A `bin_size` of 0 counts the lines overlapping each base pair, i.e.,
+/// the depth.
+pub fn get_target_chrom_read_count_map(
+    track: &TrackVariant,
+    exclude: Option<&HashMap<String, OrderedIntegerSet<i64>>>,
+    read_options: &ReadOptions,
+    target_chroms: Option<&HashSet<String>>,
+    bin_size: i64,
+) -> Result<(HashMap<String, IntegerIntervalMap<f64>>, ReadStats), Error> {
+    get_target_chrom_map(
+        track,
+        exclude,
+        read_options,
+        target_chroms,
+        Some(bin_size),
+    )
+}
+
+/// Same as `get_target_chrom_read_count_map` except that the intervals of
+/// the lines are returned instead of their read counts, so that the read
+/// counts for several bin sizes can be computed by `get_read_count_map`
+/// without reading the track again for each bin size.
+pub fn get_target_chrom_read_intervals(
+    track: &TrackVariant,
+    exclude: Option<&HashMap<String, OrderedIntegerSet<i64>>>,
+    read_options: &ReadOptions,
+    target_chroms: Option<&HashSet<String>>,
+) -> Result<(HashMap<String, Vec<I64Interval>>, ReadStats), Error> {
+    let mut chrom_to_intervals = HashMap::<String, Vec<I64Interval>>::new();
+    let read_stats = for_each_target_line(
+        track,
+        read_options,
+        target_chroms,
+        |(chrom, start, end, _)| {
+            if end <= start {
+                return;
+            }
+            let interval = I64Interval::new(start, end - 1);
+            if !is_excluded(&chrom, &interval, exclude) {
+                chrom_to_intervals
+                    .entry(chrom)
+                    .or_insert_with(Vec::new)
+                    .push(interval);
+            }
+        },
+    )?;
+    Ok((chrom_to_intervals, read_stats))
+}
+
+/// Returns the read counts of the bins of size `bin_size` over the intervals
+/// of the lines returned by `get_target_chrom_read_intervals`, as in
+/// `get_target_chrom_read_count_map`.
+pub fn get_read_count_map(
+    chrom_to_intervals: &HashMap<String, Vec<I64Interval>>,
+    bin_size: i64,
+) -> HashMap<String, IntegerIntervalMap<f64>> {
+    chrom_to_intervals
+        .iter()
+        .map(|(chrom, intervals)| {
+            let mut interval_map = IntegerIntervalMap::new();
+            for interval in intervals.iter() {
+                interval_map.aggregate(
+                    get_read_count_interval(
+                        interval.get_start(),
+                        interval.get_end() + 1,
+                        bin_size,
+                    ),
+                    1.,
+                );
+            }
+            (chrom.clone(), interval_map)
+        })
+        .collect()
+}
+
+fn get_target_chrom_map(
+    track: &TrackVariant,
+    exclude: Option<&HashMap<String, OrderedIntegerSet<i64>>>,
+    read_options: &ReadOptions,
+    target_chroms: Option<&HashSet<String>>,
+    count_bin_size: Option<i64>,
+) -> Result<(HashMap<String, IntegerIntervalMap<f64>>, ReadStats), Error> {
+    let mut chrom_to_interval_map = HashMap::new();
+    let read_stats =
+        for_each_target_line(track, read_options, target_chroms, |line| {
+            aggregate_line(
+                &mut chrom_to_interval_map,
+                line,
+                exclude,
+                count_bin_size,
             )
+        })?;
+    Ok((chrom_to_interval_map, read_stats))
+}
+
+/// Calls `f` with the `(chrom, start, end_exclusive, value)` of each line of
+/// the track on the `target_chroms` if provided, counting the lines skipped
+/// on each of the other chromosomes in the returned `ReadStats`.
+fn for_each_target_line<F>(
+    track: &TrackVariant,
+    read_options: &ReadOptions,
+    target_chroms: Option<&HashSet<String>>,
+    mut f: F,
+) -> Result<ReadStats, Error>
+where
+    F: FnMut((Chrom, i64, i64, Option<f64>)),
+{
+    let mut track_lines =
+        TrackLineIter::<Value>::from_track(track, read_options)?;
+    let mut skipped_chroms = SkippedChroms::new();
+    for line in track_lines.by_ref() {
+        let line = line?.to_chrom_start_end_val();
+        match target_chroms {
+            Some(target_chroms) if !target_chroms.contains(&line.0) => {
+                *skipped_chroms.entry(line.0).or_insert(0) += 1;
+            }
+            _ => f(line),
         }
-    };
-    result.map_err(|why| {
-        format!("failed to get the first chrom interval map: {}", why)
+    }
+    Ok(ReadStats {
+        skipped_chroms,
+        num_bad_lines: track_lines.num_bad_lines(),
+        num_missing_score_lines: track_lines.num_missing_score_lines(),
     })
 }
 
+/// Whether the lines without a known strand, i.e., with a `.`, empty or
+/// missing strand field, are aggregated into `Both` strands or into `Neither`
+/// strand when a track is split by strand.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum UnknownStrand {
+    Both,
+    Neither,
+}
+
+impl Default for UnknownStrand {
+    fn default() -> Self {
+        UnknownStrand::Neither
+    }
+}
+
+/// Same as `get_chrom_interval_map_with_options` except that the lines of the
+/// BED file at `path` are split by their strand fields, returning the maps of
+/// the plus strand lines and of the minus strand lines in this order. The
+/// lines without a known strand, including the lines with an unexpected
+/// strand token, are aggregated according to `unknown_strand`. Each line is
+/// aggregated into the maps as it is read.
+pub fn get_stranded_chrom_interval_maps(
+    path: &str,
+    binarize_score: bool,
+    exclude: Option<&HashMap<String, OrderedIntegerSet<i64>>>,
+    read_options: &ReadOptions,
+    unknown_strand: UnknownStrand,
+) -> Result<
+    (
+        HashMap<String, IntegerIntervalMap<f64>>,
+        HashMap<String, IntegerIntervalMap<f64>>,
+    ),
+    Error,
+> {
+    let mut plus_map = HashMap::new();
+    let mut minus_map = HashMap::new();
+    for line in TrackLineIter::<Value>::new(
+        path,
+        TrackFormat::Bed,
+        binarize_score,
+        read_options,
+    )? {
+        let line = line?;
+        let (is_plus, is_minus) = match line.strand().unwrap_or(None) {
+            Some(Strand::Positive) => (true, false),
+            Some(Strand::Negative) => (false, true),
+            None => {
+                let is_both = unknown_strand == UnknownStrand::Both;
+                (is_both, is_both)
+            }
+        };
+        let line = line.to_chrom_start_end_val();
+        if is_plus {
+            aggregate_line(&mut plus_map, line.clone(), exclude, None);
+        }
+        if is_minus {
+            aggregate_line(&mut minus_map, line, exclude, None);
+        }
+    }
+    Ok((plus_map, minus_map))
+}
+
+/// Same as `get_chrom_interval_map` except that only the lines on `chrom` are
+/// aggregated. Returns an empty map if the track has no lines on `chrom`.
+pub fn get_single_chrom_interval_map(
+    track: &TrackVariant,
+    chrom: &str,
+    exclude: Option<&HashMap<String, OrderedIntegerSet<i64>>>,
+) -> Result<IntegerIntervalMap<f64>, Error> {
+    let read_options = ReadOptions::default();
+    let lines = get_chrom_start_end_val_iter_with_options(track, &read_options)
+        .filter(|line| match line {
+            Ok((c, ..)) => c == chrom,
+            Err(_) => true,
+        });
+    Ok(aggregate_chrom_intervals(lines, exclude, None)?
+        .remove(chrom)
+        .unwrap_or_else(IntegerIntervalMap::new))
+}
+
+/// If `count_bin_size` is not `None`, each line contributes a unit value to
+/// the whole of every bin it overlaps instead of its value to its interval.
+fn aggregate_chrom_intervals<I: Iterator<Item = ChromStartEndValResult>>(
+    lines: I,
+    exclude: Option<&HashMap<String, OrderedIntegerSet<i64>>>,
+    count_bin_size: Option<i64>,
+) -> Result<HashMap<String, IntegerIntervalMap<f64>>, Error> {
+    let mut chrom_to_interval_map = HashMap::new();
+    for line in lines {
+        aggregate_line(
+            &mut chrom_to_interval_map,
+            line?,
+            exclude,
+            count_bin_size,
+        );
+    }
+    Ok(chrom_to_interval_map)
+}
+
+/// Aggregates the `(chrom, start, end_exclusive, value)` of a line into the
+/// `chrom_to_interval_map` as in `aggregate_chrom_intervals`, unless the line
+/// overlaps with the `exclude` intervals.
+fn aggregate_line(
+    chrom_to_interval_map: &mut HashMap<String, IntegerIntervalMap<f64>>,
+    (chrom, start, end, value): (Chrom, i64, i64, Option<f64>),
+    exclude: Option<&HashMap<String, OrderedIntegerSet<i64>>>,
+    count_bin_size: Option<i64>,
+) {
+    let interval = I64Interval::new(start, end - 1);
+    if is_excluded(&chrom, &interval, exclude) {
+        return;
+    }
+    let (interval, value) = match count_bin_size {
+        None => (interval, value.unwrap_or(0.)),
+        Some(_) if end <= start => return,
+        Some(bin_size) => (get_read_count_interval(start, end, bin_size), 1.),
+    };
+    chrom_to_interval_map
+        .entry(chrom)
+        .or_insert_with(IntegerIntervalMap::new)
+        .aggregate(interval, value);
+}
+
+fn is_excluded(
+    chrom: &str,
+    interval: &I64Interval,
+    exclude: Option<&HashMap<String, OrderedIntegerSet<i64>>>,
+) -> bool {
+    exclude.and_then(|exclude| exclude.get(chrom)).map_or(
+        false,
+        |excluded_intervals| {
+            interval.has_non_empty_intersection_with(excluded_intervals)
+        },
+    )
+}
+
+/// Returns the union of the bins of size `bin_size`, aligned at index 0,
+/// overlapping the line `[start, end_exclusive)`, over which a read is
+/// counted. A `bin_size` of 0 counts the read over its own interval.
+fn get_read_count_interval(start: i64, end: i64, bin_size: i64) -> I64Interval {
+    if bin_size == 0 {
+        I64Interval::new(start, end - 1)
+    } else {
+        let first_bin_start = start / bin_size * bin_size;
+        let last_bin_end = ((end - 1) / bin_size + 1) * bin_size - 1;
+        I64Interval::new(first_bin_start, last_bin_end)
+    }
+}
+
+/// Iterates over the lines of either a BED or a bedGraph track in file order,
+/// yielding `(chrom, start, end_exclusive, value)` for each line.
+pub fn get_chrom_start_end_val_iter(
+    track: &TrackVariant,
+) -> Box<dyn Iterator<Item = (Chrom, Boundary, Boundary, Option<Value>)> + '_> {
+    match track {
+        TrackVariant::Bed(bed) => {
+            let iter: BedDataLineIter<Value> = bed.to_iter();
+            Box::new(iter.map(|line| line.to_chrom_start_end_val()))
+        }
+        TrackVariant::BedGraph(bedgraph) => {
+            let iter: BedGraphDataLineIter<Value> = bedgraph.to_iter();
+            Box::new(iter.map(|line| line.to_chrom_start_end_val()))
+        }
+    }
+}
+
+/// Same as `get_chrom_start_end_val_iter` except that the value of each line
+/// is read according to the `read_options`, including the limit of
+/// `read_options.max_lines` data lines. The lines are read by
+/// `TrackLineIter`, so an error is yielded for a line with malformed
+/// coordinates or whose value cannot be read from the selected column.
+pub fn get_chrom_start_end_val_iter_with_options<'a>(
+    track: &'a TrackVariant,
+    read_options: &'a ReadOptions,
+) -> Box<dyn Iterator<Item = ChromStartEndValResult> + 'a> {
+    match TrackLineIter::<Value>::from_track(track, read_options) {
+        Ok(lines) => Box::new(
+            lines.map(|line| line.map(TrackLine::to_chrom_start_end_val)),
+        ),
+        Err(error) => Box::new(std::iter::once(Err(error))),
+    }
+}
+
 ///
 /// * `list_of_chrom_interval_maps`: a vector of maps each mapping chromosomes
 ///   to integer interval maps.
@@ -221,12 +1318,192 @@ pub fn get_union_zipped_chrom_interval_maps<'a, D: Copy + Num>(
         .collect()
 }
 
+/// Formats the warning for an output that is left empty after reading the
+/// tracks, naming the `cause`, so that the empty output is not mistaken for a
+/// crash.
+pub fn format_empty_output_warning(cause: &str) -> String {
+    format!("warning: the output is empty because {}", cause)
+}
+
+/// Returns a warning naming the likely cause of an output that is left empty
+/// after reading the tracks.
+///
+/// * `num_chroms_read`: the number of chromosomes with intervals left after
+///   dropping the intervals overlapping with the exclude track.
+/// * `has_chrom_filter`: whether only a subset of the chromosomes is targeted.
+/// * `has_exclude`: whether an exclude track is used.
+pub fn get_empty_output_warning(
+    num_chroms_read: usize,
+    has_chrom_filter: bool,
+    has_exclude: bool,
+) -> String {
+    if num_chroms_read > 0 && has_chrom_filter {
+        format_empty_output_warning(&format!(
+            "none of the {} chromosomes in the input passed the chromosome \
+            filter, check that the filter uses the same chromosome names as \
+            the input, e.g., chr1 versus 1",
+            num_chroms_read
+        ))
+    } else if has_exclude {
+        format_empty_output_warning(
+            "no input line is left after dropping the lines overlapping with \
+            the exclude track",
+        )
+    } else {
+        format_empty_output_warning("the input has no valid lines")
+    }
+}
+
 pub fn get_sorted_keys<K: Clone + Ord, V>(map: &HashMap<K, V>) -> Vec<K> {
     let mut keys: Vec<K> = map.keys().cloned().collect();
     keys.sort();
     keys
 }
 
+/// Opens `path` for writing, compressing the output with gzip if the path ends
+/// in `.gz`. If `append` is true, the output is appended to any existing
+/// content of `path`, in which case a gzip output becomes a multi-member gzip
+/// file. `MaybeGzWriter::finish` must be called once the writing is done.
+pub fn create_maybe_gzip(
+    path: &str,
+    append: bool,
+) -> Result<MaybeGzWriter, io::Error> {
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(!append)
+        .append(append)
+        .write(true)
+        .open(path)?;
+    if path.ends_with(".gz") {
+        Ok(MaybeGzWriter::Gzip(BufWriter::new(GzEncoder::new(
+            file,
+            Compression::default(),
+        ))))
+    } else {
+        Ok(MaybeGzWriter::Plain(BufWriter::new(file)))
+    }
+}
+
+/// A buffered writer into either a plain or a gzip file, created by
+/// `create_maybe_gzip`. Dropping a `GzEncoder` finishes the gzip stream but
+/// discards any error in doing so, which would leave a truncated file without
+/// notice, so the writing has to end with `finish` instead.
+pub enum MaybeGzWriter {
+    Plain(BufWriter<File>),
+    Gzip(BufWriter<GzEncoder<File>>),
+}
+
+impl MaybeGzWriter {
+    /// Flushes the buffered output and, for a gzip file, writes the gzip
+    /// trailer, returning any error encountered.
+    pub fn finish(self) -> Result<(), io::Error> {
+        match self {
+            MaybeGzWriter::Plain(mut writer) => writer.flush(),
+            MaybeGzWriter::Gzip(writer) => writer
+                .into_inner()
+                .map_err(io::Error::from)?
+                .finish()?
+                .flush(),
+        }
+    }
+}
+
+impl Write for MaybeGzWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            MaybeGzWriter::Plain(writer) => writer.write(buf),
+            MaybeGzWriter::Gzip(writer) => writer.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            MaybeGzWriter::Plain(writer) => writer.flush(),
+            MaybeGzWriter::Gzip(writer) => writer.flush(),
+        }
+    }
+}
+
+/// Writes a BED line with tab-separated fields. The fields after the end are
+/// written up to the last one present, with "." standing in for a missing
+/// name, score or strand before it.
+pub fn write_bed_line<W: Write, D: Display>(
+    writer: &mut W,
+    line: &BedDataLine<D>,
+) -> Result<(), io::Error> {
+    let num_optional_fields = if line.strand.is_some() {
+        3
+    } else if line.score.is_some() {
+        2
+    } else if line.name.is_some() {
+        1
+    } else {
+        0
+    };
+    write_bed_fields(writer, line, num_optional_fields)?;
+    writeln!(writer)
+}
+
+/// Same as `write_bed_line` except that all the six BED6 fields are written
+/// and the line is padded to the 12 columns of BED12 with the interval as a
+/// single block. The thickStart and thickEnd are the start and the end, the
+/// itemRgb is `0,0,0`, and the block size is the difference between the
+/// written end and start so that the block spans the line under either end
+/// convention.
+pub fn write_bed12_line<W: Write, D: Display>(
+    writer: &mut W,
+    line: &BedDataLine<D>,
+) -> Result<(), io::Error> {
+    write_bed_fields(writer, line, 3)?;
+    writeln!(
+        writer,
+        "\t{}\t{}\t0,0,0\t1\t{},\t0,",
+        line.start,
+        line.end,
+        line.end - line.start
+    )
+}
+
+/// Writes the chrom, start and end of the `line` followed by the first
+/// `num_optional_fields` of its name, score and strand, without a newline.
+fn write_bed_fields<W: Write, D: Display>(
+    writer: &mut W,
+    line: &BedDataLine<D>,
+    num_optional_fields: usize,
+) -> Result<(), io::Error> {
+    write!(writer, "{}\t{}\t{}", line.chrom, line.start, line.end)?;
+    if num_optional_fields >= 1 {
+        write!(writer, "\t{}", line.name.as_deref().unwrap_or("."))?;
+    }
+    if num_optional_fields >= 2 {
+        match line.score.as_ref() {
+            Some(score) => write!(writer, "\t{}", score)?,
+            None => write!(writer, "\t.")?,
+        }
+    }
+    if num_optional_fields >= 3 {
+        let strand = match line.strand {
+            Some(Strand::Positive) => "+",
+            Some(Strand::Negative) => "-",
+            None => ".",
+        };
+        write!(writer, "\t{}", strand)?;
+    }
+    Ok(())
+}
+
+/// Writes a bedGraph line with tab-separated fields.
+pub fn write_bedgraph_line<W: Write, D: Display>(
+    writer: &mut W,
+    line: &BedGraphDataLine<D>,
+) -> Result<(), io::Error> {
+    writeln!(
+        writer,
+        "{}\t{}\t{}\t{}",
+        line.chrom, line.start, line.end_exclusive, line.value
+    )
+}
+
 pub fn manifest_path_join(filename: &str) -> PathBuf {
     let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
     path.push(filename);
@@ -243,3 +1520,143 @@ pub fn get_excluded_interval_maps(
         None
     }
 }
+
+/// Same as `get_excluded_interval_maps` except that the chromosome names of
+/// the exclude track are canonicalized by the `chrom_alias`, so that they
+/// match the names of the tracks read with the same `ChromAlias`. Returns an
+/// error if two chromosomes of the exclude track have the same canonical name.
+pub fn get_aliased_excluded_interval_maps(
+    exclude_track_filepath: Option<&str>,
+    chrom_alias: &ChromAlias,
+) -> Result<Option<HashMap<String, OrderedIntegerSet<i64>>>, Error> {
+    let path = match exclude_track_filepath {
+        Some(path) => path,
+        None => return Ok(None),
+    };
+    let mut exclude = HashMap::new();
+    // binarize_score is irrelevant for getting the intervals
+    for (chrom, intervals) in Bed::new(path, false).get_chrom_to_intervals() {
+        let canonical = chrom_alias.canonicalize(chrom.clone());
+        if exclude.insert(canonical.clone(), intervals).is_some() {
+            return Err(Error::Validation(format!(
+                "the chromosome {} of the exclude track {} is an alias of \
+                {}, which is also in the exclude track",
+                chrom, path, canonical
+            )));
+        }
+    }
+    Ok(Some(exclude))
+}
+
+/// A Greenwald-Khanna sketch of the values added so far, which answers the
+/// quantiles of the values in a single pass without holding all of them, so
+/// that the quantile groups of the bins of a huge track are found in bounded
+/// memory.
+///
+/// The rank of the value returned for a quantile `p` is within `epsilon * n`
+/// of the exact rank `p * n`, where `n` is the number of values added. The
+/// sketch holds `O(log(epsilon * n) / epsilon)` entries of three words each,
+/// so halving `epsilon` roughly doubles the memory, e.g., an `epsilon` of
+/// 0.001 holds tens of thousands of entries for a billion values, while the
+/// exact quantiles require sorting all of them. The minimum and the maximum
+/// are always exact.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ApproxQuantiles {
+    epsilon: f64,
+    count: usize,
+    // (value, g, delta) sorted by the value, where g is the difference between
+    // the minimum rank of the value and that of the preceding value, and
+    // delta is the uncertainty of the rank of the value
+    tuples: Vec<(f64, usize, usize)>,
+}
+
+impl ApproxQuantiles {
+    /// `epsilon` is the tolerated error of the ranks as a fraction of the
+    /// number of values and must be in `(0, 1)`.
+    pub fn new(epsilon: f64) -> Result<ApproxQuantiles, Error> {
+        if !(epsilon > 0. && epsilon < 1.) {
+            return Err(Error::Validation(format!(
+                "epsilon must be in (0, 1), received {}",
+                epsilon
+            )));
+        }
+        Ok(ApproxQuantiles {
+            epsilon,
+            count: 0,
+            tuples: Vec::new(),
+        })
+    }
+
+    /// Adds a value to the sketch. NaN values are ignored.
+    pub fn add(&mut self, value: f64) {
+        if value.is_nan() {
+            return;
+        }
+        self.count += 1;
+        let index = self.tuples.partition_point(|&(v, ..)| v <= value);
+        let delta = if index == 0 || index == self.tuples.len() {
+            0
+        } else {
+            self.max_band()
+        };
+        self.tuples.insert(index, (value, 1, delta));
+        let compress_period = (1. / (2. * self.epsilon)).floor() as usize;
+        if self.count % compress_period.max(1) == 0 {
+            self.compress();
+        }
+    }
+
+    /// Returns the approximate `p` quantile of the values added by the nearest
+    /// rank, i.e., the smallest value such that at least a fraction `p` of the
+    /// values are at most the value, where `p` is clamped to `[0, 1]`. Returns
+    /// NaN if no value has been added.
+    pub fn quantile(&self, p: f64) -> f64 {
+        let first = match self.tuples.first() {
+            Some(&(value, ..)) => value,
+            None => return std::f64::NAN,
+        };
+        let rank = (p.max(0.).min(1.) * self.count as f64).ceil();
+        let tolerance = self.epsilon * self.count as f64;
+        let mut previous = first;
+        let mut min_rank = 0;
+        for &(value, g, delta) in self.tuples.iter() {
+            min_rank += g;
+            if (min_rank + delta) as f64 > rank + tolerance {
+                return previous;
+            }
+            previous = value;
+        }
+        previous
+    }
+
+    /// The number of values added, not counting the NaN values.
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// The number of entries held by the sketch.
+    pub fn size(&self) -> usize {
+        self.tuples.len()
+    }
+
+    fn max_band(&self) -> usize {
+        (2. * self.epsilon * self.count as f64).floor() as usize
+    }
+
+    /// Merges each entry into its successor when the merged entry still
+    /// respects the error bound, keeping the first and the last entries so
+    /// that the minimum and the maximum stay exact.
+    fn compress(&mut self) {
+        let max_band = self.max_band();
+        let mut i = self.tuples.len().saturating_sub(2);
+        while i >= 1 {
+            let (_, g, _) = self.tuples[i];
+            let (_, next_g, next_delta) = self.tuples[i + 1];
+            if g + next_g + next_delta <= max_band {
+                self.tuples[i + 1].1 += g;
+                self.tuples.remove(i);
+            }
+            i -= 1;
+        }
+    }
+}
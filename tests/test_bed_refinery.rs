@@ -1,15 +1,44 @@
 #![feature(type_ascription)]
 
 use biofile::{
-    bed::{Bed, BedDataLineIter},
+    bed::{Bed, BedDataLineIter, Strand},
     bedgraph::{BedGraph, BedGraphDataLineIter},
+    util::TrackVariant,
 };
 use biostats::{
-    assert_almost_eq, bed_refinery::BedRefinery, util::manifest_path_join,
+    assert_almost_eq, assert_track_eq,
+    bed_refinery::{
+        verify_idempotent, BedRefinery, BinAggregation, GenomeCoordinates,
+        NormalizeScope, OutputOrder, PreMergeScore, RefineryOptions,
+        UniqueScoreConflict, ZeroLengthHandling,
+    },
+    concatenated_genome::{GenomeOffsets, GENOME_CHROM},
+    error::Error,
+    test_util::{create_temp_bed, create_temp_bed_with_suffix},
+    track_conversion::TrackFormat,
+    util::{
+        get_chrom_interval_map, get_chrom_interval_map_with_options,
+        get_covered_positions, get_read_count_map,
+        get_target_chrom_interval_map, get_target_chrom_read_count_map,
+        get_target_chrom_read_intervals, manifest_path_join, merge_intervals,
+        normalize_strand, resample_to_grid, split_bed_fields, ApproxQuantiles,
+        CoordinateSystem, EndConvention, MissingScore, ReadOptions,
+        TrackLineIter, ValueColumn,
+    },
+};
+use flate2::read::GzDecoder;
+use math::{
+    interval::{traits::Interval, I64Interval},
+    iter::AggregateOp,
+    partition::integer_interval_map::IntegerIntervalMap,
+    set::{ordered_integer_set::OrderedIntegerSet, traits::Finite},
+    traits::ToIterator,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::Read,
 };
-use math::traits::ToIterator;
-use num::Float;
-use std::{fmt::Debug, str::FromStr};
 use tempfile::NamedTempFile;
 
 #[test]
@@ -32,20 +61,7 @@ fn test_unique() {
         .write_refined_bed(&out_path, bin_size, false, None, false)
         .unwrap();
 
-    let bed = Bed::new(&out_path, false);
-    let expected = vec![(0, 5, 2.), (5, 8, 1.)];
-    assert_eq!(
-        (bed.to_iter(): BedDataLineIter<f64>).count(),
-        expected.len()
-    );
-    for ((start, end, score), line) in expected
-        .into_iter()
-        .zip(bed.to_iter(): BedDataLineIter<f64>)
-    {
-        assert_eq!(line.start, start);
-        assert_eq!(line.end, end);
-        assert_almost_eq!(line.score.unwrap(), score);
-    }
+    assert_track_eq!(&out_path, vec![("chr1", 0, 5, 2.), ("chr1", 5, 8, 1.)]);
 }
 
 #[test]
@@ -66,8 +82,6 @@ fn test_bedgraph() {
         .write_refined_bed(&out_path, 0, false, None, true)
         .unwrap();
 
-    let bedgraph = BedGraph::new(&out_path, false);
-
     let expected = vec![
         ("chr1", 2, 3, 1.),
         ("chr1", 3, 4, 8.),
@@ -77,8 +91,7 @@ fn test_bedgraph() {
         ("chr1", 10, 21, 12.),
         ("chr1", 21, 40, 2.),
     ];
-
-    compare_bedgraph_output(&bedgraph, &expected);
+    assert_track_eq!(&out_path, expected);
 }
 
 #[test]
@@ -99,40 +112,2099 @@ fn test_max_len() {
         .write_refined_bed(&out_path, 0, false, None, true)
         .unwrap();
 
-    let bedgraph = BedGraph::new(&out_path, false);
-
     let expected = vec![
         ("chr1", 0, 500, 1.),
         ("chr1", 500, 1000, 1.),
         ("chr3", 30, 50, 2.),
         ("chr4", 4, 10, 1.),
     ];
-    compare_bedgraph_output(&bedgraph, &expected)
+    assert_track_eq!(&out_path, expected);
+}
+
+#[test]
+fn test_input_log2() {
+    let bed_path = create_temp_bed(
+        "chr1 0 1 id0 1\n\
+        chr1 1 2 id1 3\n",
+    )
+    .unwrap();
+    let out_file = NamedTempFile::new().unwrap();
+    let out_temp_path = out_file.into_temp_path();
+    let out_path = out_temp_path.to_str().unwrap().to_string();
+
+    let refinery = BedRefinery::<f64>::with_options(
+        bed_path.to_str().unwrap(),
+        RefineryOptions {
+            read_options: ReadOptions {
+                input_log2: true,
+                ..ReadOptions::default()
+            },
+            relog_output: true,
+            ..RefineryOptions::default()
+        },
+    )
+    .unwrap();
+    refinery
+        .write_refined_bed(&out_path, 2, false, None, true)
+        .unwrap();
+
+    // averaging 2^1 and 2^3 in the linear space before taking the log2
+    let linear_average = ((2f64 + 8f64) / 2.).log2();
+    // naively averaging 1 and 3 in the log space
+    let log_space_average = (1f64 + 3f64) / 2.;
+    assert!((linear_average - log_space_average).abs() > 0.1);
+
+    let bedgraph = BedGraph::new(&out_path, false);
+    let lines: Vec<_> =
+        (bedgraph.to_iter(): BedGraphDataLineIter<f64>).collect();
+    assert_eq!(lines.len(), 1);
+    assert_eq!((lines[0].start, lines[0].end_exclusive), (0, 2));
+    assert_almost_eq!(lines[0].value, linear_average);
+}
+
+#[test]
+fn test_unique_position_count() {
+    let bed_path = create_temp_bed(
+        "chr1 0 5 id0 1\n\
+        chr1 0 8 id1 1\n\
+        chr1 3 5 id2 1\n\
+        chr1 0 5 id3 1\n\
+        chr2 10 20 id4 1\n\
+        chr2 10 20 id5 1\n",
+    )
+    .unwrap();
+    let refinery = BedRefinery::<f64>::new(
+        bed_path.to_str().unwrap(),
+        false,
+        None,
+        false,
+        None,
+        None,
+        false,
+    );
+    let counts = refinery.unique_position_count();
+    assert_eq!(counts.len(), 2);
+    assert_eq!(counts["chr1"], 2);
+    assert_eq!(counts["chr2"], 1);
+}
+
+#[test]
+fn test_bin_aggregation() {
+    // the bin [0, 10) is only covered in [0, 5)
+    let bed_path = create_temp_bed("chr1 0 5 id0 2\n").unwrap();
+    for (bin_aggregation, expected_value) in
+        vec![(BinAggregation::Average, 1.), (BinAggregation::Sum, 10.)]
+    {
+        let refinery = BedRefinery::<f64>::with_options(
+            bed_path.to_str().unwrap(),
+            RefineryOptions {
+                bin_aggregation,
+                ..RefineryOptions::default()
+            },
+        )
+        .unwrap();
+        let out_file = NamedTempFile::new().unwrap();
+        let out_temp_path = out_file.into_temp_path();
+        let out_path = out_temp_path.to_str().unwrap().to_string();
+        refinery
+            .write_refined_bed(&out_path, 10, false, None, true)
+            .unwrap();
+
+        assert_track_eq!(&out_path, vec![("chr1", 0, 10, expected_value)]);
+    }
+}
+
+#[test]
+fn test_value_from_name() {
+    let bed_path = create_temp_bed(
+        "chr1 0 5 2.5 0\n\
+        chr1 3 8 4 0\n\
+        chr2 10 20 1.5 0\n",
+    )
+    .unwrap();
+    let refinery = BedRefinery::<f64>::with_options(
+        bed_path.to_str().unwrap(),
+        RefineryOptions {
+            read_options: ReadOptions {
+                value_column: ValueColumn::Name,
+                ..ReadOptions::default()
+            },
+            ..RefineryOptions::default()
+        },
+    )
+    .unwrap();
+    let out_file = NamedTempFile::new().unwrap();
+    let out_temp_path = out_file.into_temp_path();
+    let out_path = out_temp_path.to_str().unwrap().to_string();
+    refinery
+        .write_refined_bed(&out_path, 0, false, None, true)
+        .unwrap();
+
+    let expected = vec![
+        ("chr1", 0, 3, 2.5),
+        ("chr1", 3, 5, 6.5),
+        ("chr1", 5, 8, 4.),
+        ("chr2", 10, 20, 1.5),
+    ];
+    assert_track_eq!(&out_path, expected);
+
+    let non_numeric_bed_path = create_temp_bed("chr1 0 5 id0 0\n").unwrap();
+    assert!(BedRefinery::<f64>::with_options(
+        non_numeric_bed_path.to_str().unwrap(),
+        RefineryOptions {
+            read_options: ReadOptions {
+                value_column: ValueColumn::Name,
+                ..ReadOptions::default()
+            },
+            ..RefineryOptions::default()
+        },
+    )
+    .is_err());
+}
+
+#[test]
+fn test_emit_support_count() {
+    let bed_path = create_temp_bed(
+        "chr1 0 10 id0 1\n\
+        chr1 5 15 id1 1\n\
+        chr1 8 20 id2 1\n\
+        chr1 30 40 id3 1\n",
+    )
+    .unwrap();
+    let refinery = BedRefinery::<f64>::with_options(
+        bed_path.to_str().unwrap(),
+        RefineryOptions {
+            emit_support_count: true,
+            ..RefineryOptions::default()
+        },
+    )
+    .unwrap();
+
+    let get_start_to_support_count = |bin_size: i64| -> HashMap<i64, String> {
+        let out_file = NamedTempFile::new().unwrap();
+        let out_temp_path = out_file.into_temp_path();
+        let out_path = out_temp_path.to_str().unwrap().to_string();
+        refinery
+            .write_refined_bed(&out_path, bin_size, false, None, false)
+            .unwrap();
+        (Bed::new(&out_path, false).to_iter(): BedDataLineIter<f64>)
+            .map(|line| (line.start, line.name.unwrap()))
+            .collect()
+    };
+
+    let unbinned = get_start_to_support_count(0);
+    assert_eq!(unbinned.len(), 6);
+    for (start, count) in vec![
+        (0, "1"),
+        (5, "2"),
+        (8, "3"),
+        (10, "2"),
+        (15, "1"),
+        (30, "1"),
+    ] {
+        assert_eq!(unbinned[&start], count);
+    }
+
+    let binned = get_start_to_support_count(10);
+    assert_eq!(binned[&0], "3");
+    assert_eq!(binned[&10], "2");
+    assert_eq!(binned[&30], "1");
+}
+
+#[test]
+fn test_check_precision() {
+    // 2^53 + 1 is not representable in f64
+    let bed_path = create_temp_bed(
+        "chr1 0 10 id0 9007199254740992\n\
+        chr1 5 10 id1 1\n\
+        chr2 0 10 id2 1\n",
+    )
+    .unwrap();
+    let refinery = BedRefinery::<f64>::with_options(
+        bed_path.to_str().unwrap(),
+        RefineryOptions {
+            check_precision: true,
+            ..RefineryOptions::default()
+        },
+    )
+    .unwrap();
+    assert_eq!(refinery.stats().num_imprecise_intervals, Some(2));
+
+    let unchecked_refinery = BedRefinery::<f64>::with_options(
+        bed_path.to_str().unwrap(),
+        RefineryOptions::default(),
+    )
+    .unwrap();
+    assert_eq!(unchecked_refinery.stats().num_imprecise_intervals, None);
+    assert_eq!(unchecked_refinery.count_imprecise_bins(10), None);
+
+    // 2^50 per base pair sums to 10 * 2^50 over a bin of size 10
+    let bed_path = create_temp_bed(
+        "chr1 0 10 id0 1125899906842624\n\
+        chr1 10 20 id1 1\n",
+    )
+    .unwrap();
+    for (bin_aggregation, expected) in
+        vec![(BinAggregation::Sum, 1), (BinAggregation::Average, 0)]
+    {
+        let refinery = BedRefinery::<f64>::with_options(
+            bed_path.to_str().unwrap(),
+            RefineryOptions {
+                check_precision: true,
+                bin_aggregation,
+                ..RefineryOptions::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(refinery.stats().num_imprecise_intervals, Some(0));
+        assert_eq!(refinery.count_imprecise_bins(10), Some(expected));
+        assert_eq!(refinery.count_imprecise_bins(0), Some(0));
+    }
+}
+
+#[test]
+fn test_sort_by_value() {
+    let expected_all = vec![
+        ("chr1", 20, 26, 20.),
+        ("chr1", 26, 27, 13.),
+        ("chr1", 34, 37, 9.),
+        ("chr1", 10, 20, 7.),
+        ("chr1", 3, 5, 5.),
+        // ties are broken by the start coordinate
+        ("chr1", 0, 3, 3.),
+        ("chr1", 5, 10, 3.),
+    ];
+    // no tie at the cutoff of the top 5
+    for top_n in vec![None, Some(5), Some(100)] {
+        let refinery = BedRefinery::<f64>::with_options(
+            manifest_path_join("tests/test_3.bed").to_str().unwrap(),
+            RefineryOptions {
+                output_order: OutputOrder::Value {
+                    top_n,
+                },
+                ..RefineryOptions::default()
+            },
+        )
+        .unwrap();
+        let out_file = NamedTempFile::new().unwrap();
+        let out_temp_path = out_file.into_temp_path();
+        let out_path = out_temp_path.to_str().unwrap().to_string();
+        refinery
+            .write_refined_bed(&out_path, 0, false, None, true)
+            .unwrap();
+
+        let num_expected = top_n.unwrap_or(expected_all.len());
+        let expected: Vec<(&str, i64, i64, f64)> =
+            expected_all.iter().take(num_expected).cloned().collect();
+        assert_track_eq!(&out_path, expected);
+    }
+}
+
+#[test]
+fn test_merge_bedgraph_runs() {
+    // binning gives three consecutive bins with the same value
+    let bed_path = create_temp_bed(
+        "chr1 0 30 id0 3\n\
+        chr1 30 35 id1 3\n\
+        chr2 0 10 id2 3\n",
+    )
+    .unwrap();
+    for (merge_bedgraph_runs, expected) in vec![
+        (true, vec![
+            ("chr1", 0, 30, 3.),
+            ("chr1", 30, 40, 1.5),
+            ("chr2", 0, 10, 3.),
+        ]),
+        (false, vec![
+            ("chr1", 0, 10, 3.),
+            ("chr1", 10, 20, 3.),
+            ("chr1", 20, 30, 3.),
+            ("chr1", 30, 40, 1.5),
+            ("chr2", 0, 10, 3.),
+        ]),
+    ] {
+        let refinery = BedRefinery::<f64>::with_options(
+            bed_path.to_str().unwrap(),
+            RefineryOptions {
+                merge_bedgraph_runs,
+                ..RefineryOptions::default()
+            },
+        )
+        .unwrap();
+        let out_file = NamedTempFile::new().unwrap();
+        let out_temp_path = out_file.into_temp_path();
+        let out_path = out_temp_path.to_str().unwrap().to_string();
+        refinery
+            .write_refined_bed(&out_path, 10, false, None, true)
+            .unwrap();
+        assert_track_eq!(&out_path, expected);
+    }
+}
+
+#[test]
+fn test_normalize_scope() {
+    // chr1 has a total signal of 30 and chr2 has a total signal of 10
+    let bed_path = create_temp_bed(
+        "chr1 0 10 id0 3\n\
+        chr2 0 5 id1 2\n",
+    )
+    .unwrap();
+    for (normalize_scope, expected_values) in vec![
+        (NormalizeScope::Genome, vec![3. / 40., 2. / 40.]),
+        (NormalizeScope::Chrom, vec![3. / 30., 2. / 10.]),
+    ] {
+        let refinery = BedRefinery::<f64>::with_options(
+            bed_path.to_str().unwrap(),
+            RefineryOptions {
+                normalize_scope,
+                ..RefineryOptions::default()
+            },
+        )
+        .unwrap();
+        let out_file = NamedTempFile::new().unwrap();
+        let out_temp_path = out_file.into_temp_path();
+        let out_path = out_temp_path.to_str().unwrap().to_string();
+        refinery
+            .write_refined_bed(&out_path, 0, true, None, true)
+            .unwrap();
+
+        let bedgraph = BedGraph::new(&out_path, false);
+        let values: Vec<f64> = (bedgraph.to_iter()
+            as BedGraphDataLineIter<f64>)
+            .map(|line| line.value)
+            .collect();
+        assert_eq!(values.len(), expected_values.len());
+        for (value, expected) in values.iter().zip(expected_values.iter()) {
+            assert_almost_eq!(value, expected);
+        }
+    }
+}
+
+#[test]
+fn test_refined_iter() {
+    let refinery = BedRefinery::<f64>::new(
+        manifest_path_join("tests/test_3.bed").to_str().unwrap(),
+        false,
+        None,
+        false,
+        None,
+        None,
+        false,
+    );
+    for (bin_size, normalize, scaling) in vec![
+        (0, false, None),
+        (5, true, Some(100.)),
+        (7, false, Some(2.)),
+    ] {
+        let refined: Vec<(String, i64, i64, f64)> = refinery
+            .refined_iter(bin_size, normalize, scaling)
+            .unwrap()
+            .map(|(chrom, interval, value)| {
+                (chrom, interval.get_start(), interval.get_end() + 1, value)
+            })
+            .collect();
+
+        let out_file = NamedTempFile::new().unwrap();
+        let out_temp_path = out_file.into_temp_path();
+        let out_path = out_temp_path.to_str().unwrap().to_string();
+        refinery
+            .write_refined_bed(&out_path, bin_size, normalize, scaling, true)
+            .unwrap();
+        let expected: Vec<(&str, i64, i64, f64)> = refined
+            .iter()
+            .map(|(chrom, start, end, value)| {
+                (chrom.as_str(), *start, *end, *value)
+            })
+            .collect();
+        assert_track_eq!(&out_path, expected);
+    }
+}
+
+#[test]
+fn test_one_based_coordinates() {
+    let half_open_path = create_temp_bed(
+        "chr1 0 10 id0 3\n\
+        chr1 5 20 id1 2\n\
+        chr2 99 100 id2 7\n",
+    )
+    .unwrap();
+    // the same features in 1-based fully-closed coordinates
+    let closed_path = create_temp_bed(
+        "chr1 1 10 id0 3\n\
+        chr1 6 20 id1 2\n\
+        chr2 100 100 id2 7\n",
+    )
+    .unwrap();
+    let one_based = ReadOptions {
+        coordinate_system: CoordinateSystem::Closed1,
+        ..ReadOptions::default()
+    };
+
+    let half_open_refinery = BedRefinery::<f64>::with_options(
+        half_open_path.to_str().unwrap(),
+        RefineryOptions::default(),
+    )
+    .unwrap();
+    let closed_refinery = BedRefinery::<f64>::with_options(
+        closed_path.to_str().unwrap(),
+        RefineryOptions {
+            read_options: one_based.clone(),
+            ..RefineryOptions::default()
+        },
+    )
+    .unwrap();
+    let half_open_map = half_open_refinery.get_chrom_to_interval_map();
+    let closed_map = closed_refinery.get_chrom_to_interval_map();
+    assert_eq!(half_open_map.len(), 2);
+    assert_eq!(closed_map.len(), 2);
+    for chrom in vec!["chr1", "chr2"] {
+        assert_eq!(
+            half_open_map[chrom].iter().collect::<Vec<_>>(),
+            closed_map[chrom].iter().collect::<Vec<_>>()
+        );
+    }
+
+    let half_open_map = get_chrom_interval_map(
+        &TrackVariant::Bed(Bed::new(half_open_path.to_str().unwrap(), false)),
+        None,
+    )
+    .unwrap();
+    let closed_map = get_chrom_interval_map_with_options(
+        &TrackVariant::Bed(Bed::new(closed_path.to_str().unwrap(), false)),
+        None,
+        &one_based,
+    )
+    .unwrap();
+    for chrom in vec!["chr1", "chr2"] {
+        assert_eq!(
+            half_open_map[chrom].iter().collect::<Vec<_>>(),
+            closed_map[chrom].iter().collect::<Vec<_>>()
+        );
+    }
+}
+
+#[test]
+fn test_min_exclude_overlap_fraction() {
+    let track_path = create_temp_bed(
+        "chr1 0 100 id0 1\n\
+        chr1 9 11 id1 2\n\
+        chr1 9 10 id2 5\n\
+        chr2 0 10 id3 4\n",
+    )
+    .unwrap();
+    let exclude_path = create_temp_bed("chr1 9 10\n").unwrap();
+    let get_refinery = |min_exclude_overlap_fraction| {
+        BedRefinery::<f64>::with_options(
+            track_path.to_str().unwrap(),
+            RefineryOptions {
+                exclude_track_filepath: Some(
+                    exclude_path.to_str().unwrap().to_string(),
+                ),
+                min_exclude_overlap_fraction,
+                ..RefineryOptions::default()
+            },
+        )
+        .unwrap()
+    };
+
+    // any overlap with the excluded base pair drops the line by default
+    let refinery = get_refinery(0.);
+    let chrom_to_interval_map = refinery.get_chrom_to_interval_map();
+    assert_eq!(chrom_to_interval_map.len(), 1);
+    assert!(chrom_to_interval_map.contains_key("chr2"));
+
+    // only the line lying entirely in the excluded base pair is dropped
+    let refinery = get_refinery(0.5);
+    let chrom_to_interval_map = refinery.get_chrom_to_interval_map();
+    assert_eq!(chrom_to_interval_map.len(), 2);
+    let chr1_total: f64 = chrom_to_interval_map["chr1"]
+        .iter()
+        .map(|(interval, value)| interval.size() as f64 * *value)
+        .sum();
+    assert_almost_eq!(chr1_total, 104.);
+}
+
+#[test]
+fn test_skipped_chroms() {
+    let track_path = create_temp_bed(
+        "chr1 0 10 id0 1\n\
+        chr1_KI270706v1_random 0 20 id1 2\n\
+        chr1 20 30 id2 3\n\
+        chr1_KI270706v1_random 40 50 id3 4\n\
+        chrUn_GL000195v1 0 5 id4 5\n",
+    )
+    .unwrap();
+    let target_chroms: HashSet<String> =
+        vec!["chr1".to_string()].into_iter().collect();
+    let expected_skipped: Vec<(&str, usize)> =
+        vec![("chr1_KI270706v1_random", 2), ("chrUn_GL000195v1", 1)];
+
+    let refinery = BedRefinery::<f64>::with_options(
+        track_path.to_str().unwrap(),
+        RefineryOptions {
+            filter_chroms: Some(target_chroms.clone()),
+            ..RefineryOptions::default()
+        },
+    )
+    .unwrap();
+    assert_eq!(refinery.get_chrom_to_interval_map().len(), 1);
+    assert_eq!(
+        refinery
+            .stats()
+            .skipped_chrom_line_counts
+            .iter()
+            .map(|(chrom, &count)| (chrom.as_str(), count))
+            .collect::<Vec<(&str, usize)>>(),
+        expected_skipped
+    );
+
+    let (chrom_to_interval_map, read_stats) = get_target_chrom_interval_map(
+        &TrackVariant::Bed(Bed::new(track_path.to_str().unwrap(), false)),
+        None,
+        &ReadOptions::default(),
+        Some(&target_chroms),
+    )
+    .unwrap();
+    assert_eq!(chrom_to_interval_map.len(), 1);
+    assert!(chrom_to_interval_map.contains_key("chr1"));
+    assert_eq!(
+        read_stats
+            .skipped_chroms
+            .iter()
+            .map(|(chrom, &count)| (chrom.as_str(), count))
+            .collect::<Vec<(&str, usize)>>(),
+        expected_skipped
+    );
+}
+
+#[test]
+fn test_read_count_map() {
+    let track_path = create_temp_bed(
+        "chr1 0 10 a 1\n\
+        chr1 5 25 b 2\n\
+        chr1 30 30 c 1\n\
+        chr2 3 4 d 1\n",
+    )
+    .unwrap();
+    let track =
+        TrackVariant::Bed(Bed::new(track_path.to_str().unwrap(), false));
+    let (chrom_to_intervals, _) = get_target_chrom_read_intervals(
+        &track,
+        None,
+        &ReadOptions::default(),
+        None,
+    )
+    .unwrap();
+    // the zero-length line is not counted
+    assert_eq!(chrom_to_intervals["chr1"].len(), 2);
+
+    let to_vec = |map: &IntegerIntervalMap<f64>| {
+        map.iter()
+            .map(|(&interval, &count)| (interval, count))
+            .collect::<Vec<(I64Interval, f64)>>()
+    };
+    assert_eq!(
+        to_vec(&get_read_count_map(&chrom_to_intervals, 10)["chr1"]),
+        vec![(I64Interval::new(0, 9), 2.), (I64Interval::new(10, 29), 1.)]
+    );
+    for bin_size in vec![0, 7, 10] {
+        let count_map = get_read_count_map(&chrom_to_intervals, bin_size);
+        let (expected, _) = get_target_chrom_read_count_map(
+            &track,
+            None,
+            &ReadOptions::default(),
+            None,
+            bin_size,
+        )
+        .unwrap();
+        assert_eq!(count_map.len(), expected.len());
+        for (chrom, map) in expected.iter() {
+            assert_eq!(to_vec(&count_map[chrom]), to_vec(map));
+        }
+    }
+}
+
+#[test]
+fn test_empty_output_warning() {
+    let track_path = create_temp_bed(
+        "1 0 10 id0 1\n\
+        1 20 30 id1 3\n\
+        2 0 500 id2 5\n",
+    )
+    .unwrap();
+
+    let refinery = BedRefinery::<f64>::with_options(
+        track_path.to_str().unwrap(),
+        RefineryOptions::default(),
+    )
+    .unwrap();
+    assert!(refinery.empty_output_warning().is_none());
+
+    // the chromosome filter uses UCSC-style names unlike the track
+    let refinery = BedRefinery::<f64>::with_options(
+        track_path.to_str().unwrap(),
+        RefineryOptions {
+            filter_chroms: Some(
+                vec!["chr1".to_string(), "chr2".to_string()]
+                    .into_iter()
+                    .collect(),
+            ),
+            max_len: Some(100),
+            ..RefineryOptions::default()
+        },
+    )
+    .unwrap();
+    assert!(refinery.get_chrom_to_interval_map().is_empty());
+    let warning = refinery.empty_output_warning().unwrap();
+    assert!(warning.contains("all the 3 input lines were filtered out"));
+    assert!(
+        warning.contains("3 lines on chromosomes not in the chromosome filter")
+    );
+    assert!(!warning.contains("max_len"));
+
+    let out_path = NamedTempFile::new().unwrap().into_temp_path();
+    refinery
+        .write_refined_bed(out_path.to_str().unwrap(), 0, false, None, false)
+        .unwrap();
+    assert_eq!(std::fs::read_to_string(&out_path).unwrap(), "");
+
+    let empty_path = create_temp_bed("").unwrap();
+    let refinery = BedRefinery::<f64>::with_options(
+        empty_path.to_str().unwrap(),
+        RefineryOptions::default(),
+    )
+    .unwrap();
+    assert_eq!(
+        refinery.empty_output_warning().unwrap(),
+        "warning: the output is empty because the input track has no data \
+        lines"
+    );
+}
+
+#[test]
+fn test_end_convention() {
+    let track_path = create_temp_bed(
+        "1 0 10 id0 1\n\
+        1 20 30 id1 3\n\
+        2 5 500 id2 5\n",
+    )
+    .unwrap();
+
+    let get_coordinates = |end_convention: EndConvention| {
+        let refinery = BedRefinery::<f64>::with_options(
+            track_path.to_str().unwrap(),
+            RefineryOptions {
+                end_convention,
+                ..RefineryOptions::default()
+            },
+        )
+        .unwrap();
+        let out_path = NamedTempFile::new().unwrap().into_temp_path();
+        refinery
+            .write_refined_bed(
+                out_path.to_str().unwrap(),
+                0,
+                false,
+                None,
+                false,
+            )
+            .unwrap();
+        std::fs::read_to_string(&out_path)
+            .unwrap()
+            .lines()
+            .map(|line| {
+                let toks: Vec<&str> = line.split_whitespace().collect();
+                (
+                    toks[0].to_string(),
+                    toks[1].parse::<i64>().unwrap(),
+                    toks[2].parse::<i64>().unwrap(),
+                )
+            })
+            .collect::<Vec<(String, i64, i64)>>()
+    };
+
+    let exclusive = get_coordinates(EndConvention::Exclusive);
+    let inclusive = get_coordinates(EndConvention::Inclusive);
+    assert_eq!(exclusive, vec![
+        ("1".to_string(), 0, 10),
+        ("1".to_string(), 20, 30),
+        ("2".to_string(), 5, 500),
+    ]);
+    assert_eq!(exclusive.len(), inclusive.len());
+    for ((chrom_e, start_e, end_e), (chrom_i, start_i, end_i)) in
+        exclusive.iter().zip(inclusive.iter())
+    {
+        assert_eq!(chrom_e, chrom_i);
+        assert_eq!(start_e, start_i);
+        assert_eq!(*end_e, end_i + 1);
+    }
+}
+
+#[test]
+fn test_covered_positions() {
+    let bed_path = create_temp_bed(
+        "chr1 0 10 id0 1\n\
+        chr1 5 20 id1 3\n\
+        chr1 30 40 id2 0\n\
+        chr2 100 150 id3 5\n",
+    )
+    .unwrap();
+    let bedgraph_path = create_temp_bed(
+        "chr1 0 20 4\n\
+        chr1 30 40 1\n\
+        chr2 100 120 2\n\
+        chr2 120 150 7\n",
+    )
+    .unwrap();
+
+    let bed_positions = get_covered_positions(
+        &TrackVariant::Bed(Bed::new(bed_path.to_str().unwrap(), false)),
+        None,
+    )
+    .unwrap();
+    let bedgraph_positions = get_covered_positions(
+        &TrackVariant::BedGraph(BedGraph::new(
+            bedgraph_path.to_str().unwrap(),
+            false,
+        )),
+        None,
+    )
+    .unwrap();
+
+    assert_eq!(bed_positions, bedgraph_positions);
+    assert_eq!(bed_positions.len(), 2);
+    assert_eq!(bed_positions["chr1"].size(), 30);
+    assert_eq!(bed_positions["chr2"].size(), 50);
+    assert_eq!(
+        bed_positions["chr1"],
+        OrderedIntegerSet::from_slice(&[[0, 19], [30, 39]])
+    );
+}
+
+#[test]
+fn test_zero_length_lines() {
+    // the lines are numbered counting the comment line
+    let track_path = create_temp_bed(
+        "# a comment line\n\
+        chr1 0 10 id0 1\n\
+        chr1 20 20 id1 3\n\
+        chr2 5 5 id2 5\n",
+    )
+    .unwrap();
+    let get_refinery = |zero_length_handling| {
+        BedRefinery::<f64>::with_options(
+            track_path.to_str().unwrap(),
+            RefineryOptions {
+                zero_length_handling,
+                ..RefineryOptions::default()
+            },
+        )
+    };
+
+    let refinery = get_refinery(ZeroLengthHandling::Skip).unwrap();
+    assert_eq!(refinery.stats().num_zero_length_lines, 2);
+    let chrom_to_interval_map = refinery.get_chrom_to_interval_map();
+    assert_eq!(chrom_to_interval_map.len(), 1);
+    assert_eq!(
+        chrom_to_interval_map["chr1"].iter().collect::<Vec<_>>(),
+        vec![(&I64Interval::new(0, 9), &1.)]
+    );
+
+    let error = get_refinery(ZeroLengthHandling::Error)
+        .err()
+        .unwrap()
+        .to_string();
+    assert!(error.contains("zero-length"));
+    assert!(error.contains("line 3 of"));
+    assert!(error.contains("(chr1, 20, 20)"));
+
+    // a negative length is an error either way
+    let track_path = create_temp_bed(
+        "# a comment line\n\
+        chr1 0 10 id0 1\n\
+        chr1 30 20 id1 3\n",
+    )
+    .unwrap();
+    let error = BedRefinery::<f64>::with_options(
+        track_path.to_str().unwrap(),
+        RefineryOptions::default(),
+    )
+    .err()
+    .unwrap()
+    .to_string();
+    assert!(error.contains("the end coordinate is less than the start"));
+    assert!(error.contains("line 3 of"));
+    assert!(error.contains("(chr1, 30, 20)"));
+}
+
+#[test]
+fn test_scientific_notation_coordinates() {
+    let track_path = create_temp_bed(
+        "# a comment line\n\
+        chr1 0 10 id0 1\n\
+        chr1 1e5 200000 id1 3\n",
+    )
+    .unwrap();
+    let error = BedRefinery::<f64>::with_options(
+        track_path.to_str().unwrap(),
+        RefineryOptions::default(),
+    )
+    .err()
+    .unwrap()
+    .to_string();
+    assert!(error.contains("line 3"));
+    assert!(error.contains("the start field \"1e5\" is not an integer"));
+
+    let track_path = create_temp_bed("chr1 0 2.5E3 id0 1\n").unwrap();
+    let error = BedRefinery::<f64>::with_options(
+        track_path.to_str().unwrap(),
+        RefineryOptions::default(),
+    )
+    .err()
+    .unwrap()
+    .to_string();
+    assert!(error.contains("line 1"));
+    assert!(error.contains("the end field \"2.5E3\" is not an integer"));
+
+    // the lines are checked as they are read into the interval maps as well
+    let error = get_chrom_interval_map(
+        &TrackVariant::Bed(Bed::new(track_path.to_str().unwrap(), false)),
+        None,
+    )
+    .unwrap_err()
+    .to_string();
+    assert!(error.contains("line 1"));
+    assert!(error.contains("the end field \"2.5E3\" is not an integer"));
+}
+
+#[test]
+fn test_gzip_output() {
+    let track_path = create_temp_bed(
+        "chr1 0 10 id0 1\n\
+        chr1 5 30 id1 3\n\
+        chr2 0 500 id2 5\n",
+    )
+    .unwrap();
+    let refinery = BedRefinery::<f64>::new(
+        track_path.to_str().unwrap(),
+        false,
+        None,
+        false,
+        None,
+        None,
+        false,
+    );
+
+    let plain_path = NamedTempFile::new().unwrap().into_temp_path();
+    let gz_path = create_temp_bed_with_suffix("", ".bed.gz").unwrap();
+    for &out_bedgraph in [false, true].iter() {
+        refinery
+            .write_refined_bed(
+                plain_path.to_str().unwrap(),
+                10,
+                false,
+                None,
+                out_bedgraph,
+            )
+            .unwrap();
+        refinery
+            .write_refined_bed(
+                gz_path.to_str().unwrap(),
+                10,
+                false,
+                None,
+                out_bedgraph,
+            )
+            .unwrap();
+
+        let mut decompressed = String::new();
+        GzDecoder::new(File::open(&gz_path).unwrap())
+            .read_to_string(&mut decompressed)
+            .unwrap();
+        let plain = std::fs::read_to_string(&plain_path).unwrap();
+        assert!(!plain.is_empty());
+        assert_eq!(decompressed, plain);
+    }
+}
+
+#[test]
+fn test_weight_column() {
+    // the weights are in column 7, and the last line has no weight
+    let track_path = create_temp_bed(
+        "chr1 0 10 id0 2 + 3\n\
+        chr1 5 15 id1 8 + 1\n\
+        chr1 20 30 id2 4 +\n",
+    )
+    .unwrap();
+
+    let get_intervals = |weight_column: Option<usize>| {
+        let refinery = BedRefinery::<f64>::with_options(
+            track_path.to_str().unwrap(),
+            RefineryOptions {
+                weight_column,
+                ..RefineryOptions::default()
+            },
+        )
+        .unwrap();
+        refinery.get_chrom_to_interval_map()["chr1"]
+            .iter()
+            .map(|(interval, &value)| {
+                (interval.get_start(), interval.get_end(), value)
+            })
+            .collect::<Vec<(i64, i64, f64)>>()
+    };
+
+    // overlapping values are summed without weights
+    assert_eq!(get_intervals(None), vec![
+        (0, 4, 2.),
+        (5, 9, 10.),
+        (10, 14, 8.),
+        (20, 29, 4.)
+    ]);
+
+    // (2 * 3 + 8 * 1) / (3 + 1) on the overlap
+    assert_eq!(get_intervals(Some(7)), vec![
+        (0, 4, 2.),
+        (5, 9, 3.5),
+        (10, 14, 8.),
+        (20, 29, 4.)
+    ]);
+
+    // an invalid weight is an error naming the line
+    let invalid_path =
+        create_temp_bed("chr1 0 10 id0 2 + 1\nchr1 0 10 id0 2 + x\n").unwrap();
+    let error = BedRefinery::<f64>::with_options(
+        invalid_path.to_str().unwrap(),
+        RefineryOptions {
+            weight_column: Some(7),
+            ..RefineryOptions::default()
+        },
+    )
+    .err()
+    .unwrap()
+    .to_string();
+    assert!(error.contains("line 2"), "{}", error);
+    assert!(error.contains("weight"), "{}", error);
 }
 
-// `expected`: (chrom, start, end_exclusive, value)
-fn compare_bedgraph_output<
-    Value: Debug + Float + FromStr<Err = E>,
-    E: Debug,
->(
-    bedgraph: &BedGraph,
-    expected: &Vec<(&str, i64, i64, Value)>,
-) {
+#[test]
+fn test_multi_value_columns() {
+    // the three signal columns are 5, 7 and 8
+    let track_path = create_temp_bed(
+        "chr1 0 10 id0 1 + 2 3\n\
+        chr1 10 20 id1 4 + 5 6\n\
+        chr2 0 5 id2 7 + 8 9\n",
+    )
+    .unwrap();
+    let refinery = BedRefinery::<f64>::new_multi(
+        track_path.to_str().unwrap(),
+        vec![5, 7, 8],
+        RefineryOptions::default(),
+    )
+    .unwrap();
+
+    let out_path = NamedTempFile::new().unwrap().into_temp_path();
+    refinery
+        .write_refined_multi(out_path.to_str().unwrap(), 10)
+        .unwrap();
+    let lines: Vec<String> = std::fs::read_to_string(&out_path)
+        .unwrap()
+        .lines()
+        .map(|line| line.to_string())
+        .collect();
+    assert_eq!(lines, vec![
+        "0 10 chr1 1 2 3",
+        "10 20 chr1 4 5 6",
+        "0 10 chr2 3.5 4 4.5"
+    ]);
+
+    // the usual value is still read from the score column
     assert_eq!(
-        (bedgraph.to_iter(): BedGraphDataLineIter<Value>).count(),
-        expected.len()
+        refinery.get_chrom_to_interval_map()["chr2"]
+            .iter()
+            .map(|(interval, &value)| {
+                (interval.get_start(), interval.get_end(), value)
+            })
+            .collect::<Vec<(i64, i64, f64)>>(),
+        vec![(0, 4, 7.)]
     );
-    for ((chrom, start, end_exclusive, value), line) in expected
+
+    // a missing value column is an error naming the line
+    let error = BedRefinery::<f64>::new_multi(
+        track_path.to_str().unwrap(),
+        vec![5, 9],
+        RefineryOptions::default(),
+    )
+    .err()
+    .unwrap()
+    .to_string();
+    assert!(error.contains("line 1"), "{}", error);
+    assert!(error.contains("column 9"), "{}", error);
+}
+
+#[test]
+fn test_merge_intervals() {
+    let chrom_to_intervals: HashMap<String, Vec<I64Interval>> =
+        vec![("chr1".to_string(), vec![
+            // [20, 30) is exactly 5 bp after [0, 15)
+            I64Interval::new(20, 29),
+            I64Interval::new(0, 14),
+            // book-ended with [20, 30)
+            I64Interval::new(30, 34),
+            // 6 bp after [30, 35)
+            I64Interval::new(41, 49),
+            // contained in [41, 50)
+            I64Interval::new(43, 45),
+        ])]
         .into_iter()
-        .zip(bedgraph.to_iter(): BedGraphDataLineIter<Value>)
+        .collect();
+
+    assert_eq!(merge_intervals(&chrom_to_intervals, 5)["chr1"], vec![
+        I64Interval::new(0, 34),
+        I64Interval::new(41, 49)
+    ]);
+    assert_eq!(merge_intervals(&chrom_to_intervals, 4)["chr1"], vec![
+        I64Interval::new(0, 14),
+        I64Interval::new(20, 34),
+        I64Interval::new(41, 49)
+    ]);
+    assert_eq!(merge_intervals(&chrom_to_intervals, 6)["chr1"], vec![
+        I64Interval::new(0, 49)
+    ]);
+}
+
+#[test]
+fn test_resample_to_grid() {
+    // 50 bp bins
+    let mut map = IntegerIntervalMap::new();
+    for (i, &value) in [2., 4., 6., 8.].iter().enumerate() {
+        let start = i as i64 * 50;
+        map.aggregate(I64Interval::new(start, start + 49), value);
+    }
+    let to_vec = |map: IntegerIntervalMap<f64>| {
+        map.iter()
+            .map(|(i, &v)| (i.get_start(), i.get_end(), v))
+            .collect::<Vec<(i64, i64, f64)>>()
+    };
+
+    // aggregating into larger bins
+    assert_eq!(
+        to_vec(resample_to_grid(&map, 100, AggregateOp::Average)),
+        vec![(0, 99, 3.), (100, 199, 7.)]
+    );
+    assert_eq!(to_vec(resample_to_grid(&map, 100, AggregateOp::Sum)), vec![
+        (0, 99, 300.),
+        (100, 199, 700.)
+    ]);
+
+    // splitting into smaller bins
+    assert_eq!(
+        to_vec(resample_to_grid(&map, 25, AggregateOp::Average)),
+        vec![
+            (0, 24, 2.),
+            (25, 49, 2.),
+            (50, 74, 4.),
+            (75, 99, 4.),
+            (100, 124, 6.),
+            (125, 149, 6.),
+            (150, 174, 8.),
+            (175, 199, 8.)
+        ]
+    );
+
+    // the same grid leaves the bins unchanged
+    assert_eq!(
+        to_vec(resample_to_grid(&map, 50, AggregateOp::Average)),
+        vec![(0, 49, 2.), (50, 99, 4.), (100, 149, 6.), (150, 199, 8.)]
+    );
+}
+
+#[test]
+fn test_pre_merge() {
+    // the first two lines are 5 bp apart and the third is 6 bp further away
+    let track_path = create_temp_bed(
+        "chr1 0 10 id0 2\n\
+        chr1 15 20 id1 3\n\
+        chr1 26 30 id2 4\n",
+    )
+    .unwrap();
+    let get_intervals = |pre_merge_score| {
+        let refinery = BedRefinery::<f64>::with_options(
+            track_path.to_str().unwrap(),
+            RefineryOptions {
+                pre_merge_gap: Some(5),
+                pre_merge_score,
+                ..RefineryOptions::default()
+            },
+        )
+        .unwrap();
+        refinery.get_chrom_to_interval_map()["chr1"]
+            .iter()
+            .map(|(interval, &value)| {
+                (interval.get_start(), interval.get_end(), value)
+            })
+            .collect::<Vec<(i64, i64, f64)>>()
+    };
+
+    assert_eq!(get_intervals(PreMergeScore::Sum), vec![
+        (0, 19, 5.),
+        (26, 29, 4.)
+    ]);
+    assert_eq!(get_intervals(PreMergeScore::Max), vec![
+        (0, 19, 3.),
+        (26, 29, 4.)
+    ]);
+
+    // pre-merging cannot be combined with a weight column
+    assert!(BedRefinery::<f64>::with_options(
+        track_path.to_str().unwrap(),
+        RefineryOptions {
+            pre_merge_gap: Some(5),
+            weight_column: Some(7),
+            ..RefineryOptions::default()
+        },
+    )
+    .is_err());
+
+    // the gap cannot be negative
+    match BedRefinery::<f64>::with_options(
+        track_path.to_str().unwrap(),
+        RefineryOptions {
+            pre_merge_gap: Some(-1),
+            ..RefineryOptions::default()
+        },
+    ) {
+        Err(Error::Validation(_)) => {}
+        Err(error) => panic!("expected a Validation error, got {}", error),
+        Ok(_) => panic!("expected a Validation error"),
+    }
+}
+
+#[test]
+fn test_running_max() {
+    let mut content = String::new();
+    for (i, value) in vec![5, 1, 1, 8, 1, 1, 1, 1, 9, 1].into_iter().enumerate()
     {
-        assert_eq!(&line.chrom, chrom);
-        assert_eq!(line.start, *start);
-        assert_eq!(line.end_exclusive, *end_exclusive);
-        assert_eq!(line.value, *value);
+        content.push_str(&format!(
+            "chr1 {} {} id{} {}\n",
+            i * 10,
+            (i + 1) * 10,
+            i,
+            value
+        ));
+    }
+    // separated from the peak at [80, 90) by a gap
+    content.push_str("chr1 200 210 id10 2\nchr2 0 10 id11 3\n");
+    let track_path = create_temp_bed(&content).unwrap();
+
+    let get_scaled_values = |running_max_window, scaling| {
+        BedRefinery::<f64>::with_options(
+            track_path.to_str().unwrap(),
+            RefineryOptions {
+                running_max_window,
+                ..RefineryOptions::default()
+            },
+        )
+        .unwrap()
+        .refined_iter(10, false, scaling)
+        .unwrap()
+        .map(|(_, _, value)| value)
+        .collect::<Vec<f64>>()
+    };
+    let get_values =
+        |running_max_window| get_scaled_values(running_max_window, None);
+
+    assert_eq!(get_values(None), vec![
+        5., 1., 1., 8., 1., 1., 1., 1., 9., 1., 2., 3.
+    ]);
+    assert_eq!(get_values(Some(1)), vec![
+        5., 5., 8., 8., 8., 1., 1., 9., 9., 9., 2., 3.
+    ]);
+    // the valleys between the peaks at most 5 bins apart are filled
+    assert_eq!(get_values(Some(2)), vec![
+        5., 8., 8., 8., 8., 8., 9., 9., 9., 9., 2., 3.
+    ]);
+    // the maximum is taken over the scaled values, so a negative scaling
+    // turns the envelope around the peaks into one around the valleys
+    assert_eq!(get_scaled_values(Some(1), Some(-1.)), vec![
+        -1., -1., -1., -1., -1., -1., -1., -1., -1., -1., -2., -3.
+    ]);
+}
+
+#[test]
+fn test_unique_score_conflict() {
+    // the first two lines are duplicates with different scores, and the last
+    // two are duplicates with equal scores
+    let track_path = create_temp_bed(
+        "chr1 0 10 id0 2 +\n\
+        chr1 0 10 id1 5 +\n\
+        chr1 20 30 id2 4 +\n\
+        chr1 20 30 id3 4 +\n",
+    )
+    .unwrap();
+    let get_intervals = |unique_score_conflict| {
+        BedRefinery::<f64>::with_options(
+            track_path.to_str().unwrap(),
+            RefineryOptions {
+                unique: true,
+                unique_score_conflict,
+                ..RefineryOptions::default()
+            },
+        )
+        .map(|refinery| {
+            assert_eq!(refinery.stats().num_duplicate_lines, Some(2));
+            refinery.get_chrom_to_interval_map()["chr1"]
+                .iter()
+                .map(|(interval, &value)| {
+                    (interval.get_start(), interval.get_end(), value)
+                })
+                .collect::<Vec<(i64, i64, f64)>>()
+        })
+    };
+
+    assert_eq!(get_intervals(UniqueScoreConflict::First).unwrap(), vec![
+        (0, 9, 2.),
+        (20, 29, 4.)
+    ]);
+    assert_eq!(get_intervals(UniqueScoreConflict::Max).unwrap(), vec![
+        (0, 9, 5.),
+        (20, 29, 4.)
+    ]);
+    assert_eq!(get_intervals(UniqueScoreConflict::Sum).unwrap(), vec![
+        (0, 9, 7.),
+        (20, 29, 4.)
+    ]);
+    assert!(get_intervals(UniqueScoreConflict::Error).is_err());
+}
+
+#[test]
+fn test_value_queries() {
+    let refinery = BedRefinery::<f64>::with_options(
+        manifest_path_join("tests/test_3.bed").to_str().unwrap(),
+        RefineryOptions::default(),
+    )
+    .unwrap();
+    let get_values = |chrom, start, end| {
+        refinery
+            .values_in(chrom, I64Interval::new(start, end))
+            .into_iter()
+            .map(|(interval, value)| {
+                (interval.get_start(), interval.get_end(), value)
+            })
+            .collect::<Vec<(i64, i64, f64)>>()
+    };
+
+    // inside the covered intervals
+    assert_eq!(refinery.value_at("chr1", 0), Some(3.));
+    assert_eq!(refinery.value_at("chr1", 4), Some(5.));
+    assert_eq!(refinery.value_at("chr1", 20), Some(20.));
+    assert_eq!(refinery.value_at("chr1", 36), Some(9.));
+    assert_eq!(get_values("chr1", 8, 21), vec![
+        (8, 9, 3.),
+        (10, 19, 7.),
+        (20, 21, 20.)
+    ]);
+
+    // between the covered intervals
+    assert_eq!(refinery.value_at("chr1", 27), None);
+    assert_eq!(refinery.value_at("chr1", 33), None);
+    assert_eq!(get_values("chr1", 27, 33), vec![]);
+    assert_eq!(get_values("chr1", 26, 34), vec![
+        (26, 26, 13.),
+        (34, 34, 9.)
+    ]);
+
+    // outside the covered intervals
+    assert_eq!(refinery.value_at("chr1", 37), None);
+    assert_eq!(refinery.value_at("chr1", -1), None);
+    assert_eq!(refinery.value_at("chr2", 4), None);
+    assert_eq!(get_values("chr1", 40, 50), vec![]);
+    assert_eq!(get_values("chr2", 0, 10), vec![]);
+}
+
+#[test]
+fn test_extend() {
+    let track_path = create_temp_bed(
+        "chr1 100 101 id0 1\n\
+        chr1 20 30 id1 2\n\
+        chr1 200 200 id3 4\n\
+        chr2 980 990 id2 3\n",
+    )
+    .unwrap();
+    let get_intervals = |chrom_sizes: Option<HashMap<String, i64>>| {
+        let refinery = BedRefinery::<f64>::with_options(
+            track_path.to_str().unwrap(),
+            RefineryOptions {
+                extend_bp: Some(50),
+                chrom_sizes,
+                ..RefineryOptions::default()
+            },
+        )
+        .unwrap();
+        // the zero-length line is skipped rather than extended
+        assert_eq!(refinery.stats().num_zero_length_lines, 1);
+        let mut intervals: Vec<(String, i64, i64, f64)> = Vec::new();
+        for (chrom, interval_map) in refinery.get_chrom_to_interval_map() {
+            for (interval, &value) in interval_map.iter() {
+                intervals.push((
+                    chrom.clone(),
+                    interval.get_start(),
+                    interval.get_end(),
+                    value,
+                ));
+            }
+        }
+        intervals.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        intervals
+    };
+
+    // the single base at 100 becomes the interval [50, 151), and the start
+    // of [20, 30) is clamped at 0
+    assert_eq!(get_intervals(None), vec![
+        ("chr1".to_string(), 0, 49, 2.),
+        ("chr1".to_string(), 50, 79, 3.),
+        ("chr1".to_string(), 80, 150, 1.),
+        ("chr2".to_string(), 930, 1039, 3.),
+    ]);
+
+    // the end is clamped at the chromosome size
+    let chrom_sizes: HashMap<String, i64> =
+        vec![("chr2".to_string(), 1000)].into_iter().collect();
+    assert_eq!(
+        get_intervals(Some(chrom_sizes))[3],
+        ("chr2".to_string(), 930, 999, 3.)
+    );
+
+    assert!(BedRefinery::<f64>::with_options(
+        track_path.to_str().unwrap(),
+        RefineryOptions {
+            extend_bp: Some(-1),
+            ..RefineryOptions::default()
+        },
+    )
+    .is_err());
+
+    // a negative length is rejected before the line would be extended into
+    // the valid interval [80, 110)
+    let track_path = create_temp_bed("chr1 100 90 id0 1\n").unwrap();
+    let error = BedRefinery::<f64>::with_options(
+        track_path.to_str().unwrap(),
+        RefineryOptions {
+            extend_bp: Some(20),
+            ..RefineryOptions::default()
+        },
+    )
+    .err()
+    .unwrap()
+    .to_string();
+    assert!(error.contains("(chr1, 100, 90)"));
+}
+
+#[test]
+fn test_strand_extend() {
+    let track_path = create_temp_bed(
+        "chr1 100 110 id0 1 +\n\
+        chr1 300 310 id1 2 -\n\
+        chr1 500 510 id2 3 .\n\
+        chr1 20 30 id3 4 -\n",
+    )
+    .unwrap();
+    let refinery = BedRefinery::<f64>::with_options(
+        track_path.to_str().unwrap(),
+        RefineryOptions {
+            strand_extend_bp: Some(50),
+            ..RefineryOptions::default()
+        },
+    )
+    .unwrap();
+    let intervals: Vec<(i64, i64, f64)> = refinery.get_chrom_to_interval_map()
+        ["chr1"]
+        .iter()
+        .map(|(interval, &value)| {
+            (interval.get_start(), interval.get_end(), value)
+        })
+        .collect();
+
+    // the plus strand and the missing strand extend the end, while the minus
+    // strand extends the start, clamped at 0
+    assert_eq!(intervals, vec![
+        (0, 29, 4.),
+        (100, 159, 1.),
+        (250, 309, 2.),
+        (500, 559, 3.)
+    ]);
+}
+
+#[test]
+fn test_max_bad_lines() {
+    let track_path = create_temp_bed(
+        "chr1 0 10 id0 1\n\
+        chr1 1e5 200000 id1 3\n\
+        chr1 20 30 id2 2\n\
+        chr1 40\n\
+        chr1 50 60 id3 4\n",
+    )
+    .unwrap();
+    let read_track = |max_bad_lines| {
+        BedRefinery::<f64>::with_options(
+            track_path.to_str().unwrap(),
+            RefineryOptions {
+                read_options: ReadOptions {
+                    max_bad_lines,
+                    ..ReadOptions::default()
+                },
+                ..RefineryOptions::default()
+            },
+        )
+    };
+
+    assert!(read_track(None).is_err());
+    assert!(read_track(Some(1)).is_err());
+
+    let refinery = read_track(Some(5)).unwrap();
+    assert_eq!(refinery.stats().num_bad_lines, 2);
+    assert_eq!(refinery.stats().num_lines, 3);
+    let intervals: Vec<(i64, i64, f64)> = refinery.get_chrom_to_interval_map()
+        ["chr1"]
+        .iter()
+        .map(|(interval, &value)| {
+            (interval.get_start(), interval.get_end(), value)
+        })
+        .collect();
+    assert_eq!(intervals, vec![(0, 9, 1.), (20, 29, 2.), (50, 59, 4.)]);
+
+    // the interval maps skip the same lines
+    let track =
+        TrackVariant::Bed(Bed::new(track_path.to_str().unwrap(), false));
+    let read_options = ReadOptions {
+        max_bad_lines: Some(5),
+        ..ReadOptions::default()
+    };
+    let (chrom_to_interval_map, read_stats) =
+        get_target_chrom_interval_map(&track, None, &read_options, None)
+            .unwrap();
+    assert_eq!(read_stats.num_bad_lines, 2);
+    assert_eq!(chrom_to_interval_map["chr1"].iter().count(), 3);
+    assert!(get_target_chrom_interval_map(
+        &track,
+        None,
+        &ReadOptions {
+            max_bad_lines: Some(1),
+            ..ReadOptions::default()
+        },
+        None
+    )
+    .unwrap_err()
+    .to_string()
+    .contains("more than 1 malformed lines"));
+}
+
+#[test]
+fn test_concatenate_genome() {
+    let track_path =
+        create_temp_bed("chr2 0 10 id0 4\nchr1 20 30 id1 2\n").unwrap();
+    let offsets = GenomeOffsets::new(vec![
+        ("chr1".to_string(), 30),
+        ("chr2".to_string(), 20),
+    ])
+    .unwrap();
+    let get_intervals = |genome_coordinates| {
+        BedRefinery::<f64>::with_options(
+            track_path.to_str().unwrap(),
+            RefineryOptions {
+                concatenate_genome: Some(offsets.clone()),
+                genome_coordinates,
+                ..RefineryOptions::default()
+            },
+        )
+        .unwrap()
+        .refined_iter(20, false, None)
+        .unwrap()
+        .map(|(chrom, interval, value)| {
+            (chrom, interval.get_start(), interval.get_end() + 1, value)
+        })
+        .collect::<Vec<(String, i64, i64, f64)>>()
+    };
+
+    // chr2 starts at the global coordinate 30 right after chr1, so that the
+    // bin [20, 40) covers the end of chr1 and the start of chr2
+    assert_eq!(get_intervals(GenomeCoordinates::Global), vec![(
+        GENOME_CHROM.to_string(),
+        20,
+        40,
+        3.
+    )]);
+    assert_eq!(get_intervals(GenomeCoordinates::PerChrom), vec![
+        ("chr1".to_string(), 20, 30, 3.),
+        ("chr2".to_string(), 0, 10, 3.),
+    ]);
+
+    // the interval extends beyond the size of chr2
+    let track_path = create_temp_bed("chr2 15 25 id0 1\n").unwrap();
+    assert!(BedRefinery::<f64>::with_options(
+        track_path.to_str().unwrap(),
+        RefineryOptions {
+            concatenate_genome: Some(offsets),
+            ..RefineryOptions::default()
+        },
+    )
+    .is_err());
+}
+
+#[test]
+fn test_interpolate_gaps() {
+    let track_path = create_temp_bed(
+        "chr1 0 10 id0 2\n\
+        chr1 40 50 id1 8\n\
+        chr1 100 110 id2 2\n\
+        chr2 0 10 id3 1\n",
+    )
+    .unwrap();
+    let get_bins = |max_interpolate_gap| {
+        BedRefinery::<f64>::with_options(
+            track_path.to_str().unwrap(),
+            RefineryOptions {
+                interpolate_gaps: true,
+                max_interpolate_gap,
+                ..RefineryOptions::default()
+            },
+        )
+        .unwrap()
+        .refined_iter(10, false, None)
+        .unwrap()
+        .map(|(chrom, interval, value)| {
+            (chrom, interval.get_start(), interval.get_end() + 1, value)
+        })
+        .collect::<Vec<(String, i64, i64, f64)>>()
+    };
+
+    // the gap of 3 bins is filled while the gap of 5 bins is left empty
+    let chr1 = "chr1".to_string();
+    assert_eq!(get_bins(Some(3)), vec![
+        (chr1.clone(), 0, 10, 2.),
+        (chr1.clone(), 10, 20, 3.5),
+        (chr1.clone(), 20, 30, 5.),
+        (chr1.clone(), 30, 40, 6.5),
+        (chr1.clone(), 40, 50, 8.),
+        (chr1, 100, 110, 2.),
+        ("chr2".to_string(), 0, 10, 1.),
+    ]);
+
+    let bins = get_bins(None);
+    assert_eq!(bins.len(), 12);
+    let values: Vec<f64> = bins[5..10].iter().map(|bin| bin.3).collect();
+    assert_eq!(values, vec![7., 6., 5., 4., 3.]);
+
+    assert!(BedRefinery::<f64>::with_options(
+        track_path.to_str().unwrap(),
+        RefineryOptions {
+            interpolate_gaps: true,
+            ..RefineryOptions::default()
+        },
+    )
+    .unwrap()
+    .refined_iter(0, false, None)
+    .is_err());
+}
+
+#[test]
+fn test_strand_normalization() {
+    assert_eq!(normalize_strand(Some("+")).unwrap(), Some(Strand::Positive));
+    assert_eq!(normalize_strand(Some("-")).unwrap(), Some(Strand::Negative));
+    assert_eq!(normalize_strand(Some(".")).unwrap(), None);
+    assert_eq!(normalize_strand(Some("")).unwrap(), None);
+    assert_eq!(normalize_strand(None).unwrap(), None);
+    assert!(normalize_strand(Some("plus")).is_err());
+
+    let track_path = create_temp_bed(
+        "track name=strands\n\
+        chr1 0 10 id0 1 +\n\
+        chr1 0 10 id1 1 -\n\
+        chr1 0 10 id2 1 .\n\
+        chr1 0 10 id3 1\n\
+        chr1 0 10 id4 1 x\n",
+    )
+    .unwrap();
+    let read_options = ReadOptions::default();
+    let mut strands: Vec<Result<Option<Strand>, Error>> =
+        TrackLineIter::<f64>::new(
+            track_path.to_str().unwrap(),
+            TrackFormat::Bed,
+            false,
+            &read_options,
+        )
+        .unwrap()
+        .map(|line| line.unwrap().strand())
+        .collect();
+    let valid_strands: Vec<Option<Strand>> =
+        strands.drain(..4).map(Result::unwrap).collect();
+    assert_eq!(valid_strands, vec![
+        Some(Strand::Positive),
+        Some(Strand::Negative),
+        None,
+        None
+    ]);
+    assert!(strands[0].is_err());
+
+    // the malformed strand is a duplicate of the unknown strands
+    let refinery = BedRefinery::<f64>::with_options(
+        track_path.to_str().unwrap(),
+        RefineryOptions {
+            unique: true,
+            ..RefineryOptions::default()
+        },
+    )
+    .unwrap();
+    assert_eq!(refinery.stats().num_duplicate_lines, Some(2));
+    assert_eq!(refinery.stats().num_invalid_strand_lines, 1);
+
+    // the strands are not read otherwise
+    let refinery = BedRefinery::<f64>::with_options(
+        track_path.to_str().unwrap(),
+        RefineryOptions::default(),
+    )
+    .unwrap();
+    assert_eq!(refinery.stats().num_invalid_strand_lines, 0);
+}
+
+#[test]
+fn test_bed12() {
+    let bed_path = create_temp_bed(
+        "chr1 0 10 id0 2\n\
+        chr1 20 25 id1 3\n",
+    )
+    .unwrap();
+    let refinery = BedRefinery::<f64>::with_options(
+        bed_path.to_str().unwrap(),
+        RefineryOptions {
+            bed12: true,
+            ..RefineryOptions::default()
+        },
+    )
+    .unwrap();
+    let out_temp_path = NamedTempFile::new().unwrap().into_temp_path();
+    let out_path = out_temp_path.to_str().unwrap();
+    refinery
+        .write_refined_bed(out_path, 0, false, None, false)
+        .unwrap();
+
+    let lines: Vec<Vec<String>> = std::fs::read_to_string(out_path)
+        .unwrap()
+        .lines()
+        .map(|line| line.split('\t').map(|f| f.to_string()).collect())
+        .collect();
+    assert_eq!(lines, vec![
+        vec![
+            "chr1", "0", "10", ".", "2", ".", "0", "10", "0,0,0", "1", "10,",
+            "0,"
+        ],
+        vec![
+            "chr1", "20", "25", ".", "3", ".", "20", "25", "0,0,0", "1", "5,",
+            "0,"
+        ],
+    ]);
+
+    // the block sizes follow the written coordinates with inclusive ends
+    let inclusive_refinery = BedRefinery::<f64>::with_options(
+        bed_path.to_str().unwrap(),
+        RefineryOptions {
+            bed12: true,
+            end_convention: EndConvention::Inclusive,
+            ..RefineryOptions::default()
+        },
+    )
+    .unwrap();
+    inclusive_refinery
+        .write_refined_bed(out_path, 0, false, None, false)
+        .unwrap();
+    assert_eq!(
+        std::fs::read_to_string(out_path).unwrap(),
+        "chr1\t0\t9\t.\t2\t.\t0\t9\t0,0,0\t1\t9,\t0,\n\
+        chr1\t20\t24\t.\t3\t.\t20\t24\t0,0,0\t1\t4,\t0,\n"
+    );
+
+    assert!(refinery
+        .write_refined_bed(out_path, 0, false, None, true)
+        .is_err());
+}
+
+#[test]
+fn test_max_lines() {
+    let bed_path = create_temp_bed(
+        "chr1 0 10 id0 2\n\
+        chr1 5 15 id1 3\n\
+        chr1 10 20 id2 4\n\
+        chr2 0 10 id3 5\n",
+    )
+    .unwrap();
+    let refine = |max_lines: Option<usize>| {
+        let refinery = BedRefinery::<f64>::with_options(
+            bed_path.to_str().unwrap(),
+            RefineryOptions {
+                read_options: ReadOptions {
+                    max_lines,
+                    ..ReadOptions::default()
+                },
+                ..RefineryOptions::default()
+            },
+        )
+        .unwrap();
+        let out_temp_path = NamedTempFile::new().unwrap().into_temp_path();
+        refinery
+            .write_refined_bed(
+                out_temp_path.to_str().unwrap(),
+                0,
+                false,
+                None,
+                true,
+            )
+            .unwrap();
+        out_temp_path
+    };
+
+    // only the first two lines contribute
+    let out_path = refine(Some(2));
+    assert_track_eq!(out_path.to_str().unwrap(), vec![
+        ("chr1", 0, 5, 2.),
+        ("chr1", 5, 10, 5.),
+        ("chr1", 10, 15, 3.)
+    ]);
+
+    let out_path = refine(None);
+    assert_track_eq!(out_path.to_str().unwrap(), vec![
+        ("chr1", 0, 5, 2.),
+        ("chr1", 5, 10, 5.),
+        ("chr1", 10, 15, 7.),
+        ("chr1", 15, 20, 4.),
+        ("chr2", 0, 10, 5.),
+    ]);
+
+    // the same limit applies when reading an interval map
+    let track = TrackVariant::Bed(Bed::new(bed_path.to_str().unwrap(), false));
+    let chrom_interval_map =
+        get_chrom_interval_map_with_options(&track, None, &ReadOptions {
+            max_lines: Some(3),
+            ..ReadOptions::default()
+        })
+        .unwrap();
+    assert_eq!(chrom_interval_map.len(), 1);
+    assert!(chrom_interval_map.contains_key("chr1"));
+
+    // the lines beyond the limit are not read, so a malformed line there is
+    // not an error, while the comment lines do not count towards the limit
+    let malformed_path = create_temp_bed(
+        "# comment\n\
+        chr1 0 10 id0 2\n\
+        chr1 5 15 id1 3\n\
+        chr1 1e5 2e5 id2 4\n",
+    )
+    .unwrap();
+    let malformed =
+        TrackVariant::Bed(Bed::new(malformed_path.to_str().unwrap(), false));
+    let read_options = ReadOptions {
+        max_lines: Some(2),
+        ..ReadOptions::default()
+    };
+    assert!(get_chrom_interval_map_with_options(
+        &malformed,
+        None,
+        &read_options
+    )
+    .is_ok());
+    assert!(BedRefinery::<f64>::with_options(
+        malformed_path.to_str().unwrap(),
+        RefineryOptions {
+            read_options,
+            ..RefineryOptions::default()
+        },
+    )
+    .is_ok());
+    assert!(get_chrom_interval_map_with_options(
+        &malformed,
+        None,
+        &ReadOptions::default()
+    )
+    .is_err());
+}
+
+#[test]
+fn test_idempotent_refinement() {
+    let bed_path = create_temp_bed(
+        "chr1 0 10 id0 2\n\
+        chr1 5 17 id1 3\n\
+        chr1 30 42 id2 1.5\n\
+        chr2 3 8 id3 4\n",
+    )
+    .unwrap();
+    let refinery = BedRefinery::<f64>::with_options(
+        bed_path.to_str().unwrap(),
+        RefineryOptions::default(),
+    )
+    .unwrap();
+    for &bin_size in [0, 1, 7, 10].iter() {
+        for &out_bedgraph in [false, true].iter() {
+            let out_temp_path = NamedTempFile::new().unwrap().into_temp_path();
+            let out_path = out_temp_path.to_str().unwrap();
+            refinery
+                .write_refined_bed(
+                    out_path,
+                    bin_size,
+                    false,
+                    None,
+                    out_bedgraph,
+                )
+                .unwrap();
+            assert!(
+                verify_idempotent(out_path, bin_size, 1e-8).is_ok(),
+                "bin size {}, bedGraph {}",
+                bin_size,
+                out_bedgraph
+            );
+        }
+    }
+
+    // an interval covering half of a bin is averaged over the whole bin
+    let unbinned_path = create_temp_bed("chr1 0 5 id0 2\n").unwrap();
+    let unbinned = unbinned_path.to_str().unwrap();
+    assert!(verify_idempotent(unbinned, 0, 1e-8).is_ok());
+    assert!(verify_idempotent(unbinned, 10, 1e-8).is_err());
+}
+
+#[test]
+fn test_center_of_mass() {
+    let bed_path = create_temp_bed(
+        "chr1 0 10 id0 1\n\
+        chr1 990 1000 id1 100\n\
+        chr2 0 10 id2 2\n\
+        chr3 0 10 id3 0\n",
+    )
+    .unwrap();
+    let refinery = BedRefinery::<f64>::with_options(
+        bed_path.to_str().unwrap(),
+        RefineryOptions::default(),
+    )
+    .unwrap();
+    let center_of_mass = refinery.center_of_mass();
+
+    // most of the signal is concentrated at the end of chr1
+    let (mean, _) = center_of_mass["chr1"];
+    assert_almost_eq!(mean, (10. * 4.5 + 1000. * 994.5) / 1010.);
+    assert!(mean > 950.);
+
+    // the positions 0, 1, ..., 9 weighted uniformly
+    let (mean, std) = center_of_mass["chr2"];
+    assert_almost_eq!(mean, 4.5);
+    assert_almost_eq!(std, (99f64 / 12.).sqrt());
+
+    // chr3 has no signal
+    assert_eq!(center_of_mass.len(), 2);
+}
+
+#[test]
+fn test_missing_score() {
+    let track_path = create_temp_bed(
+        "chr1\t0\t10\tid0\t1\n\
+        chr1\t20\t30\tid1\t.\n\
+        chr1\t40\t50\tid2\t\t+\n\
+        chr1\t60\t70\tid3\t4\n",
+    )
+    .unwrap();
+    let read_track = |missing_score| {
+        BedRefinery::<f64>::with_options(
+            track_path.to_str().unwrap(),
+            RefineryOptions {
+                read_options: ReadOptions {
+                    missing_score,
+                    ..ReadOptions::default()
+                },
+                ..RefineryOptions::default()
+            },
+        )
+        .unwrap()
+    };
+    let get_intervals = |refinery: &BedRefinery<f64>| -> Vec<(i64, i64, f64)> {
+        refinery.get_chrom_to_interval_map()["chr1"]
+            .iter()
+            .map(|(interval, &value)| {
+                (interval.get_start(), interval.get_end(), value)
+            })
+            .collect()
+    };
+
+    let zero_refinery = read_track(MissingScore::Zero);
+    assert_eq!(zero_refinery.stats().num_missing_score_lines, 2);
+    assert_eq!(zero_refinery.stats().num_lines, 4);
+    assert_eq!(get_intervals(&zero_refinery), vec![
+        (0, 9, 1.),
+        (20, 29, 0.),
+        (40, 49, 0.),
+        (60, 69, 4.)
+    ]);
+
+    let skip_refinery = read_track(MissingScore::Skip);
+    assert_eq!(skip_refinery.stats().num_missing_score_lines, 2);
+    assert_eq!(skip_refinery.stats().num_lines, 2);
+    assert_eq!(get_intervals(&skip_refinery), vec![
+        (0, 9, 1.),
+        (60, 69, 4.)
+    ]);
+
+    // the interval maps read the missing values in the same way
+    let track =
+        TrackVariant::Bed(Bed::new(track_path.to_str().unwrap(), false));
+    for (missing_score, expected) in vec![
+        (MissingScore::Zero, vec![
+            (0, 9, 1.),
+            (20, 29, 0.),
+            (40, 49, 0.),
+            (60, 69, 4.),
+        ]),
+        (MissingScore::Skip, vec![(0, 9, 1.), (60, 69, 4.)]),
+    ] {
+        let chrom_interval_map =
+            get_chrom_interval_map_with_options(&track, None, &ReadOptions {
+                missing_score,
+                ..ReadOptions::default()
+            })
+            .unwrap();
+        assert_eq!(
+            chrom_interval_map["chr1"]
+                .iter()
+                .map(|(interval, &value)| {
+                    (interval.get_start(), interval.get_end(), value)
+                })
+                .collect::<Vec<(i64, i64, f64)>>(),
+            expected
+        );
+    }
+}
+
+#[test]
+fn test_field_separators() {
+    let space_separated = "chr1 0 10 id0 1 + 2\n\
+        chr1 5 20 id1 3 - 1\n\
+        chr2 0 10 id2 4 . 3\n";
+    let tab_separated = space_separated.replace(' ', "\t");
+    // mixes tabs and runs of spaces on the same lines
+    let mixed_separated = "chr1\t0 10\tid0  1\t+ 2\n\
+        chr1 5\t20\tid1 3\t-\t1\n\
+        chr2  0\t10 id2\t4 .\t3\n";
+
+    let read_track = |content: &str| {
+        let path = create_temp_bed(content).unwrap();
+        let refinery = BedRefinery::<f64>::with_options(
+            path.to_str().unwrap(),
+            RefineryOptions {
+                strand_extend_bp: Some(5),
+                weight_column: Some(7),
+                ..RefineryOptions::default()
+            },
+        )
+        .unwrap();
+        let mut intervals: Vec<(String, i64, i64, f64)> = refinery
+            .get_chrom_to_interval_map()
+            .iter()
+            .flat_map(|(chrom, interval_map)| {
+                interval_map
+                    .iter()
+                    .map(|(interval, &value)| {
+                        (
+                            chrom.clone(),
+                            interval.get_start(),
+                            interval.get_end(),
+                            value,
+                        )
+                    })
+                    .collect::<Vec<(String, i64, i64, f64)>>()
+            })
+            .collect();
+        intervals.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        intervals
+    };
+    let expected = read_track(space_separated);
+    assert!(!expected.is_empty());
+    assert_eq!(read_track(&tab_separated), expected);
+    assert_eq!(read_track(mixed_separated), expected);
+
+    // an empty field between two tabs keeps the later columns aligned
+    assert_eq!(split_bed_fields("chr1\t0\t10\tid0\t\t-"), vec![
+        "chr1", "0", "10", "id0", "", "-"
+    ]);
+    assert_eq!(split_bed_fields("chr1 0  10\tid0 1"), vec![
+        "chr1", "0", "10", "id0", "1"
+    ]);
+    let path = create_temp_bed("chr1\t0\t10\tid0\t\t-\n").unwrap();
+    let read_options = ReadOptions::default();
+    let line = TrackLineIter::<f64>::new(
+        path.to_str().unwrap(),
+        TrackFormat::Bed,
+        false,
+        &read_options,
+    )
+    .unwrap()
+    .next()
+    .unwrap()
+    .unwrap();
+    assert_eq!(line.strand().unwrap(), Some(Strand::Negative));
+}
+
+#[test]
+fn test_approx_quantiles() {
+    let n = 10000;
+    // a permutation of 0, 1, ..., n - 1 since 7919 is coprime with n
+    let values: Vec<f64> = (0..n).map(|i| ((i * 7919) % n) as f64).collect();
+    let mut sorted = values.clone();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+    let epsilon = 0.01;
+    let mut sketch = ApproxQuantiles::new(epsilon).unwrap();
+    for &v in values.iter() {
+        sketch.add(v);
+    }
+    sketch.add(std::f64::NAN);
+    assert_eq!(sketch.count(), n);
+    assert!(sketch.size() < n / 10);
+    for &p in [0., 0.1, 0.5, 0.9, 0.99, 1.].iter() {
+        // the nearest rank
+        let exact = sorted[((p * n as f64).ceil() as usize).max(1) - 1];
+        let approx = sketch.quantile(p);
+        assert!(
+            (approx - exact).abs() <= epsilon * n as f64 + 1.,
+            "p: {}, approx: {}, exact: {}",
+            p,
+            approx,
+            exact
+        );
+    }
+    assert_eq!(sketch.quantile(0.), 0.);
+    assert_eq!(sketch.quantile(1.), (n - 1) as f64);
+
+    assert!(ApproxQuantiles::new(0.01).unwrap().quantile(0.5).is_nan());
+    assert!(ApproxQuantiles::new(0.).is_err());
+    assert!(ApproxQuantiles::new(1.).is_err());
+}
+
+#[test]
+fn test_error_variants() {
+    let missing_path = manifest_path_join("tests/data/no_such_track.bed");
+    match BedRefinery::<f64>::with_options(
+        missing_path.to_str().unwrap(),
+        RefineryOptions::default(),
+    ) {
+        Err(Error::Io(_)) => {}
+        other => panic!("expected an I/O error, got {:?}", other.err()),
+    }
+
+    let track_path =
+        create_temp_bed("chr1 0 10 id 1\nchr1 x 20 id 2\n").unwrap();
+    match get_chrom_interval_map(
+        &TrackVariant::Bed(Bed::new(track_path.to_str().unwrap(), false)),
+        None,
+    ) {
+        Err(Error::Parse(_)) => {}
+        other => panic!("expected a parse error, got {:?}", other.err()),
     }
 }
 
 // TODO: test with different bin sizes
 // TODO: test with scaling and normalize
-// TODO: test exclude
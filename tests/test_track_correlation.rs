@@ -1,9 +1,32 @@
 use biofile::{bed::Bed, util::TrackVariant};
 use biostats::{
-    assert_vec_almost_eq, track_correlation::ValueTransform,
-    util::manifest_path_join,
+    assert_almost_eq, assert_vec_almost_eq,
+    error::Error,
+    test_util::create_temp_bed,
+    track_correlation::{
+        collect_correlation_matrix, compute_stranded_correlations,
+        compute_track_correlations_streaming_with_options,
+        compute_track_correlations_with_options,
+        compute_track_correlations_with_stats, compute_track_regressions,
+        correlate_against_matrix, correlation_p_value, effective_sample_size,
+        find_stable_bin_size, phi_coefficient, read_overall_correlations,
+        write_correlation_contribution_track, write_correlations_to_dir,
+        BinWeight, CorrelationOptions, CoveragePredicate, DifferenceOrder,
+        PValueMode, QuantileBinning, StreamingOptions, ValueTransform,
+        WeightedMoments, ZeroFillMode,
+    },
+    track_zipper::TrackZipper,
+    util::{
+        get_chrom_interval_map, get_common_refined_binned_iter,
+        manifest_path_join, ChromAlias, ReadOptions, UnknownStrand,
+    },
 };
-use std::collections::HashSet;
+use math::{set::traits::Finite, stats::correlation::weighted_correlation};
+use std::{
+    collections::{HashMap, HashSet},
+    process::Command,
+};
+use tempfile::NamedTempFile;
 
 #[test]
 fn test_identical_tracks() {
@@ -149,3 +172,1672 @@ fn test_two_chroms() {
         0.6687843872007803
     ]);
 }
+
+#[test]
+fn test_streaming_matches_in_memory() {
+    let get_track = |filename: &str| {
+        TrackVariant::Bed(Bed::new(
+            manifest_path_join(filename).to_str().unwrap(),
+            false,
+        ))
+    };
+    let bin_sizes = vec![0, 1, 2, 5];
+    for (first, second) in vec![
+        ("tests/test_1.bed", "tests/test_2.bed"),
+        ("tests/test_5.bed", "tests/test_6.bed"),
+    ] {
+        let (mut chrom_correlations, overall_correlations) =
+            biostats::track_correlation::compute_track_correlations(
+                &get_track(first),
+                &get_track(second),
+                &bin_sizes,
+                None,
+                ValueTransform::Identity,
+                None,
+                None,
+            )
+            .unwrap();
+
+        let (streamed_chrom_correlations, streamed_overall_correlations) =
+            biostats::track_correlation::compute_track_correlations_streaming(
+                &get_track(first),
+                &get_track(second),
+                &bin_sizes,
+                None,
+                ValueTransform::Identity,
+                None,
+            )
+            .unwrap();
+
+        chrom_correlations.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(chrom_correlations.len(), streamed_chrom_correlations.len());
+        for ((chrom, correlations), (streamed_chrom, streamed)) in
+            chrom_correlations
+                .iter()
+                .zip(streamed_chrom_correlations.iter())
+        {
+            assert_eq!(chrom, streamed_chrom);
+            assert_vec_almost_eq!(correlations, streamed);
+        }
+        assert_vec_almost_eq!(
+            overall_correlations,
+            streamed_overall_correlations
+        );
+    }
+}
+
+#[test]
+fn test_streaming_rejects_unsorted_input() {
+    // the intervals in test_3.bed are not sorted by the start coordinates
+    let first_track = TrackVariant::Bed(Bed::new(
+        manifest_path_join("tests/test_3.bed").to_str().unwrap(),
+        false,
+    ));
+    let second_track = TrackVariant::Bed(Bed::new(
+        manifest_path_join("tests/test_4.bed").to_str().unwrap(),
+        false,
+    ));
+    match biostats::track_correlation::compute_track_correlations_streaming(
+        &first_track,
+        &second_track,
+        &vec![0],
+        None,
+        ValueTransform::Identity,
+        None,
+    ) {
+        Err(Error::UnsortedInput(_)) => {}
+        result => panic!("expected an UnsortedInput error, got {:?}", result),
+    }
+}
+
+#[test]
+fn test_windowed_local_correlation() {
+    // the two tracks agree on [0, 100) and disagree on [100, 200)
+    let mut content_a = String::new();
+    let mut content_b = String::new();
+    for i in 0..10 {
+        content_a.push_str(&format!(
+            "chr1 {} {} a {}\n",
+            i * 10,
+            i * 10 + 10,
+            i
+        ));
+        content_b.push_str(&format!(
+            "chr1 {} {} b {}\n",
+            i * 10,
+            i * 10 + 10,
+            i
+        ));
+    }
+    for i in 0..10 {
+        content_a.push_str(&format!(
+            "chr1 {} {} a {}\n",
+            100 + i * 10,
+            110 + i * 10,
+            i
+        ));
+        content_b.push_str(&format!(
+            "chr1 {} {} b {}\n",
+            100 + i * 10,
+            110 + i * 10,
+            10 - i
+        ));
+    }
+    let path_a = create_temp_bed(&content_a).unwrap();
+    let path_b = create_temp_bed(&content_b).unwrap();
+
+    let local_correlations =
+        biostats::track_correlation::compute_windowed_local_correlation(
+            &TrackVariant::Bed(Bed::new(path_a.to_str().unwrap(), false)),
+            &TrackVariant::Bed(Bed::new(path_b.to_str().unwrap(), false)),
+            100,
+            100,
+            10,
+        )
+        .unwrap();
+
+    assert_eq!(local_correlations.len(), 2);
+    assert_eq!(local_correlations[0].0, "chr1");
+    assert_eq!((local_correlations[0].1, local_correlations[0].2), (0, 100));
+    assert_almost_eq!(local_correlations[0].3, 1.);
+    assert_eq!(
+        (local_correlations[1].1, local_correlations[1].2),
+        (100, 200)
+    );
+    assert_almost_eq!(local_correlations[1].3, -1.);
+
+    // windows spanning only two bins are too small to be correlated
+    let small_windows =
+        biostats::track_correlation::compute_windowed_local_correlation(
+            &TrackVariant::Bed(Bed::new(path_a.to_str().unwrap(), false)),
+            &TrackVariant::Bed(Bed::new(path_b.to_str().unwrap(), false)),
+            20,
+            100,
+            10,
+        )
+        .unwrap();
+    assert!(small_windows.iter().all(|(.., r)| r.is_nan()));
+}
+
+#[test]
+fn test_binary_fast_path() {
+    let gapped_path =
+        create_temp_bed("chr1 5 12 id1 1\nchr1 16 30 id2 1\n").unwrap();
+    let gapped_track =
+        TrackVariant::Bed(Bed::new(gapped_path.to_str().unwrap(), true));
+    let gapped_map = get_chrom_interval_map(&gapped_track, None).unwrap();
+
+    for path in vec!["tests/test_1.bed", "tests/test_2.bed"] {
+        let track = TrackVariant::Bed(Bed::new(
+            manifest_path_join(path).to_str().unwrap(),
+            true,
+        ));
+        let map = get_chrom_interval_map(&track, None).unwrap();
+
+        // overlapping lines in a binarized track can add up to more than 1
+        let items: Vec<(f64, f64, f64)> = get_common_refined_binned_iter(
+            &map["chr1"],
+            &gapped_map["chr1"],
+            1,
+        )
+        .map(|(interval, v)| {
+            (
+                v[0].unwrap_or(0.).min(1.),
+                v[1].unwrap_or(0.).min(1.),
+                interval.size() as f64,
+            )
+        })
+        .collect();
+        let general =
+            weighted_correlation(|| items.iter(), |&(x, y, w)| (x, y, w));
+        assert!(!general.is_nan());
+        assert_almost_eq!(
+            phi_coefficient(items.iter().cloned()).unwrap(),
+            general
+        );
+
+        // thresholding at 1 makes the values binary so that
+        // compute_track_correlations takes the fast path
+        let (chrom_correlations, overall_correlations) =
+            biostats::track_correlation::compute_track_correlations(
+                &track,
+                &gapped_track,
+                &vec![1],
+                None,
+                ValueTransform::Thresholding(1.),
+                None,
+                None,
+            )
+            .unwrap();
+        assert_almost_eq!(chrom_correlations[0].1[0], general);
+        assert_almost_eq!(overall_correlations[0], general);
+    }
+
+    assert!(phi_coefficient(vec![(0.5, 1., 1.)].into_iter()).is_none());
+}
+
+#[test]
+fn test_cross_resolution_correlations() {
+    let bed_path = create_temp_bed(
+        "chr1 0 10 id1 5\n\
+        chr1 10 20 id2 1\n\
+        chr1 20 30 id3 8\n\
+        chr1 30 40 id4 2\n\
+        chr1 40 50 id5 7\n\
+        chr1 50 60 id6 3\n\
+        chr1 100 110 id7 9\n\
+        chr1 110 120 id8 4\n\
+        chr1 130 140 id9 6\n\
+        chr1 170 180 id10 2\n",
+    )
+    .unwrap();
+    let track = TrackVariant::Bed(Bed::new(bed_path.to_str().unwrap(), false));
+    let correlations =
+        biostats::track_correlation::compute_cross_resolution_correlations(
+            &track,
+            &track,
+            &[10, 100],
+            &[10, 100],
+        )
+        .unwrap();
+    assert_eq!(correlations.len(), 2);
+    assert!(correlations.iter().all(|row| row.len() == 2));
+
+    // matched resolutions
+    assert_almost_eq!(correlations[0][0], 1.);
+    assert_almost_eq!(correlations[1][1], 1.);
+
+    // mismatched resolutions
+    assert!(correlations[0][1] < correlations[0][0]);
+    assert!(correlations[1][0] < correlations[1][1]);
+    assert_almost_eq!(correlations[0][1], correlations[1][0]);
+
+    assert!(
+        biostats::track_correlation::compute_cross_resolution_correlations(
+            &track,
+            &track,
+            &[0],
+            &[10],
+        )
+        .is_err()
+    );
+}
+
+#[test]
+fn test_single_chrom_correlation() {
+    let first_track = TrackVariant::Bed(Bed::new(
+        manifest_path_join("tests/test_3.bed").to_str().unwrap(),
+        false,
+    ));
+    let second_bed_path = create_temp_bed(
+        "chr1 0 8 id1 2\n\
+        chr1 8 20 id2 5\n\
+        chr1 22 30 id3 11\n\
+        chr2 0 100 id4 7\n\
+        chr2 100 150 id5 1\n",
+    )
+    .unwrap();
+    let second_track =
+        TrackVariant::Bed(Bed::new(second_bed_path.to_str().unwrap(), false));
+    let bin_sizes = vec![0, 1, 5, 7];
+
+    let (chrom_correlations, _) =
+        biostats::track_correlation::compute_track_correlations(
+            &first_track,
+            &second_track,
+            &bin_sizes,
+            None,
+            ValueTransform::Identity,
+            None,
+            None,
+        )
+        .unwrap();
+    let chr1_correlations = &chrom_correlations
+        .iter()
+        .find(|(chrom, _)| chrom == "chr1")
+        .unwrap()
+        .1;
+
+    let single_chrom_correlations =
+        biostats::track_correlation::compute_single_chrom_correlation(
+            &first_track,
+            &second_track,
+            "chr1",
+            &bin_sizes,
+            ValueTransform::Identity,
+            None,
+        )
+        .unwrap();
+    assert_vec_almost_eq!(single_chrom_correlations, chr1_correlations);
+}
+
+#[test]
+fn test_chrom_alias() {
+    let ucsc_path = create_temp_bed(
+        "chr1 0 10 id1 3\n\
+        chr1 10 20 id2 7\n\
+        chr1 25 30 id3 1\n\
+        chrM 0 5 id4 2\n\
+        chrM 5 10 id5 4\n",
+    )
+    .unwrap();
+    let ensembl_path = create_temp_bed(
+        "1 0 10 id1 3\n\
+        1 10 20 id2 7\n\
+        1 25 30 id3 1\n\
+        MT 0 5 id4 2\n\
+        MT 5 10 id5 4\n",
+    )
+    .unwrap();
+    let ucsc_track =
+        TrackVariant::Bed(Bed::new(ucsc_path.to_str().unwrap(), false));
+    let ensembl_track =
+        TrackVariant::Bed(Bed::new(ensembl_path.to_str().unwrap(), false));
+
+    let aliases: HashMap<String, String> = vec![
+        ("1".to_string(), "chr1".to_string()),
+        ("MT".into(), "chrM".into()),
+    ]
+    .into_iter()
+    .collect();
+    for chrom_alias in vec![ChromAlias::Ucsc, ChromAlias::Map(aliases)] {
+        let (chrom_correlations, overall_correlations) =
+            compute_track_correlations_with_options(
+                &ucsc_track,
+                &ensembl_track,
+                &vec![0, 5],
+                &CorrelationOptions {
+                    read_options: ReadOptions {
+                        chrom_alias,
+                        ..ReadOptions::default()
+                    },
+                    ..CorrelationOptions::default()
+                },
+            )
+            .unwrap();
+        let mut chroms: Vec<&str> = chrom_correlations
+            .iter()
+            .map(|(chrom, _)| chrom.as_str())
+            .collect();
+        chroms.sort();
+        assert_eq!(chroms, vec!["chr1", "chrM"]);
+        for (_, correlations) in chrom_correlations.iter() {
+            assert_vec_almost_eq!(correlations, vec![1., 1.]);
+        }
+        assert_vec_almost_eq!(overall_correlations, vec![1., 1.]);
+    }
+
+    // the chromosomes of the exclude track are aliased in the same way
+    let exclude_path = create_temp_bed("MT 0 10 id1 1\n").unwrap();
+    let (chrom_correlations, _) = compute_track_correlations_with_options(
+        &ucsc_track,
+        &ensembl_track,
+        &vec![0],
+        &CorrelationOptions {
+            read_options: ReadOptions {
+                chrom_alias: ChromAlias::Ucsc,
+                ..ReadOptions::default()
+            },
+            exclude_track_filepath: Some(
+                exclude_path.to_str().unwrap().to_string(),
+            ),
+            ..CorrelationOptions::default()
+        },
+    )
+    .unwrap();
+    assert_eq!(
+        chrom_correlations
+            .iter()
+            .map(|(chrom, _)| chrom.as_str())
+            .collect::<Vec<&str>>(),
+        vec!["chr1"]
+    );
+
+    // without aliasing, the chromosomes are disjoint
+    let (chrom_correlations, _) =
+        biostats::track_correlation::compute_track_correlations(
+            &ucsc_track,
+            &ensembl_track,
+            &vec![0],
+            None,
+            ValueTransform::Identity,
+            None,
+            None,
+        )
+        .unwrap();
+    assert_eq!(chrom_correlations.len(), 4);
+}
+
+#[test]
+fn test_correlation_contribution_track() {
+    let path_a = create_temp_bed(
+        "chr1 0 10 id1 3\n\
+        chr1 10 20 id2 7\n\
+        chr1 25 35 id3 1\n\
+        chr2 0 20 id4 5\n\
+        chr2 40 45 id5 2\n",
+    )
+    .unwrap();
+    let path_b = create_temp_bed(
+        "chr1 0 15 id1 2\n\
+        chr1 15 20 id2 9\n\
+        chr1 30 40 id3 4\n\
+        chr2 5 25 id4 6\n\
+        chr3 0 10 id5 1\n",
+    )
+    .unwrap();
+    let track_a = TrackVariant::Bed(Bed::new(path_a.to_str().unwrap(), false));
+    let track_b = TrackVariant::Bed(Bed::new(path_b.to_str().unwrap(), false));
+    let bin_size = 5;
+    let out_file = NamedTempFile::new().unwrap();
+    write_correlation_contribution_track(
+        &track_a,
+        &track_b,
+        bin_size,
+        out_file.path().to_str().unwrap(),
+    )
+    .unwrap();
+
+    // the size-weighted average of the contributions is the correlation
+    let mut weighted_sum = 0.;
+    let mut total_size = 0.;
+    for line in std::fs::read_to_string(out_file.path()).unwrap().lines() {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        let start: i64 = tokens[1].parse().unwrap();
+        let end_exclusive: i64 = tokens[2].parse().unwrap();
+        let contribution: f64 = tokens[3].parse().unwrap();
+        weighted_sum += contribution * (end_exclusive - start) as f64;
+        total_size += (end_exclusive - start) as f64;
+    }
+    let (_, overall_correlations) =
+        biostats::track_correlation::compute_track_correlations(
+            &track_a,
+            &track_b,
+            &vec![bin_size],
+            None,
+            ValueTransform::Identity,
+            None,
+            None,
+        )
+        .unwrap();
+    assert_almost_eq!(weighted_sum / total_size, overall_correlations[0], 1e-6);
+}
+
+#[test]
+fn test_quiet_correlations() {
+    let run = |extra_args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_compute_track_correlation"))
+            .arg("--first")
+            .arg(manifest_path_join("tests/test_1.bed"))
+            .arg("--second")
+            .arg(manifest_path_join("tests/test_2.bed"))
+            .args(&["--bin", "0", "3"])
+            .args(extra_args)
+            .output()
+            .unwrap()
+    };
+    let verbose_output = run(&[]);
+    let quiet_output = run(&["--quiet"]);
+    assert!(verbose_output.status.success());
+    assert!(quiet_output.status.success());
+    assert!(!verbose_output.stderr.is_empty());
+    assert!(
+        quiet_output.stderr.is_empty(),
+        "unexpected stderr: {}",
+        String::from_utf8_lossy(&quiet_output.stderr)
+    );
+    assert_eq!(verbose_output.stdout, quiet_output.stdout);
+}
+
+#[test]
+fn test_first_difference_correlations() {
+    // the second track has the same shape as the first one but is shifted
+    // upward on chr2
+    let path_a = create_temp_bed(
+        "chr1 0 10 id1 1\n\
+        chr1 10 20 id2 5\n\
+        chr1 20 30 id3 2\n\
+        chr1 30 40 id4 4\n\
+        chr2 0 10 id5 3\n\
+        chr2 10 20 id6 1\n\
+        chr2 20 30 id7 6\n",
+    )
+    .unwrap();
+    let path_b = create_temp_bed(
+        "chr1 0 10 id1 1\n\
+        chr1 10 20 id2 5\n\
+        chr1 20 30 id3 2\n\
+        chr1 30 40 id4 4\n\
+        chr2 0 10 id5 53\n\
+        chr2 10 20 id6 51\n\
+        chr2 20 30 id7 56\n",
+    )
+    .unwrap();
+    let track_a = TrackVariant::Bed(Bed::new(path_a.to_str().unwrap(), false));
+    let track_b = TrackVariant::Bed(Bed::new(path_b.to_str().unwrap(), false));
+    let get_overall_correlations = |difference_order| {
+        compute_track_correlations_with_options(
+            &track_a,
+            &track_b,
+            &vec![0, 10],
+            &CorrelationOptions {
+                difference_order,
+                ..CorrelationOptions::default()
+            },
+        )
+        .unwrap()
+        .1
+    };
+
+    let level_correlations = get_overall_correlations(DifferenceOrder::None);
+    assert!(level_correlations.iter().all(|&c| c < 0.9));
+
+    let difference_correlations =
+        get_overall_correlations(DifferenceOrder::First);
+    assert_vec_almost_eq!(difference_correlations, vec![1., 1.]);
+}
+
+#[test]
+fn test_bin_weight() {
+    // a giant interval followed by two single base pairs
+    let path_a = create_temp_bed(
+        "chr1 0 1000 id1 1\n\
+        chr1 1000 1001 id2 2\n\
+        chr1 1001 1002 id3 3\n",
+    )
+    .unwrap();
+    let path_b = create_temp_bed(
+        "chr1 0 1000 id1 2\n\
+        chr1 1000 1001 id2 6\n\
+        chr1 1001 1002 id3 1\n",
+    )
+    .unwrap();
+    let track_a = TrackVariant::Bed(Bed::new(path_a.to_str().unwrap(), false));
+    let track_b = TrackVariant::Bed(Bed::new(path_b.to_str().unwrap(), false));
+    let get_correlation = |bin_weight| {
+        let (chrom_correlations, overall_correlations) =
+            compute_track_correlations_with_options(
+                &track_a,
+                &track_b,
+                &vec![0],
+                &CorrelationOptions {
+                    bin_weight,
+                    ..CorrelationOptions::default()
+                },
+            )
+            .unwrap();
+        assert_eq!(chrom_correlations.len(), 1);
+        assert_almost_eq!(chrom_correlations[0].1[0], overall_correlations[0]);
+        overall_correlations[0]
+    };
+
+    // the giant interval dominates under the Size weighting, whereas every
+    // interval counts equally under the Uniform weighting
+    assert_almost_eq!(get_correlation(BinWeight::Size), 0.2162075783498533);
+    assert_almost_eq!(get_correlation(BinWeight::Uniform), -0.1889822365046136);
+    assert_almost_eq!(get_correlation(BinWeight::SqrtSize), 0.1946766269998622);
+    assert_almost_eq!(
+        get_correlation(BinWeight::Capped(10)),
+        0.1504142093990467
+    );
+}
+
+#[test]
+fn test_quantile_binning() {
+    // the second track follows the first one with a noise of +/- 30 that
+    // alternates between adjacent bins and hence cancels within each decile
+    let mut content_a = String::new();
+    let mut content_b = String::new();
+    for i in 0..100i64 {
+        let noise = if i % 2 == 0 { 30 } else { -30 };
+        content_a.push_str(&format!(
+            "chr1 {} {} id{} {}\n",
+            i * 10,
+            (i + 1) * 10,
+            i,
+            i + 1
+        ));
+        content_b.push_str(&format!(
+            "chr1 {} {} id{} {}\n",
+            i * 10,
+            (i + 1) * 10,
+            i,
+            i + 41 + noise
+        ));
+    }
+    let path_a = create_temp_bed(&content_a).unwrap();
+    let path_b = create_temp_bed(&content_b).unwrap();
+    let track_a = TrackVariant::Bed(Bed::new(path_a.to_str().unwrap(), false));
+    let track_b = TrackVariant::Bed(Bed::new(path_b.to_str().unwrap(), false));
+    let get_correlations = |quantile_binning, approx_quantiles| {
+        let (chrom_correlations, overall_correlations) =
+            compute_track_correlations_with_options(
+                &track_a,
+                &track_b,
+                &vec![10],
+                &CorrelationOptions {
+                    quantile_binning,
+                    approx_quantiles,
+                    ..CorrelationOptions::default()
+                },
+            )
+            .unwrap();
+        assert_eq!(chrom_correlations.len(), 1);
+        (chrom_correlations[0].1[0], overall_correlations[0])
+    };
+
+    let (chrom_correlation, overall_correlation) =
+        get_correlations(QuantileBinning::None, false);
+    assert_almost_eq!(chrom_correlation, 0.6868456530578313);
+    assert_almost_eq!(overall_correlation, 0.6868456530578313);
+
+    for quantile_binning in
+        vec![QuantileBinning::Deciles, QuantileBinning::Custom(5)]
+    {
+        // the approximate quantiles of the 100 distinct bins are exact
+        for approx_quantiles in vec![false, true] {
+            let (chrom_correlation, overall_correlation) =
+                get_correlations(quantile_binning, approx_quantiles);
+            assert_almost_eq!(chrom_correlation, 1.);
+            assert_almost_eq!(overall_correlation, 1.);
+        }
+    }
+}
+
+#[test]
+fn test_zero_fill_mode() {
+    // the second track only covers the first half of the first track, on
+    // which the two tracks agree
+    let mut content_a = String::new();
+    let mut content_b = String::new();
+    for i in 0..10i64 {
+        let line =
+            format!("chr1 {} {} id{} {}\n", i * 10, (i + 1) * 10, i, i + 1);
+        content_a.push_str(&line);
+        if i < 5 {
+            content_b.push_str(&line);
+        }
+    }
+    let path_a = create_temp_bed(&content_a).unwrap();
+    let path_b = create_temp_bed(&content_b).unwrap();
+    let track_a = TrackVariant::Bed(Bed::new(path_a.to_str().unwrap(), false));
+    let track_b = TrackVariant::Bed(Bed::new(path_b.to_str().unwrap(), false));
+    let get_correlations = |zero_fill_mode| {
+        let (chrom_correlations, overall_correlations) =
+            compute_track_correlations_with_options(
+                &track_a,
+                &track_b,
+                &vec![0, 10],
+                &CorrelationOptions {
+                    zero_fill_mode,
+                    ..CorrelationOptions::default()
+                },
+            )
+            .unwrap();
+        assert_eq!(chrom_correlations.len(), 1);
+        (chrom_correlations[0].1.clone(), overall_correlations)
+    };
+
+    let (chrom_correlations, overall_correlations) =
+        get_correlations(ZeroFillMode::WholeChromosome);
+    assert_vec_almost_eq!(chrom_correlations, vec![
+        -0.5310850045437944,
+        -0.5310850045437944
+    ]);
+    assert_vec_almost_eq!(overall_correlations, vec![
+        -0.5310850045437944,
+        -0.5310850045437944
+    ]);
+
+    // the second half is beyond the span of the second track, so it is left
+    // out instead of counting as zero signal in the second track
+    let (chrom_correlations, overall_correlations) =
+        get_correlations(ZeroFillMode::WithinCoveredSpan);
+    assert_vec_almost_eq!(chrom_correlations, vec![1., 1.]);
+    assert_vec_almost_eq!(overall_correlations, vec![1., 1.]);
+}
+
+#[test]
+fn test_streaming_max_buffer_bp() {
+    // the third line starts 5 bp before the second one
+    let path_a = create_temp_bed(
+        "chr1 0 10 a 1\n\
+        chr1 20 30 a 2\n\
+        chr1 15 20 a 3\n\
+        chr1 40 50 a 4\n",
+    )
+    .unwrap();
+    let path_b = create_temp_bed(
+        "chr1 0 10 b 2\n\
+        chr1 15 30 b 3\n\
+        chr1 40 50 b 1\n",
+    )
+    .unwrap();
+    let track_a = TrackVariant::Bed(Bed::new(path_a.to_str().unwrap(), false));
+    let track_b = TrackVariant::Bed(Bed::new(path_b.to_str().unwrap(), false));
+    let bin_sizes = vec![0, 10];
+    let get_streamed_correlations = |max_buffer_bp| {
+        compute_track_correlations_streaming_with_options(
+            &track_a,
+            &track_b,
+            &bin_sizes,
+            &StreamingOptions {
+                verbose: false,
+                max_buffer_bp,
+                ..StreamingOptions::default()
+            },
+        )
+    };
+
+    assert!(get_streamed_correlations(0).is_err());
+    assert!(get_streamed_correlations(4).is_err());
+
+    let (chrom_correlations, overall_correlations) =
+        compute_track_correlations_with_options(
+            &track_a,
+            &track_b,
+            &bin_sizes,
+            &CorrelationOptions {
+                verbose: false,
+                ..CorrelationOptions::default()
+            },
+        )
+        .unwrap();
+    let (streamed_chrom_correlations, streamed_overall_correlations) =
+        get_streamed_correlations(5).unwrap();
+    assert_eq!(streamed_chrom_correlations.len(), 1);
+    assert_eq!(streamed_chrom_correlations[0].0, "chr1");
+    assert_vec_almost_eq!(
+        chrom_correlations[0].1,
+        streamed_chrom_correlations[0].1
+    );
+    assert_vec_almost_eq!(overall_correlations, streamed_overall_correlations);
+}
+
+#[test]
+fn test_streaming_read_options() {
+    // the second line of the first track has a malformed end coordinate, and
+    // the chromosome names of the second track and the exclude track are
+    // aliased into those of the first track
+    let path_a = create_temp_bed(
+        "chr1 0 10 a 1\n\
+        chr1 10 2e1 a 5\n\
+        chr1 20 30 a 3\n\
+        chr1 30 40 a 2\n\
+        chr1 40 50 a 4\n",
+    )
+    .unwrap();
+    let path_b = create_temp_bed(
+        "1 0 10 b 2\n\
+        1 20 30 b 4\n\
+        1 30 40 b 1\n\
+        1 40 50 b 8\n",
+    )
+    .unwrap();
+    let exclude_path = create_temp_bed("1 30 40 x 1\n").unwrap();
+    let track_a = TrackVariant::Bed(Bed::new(path_a.to_str().unwrap(), false));
+    let track_b = TrackVariant::Bed(Bed::new(path_b.to_str().unwrap(), false));
+    let bin_sizes = vec![0, 10];
+    let get_streamed_correlations = |max_bad_lines| {
+        compute_track_correlations_streaming_with_options(
+            &track_a,
+            &track_b,
+            &bin_sizes,
+            &StreamingOptions {
+                exclude_track_filepath: Some(
+                    exclude_path.to_str().unwrap().to_string(),
+                ),
+                read_options: ReadOptions {
+                    chrom_alias: ChromAlias::Ucsc,
+                    max_bad_lines,
+                    ..ReadOptions::default()
+                },
+                verbose: false,
+                ..StreamingOptions::default()
+            },
+        )
+    };
+
+    match get_streamed_correlations(None) {
+        Err(Error::Parse(why)) => assert!(why.contains("line 2")),
+        result => panic!("expected a Parse error, got {:?}", result),
+    }
+
+    let (chrom_correlations, overall_correlations) =
+        compute_track_correlations_with_options(
+            &track_a,
+            &track_b,
+            &bin_sizes,
+            &CorrelationOptions {
+                exclude_track_filepath: Some(
+                    exclude_path.to_str().unwrap().to_string(),
+                ),
+                read_options: ReadOptions {
+                    chrom_alias: ChromAlias::Ucsc,
+                    max_bad_lines: Some(1),
+                    ..ReadOptions::default()
+                },
+                verbose: false,
+                ..CorrelationOptions::default()
+            },
+        )
+        .unwrap();
+    let (streamed_chrom_correlations, streamed_overall_correlations) =
+        get_streamed_correlations(Some(1)).unwrap();
+    assert_eq!(chrom_correlations.len(), 1);
+    assert_eq!(streamed_chrom_correlations.len(), 1);
+    assert_eq!(streamed_chrom_correlations[0].0, "chr1");
+    assert_vec_almost_eq!(
+        chrom_correlations[0].1,
+        streamed_chrom_correlations[0].1
+    );
+    assert_vec_almost_eq!(overall_correlations, streamed_overall_correlations);
+
+    // the bins [0, 10), [20, 30) and [40, 50) are left
+    let expected = weighted_correlation(
+        || vec![(1., 2.), (3., 4.), (4., 8.)].into_iter(),
+        |(a, b)| (a, b, 1.),
+    );
+    assert_vec_almost_eq!(streamed_overall_correlations, vec![
+        expected, expected
+    ]);
+}
+
+#[test]
+fn test_write_correlations_to_dir() {
+    let chrom_correlations = vec![
+        ("chr1".to_string(), vec![0.5, 1.]),
+        ("chr2".to_string(), vec![-0.25, 0.]),
+    ];
+    let overall_correlations = vec![0.125, 0.75];
+    let out_dir = tempfile::tempdir().unwrap();
+    let out_dir_path = out_dir.path().join("correlations");
+    write_correlations_to_dir(
+        &chrom_correlations,
+        &overall_correlations,
+        &[0, 100],
+        out_dir_path.to_str().unwrap(),
+    )
+    .unwrap();
+
+    for (name, expected) in vec![
+        ("chroms/chr1", "chrom\t0\t100\nchr1\t0.5\t1\n"),
+        ("chroms/chr2", "chrom\t0\t100\nchr2\t-0.25\t0\n"),
+        ("overall", "chrom\t0\t100\noverall\t0.125\t0.75\n"),
+    ] {
+        assert_eq!(
+            std::fs::read_to_string(
+                out_dir_path.join(format!("{}.corr.tsv", name))
+            )
+            .unwrap(),
+            expected
+        );
+    }
+
+    // a chromosome named overall does not overwrite the overall correlations
+    write_correlations_to_dir(
+        &vec![("overall".to_string(), vec![0.5, 1.])],
+        &overall_correlations,
+        &[0, 100],
+        out_dir_path.to_str().unwrap(),
+    )
+    .unwrap();
+    assert_eq!(
+        read_overall_correlations(
+            out_dir_path.join("overall.corr.tsv").to_str().unwrap()
+        )
+        .unwrap(),
+        (Some(vec![0, 100]), overall_correlations.clone())
+    );
+
+    // nor is its line in the stdout format mistaken for the overall line
+    let stdout_path = create_temp_bed(
+        "overall, 0.50000, 1.00000, \n\
+        overall, 0.12500, 0.75000, \n",
+    )
+    .unwrap();
+    assert_eq!(
+        read_overall_correlations(stdout_path.to_str().unwrap()).unwrap(),
+        (None, overall_correlations)
+    );
+}
+
+#[test]
+fn test_min_bins() {
+    // chr2 only has two bins, which are perfectly anti-correlated, and so
+    // does chr3 once its bins with NaN values are skipped
+    let path_a = create_temp_bed(
+        "chr1 0 10 a 1\n\
+        chr1 10 20 a 2\n\
+        chr1 20 30 a 4\n\
+        chr1 30 40 a 3\n\
+        chr2 0 10 a 1\n\
+        chr2 10 20 a 2\n\
+        chr3 0 10 a 1\n\
+        chr3 10 20 a NaN\n\
+        chr3 20 30 a NaN\n\
+        chr3 30 40 a 2\n",
+    )
+    .unwrap();
+    let path_b = create_temp_bed(
+        "chr1 0 10 b 2\n\
+        chr1 10 20 b 1\n\
+        chr1 20 30 b 4\n\
+        chr1 30 40 b 3\n\
+        chr2 0 10 b 2\n\
+        chr2 10 20 b 1\n\
+        chr3 0 10 b 2\n\
+        chr3 10 20 b 5\n\
+        chr3 20 30 b 4\n\
+        chr3 30 40 b 1\n",
+    )
+    .unwrap();
+    let track_a = TrackVariant::Bed(Bed::new(path_a.to_str().unwrap(), false));
+    let track_b = TrackVariant::Bed(Bed::new(path_b.to_str().unwrap(), false));
+    let get_chrom_correlations = |min_bins| {
+        let (mut chrom_correlations, _) =
+            compute_track_correlations_with_options(
+                &track_a,
+                &track_b,
+                &vec![10],
+                &CorrelationOptions {
+                    verbose: false,
+                    min_bins,
+                    ..CorrelationOptions::default()
+                },
+            )
+            .unwrap();
+        chrom_correlations.sort_by(|a, b| a.0.cmp(&b.0));
+        chrom_correlations
+    };
+
+    let chrom_correlations = get_chrom_correlations(0);
+    assert_almost_eq!(chrom_correlations[0].1[0], 0.8);
+    assert_almost_eq!(chrom_correlations[1].1[0], -1.);
+    assert_almost_eq!(chrom_correlations[2].1[0], -1.);
+
+    let chrom_correlations = get_chrom_correlations(3);
+    assert_almost_eq!(chrom_correlations[0].1[0], 0.8);
+    assert!(chrom_correlations[1].1[0].is_nan());
+    assert!(chrom_correlations[2].1[0].is_nan());
+}
+
+#[test]
+fn test_min_coverage() {
+    // the first bin is weakly covered in the first track
+    let path_a = create_temp_bed(
+        "chr1 0 10 a 1\n\
+        chr1 10 20 a 4\n\
+        chr1 20 30 a 5\n\
+        chr1 30 40 a 6\n",
+    )
+    .unwrap();
+    let path_b = create_temp_bed(
+        "chr1 0 10 b 8\n\
+        chr1 10 20 b 5\n\
+        chr1 20 30 b 6\n\
+        chr1 30 40 b 7\n",
+    )
+    .unwrap();
+    let track_a = TrackVariant::Bed(Bed::new(path_a.to_str().unwrap(), false));
+    let track_b = TrackVariant::Bed(Bed::new(path_b.to_str().unwrap(), false));
+    let get_correlations = |min_coverage| {
+        let (chrom_correlations, overall_correlations) =
+            compute_track_correlations_with_options(
+                &track_a,
+                &track_b,
+                &vec![0, 10],
+                &CorrelationOptions {
+                    verbose: false,
+                    min_coverage,
+                    ..CorrelationOptions::default()
+                },
+            )
+            .unwrap();
+        assert_eq!(chrom_correlations.len(), 1);
+        (chrom_correlations[0].1.clone(), overall_correlations)
+    };
+
+    let expected = -4. / 70f64.sqrt();
+    for min_coverage in vec![None, Some(1.)] {
+        let (chrom_correlations, overall_correlations) =
+            get_correlations(min_coverage);
+        assert_vec_almost_eq!(chrom_correlations, vec![expected, expected]);
+        assert_vec_almost_eq!(overall_correlations, vec![expected, expected]);
+    }
+
+    let (chrom_correlations, overall_correlations) = get_correlations(Some(2.));
+    assert_vec_almost_eq!(chrom_correlations, vec![1., 1.]);
+    assert_vec_almost_eq!(overall_correlations, vec![1., 1.]);
+}
+
+#[test]
+fn test_coverage_predicate() {
+    // the first track covers [0, 30) and the second track covers [10, 40)
+    let path_a = create_temp_bed(
+        "chr1 0 10 a 1\n\
+        chr1 10 20 a 2\n\
+        chr1 20 30 a 4\n",
+    )
+    .unwrap();
+    let path_b = create_temp_bed(
+        "chr1 10 20 b 3\n\
+        chr1 20 30 b 1\n\
+        chr1 30 40 b 5\n",
+    )
+    .unwrap();
+    let track_a = TrackVariant::Bed(Bed::new(path_a.to_str().unwrap(), false));
+    let track_b = TrackVariant::Bed(Bed::new(path_b.to_str().unwrap(), false));
+    let get_correlations = |coverage_predicate| {
+        let (chrom_correlations, overall_correlations) =
+            compute_track_correlations_with_options(
+                &track_a,
+                &track_b,
+                &vec![0, 10],
+                &CorrelationOptions {
+                    verbose: false,
+                    coverage_predicate,
+                    ..CorrelationOptions::default()
+                },
+            )
+            .unwrap();
+        assert_eq!(chrom_correlations.len(), 1);
+        assert_vec_almost_eq!(chrom_correlations[0].1, overall_correlations);
+        overall_correlations
+    };
+
+    // all four bins, with the uncovered values as zeros
+    let any = -0.5061366753560108;
+    assert_vec_almost_eq!(get_correlations(CoveragePredicate::Any), vec![
+        any, any
+    ]);
+    assert_vec_almost_eq!(get_correlations(CoveragePredicate::Count(1)), vec![
+        any, any
+    ]);
+    // the bins [10, 20) and [20, 30)
+    assert_vec_almost_eq!(get_correlations(CoveragePredicate::All), vec![
+        -1., -1.
+    ]);
+    assert_vec_almost_eq!(get_correlations(CoveragePredicate::Count(2)), vec![
+        -1., -1.
+    ]);
+    // the bins [0, 10), [10, 20) and [20, 30)
+    assert_vec_almost_eq!(get_correlations(CoveragePredicate::First), vec![
+        1. / 7.,
+        1. / 7.
+    ]);
+}
+
+#[test]
+fn test_non_finite_values_are_skipped() {
+    let path_a = create_temp_bed(
+        "chr1 0 10 a 1\n\
+        chr1 10 20 a NaN\n\
+        chr1 20 30 a 3\n\
+        chr1 30 40 a 2\n",
+    )
+    .unwrap();
+    let path_b = create_temp_bed(
+        "chr1 0 10 b 2\n\
+        chr1 10 20 b 5\n\
+        chr1 20 30 b 4\n\
+        chr1 30 40 b 1\n",
+    )
+    .unwrap();
+    let track_a = TrackVariant::Bed(Bed::new(path_a.to_str().unwrap(), false));
+    let track_b = TrackVariant::Bed(Bed::new(path_b.to_str().unwrap(), false));
+    let (chrom_correlations, overall_correlations, stats) =
+        compute_track_correlations_with_stats(
+            &track_a,
+            &track_b,
+            &vec![0, 10],
+            &CorrelationOptions {
+                verbose: false,
+                ..CorrelationOptions::default()
+            },
+        )
+        .unwrap();
+
+    // the correlation over the three remaining bins
+    let expected = 0.6546536707079771;
+    assert_vec_almost_eq!(chrom_correlations[0].1, vec![expected, expected]);
+    assert_vec_almost_eq!(overall_correlations, vec![expected, expected]);
+    assert_eq!(stats.num_skipped_pairs, vec![1, 1]);
+
+    // the streamed overall correlations skip the same bins
+    let (streamed_chrom_correlations, streamed_overall_correlations) =
+        compute_track_correlations_streaming_with_options(
+            &track_a,
+            &track_b,
+            &vec![0, 10],
+            &StreamingOptions {
+                verbose: false,
+                ..StreamingOptions::default()
+            },
+        )
+        .unwrap();
+    assert_vec_almost_eq!(streamed_chrom_correlations[0].1, vec![
+        expected, expected
+    ]);
+    assert_vec_almost_eq!(streamed_overall_correlations, vec![
+        expected, expected
+    ]);
+}
+
+#[test]
+fn test_p_value_sample_size() {
+    let path_a = create_temp_bed(
+        "chr1 0 10 a 1\n\
+        chr1 10 20 a NaN\n\
+        chr1 20 30 a 3\n\
+        chr1 30 40 a 2\n\
+        chr1 50 60 a 4\n",
+    )
+    .unwrap();
+    let path_b = create_temp_bed(
+        "chr1 0 10 b 2\n\
+        chr1 10 20 b 5\n\
+        chr1 20 30 b 4\n\
+        chr1 30 40 b 1\n",
+    )
+    .unwrap();
+    let track_a = TrackVariant::Bed(Bed::new(path_a.to_str().unwrap(), false));
+    let track_b = TrackVariant::Bed(Bed::new(path_b.to_str().unwrap(), false));
+    let get_num_effective_bins =
+        |p_values: PValueMode, coverage_predicate: CoveragePredicate| {
+            compute_track_correlations_with_stats(
+                &track_a,
+                &track_b,
+                &vec![10],
+                &CorrelationOptions {
+                    verbose: false,
+                    p_values,
+                    coverage_predicate,
+                    ..CorrelationOptions::default()
+                },
+            )
+            .unwrap()
+            .2
+            .num_effective_bins
+        };
+
+    // the bin with the NaN value does not enter the correlation
+    assert_eq!(
+        get_num_effective_bins(PValueMode::Naive, CoveragePredicate::Any),
+        vec![4.]
+    );
+    // neither does the bin [50, 60) without data in the second track
+    assert_eq!(
+        get_num_effective_bins(PValueMode::Naive, CoveragePredicate::All),
+        vec![3.]
+    );
+    let corrected = get_num_effective_bins(
+        PValueMode::AutocorrCorrected,
+        CoveragePredicate::Any,
+    );
+    assert!(corrected[0] > 0. && corrected[0] <= 4.);
+    assert!(
+        get_num_effective_bins(PValueMode::None, CoveragePredicate::Any)
+            .is_empty()
+    );
+}
+
+#[test]
+fn test_correlate_against_matrix() {
+    let path_a = create_temp_bed(
+        "chr1 0 30 a 2\n\
+        chr1 30 50 a 5\n\
+        chr2 0 20 a 1\n\
+        chr2 40 45 a 6\n",
+    )
+    .unwrap();
+    let path_b = create_temp_bed(
+        "chr1 10 40 b 3\n\
+        chr2 10 30 b 4\n\
+        chr3 0 10 b 1\n",
+    )
+    .unwrap();
+    let bin_size = 10;
+    let matrix_path = NamedTempFile::new().unwrap().into_temp_path();
+    TrackZipper::new(
+        vec![
+            Bed::new(path_a.to_str().unwrap(), false),
+            Bed::new(path_b.to_str().unwrap(), false),
+        ],
+        None,
+    )
+    .unwrap()
+    .write_concatenated_tracks(None, bin_size, matrix_path.to_str().unwrap())
+    .unwrap();
+
+    let track_a = TrackVariant::Bed(Bed::new(path_a.to_str().unwrap(), false));
+    let track_b = TrackVariant::Bed(Bed::new(path_b.to_str().unwrap(), false));
+    let correlations = correlate_against_matrix(
+        &track_a,
+        matrix_path.to_str().unwrap(),
+        bin_size,
+    )
+    .unwrap();
+    let (_, overall_correlations) = compute_track_correlations_with_options(
+        &track_a,
+        &track_b,
+        &vec![bin_size],
+        &CorrelationOptions {
+            verbose: false,
+            ..CorrelationOptions::default()
+        },
+    )
+    .unwrap();
+
+    // the first column is the track itself
+    assert_eq!(correlations.len(), 2);
+    assert_almost_eq!(correlations[0], 1.);
+    assert_almost_eq!(correlations[1], overall_correlations[0]);
+}
+
+#[test]
+fn test_log_transform() {
+    let log2 = ValueTransform::log2();
+    assert_almost_eq!(log2.apply(0.), 0.);
+    assert_almost_eq!(log2.apply(1.), 1.);
+    assert_almost_eq!(log2.apply(3.), 2.);
+    assert_almost_eq!(log2.apply(-7.), -3.);
+    assert_almost_eq!(log2.apply(-0.5), -(1.5f64).log2());
+
+    let log10 = ValueTransform::log10();
+    assert_almost_eq!(log10.apply(0.), 0.);
+    assert_almost_eq!(log10.apply(9.), 1.);
+    assert_almost_eq!(log10.apply(99.), 2.);
+    assert_almost_eq!(log10.apply(-999.), -3.);
+
+    let log2_with_pseudocount = ValueTransform::Log {
+        base: 2.,
+        pseudocount: 0.5,
+    };
+    assert_almost_eq!(log2_with_pseudocount.apply(0.), 0.);
+    assert_almost_eq!(log2_with_pseudocount.apply(1.5), 1.);
+    assert_almost_eq!(log2_with_pseudocount.apply(-3.5), -2.);
+
+    assert!(log2.validate().is_ok());
+    assert!(log10.validate().is_ok());
+    for base in vec![0., -2., 1., f64::NAN] {
+        match (ValueTransform::Log {
+            base,
+            pseudocount: 1.,
+        })
+        .validate()
+        {
+            Err(Error::Validation(_)) => {}
+            result => panic!("expected a Validation error, got {:?}", result),
+        }
+    }
+}
+
+#[test]
+fn test_collect_correlation_matrix() {
+    let out_dir = tempfile::tempdir().unwrap();
+    let ab_dir = out_dir.path().join("a_b");
+    write_correlations_to_dir(
+        &vec![("chr1".to_string(), vec![0.1, 0.2])],
+        &vec![0.5, 0.6],
+        &[10, 100],
+        ab_dir.to_str().unwrap(),
+    )
+    .unwrap();
+    let ac_dir = out_dir.path().join("a_c");
+    write_correlations_to_dir(
+        &vec![("chr1".to_string(), vec![0.3, 0.4])],
+        &vec![-0.25, 0.75],
+        &[100, 10],
+        ac_dir.to_str().unwrap(),
+    )
+    .unwrap();
+    // the stdout format of compute_track_correlation
+    let bc_path = create_temp_bed(
+        "chr1, 0.10000, 0.20000, \n\
+        overall, 0.12500, 0.87500, \n",
+    )
+    .unwrap();
+
+    let pairs = vec![
+        (
+            "a".to_string(),
+            "b".to_string(),
+            ab_dir
+                .join("overall.corr.tsv")
+                .to_str()
+                .unwrap()
+                .to_string(),
+        ),
+        (
+            "c".to_string(),
+            "a".to_string(),
+            ac_dir
+                .join("overall.corr.tsv")
+                .to_str()
+                .unwrap()
+                .to_string(),
+        ),
+        (
+            "b".to_string(),
+            "c".to_string(),
+            bc_path.to_str().unwrap().to_string(),
+        ),
+    ];
+    let correlation_matrix =
+        collect_correlation_matrix(&pairs, 100, &[10, 100]).unwrap();
+    assert_eq!(correlation_matrix.names, vec!["a", "b", "c"]);
+    assert_eq!(correlation_matrix.matrix, vec![
+        vec![1., 0.6, -0.25],
+        vec![0.6, 1., 0.875],
+        vec![-0.25, 0.875, 1.],
+    ]);
+
+    // the bin size must be present in every file
+    assert!(collect_correlation_matrix(&pairs, 1000, &[10, 100]).is_err());
+    // the bin sizes of the stdout format are ambiguous without stdout_bin_sizes
+    assert!(collect_correlation_matrix(&pairs, 100, &[]).is_err());
+    // each pair can only appear once
+    let mut duplicate_pairs = pairs.clone();
+    duplicate_pairs.push(pairs[0].clone());
+    assert!(
+        collect_correlation_matrix(&duplicate_pairs, 100, &[10, 100]).is_err()
+    );
+}
+
+#[test]
+fn test_count_reads() {
+    // the reads are at the same positions in both tracks with different scores
+    let path_a = create_temp_bed(
+        "chr1 0 5 a 1\n\
+        chr1 2 8 a 9\n\
+        chr1 12 15 a 3\n\
+        chr1 25 28 a 2\n\
+        chr1 26 30 a 7\n\
+        chr1 27 29 a 1\n",
+    )
+    .unwrap();
+    let path_b = create_temp_bed(
+        "chr1 0 5 b 5\n\
+        chr1 2 8 b 1\n\
+        chr1 12 15 b 2\n\
+        chr1 25 28 b 4\n\
+        chr1 26 30 b 1\n\
+        chr1 27 29 b 6\n",
+    )
+    .unwrap();
+    let track_a = TrackVariant::Bed(Bed::new(path_a.to_str().unwrap(), false));
+    let track_b = TrackVariant::Bed(Bed::new(path_b.to_str().unwrap(), false));
+    let get_correlations = |count_reads| {
+        let (chrom_correlations, overall_correlations) =
+            compute_track_correlations_with_options(
+                &track_a,
+                &track_b,
+                &vec![0, 10],
+                &CorrelationOptions {
+                    verbose: false,
+                    count_reads,
+                    ..CorrelationOptions::default()
+                },
+            )
+            .unwrap();
+        assert_eq!(chrom_correlations.len(), 1);
+        (chrom_correlations[0].1.clone(), overall_correlations)
+    };
+
+    let (_, overall_correlations) = get_correlations(false);
+    assert!((overall_correlations[1] - 1.).abs() > 0.01);
+
+    // the read counts of the 10 bp bins are 2, 1 and 3 in both tracks
+    let (chrom_correlations, overall_correlations) = get_correlations(true);
+    assert_vec_almost_eq!(chrom_correlations, vec![1., 1.]);
+    assert_vec_almost_eq!(overall_correlations, vec![1., 1.]);
+}
+
+#[test]
+fn test_effective_sample_size() {
+    let get_map = |content: &str| {
+        let path = create_temp_bed(content).unwrap();
+        get_chrom_interval_map(
+            &TrackVariant::Bed(Bed::new(path.to_str().unwrap(), false)),
+            None,
+        )
+        .unwrap()
+        .remove("chr1")
+        .unwrap()
+    };
+    // 100 bins in runs of 10 equal values
+    let smooth_map = get_map(
+        &(0..10)
+            .map(|i| {
+                format!(
+                    "chr1 {} {} id {}\n",
+                    i * 100,
+                    (i + 1) * 100,
+                    i % 2 * 10
+                )
+            })
+            .collect::<String>(),
+    );
+    let n_eff = effective_sample_size(&smooth_map, 10);
+    assert!(n_eff > 1.);
+    assert!(n_eff < 20.);
+
+    // the values alternate between adjacent bins, which is not corrected for
+    let alternating_map = get_map(
+        &(0..20)
+            .map(|i| format!("chr1 {} {} id {}\n", i * 10, (i + 1) * 10, i % 2))
+            .collect::<String>(),
+    );
+    assert_almost_eq!(effective_sample_size(&alternating_map, 10), 20.);
+
+    assert_almost_eq!(correlation_p_value(0., 100.), 1., 1e-6);
+    assert!(correlation_p_value(0.5, 100.) < 1e-6);
+    assert!(correlation_p_value(0.5, 10.) > 0.1);
+    assert!(correlation_p_value(0.5, 3.).is_nan());
+}
+
+#[test]
+fn test_regression() {
+    // the second track is 2 times the first track plus 1
+    let first_path = create_temp_bed(
+        "chr1 0 10 a 1\n\
+        chr1 10 20 a 3\n\
+        chr1 20 30 a 2\n\
+        chr2 0 10 a 5\n\
+        chr2 10 20 a 4\n",
+    )
+    .unwrap();
+    let second_path = create_temp_bed(
+        "chr1 0 10 b 3\n\
+        chr1 10 20 b 7\n\
+        chr1 20 30 b 5\n\
+        chr2 0 10 b 11\n\
+        chr2 10 20 b 9\n",
+    )
+    .unwrap();
+    let first_track =
+        TrackVariant::Bed(Bed::new(first_path.to_str().unwrap(), false));
+    let second_track =
+        TrackVariant::Bed(Bed::new(second_path.to_str().unwrap(), false));
+    let (chrom_regressions, overall_regressions) = compute_track_regressions(
+        &first_track,
+        &second_track,
+        &[0, 10],
+        &CorrelationOptions {
+            verbose: false,
+            ..CorrelationOptions::default()
+        },
+    )
+    .unwrap();
+
+    assert_eq!(
+        chrom_regressions
+            .iter()
+            .map(|(chrom, _)| chrom.as_str())
+            .collect::<Vec<&str>>(),
+        vec!["chr1", "chr2"]
+    );
+    for regressions in chrom_regressions
+        .iter()
+        .map(|(_, regressions)| regressions)
+        .chain(std::iter::once(&overall_regressions))
+    {
+        assert_eq!(regressions.len(), 2);
+        for &(r, slope, intercept) in regressions.iter() {
+            assert_almost_eq!(r, 1.);
+            assert_almost_eq!(slope, 2.);
+            assert_almost_eq!(intercept, 1.);
+        }
+    }
+
+    // the correlations agree with compute_track_correlations_with_options
+    let (chrom_correlations, _) = compute_track_correlations_with_options(
+        &first_track,
+        &second_track,
+        &vec![0, 10],
+        &CorrelationOptions {
+            verbose: false,
+            ..CorrelationOptions::default()
+        },
+    )
+    .unwrap();
+    for (chrom, correlations) in chrom_correlations {
+        let (_, regressions) =
+            chrom_regressions.iter().find(|(c, _)| *c == chrom).unwrap();
+        for (c, (r, ..)) in correlations.iter().zip(regressions.iter()) {
+            assert_almost_eq!(c, r);
+        }
+    }
+
+    // the options apply to the regressions as they do to the correlations
+    let (chrom_regressions, overall_regressions) = compute_track_regressions(
+        &first_track,
+        &second_track,
+        &[10],
+        &CorrelationOptions {
+            verbose: false,
+            difference_order: DifferenceOrder::First,
+            min_bins: 3,
+            ..CorrelationOptions::default()
+        },
+    )
+    .unwrap();
+    // chr1 has two differences and chr2 has one, both fewer than min_bins
+    for (_, regressions) in chrom_regressions.iter() {
+        assert!(regressions[0].1.is_nan());
+    }
+    let (r, slope, intercept) = overall_regressions[0];
+    assert_almost_eq!(r, 1.);
+    assert_almost_eq!(slope, 2.);
+    assert_almost_eq!(intercept, 0.);
+}
+
+#[test]
+fn test_stranded_correlations() {
+    // the plus strands rise together while the minus strands move in
+    // opposite directions
+    let first_path = create_temp_bed(
+        "chr1 0 10 a 1 +\n\
+        chr1 10 20 a 2 +\n\
+        chr1 20 30 a 3 +\n\
+        chr1 0 10 a 1 -\n\
+        chr1 10 20 a 2 -\n\
+        chr1 20 30 a 3 -\n\
+        chr1 30 40 a 9 .\n",
+    )
+    .unwrap();
+    let second_path = create_temp_bed(
+        "chr1 0 10 b 2 +\n\
+        chr1 10 20 b 4 +\n\
+        chr1 20 30 b 6 +\n\
+        chr1 0 10 b 3 -\n\
+        chr1 10 20 b 2 -\n\
+        chr1 20 30 b 1 -\n\
+        chr1 30 40 b 9 .\n",
+    )
+    .unwrap();
+    let compute = |unknown_strand| {
+        compute_stranded_correlations(
+            first_path.to_str().unwrap(),
+            second_path.to_str().unwrap(),
+            false,
+            &vec![10],
+            &CorrelationOptions {
+                verbose: false,
+                ..CorrelationOptions::default()
+            },
+            unknown_strand,
+        )
+        .unwrap()
+    };
+
+    let ((plus_chrom, plus_overall), (minus_chrom, minus_overall)) =
+        compute(UnknownStrand::Neither);
+    assert_eq!(plus_chrom.len(), 1);
+    assert_eq!(plus_chrom[0].0, "chr1");
+    assert_almost_eq!(plus_chrom[0].1[0], 1.);
+    assert_almost_eq!(plus_overall[0], 1.);
+    assert_eq!(minus_chrom[0].0, "chr1");
+    assert_almost_eq!(minus_chrom[0].1[0], -1.);
+    assert_almost_eq!(minus_overall[0], -1.);
+
+    // the unstranded bin at 30 counts on both strands
+    let ((_, plus_overall), (_, minus_overall)) = compute(UnknownStrand::Both);
+    assert!(plus_overall[0] < 1. - 1e-6);
+    assert!(minus_overall[0] > -1. + 1e-6);
+    assert!(plus_overall[0] > minus_overall[0]);
+}
+
+#[test]
+fn test_find_stable_bin_size() {
+    // the correlation rises with the bin size and saturates at 0.9
+    let curve: Vec<(i64, f64)> = vec![1, 10, 100, 1000, 10000, 100000]
+        .into_iter()
+        .map(|bin_size| {
+            (bin_size, 0.9 - 0.8 / (1. + (bin_size as f64 / 50.).powi(2)))
+        })
+        .collect();
+    assert_eq!(find_stable_bin_size(&curve, 0.01), Some(1000));
+    assert_eq!(find_stable_bin_size(&curve, 1e-3), Some(10000));
+    assert_eq!(find_stable_bin_size(&curve, 1.), Some(1));
+    assert_eq!(find_stable_bin_size(&curve, 1e-8), None);
+
+    // the order of the pairs does not matter
+    let mut shuffled = curve.clone();
+    shuffled.reverse();
+    shuffled.swap(1, 4);
+    assert_eq!(find_stable_bin_size(&shuffled, 0.01), Some(1000));
+
+    // a late jump resets the stable bin size
+    let mut jump = curve.clone();
+    jump.push((1000000, 0.5));
+    assert_eq!(find_stable_bin_size(&jump, 0.01), None);
+    jump.push((10000000, 0.5));
+    assert_eq!(find_stable_bin_size(&jump, 0.01), Some(1000000));
+
+    let with_nan = vec![(10, std::f64::NAN), (100, 0.5), (1000, 0.5)];
+    assert_eq!(find_stable_bin_size(&with_nan, 0.01), Some(100));
+    assert_eq!(find_stable_bin_size(&curve[..1], 0.01), None);
+    assert_eq!(find_stable_bin_size(&[], 0.01), None);
+}
+
+#[test]
+fn test_error_variants() {
+    let out_dir = tempfile::tempdir().unwrap();
+    let missing_path = out_dir.path().join("missing.corr.tsv");
+    match read_overall_correlations(missing_path.to_str().unwrap()) {
+        Err(Error::Io(why)) => {
+            assert_eq!(why.kind(), std::io::ErrorKind::NotFound)
+        }
+        result => panic!("expected an Io error, got {:?}", result),
+    }
+
+    let malformed_path = create_temp_bed("overall, 0.5, abc, \n").unwrap();
+    match read_overall_correlations(malformed_path.to_str().unwrap()) {
+        Err(Error::Parse(_)) => {}
+        result => panic!("expected a Parse error, got {:?}", result),
+    }
+
+    let path = create_temp_bed("overall, 0.5, \n").unwrap();
+    let path = path.to_str().unwrap().to_string();
+    let duplicate_pairs = vec![
+        ("a".to_string(), "b".to_string(), path.clone()),
+        ("b".to_string(), "a".to_string(), path),
+    ];
+    match collect_correlation_matrix(&duplicate_pairs, 100, &[]) {
+        Err(Error::Validation(_)) => {}
+        result => panic!("expected a Validation error, got {:?}", result),
+    }
+
+    // the errors still read as plain messages
+    let message: String = Error::Validation("bad bin size".to_string()).into();
+    assert_eq!(message, "invalid input: bad bin size");
+}
+
+#[test]
+fn test_weighted_moments_precision() {
+    // the raw sums of squares of such values cancel catastrophically
+    let offset = 1e9;
+    let mut moments = WeightedMoments::default();
+    let mut first_half = WeightedMoments::default();
+    let mut second_half = WeightedMoments::default();
+    for i in 0..10 {
+        let (x, y) = (offset + i as f64, offset + 2. * i as f64);
+        moments.add(x, y, 1.);
+        if i < 5 {
+            first_half.add(x, y, 1.);
+        } else {
+            second_half.add(x, y, 1.);
+        }
+    }
+    first_half.merge(&second_half);
+    for m in vec![moments, first_half] {
+        assert_almost_eq!(m.variance_x(), 8.25, 1e-6);
+        assert_almost_eq!(m.variance_y(), 33., 1e-6);
+        let (correlation, slope, intercept) = m.regression();
+        assert_almost_eq!(correlation, 1., 1e-9);
+        assert_almost_eq!(slope, 2., 1e-9);
+        assert_almost_eq!(intercept, -offset, 1e-4);
+    }
+}